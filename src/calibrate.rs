@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::sync::mpsc;
+use std::time;
+use clap;
+use color::*;
+use device::*;
+use keys::{Key, Terminal};
+use profile::{Profile, Value};
+
+
+/// The interval at which the test field is sent again while no keys are pressed, so outputs that
+/// turn off without new frames keep showing it.
+const REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name("calibrate")
+        .about("Show test fields on the display and adjust the white balance and gamma with the \
+                keyboard, then save them to a profile")
+        .arg(clap::Arg::with_name("path")
+            .required(true)
+            .help("The profile that is written when s is pressed. The other calibration options \
+                   that are set are saved along with the white balance and gamma"))
+}
+
+
+/// The white balance and gamma that are adjusted during calibration. The values are kept as
+/// whole steps so adjusting them back and forth does not accumulate rounding errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// The multipliers of the red, green and blue channels in percent.
+    balance: [u32; 3],
+    /// The gamma in tenths.
+    gamma: u32,
+}
+
+impl Calibration {
+    pub fn new(white_balance: [f32; 3], gamma: f64) -> Calibration {
+        let balance = white_balance.map(|v| (v * 100.0).round().clamp(0.0, 100.0) as u32);
+        let gamma = ((gamma * 10.0).round() as u32).clamp(1, 50);
+        Calibration { balance, gamma }
+    }
+
+    /// The correction that shows the test fields, with the white balance applied before the gamma
+    /// like the --white-balance option is.
+    fn correction(&self, brightness: f32) -> Correction {
+        let [r, g, b] = self.balance.map(|v| v as f32 / 100.0);
+        Correction::balance(r, g, b)
+            .then(Correction::gamma(f64::from(self.gamma) / 10.0))
+            .with_brightness(brightness)
+    }
+
+    fn adjust(&mut self, action: Action) {
+        match action {
+            Action::Channel(i, delta) => {
+                self.balance[i] = (self.balance[i] as i32 + delta).clamp(0, 100) as u32;
+            },
+            Action::Gamma(delta) => self.gamma = (self.gamma as i32 + delta).clamp(1, 50) as u32,
+            _ => (),
+        }
+    }
+
+    /// Sets the white balance and replaces the color correction with the gamma.
+    pub fn apply_to(&self, profile: &mut Profile) {
+        let balance: Vec<String> = self.balance.iter().map(|&v| percent(v)).collect();
+        profile.set("white-balance", Value::String(balance.join(",")));
+        let gamma = format!("gamma:{}.{}", self.gamma / 10, self.gamma % 10);
+        profile.set("color-correction", Value::Array(vec![Value::String(gamma)]));
+    }
+}
+
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "R {} G {} B {} gamma {}.{}",
+               percent(self.balance[0]), percent(self.balance[1]), percent(self.balance[2]),
+               self.gamma / 10, self.gamma % 10)
+    }
+}
+
+fn percent(v: u32) -> String {
+    format!("{}.{:02}", v / 100, v % 100)
+}
+
+
+/// The test fields that are shown while calibrating.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    White,
+    Gray,
+    /// Alternating full white and black pixels next to middle gray, which look equally bright
+    /// when the gamma is right.
+    Gamma,
+    Red,
+    Green,
+    Blue,
+}
+
+const FIELDS: [Field; 6] = [Field::White, Field::Gray, Field::Gamma, Field::Red, Field::Green, Field::Blue];
+
+impl Field {
+    fn colors(self, num_outputs: usize) -> Vec<[f32; 3]> {
+        (0..num_outputs)
+            .map(|i| match self {
+                Field::White => [1.0; 3],
+                Field::Gray => [0.5; 3],
+                Field::Gamma if i < num_outputs / 2 => [(i % 2) as f32; 3],
+                Field::Gamma => [0.5; 3],
+                Field::Red => [1.0, 0.0, 0.0],
+                Field::Green => [0.0, 1.0, 0.0],
+                Field::Blue => [0.0, 0.0, 1.0],
+            })
+            .collect()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::White => "white",
+            Field::Gray => "gray",
+            Field::Gamma => "gamma",
+            Field::Red => "red",
+            Field::Green => "green",
+            Field::Blue => "blue",
+        }
+    }
+}
+
+
+/// The actions of the keys while calibrating.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    NextField,
+    PreviousField,
+    /// Changes the multiplier of a channel by a number of steps.
+    Channel(usize, i32),
+    Gamma(i32),
+    Save,
+    Quit,
+}
+
+impl Action {
+    fn from_key(key: Key) -> Option<Action> {
+        match key {
+            Key::Char(b'n') | Key::Char(b' ') | Key::Right => Some(Action::NextField),
+            Key::Char(b'p') | Key::Left => Some(Action::PreviousField),
+            Key::Char(b'r') => Some(Action::Channel(0, -1)),
+            Key::Char(b'R') => Some(Action::Channel(0, 1)),
+            Key::Char(b'g') => Some(Action::Channel(1, -1)),
+            Key::Char(b'G') => Some(Action::Channel(1, 1)),
+            Key::Char(b'b') => Some(Action::Channel(2, -1)),
+            Key::Char(b'B') => Some(Action::Channel(2, 1)),
+            Key::Char(b'+') | Key::Up => Some(Action::Gamma(1)),
+            Key::Char(b'-') | Key::Down => Some(Action::Gamma(-1)),
+            Key::Char(b's') => Some(Action::Save),
+            // Ctrl-C is read as a byte in raw mode.
+            Key::Char(b'q') | Key::Char(0x03) => Some(Action::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Shows the test fields until q is pressed and writes the calibration, along with the rest of
+/// the profile, to the path when s is pressed.
+pub fn run(output: &mut dyn Output,
+           num_outputs: usize,
+           mut calibration: Calibration,
+           brightness: f32,
+           mut profile: Profile,
+           path: &path::Path)
+           -> io::Result<()> {
+    let terminal = Terminal::open()?;
+    eprintln!("n/p: next/previous field, r/R g/G b/B: lower/raise a channel, -/+: gamma, s: save, q: quit");
+    let mut field = 0;
+    let mut changed = true;
+    loop {
+        if changed {
+            eprintln!("{: <6} {}", FIELDS[field].name(), calibration);
+        }
+        let correction = calibration.correction(brightness);
+        let frame: Vec<Pixel16> = FIELDS[field].colors(num_outputs).into_iter()
+            .map(|color| correction.correct_wide(color))
+            .collect();
+        output.output_frame_wide(&frame)?;
+        let action = match terminal.keys().recv_timeout(REFRESH_INTERVAL) {
+            Ok(key) => Action::from_key(key),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Some(Action::Quit),
+        };
+        changed = action.is_some();
+        match action {
+            Some(Action::NextField) => field = (field + 1) % FIELDS.len(),
+            Some(Action::PreviousField) => field = (field + FIELDS.len() - 1) % FIELDS.len(),
+            Some(Action::Save) => {
+                calibration.apply_to(&mut profile);
+                match fs::write(path, profile.to_string()) {
+                    Ok(()) => eprintln!("Saved the profile to {}", path.display()),
+                    Err(err) => eprintln!("Could not write the profile: {}", err),
+                }
+                changed = false;
+            },
+            Some(Action::Quit) => break,
+            Some(action) => calibration.adjust(action),
+            None => (),
+        }
+    }
+    output.output_frame_wide(&vec![Pixel16::default(); num_outputs])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust_calibration() {
+        let mut calibration = Calibration::new([1.0, 0.9, 0.855], 2.2);
+        assert_eq!("R 1.00 G 0.90 B 0.86 gamma 2.2", calibration.to_string());
+        calibration.adjust(Action::Channel(0, 1));
+        calibration.adjust(Action::Channel(1, -1));
+        calibration.adjust(Action::Gamma(1));
+        assert_eq!("R 1.00 G 0.89 B 0.86 gamma 2.3", calibration.to_string());
+        for _ in 0..30 {
+            calibration.adjust(Action::Gamma(-1));
+        }
+        assert_eq!("R 1.00 G 0.89 B 0.86 gamma 0.1", calibration.to_string());
+    }
+
+    #[test]
+    fn calibration_profile() {
+        let mut profile = Profile {
+            entries: vec![
+                ("color-correction".to_string(), Value::Array(vec![Value::String("srgb".to_string())])),
+                ("dim".to_string(), Value::Number("0.5".to_string())),
+            ],
+        };
+        Calibration::new([1.0, 0.95, 0.9], 2.5).apply_to(&mut profile);
+        assert_eq!("color-correction = [\"gamma:2.5\"]\n\
+                    dim = 0.5\n\
+                    white-balance = \"1.00,0.95,0.90\"\n", profile.to_string());
+    }
+
+    #[test]
+    fn gamma_field() {
+        let colors = Field::Gamma.colors(6);
+        assert_eq!(vec![[0.0; 3], [1.0; 3], [0.0; 3], [0.5; 3], [0.5; 3], [0.5; 3]], colors);
+    }
+
+    #[test]
+    fn action_keys() {
+        let keys = [Key::Right, Key::Char(b'r'), Key::Char(b'G'), Key::Up, Key::Char(b'-'), Key::Char(b'x'), Key::Char(b's'), Key::Char(b'q')];
+        let actions: Vec<Action> = keys.iter().filter_map(|&k| Action::from_key(k)).collect();
+        assert_eq!(vec![Action::NextField, Action::Channel(0, -1), Action::Channel(1, 1), Action::Gamma(1),
+                        Action::Gamma(-1), Action::Save, Action::Quit], actions);
+    }
+}
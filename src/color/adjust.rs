@@ -0,0 +1,329 @@
+use std::fs;
+use std::io;
+use std::path;
+use color::Pixel;
+use color::planar::Planes;
+
+
+/// The adjustments that are applied to each pixel before color correction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adjust {
+    /// The input values that are mapped to black and full white, values outside of this range
+    /// are clipped.
+    pub levels: (u8, u8),
+    /// Scales the distance of each value from the middle gray.
+    pub contrast: f32,
+    /// Added to each value after the contrast is applied.
+    pub brightness_offset: i16,
+    /// The global brightness between 0 and 1.0.
+    pub dim: f32,
+    /// Multiplies the saturation of each color, 0 turns the image to grayscale.
+    pub saturation: f32,
+    /// Rotates the hue of each color by this number of degrees.
+    pub hue_rotate: f32,
+    /// Multipliers for the red, green and blue channels to correct the white point.
+    pub white_balance: [f32; 3],
+    /// A brightness multiplier for each output pixel, applied after dimming.
+    pub compensation: Option<Vec<f32>>,
+}
+
+impl Default for Adjust {
+    fn default() -> Adjust {
+        Adjust {
+            levels: (0, 255),
+            contrast: 1.0,
+            brightness_offset: 0,
+            dim: 1.0,
+            saturation: 1.0,
+            hue_rotate: 0.0,
+            white_balance: [1.0; 3],
+            compensation: None,
+        }
+    }
+}
+
+impl Adjust {
+    /// Adjusts the pixel that is shown at the specified output index. The channels of the
+    /// returned color are between 0 and 1.0 and are not rounded, so no precision is lost before
+    /// the color correction.
+    pub fn apply_wide(&self, pix: &Pixel, index: usize) -> [f32; 3] {
+        let mut color = [f32::from(pix.r) / 255.0, f32::from(pix.g) / 255.0, f32::from(pix.b) / 255.0];
+        if let Some(levels) = self.levels() {
+            for v in color.iter_mut() {
+                *v = levels.apply(*v);
+            }
+        }
+        if self.shifts_hsv() {
+            color = self.shift_hsv(color);
+        }
+        let factor = self.dim * self.compensation.as_ref().and_then(|c| c.get(index)).unwrap_or(&1.0);
+        for (v, &balance) in color.iter_mut().zip(&self.white_balance) {
+            *v = scale(*v, factor, balance);
+        }
+        color
+    }
+
+    /// Adjusts all colors of a frame, which are indexed by their output index. The result is the
+    /// same as that of apply_wide for each pixel, but the steps that treat the channels
+    /// independently are applied to a full plane at a time.
+    pub fn apply_planar(&self, planes: &mut Planes) {
+        if let Some(levels) = self.levels() {
+            for plane in planes.channels.iter_mut() {
+                for v in plane.iter_mut() {
+                    *v = levels.apply(*v);
+                }
+            }
+        }
+        if self.shifts_hsv() {
+            planes.map(|color| self.shift_hsv(color));
+        }
+        let compensation = self.compensation.as_ref().map(|c| &c[..]).unwrap_or(&[]);
+        for (plane, &balance) in planes.channels.iter_mut().zip(&self.white_balance) {
+            // Pixels past the end of the compensation are not compensated.
+            let split = compensation.len().min(plane.len());
+            let (compensated, rest) = plane.split_at_mut(split);
+            for (v, c) in compensated.iter_mut().zip(compensation) {
+                *v = scale(*v, self.dim * c, balance);
+            }
+            for v in rest.iter_mut() {
+                *v = scale(*v, self.dim, balance);
+            }
+        }
+    }
+
+    /// The mapping of the levels, contrast and brightness offset, or None if it leaves the colors
+    /// as they are.
+    fn levels(&self) -> Option<Levels> {
+        if self.levels == (0, 255) && self.contrast == 1.0 && self.brightness_offset == 0 {
+            return None;
+        }
+        Some(Levels {
+            black: f32::from(self.levels.0) / 255.0,
+            white: f32::from(self.levels.1) / 255.0,
+            contrast: self.contrast,
+            offset: f32::from(self.brightness_offset) / 255.0,
+        })
+    }
+
+    fn shifts_hsv(&self) -> bool {
+        self.saturation != 1.0 || self.hue_rotate != 0.0
+    }
+
+    /// Applies the saturation and hue rotation to a color.
+    fn shift_hsv(&self, color: [f32; 3]) -> [f32; 3] {
+        let (h, s, v) = to_hsv(color);
+        from_hsv(h + self.hue_rotate, (s * self.saturation).clamp(0.0, 1.0), v)
+    }
+
+    /// Whether each channel is adjusted independently of the others and of the position of the
+    /// pixel.
+    pub fn is_per_channel(&self) -> bool {
+        self.saturation == 1.0 && self.hue_rotate == 0.0 && self.compensation.is_none()
+    }
+
+    /// Like apply_wide, but rounds the result to 8 bits per channel.
+    pub fn apply(&self, pix: Pixel, index: usize) -> Pixel {
+        let [r, g, b] = self.apply_wide(&pix, index).map(|v| (v * 255.0).round() as u8);
+        Pixel { r, g, b }
+    }
+}
+
+/// Maps a channel from the levels to the full range and applies the contrast and brightness
+/// offset.
+#[derive(Clone, Copy)]
+struct Levels {
+    black: f32,
+    white: f32,
+    contrast: f32,
+    offset: f32,
+}
+
+impl Levels {
+    fn apply(&self, v: f32) -> f32 {
+        let t = (v - self.black) / (self.white - self.black);
+        ((t - 0.5) * self.contrast + 0.5 + self.offset).clamp(0.0, 1.0)
+    }
+}
+
+/// Dims a channel by `factor` and corrects it for the white balance.
+fn scale(v: f32, factor: f32, balance: f32) -> f32 {
+    (v * factor * balance).min(1.0)
+}
+
+/// Parses a list of brightness multipliers, one for each output pixel, separated by commas or
+/// newlines. Lines starting with `#` are ignored.
+pub fn parse_compensation(s: &str) -> Result<Vec<f32>, String> {
+    s.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.parse::<f32>() {
+            Ok(f) if f >= 0.0 && f.is_finite() => Ok(f),
+            _ => Err(format!("invalid brightness: \"{}\"", v)),
+        })
+        .collect()
+}
+
+/// Converts a color to its hue in degrees and its saturation and value in the range 0..1.
+fn to_hsv(color: [f32; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = color;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+    (h, s, max)
+}
+
+fn from_hsv(h: f32, s: f32, v: f32) -> [f32; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    [r + m, g + m, b + m].map(|v| v.clamp(0.0, 1.0))
+}
+
+/// Reads the compensation of --brightness-map from a file.
+pub fn load_compensation<P: AsRef<path::Path>>(path: P) -> io::Result<Vec<f32>> {
+    parse_compensation(&fs::read_to_string(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Parses the scale of the red, green and blue channel of --white-balance, e.g. "1,0.9,0.8".
+pub fn parse_white_balance(s: &str) -> Result<[f32; 3], String> {
+    let values = s.split(',')
+        .map(|v| match v.trim().parse::<f32>() {
+            Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+            _ => Err(format!("invalid white balance value: \"{}\", expected 0 to 1.0", v)),
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+    match values.as_slice() {
+        [r, g, b] => Ok([*r, *g, *b]),
+        _ => Err(format!("the white balance requires 3 values, got {}", values.len())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hsv_roundtrip() {
+        for &(r, g, b) in &[(0, 0, 0), (255, 255, 255), (255, 0, 0), (12, 200, 99), (1, 2, 3), (250, 10, 240)] {
+            let color = [r, g, b].map(|v: u8| f32::from(v) / 255.0);
+            let (h, s, v) = to_hsv(color);
+            let [r2, g2, b2] = from_hsv(h, s, v).map(|v| (v * 255.0).round() as u8);
+            assert_eq!([r, g, b], [r2, g2, b2]);
+        }
+    }
+
+    #[test]
+    fn adjust_planar() {
+        let adjusts = vec![
+            Adjust::default(),
+            Adjust {
+                levels: (10, 240),
+                contrast: 1.2,
+                brightness_offset: -5,
+                dim: 0.6,
+                white_balance: [1.0, 0.9, 0.7],
+                ..Adjust::default()
+            },
+            Adjust {
+                saturation: 0.5,
+                hue_rotate: 30.0,
+                compensation: Some(vec![0.5, 1.0, 2.0]),
+                ..Adjust::default()
+            },
+        ];
+        let pixels: Vec<Pixel> = (0..256)
+            .map(|v| Pixel { r: v as u8, g: (255 - v) as u8, b: (v * 7 % 256) as u8 })
+            .collect();
+        for adjust in adjusts {
+            let mut planes = Planes::default();
+            planes.reset(pixels.len());
+            for (i, pix) in pixels.iter().enumerate() {
+                planes.set_pixel(i, pix);
+            }
+            adjust.apply_planar(&mut planes);
+            for (i, pix) in pixels.iter().enumerate() {
+                assert_eq!(adjust.apply_wide(pix, i), planes.get(i));
+            }
+        }
+    }
+
+    #[test]
+    fn adjust_saturation() {
+        let adjust = Adjust { saturation: 0.0, ..Adjust::default() };
+        assert_eq!(Pixel { r: 200, g: 200, b: 200 }, adjust.apply(Pixel { r: 200, g: 0, b: 100 }, 0));
+        let adjust = Adjust { saturation: 0.5, ..Adjust::default() };
+        assert_eq!(Pixel { r: 200, g: 100, b: 150 }, adjust.apply(Pixel { r: 200, g: 0, b: 100 }, 0));
+    }
+
+    #[test]
+    fn adjust_hue_rotate() {
+        let adjust = Adjust { hue_rotate: 120.0, ..Adjust::default() };
+        assert_eq!(Pixel { r: 0, g: 255, b: 0 }, adjust.apply(Pixel { r: 255, g: 0, b: 0 }, 0));
+        let adjust = Adjust { hue_rotate: -120.0, ..Adjust::default() };
+        assert_eq!(Pixel { r: 0, g: 0, b: 255 }, adjust.apply(Pixel { r: 255, g: 0, b: 0 }, 0));
+    }
+
+    #[test]
+    fn adjust_dim() {
+        let adjust = Adjust { dim: 0.5, ..Adjust::default() };
+        assert_eq!(Pixel { r: 128, g: 0, b: 64 }, adjust.apply(Pixel { r: 255, g: 0, b: 128 }, 0));
+    }
+
+    #[test]
+    fn adjust_compensation() {
+        let adjust = Adjust { dim: 0.5, compensation: Some(vec![1.0, 0.5, 2.0]), ..Adjust::default() };
+        let pix = Pixel { r: 255, g: 100, b: 0 };
+        assert_eq!(Pixel { r: 128, g: 50, b: 0 }, adjust.apply(pix.clone(), 0));
+        assert_eq!(Pixel { r: 64, g: 25, b: 0 }, adjust.apply(pix.clone(), 1));
+        assert_eq!(Pixel { r: 255, g: 100, b: 0 }, adjust.apply(pix.clone(), 2));
+    }
+
+    #[test]
+    fn compensation_parse() {
+        assert_eq!(Ok(vec![1.0, 0.5, 0.75]), parse_compensation("# comment\n1.0, 0.5\n0.75\n"));
+        assert!(parse_compensation("1.0, -1").is_err());
+        assert!(parse_compensation("1.0, x").is_err());
+    }
+
+    #[test]
+    fn adjust_white_balance() {
+        let adjust = Adjust { white_balance: [1.0, 0.8, 0.5], ..Adjust::default() };
+        assert_eq!(Pixel { r: 255, g: 204, b: 128 }, adjust.apply(Pixel { r: 255, g: 255, b: 255 }, 0));
+    }
+
+    #[test]
+    fn adjust_levels() {
+        let adjust = Adjust { levels: (20, 220), ..Adjust::default() };
+        assert_eq!(Pixel { r: 0, g: 128, b: 255 }, adjust.apply(Pixel { r: 10, g: 120, b: 230 }, 0));
+    }
+
+    #[test]
+    fn adjust_contrast_brightness() {
+        let adjust = Adjust { contrast: 2.0, ..Adjust::default() };
+        assert_eq!(Pixel { r: 0, g: 129, b: 255 }, adjust.apply(Pixel { r: 60, g: 128, b: 200 }, 0));
+        let adjust = Adjust { brightness_offset: -20, ..Adjust::default() };
+        assert_eq!(Pixel { r: 0, g: 108, b: 235 }, adjust.apply(Pixel { r: 10, g: 128, b: 255 }, 0));
+    }
+}
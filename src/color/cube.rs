@@ -0,0 +1,167 @@
+use std::fs;
+use std::io;
+use std::path;
+use std::str;
+use color::Pixel;
+
+
+/// The largest table the .cube format allows, larger tables are not used in practice.
+const MAX_SIZE: usize = 256;
+
+/// A 3D lookup table as written to `.cube` files by color grading and calibration tools.
+///
+/// Colors that fall between the points of the table are interpolated trilinearly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cube {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    // The output color at each point, red changes fastest, then green, then blue.
+    table: Vec<[f32; 3]>,
+}
+
+impl Cube {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Cube> {
+        fs::read_to_string(path)?.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn lookup(&self, pix: &Pixel) -> Pixel {
+        let color = self.apply([f32::from(pix.r) / 255.0, f32::from(pix.g) / 255.0, f32::from(pix.b) / 255.0]);
+        let [r, g, b] = color.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8);
+        Pixel { r, g, b }
+    }
+
+    /// Looks up a color with channels between 0 and 1.0.
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        let n = self.size - 1;
+        // The position of the color in the table along each axis.
+        let pos: Vec<f32> = color.iter().enumerate()
+            .map(|(i, &v)| {
+                let (min, max) = (self.domain_min[i], self.domain_max[i]);
+                let v = (v - min) / (max - min);
+                v.clamp(0.0, 1.0) * n as f32
+            })
+            .collect();
+        let lo: Vec<usize> = pos.iter().map(|&p| (p.floor() as usize).min(n.saturating_sub(1))).collect();
+        let frac: Vec<f32> = pos.iter().zip(&lo).map(|(&p, &l)| p - l as f32).collect();
+        let mut out = [0.0f32; 3];
+        for corner in 0..8 {
+            let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = [dr, dg, db].iter().enumerate()
+                .map(|(i, &d)| if d == 1 { frac[i] } else { 1.0 - frac[i] })
+                .product::<f32>();
+            if weight == 0.0 {
+                continue;
+            }
+            let index = (lo[0] + dr).min(n) + self.size * ((lo[1] + dg).min(n) + self.size * (lo[2] + db).min(n));
+            for (o, v) in out.iter_mut().zip(&self.table[index]) {
+                *o += weight * v;
+            }
+        }
+        out
+    }
+}
+
+impl str::FromStr for Cube {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+        let triple = |values: &[&str], lineno: usize| -> Result<[f32; 3], String> {
+            let v = values.iter()
+                .map(|v| v.parse::<f32>().map_err(|_| format!("line {}: invalid number: \"{}\"", lineno, v)))
+                .collect::<Result<Vec<f32>, String>>()?;
+            match v.as_slice() {
+                [r, g, b] => Ok([*r, *g, *b]),
+                _ => Err(format!("line {}: expected 3 values, got {}", lineno, v.len())),
+            }
+        };
+        for (i, line) in s.lines().enumerate() {
+            let lineno = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[0] {
+                "TITLE" => (),
+                "LUT_3D_SIZE" => {
+                    size = match words.get(1).and_then(|v| v.parse::<usize>().ok()) {
+                        Some(n) if (2..=MAX_SIZE).contains(&n) => Some(n),
+                        _ => return Err(format!("line {}: invalid LUT_3D_SIZE", lineno)),
+                    };
+                },
+                "DOMAIN_MIN" => domain_min = triple(&words[1..], lineno)?,
+                "DOMAIN_MAX" => domain_max = triple(&words[1..], lineno)?,
+                "LUT_1D_SIZE" => return Err("1D lookup tables are not supported".to_string()),
+                _ => table.push(triple(&words, lineno)?),
+            }
+        }
+        let size = size.ok_or_else(|| "missing LUT_3D_SIZE".to_string())?;
+        if table.len() != size * size * size {
+            return Err(format!("expected {} entries, got {}", size * size * size, table.len()));
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err("DOMAIN_MAX should be larger than DOMAIN_MIN".to_string());
+        }
+        Ok(Cube { size, domain_min, domain_max, table })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(size: usize) -> String {
+        let mut s = format!("TITLE \"identity\"\n# comment\nLUT_3D_SIZE {}\n", size);
+        let n = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    s += &format!("{} {} {}\n", r as f32 / n, g as f32 / n, b as f32 / n);
+                }
+            }
+        }
+        s
+    }
+
+    #[test]
+    fn cube_parse() {
+        assert!(identity(2).parse::<Cube>().is_ok());
+        assert!("LUT_3D_SIZE 2\n0 0 0\n".parse::<Cube>().is_err());
+        assert!("LUT_3D_SIZE 4294967296\n0 0 0\n".parse::<Cube>().is_err());
+        assert!("LUT_3D_SIZE 257\n0 0 0\n".parse::<Cube>().is_err());
+        assert!("0 0 0\n".parse::<Cube>().is_err());
+        assert!("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n".parse::<Cube>().is_err());
+        assert!(identity(2).replace("1 1 1", "1 x 1").parse::<Cube>().is_err());
+    }
+
+    #[test]
+    fn cube_identity() {
+        for size in &[2, 3, 17] {
+            let cube: Cube = identity(*size).parse().unwrap();
+            for pix in &[Pixel { r: 0, g: 0, b: 0 }, Pixel { r: 10, g: 128, b: 255 }, Pixel { r: 255, g: 1, b: 77 }] {
+                assert_eq!(*pix, cube.lookup(pix));
+            }
+        }
+    }
+
+    #[test]
+    fn cube_interpolate() {
+        // Maps every color to its inverse.
+        let s = identity(2).lines()
+            .map(|l| if l.starts_with(|c: char| c.is_ascii_digit()) {
+                l.split(' ').map(|v| (1.0 - v.parse::<f32>().unwrap()).to_string()).collect::<Vec<_>>().join(" ")
+            } else {
+                l.to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let cube: Cube = s.parse().unwrap();
+        assert_eq!(Pixel { r: 255, g: 127, b: 0 }, cube.lookup(&Pixel { r: 0, g: 128, b: 255 }));
+    }
+}
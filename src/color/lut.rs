@@ -0,0 +1,92 @@
+use color::*;
+use color::adjust::Adjust;
+
+
+/// The adjustments, dimming and color correction precomputed for each of the 256 values of each
+/// channel, so a pixel is processed with three lookups.
+///
+/// This is only possible when every step treats the channels independently, see
+/// `Adjust::is_per_channel` and `Correction::is_per_channel`.
+pub struct Lut {
+    // The adjusted value of each channel, before the color correction.
+    adjusted: [[u8; 256]; 3],
+    corrected: [[u16; 256]; 3],
+}
+
+impl Lut {
+    pub fn new(adjust: &Adjust, correction: &Correction) -> Option<Lut> {
+        if !adjust.is_per_channel() || !correction.is_per_channel() {
+            return None;
+        }
+        let mut lut = Lut {
+            adjusted: [[0; 256]; 3],
+            corrected: [[0; 256]; 3],
+        };
+        for v in 0..256 {
+            let b = v as u8;
+            let color = adjust.apply_wide(&Pixel { r: b, g: b, b }, 0);
+            let wide = correction.correct_wide(color);
+            for (adjusted, c) in lut.adjusted.iter_mut().zip(&color) {
+                adjusted[v] = (c * 255.0).round() as u8;
+            }
+            lut.corrected[0][v] = wide.r;
+            lut.corrected[1][v] = wide.g;
+            lut.corrected[2][v] = wide.b;
+        }
+        Some(lut)
+    }
+
+    /// Returns the adjusted color, as Adjust::apply would.
+    pub fn adjust(&self, pix: &Pixel) -> Pixel {
+        Pixel {
+            r: self.adjusted[0][pix.r as usize],
+            g: self.adjusted[1][pix.g as usize],
+            b: self.adjusted[2][pix.b as usize],
+        }
+    }
+
+    /// Returns the adjusted and corrected color, without dithering.
+    pub fn correct(&self, pix: &Pixel) -> Pixel16 {
+        Pixel16 {
+            r: self.corrected[0][pix.r as usize],
+            g: self.corrected[1][pix.g as usize],
+            b: self.corrected[2][pix.b as usize],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_per_pixel() {
+        let adjust = Adjust {
+            levels: (10, 240),
+            contrast: 1.2,
+            dim: 0.6,
+            white_balance: [1.0, 0.9, 0.7],
+            ..Adjust::default()
+        };
+        let correction = Correction::srgb(255, 240, 230)
+            .then(Correction::gamma(1.1))
+            .with_brightness(0.8);
+        let lut = Lut::new(&adjust, &correction).unwrap();
+        for v in 0..256 {
+            let pix = Pixel { r: v as u8, g: (255 - v) as u8, b: (v * 7 % 256) as u8 };
+            let color = adjust.apply_wide(&pix, 0);
+            assert_eq!(adjust.apply(pix.clone(), 0), lut.adjust(&pix));
+            assert_eq!(correction.correct_wide(color), lut.correct(&pix));
+        }
+    }
+
+    #[test]
+    fn mixed_channels() {
+        let correction = Correction::none();
+        assert!(Lut::new(&Adjust::default(), &correction).is_some());
+        assert!(Lut::new(&Adjust { saturation: 0.5, ..Adjust::default() }, &correction).is_none());
+        assert!(Lut::new(&Adjust { hue_rotate: 10.0, ..Adjust::default() }, &correction).is_none());
+        assert!(Lut::new(&Adjust { compensation: Some(vec![1.0]), ..Adjust::default() }, &correction).is_none());
+    }
+}
@@ -1,3 +1,14 @@
+use std::fs;
+use std::str;
+use std::time;
+
+pub mod adjust;
+pub mod cube;
+pub mod lut;
+pub mod planar;
+pub mod schedule;
+pub mod white;
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Pixel {
     pub r: u8,
@@ -5,46 +16,599 @@ pub struct Pixel {
     pub b: u8,
 }
 
+/// A color with 16 bits per channel, used between the color correction and the device.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pixel16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Pixel16 {
+    /// Rounds the color to the nearest 8 bit value of each channel.
+    pub fn narrow(&self) -> Pixel {
+        let narrow = |v: u16| ((u32::from(v) + 128) / 257) as u8;
+        Pixel { r: narrow(self.r), g: narrow(self.g), b: narrow(self.b) }
+    }
+
+    /// Scales the light output of the color, with `level` between 0 and 1.0.
+    pub fn scale(&self, level: f32) -> Pixel16 {
+        let scale = |v: u16| (f32::from(v) * level).round() as u16;
+        Pixel16 { r: scale(self.r), g: scale(self.g), b: scale(self.b) }
+    }
+}
+
+impl From<Pixel> for Pixel16 {
+    fn from(pix: Pixel) -> Pixel16 {
+        Pixel16 {
+            r: u16::from(pix.r) * 257,
+            g: u16::from(pix.g) * 257,
+            b: u16::from(pix.b) * 257,
+        }
+    }
+}
+
+/// Correction maps the colors of the input to those that should be sent to the device.
+///
+/// A correction is a chain of stages that are applied in order, e.g. a white balance followed by
+/// a gamma curve. The corrected colors are computed at 16 bits per channel so they can optionally
+/// be dithered down to the bit depth of the device.
 pub struct Correction {
-    r: Vec<u8>,
-    g: Vec<u8>,
-    b: Vec<u8>,
+    stages: Vec<Stage>,
+    dither: Option<u32>,
+    // Scales the corrected colors, which dims the display linearly in light output.
+    brightness: f32,
+    // Sets the brightness depending on the time of day, the current level is set by update().
+    schedule: Option<schedule::Schedule>,
+    level: f32,
+}
+
+/// A single step of a correction. Colors are represented with channels between 0 and 1.0.
+enum Stage {
+    /// A curve for each of the red, green and blue channels, sampled at 256 evenly spaced points.
+    Curves([Vec<f32>; 3]),
+    /// Multiplies each of the channels.
+    Scale([f32; 3]),
+    /// Looks the color up in a 3D table.
+    Cube(cube::Cube),
+}
+
+impl Stage {
+    fn curve<F: Fn(f64) -> f64>(f: F) -> Stage {
+        let table: Vec<f32> = (0..256)
+            .map(|i| f(f64::from(i) / 255.0) as f32)
+            .collect();
+        Stage::Curves([table.clone(), table.clone(), table])
+    }
+
+    fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        match *self {
+            Stage::Curves(ref curves) => {
+                let mut out = [0.0; 3];
+                for i in 0..3 {
+                    // Interpolate between the two nearest samples.
+                    let pos = color[i].clamp(0.0, 1.0) * 255.0;
+                    let lo = (pos as usize).min(254);
+                    let frac = pos - lo as f32;
+                    out[i] = curves[i][lo] * (1.0 - frac) + curves[i][lo + 1] * frac;
+                }
+                out
+            },
+            Stage::Scale(factors) => {
+                [color[0] * factors[0], color[1] * factors[1], color[2] * factors[2]]
+            },
+            Stage::Cube(ref cube) => cube.apply(color),
+        }
+    }
+
+    /// Applies the stage to all colors of a frame, like apply does to a single color.
+    fn apply_planar(&self, planes: &mut planar::Planes) {
+        match *self {
+            Stage::Curves(ref curves) => {
+                for (plane, curve) in planes.channels.iter_mut().zip(curves) {
+                    for v in plane.iter_mut() {
+                        let pos = v.clamp(0.0, 1.0) * 255.0;
+                        let lo = (pos as usize).min(254);
+                        let frac = pos - lo as f32;
+                        *v = curve[lo] * (1.0 - frac) + curve[lo + 1] * frac;
+                    }
+                }
+            },
+            Stage::Scale(factors) => {
+                for (plane, factor) in planes.channels.iter_mut().zip(&factors) {
+                    for v in plane.iter_mut() {
+                        *v *= factor;
+                    }
+                }
+            },
+            Stage::Cube(ref cube) => planes.map(|color| cube.apply(color)),
+        }
+    }
 }
 
 impl Correction {
-    pub fn none() -> Correction {
+    fn from_stages(stages: Vec<Stage>) -> Correction {
         Correction {
-            r: (0..=255).collect(),
-            g: (0..=255).collect(),
-            b: (0..=255).collect(),
+            stages,
+            dither: None,
+            brightness: 1.0,
+            schedule: None,
+            level: 1.0,
         }
     }
 
+    pub fn none() -> Correction {
+        Correction::from_stages(Vec::new())
+    }
+
     // https://en.wikipedia.org/wiki/SRGB
     pub fn srgb(max_red: u8, max_green: u8, max_blue: u8) -> Correction {
-        let srgb = |x: f64| -> f64 {
+        let srgb = Stage::curve(|x| {
             if x <= 0.04045 {
                 return x / 12.92;
             }
             f64::powf((x + 0.055) / (1.0 + 0.055), 2.4)
+        });
+        let mut stages = vec![srgb];
+        if (max_red, max_green, max_blue) != (255, 255, 255) {
+            let max = |v: u8| f32::from(v) / 255.0;
+            stages.push(Stage::Scale([max(max_red), max(max_green), max(max_blue)]));
+        }
+        Correction::from_stages(stages)
+    }
+
+    /// A correction that treats the input as perceived lightness (CIE L*) and outputs the
+    /// corresponding luminance according to CIE 1931.
+    pub fn cie1931() -> Correction {
+        Correction::from_stages(vec![Stage::curve(|x| {
+            let l = x * 100.0;
+            if l <= 8.0 { l / 903.3 } else { f64::powi((l + 16.0) / 116.0, 3) }
+        })])
+    }
+
+    /// A correction that raises each channel to the power of `exponent`.
+    pub fn gamma(exponent: f64) -> Correction {
+        Correction::from_stages(vec![Stage::curve(|x| f64::powf(x, exponent))])
+    }
+
+    /// A correction that multiplies the red, green and blue channels by the specified factors.
+    pub fn balance(r: f32, g: f32, b: f32) -> Correction {
+        Correction::from_stages(vec![Stage::Scale([r, g, b])])
+    }
+
+    /// A correction that looks up colors in a 3D lookup table.
+    pub fn cube(cube: cube::Cube) -> Correction {
+        Correction::from_stages(vec![Stage::Cube(cube)])
+    }
+
+    /// Parses a table with a row for each of the 256 input values. Each row holds either the
+    /// output value of all channels or that of the red, green and blue channels, separated by
+    /// commas or whitespace.
+    pub fn parse_lut(s: &str) -> Result<Correction, String> {
+        let rows = s.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let values = line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse::<u8>().map_err(|_| format!("invalid value: \"{}\"", v)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                match values.as_slice() {
+                    [v] => Ok((*v, *v, *v)),
+                    [r, g, b] => Ok((*r, *g, *b)),
+                    _ => Err(format!("expected 1 or 3 values per row, got {}", values.len())),
+                }
+            })
+            .collect::<Result<Vec<(u8, u8, u8)>, String>>()?;
+        if rows.len() != 256 {
+            return Err(format!("expected 256 rows, got {}", rows.len()));
+        }
+        let curve = |f: &dyn Fn(&(u8, u8, u8)) -> u8| rows.iter().map(|v| f32::from(f(v)) / 255.0).collect();
+        Ok(Correction::from_stages(vec![Stage::Curves([curve(&|v| v.0), curve(&|v| v.1), curve(&|v| v.2)])]))
+    }
+
+    /// Appends the stages of another correction, which are applied after those of this one.
+    pub fn then(mut self, next: Correction) -> Correction {
+        self.stages.extend(next.stages);
+        self
+    }
+
+    /// Enables temporal dithering of the corrected colors to the specified number of bits per
+    /// channel. For fewer than 8 bits, the dithered value is placed in the most significant bits.
+    pub fn with_dither(self, bits: u32) -> Correction {
+        assert!((1..=8).contains(&bits));
+        Correction { dither: Some(bits), ..self }
+    }
+
+    /// Scales the brightness of the corrected colors, with `brightness` between 0 and 1.0.
+    pub fn with_brightness(self, brightness: f32) -> Correction {
+        Correction { brightness, ..self }
+    }
+
+    /// Changes the brightness of the corrected colors of an existing correction.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+    }
+
+    /// Sets a schedule that changes the brightness during the day. The brightness is only
+    /// changed when update() is called.
+    pub fn with_schedule(self, schedule: schedule::Schedule) -> Correction {
+        Correction { schedule: Some(schedule), ..self }
+    }
+
+    /// Updates the brightness from the schedule, if any, for the specified time.
+    pub fn update(&mut self, now: time::SystemTime) {
+        if let Some(ref schedule) = self.schedule {
+            self.level = schedule.level(now);
+        }
+    }
+
+    /// The brightness that the corrected colors are scaled by, including that of the schedule.
+    pub fn brightness(&self) -> f32 {
+        self.brightness * self.level
+    }
+
+    /// Whether each channel is corrected independently of the others, so the correction can be
+    /// precomputed for each value of each channel.
+    pub fn is_per_channel(&self) -> bool {
+        self.stages.iter().all(|stage| !matches!(*stage, Stage::Cube(_)))
+    }
+
+    /// Corrects a color with channels between 0 and 1.0.
+    pub fn correct_wide(&self, color: [f32; 3]) -> Pixel16 {
+        let color = self.stages.iter().fold(color, |color, stage| stage.apply(color));
+        let brightness = self.brightness();
+        let [r, g, b] = color.map(|v| (v.clamp(0.0, 1.0) * brightness * 65535.0).round() as u16);
+        Pixel16 { r, g, b }
+    }
+
+    pub fn correct(&self, pix: Pixel) -> Pixel {
+        let color = [f32::from(pix.r) / 255.0, f32::from(pix.g) / 255.0, f32::from(pix.b) / 255.0];
+        self.correct_wide(color).narrow()
+    }
+
+    /// Corrects the color of the pixel at the specified output index of a frame. If dithering is
+    /// enabled, the color is quantized to the dither depth with a rounding threshold that varies
+    /// between frames, so the average over time approximates the corrected color more closely
+    /// than a single frame can.
+    pub fn correct_at(&self, color: [f32; 3], index: usize, frame: usize) -> Pixel16 {
+        self.dither_at(self.correct_wide(color), index, frame)
+    }
+
+    /// Corrects all colors of a frame, which are indexed by their output index, and stores them in
+    /// `out`. The result is the same as that of correct_at for each pixel, but each stage is
+    /// applied to a full plane at a time. The planes are left in an unspecified state.
+    pub fn correct_planar(&self, planes: &mut planar::Planes, out: &mut [Pixel16], frame: usize) {
+        assert_eq!(planes.len(), out.len());
+        for stage in &self.stages {
+            stage.apply_planar(planes);
+        }
+        let brightness = self.brightness();
+        for plane in planes.channels.iter_mut() {
+            for v in plane.iter_mut() {
+                *v = (v.clamp(0.0, 1.0) * brightness * 65535.0).round();
+            }
+        }
+        let [ref r, ref g, ref b] = planes.channels;
+        for (i, pix) in out.iter_mut().enumerate() {
+            *pix = Pixel16 { r: r[i] as u16, g: g[i] as u16, b: b[i] as u16 };
+        }
+        if self.dither.is_some() {
+            for (i, pix) in out.iter_mut().enumerate() {
+                *pix = self.dither_at(pix.clone(), i, frame);
+            }
+        }
+    }
+
+    /// Applies the dithering of correct_at to a color that was already corrected.
+    pub fn dither_at(&self, wide: Pixel16, index: usize, frame: usize) -> Pixel16 {
+        let bits = match self.dither {
+            Some(bits) => bits,
+            None => return wide,
         };
-        let comp = |max| {
-            (0..256)
-                .map(|i| f64::round(srgb(f64::from(i) / 255.0) * f64::from(max)) as u8)
-                .collect()
+        let max = (1u32 << bits) - 1;
+        // A different phase for each pixel prevents the whole display from flickering in step.
+        let threshold = dither_threshold(frame.wrapping_add(index.wrapping_mul(7)));
+        let quantize = |v: u16| {
+            let level = ((u32::from(v) * max) as f32 / 65535.0 + threshold) as u32;
+            // Expand the 8 bit value again so it is kept as is when the device narrows it.
+            ((level.min(max) << (8 - bits)) * 257) as u16
         };
-        Correction {
-            r: comp(max_red),
-            g: comp(max_green),
-            b: comp(max_blue),
+        Pixel16 { r: quantize(wide.r), g: quantize(wide.g), b: quantize(wide.b) }
+    }
+}
+
+impl str::FromStr for Correction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        match (split.next().unwrap(), split.next()) {
+            ("none", None) => Ok(Correction::none()),
+            ("srgb", None) => Ok(Correction::srgb(255, 255, 255)),
+            ("srgb", Some(arg)) => {
+                let max = arg.split(',')
+                    .map(|v| v.trim().parse::<u8>().map_err(|_| format!("invalid white point value: \"{}\", expected 0 to 255", v)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                match max.as_slice() {
+                    [r, g, b] => Ok(Correction::srgb(*r, *g, *b)),
+                    _ => Err(format!("the white point requires 3 values, got {}", max.len())),
+                }
+            },
+            ("cie1931", None) => Ok(Correction::cie1931()),
+            ("gamma", Some(arg)) => match arg.parse::<f64>() {
+                Ok(exp) if exp > 0.0 && exp.is_finite() => Ok(Correction::gamma(exp)),
+                _ => Err(format!("invalid gamma: \"{}\", expected a positive number", arg)),
+            },
+            ("balance", Some(arg)) => {
+                let factors = arg.split(',')
+                    .map(|v| match v.trim().parse::<f32>() {
+                        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+                        _ => Err(format!("invalid balance: \"{}\", expected 0 to 1.0", v)),
+                    })
+                    .collect::<Result<Vec<f32>, String>>()?;
+                match factors.as_slice() {
+                    [r, g, b] => Ok(Correction::balance(*r, *g, *b)),
+                    _ => Err(format!("the balance requires 3 values, got {}", factors.len())),
+                }
+            },
+            ("cube", Some(path)) => cube::Cube::load(path)
+                .map(Correction::cube)
+                .map_err(|err| format!("could not load {}: {}", path, err)),
+            ("lut", Some(path)) => fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|s| Correction::parse_lut(&s))
+                .map_err(|err| format!("could not load {}: {}", path, err)),
+            _ => Err(format!("unknown color correction: {}, expected none, srgb[:R,G,B], cie1931, gamma:VALUE, balance:R,G,B, lut:FILE or cube:FILE", s)),
         }
     }
+}
 
-    pub fn correct(&self, pix: Pixel) -> Pixel {
+/// Returns the rounding threshold in the range 0..1 for the specified dither phase. The
+/// thresholds of 16 consecutive phases are spread evenly by reversing the bits of the phase.
+fn dither_threshold(phase: usize) -> f32 {
+    let p = phase % 16;
+    let reversed = ((p & 1) << 3) | ((p & 2) << 1) | ((p & 4) >> 1) | ((p & 8) >> 3);
+    (reversed as f32 + 0.5) / 16.0
+}
+
+/// The order in which the channels of a pixel are sent, such as GRB.
+///
+/// Reordering a pixel moves the color named first into the red channel, the second into the
+/// green channel and the third into the blue channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorOrder([usize; 3]);
+
+impl ColorOrder {
+    pub fn reorder(&self, pix: &Pixel) -> Pixel {
+        let channels = [pix.r, pix.g, pix.b];
         Pixel {
-            r: self.r[pix.r as usize],
-            g: self.g[pix.g as usize],
-            b: self.b[pix.b as usize],
+            r: channels[self.0[0]],
+            g: channels[self.0[1]],
+            b: channels[self.0[2]],
         }
     }
+
+    pub fn reorder_wide(&self, pix: &Pixel16) -> Pixel16 {
+        let channels = [pix.r, pix.g, pix.b];
+        Pixel16 {
+            r: channels[self.0[0]],
+            g: channels[self.0[1]],
+            b: channels[self.0[2]],
+        }
+    }
+
+    /// Whether the channels are kept in place.
+    pub fn is_identity(&self) -> bool {
+        self.0 == [0, 1, 2]
+    }
+
+    /// Reorders all pixels of a frame into `out`, which is resized to fit.
+    pub fn reorder_frame(&self, pixels: &[Pixel], out: &mut Vec<Pixel>) {
+        out.resize(pixels.len(), Pixel::default());
+        reorder_channels(self.0, pixels, out, |p| [p.r, p.g, p.b], |[r, g, b]| Pixel { r, g, b });
+    }
+
+    /// Like reorder_frame, for colors with 16 bits per channel.
+    pub fn reorder_frame_wide(&self, pixels: &[Pixel16], out: &mut Vec<Pixel16>) {
+        out.resize(pixels.len(), Pixel16::default());
+        reorder_channels(self.0, pixels, out, |p| [p.r, p.g, p.b], |[r, g, b]| Pixel16 { r, g, b });
+    }
+}
+
+/// Moves the channels of each pixel to their place in the order. The order is matched once for
+/// the whole frame, so the loop over the pixels is a fixed shuffle without branches.
+fn reorder_channels<P, T: Copy>(order: [usize; 3], pixels: &[P], out: &mut [P], get: impl Fn(&P) -> [T; 3], make: impl Fn([T; 3]) -> P) {
+    macro_rules! shuffle {
+        ($a:expr, $b:expr, $c:expr) => {
+            for (o, p) in out.iter_mut().zip(pixels) {
+                let ch = get(p);
+                *o = make([ch[$a], ch[$b], ch[$c]]);
+            }
+        }
+    }
+    match order {
+        [0, 1, 2] => shuffle!(0, 1, 2),
+        [0, 2, 1] => shuffle!(0, 2, 1),
+        [1, 0, 2] => shuffle!(1, 0, 2),
+        [1, 2, 0] => shuffle!(1, 2, 0),
+        [2, 0, 1] => shuffle!(2, 0, 1),
+        _ => shuffle!(2, 1, 0),
+    }
+}
+
+impl str::FromStr for ColorOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let order: Vec<usize> = s.chars()
+            .filter_map(|c| "RGB".find(c.to_ascii_uppercase()))
+            .collect();
+        if s.len() != 3 || order.len() != 3 || (0..3).any(|c| !order.contains(&c)) {
+            return Err(format!("invalid color order: \"{}\", expected a permutation of RGB", s));
+        }
+        Ok(ColorOrder([order[0], order[1], order[2]]))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_pixel16() {
+        let pix = Pixel16 { r: 65535, g: 1000, b: 0 };
+        assert_eq!(Pixel16 { r: 32768, g: 500, b: 0 }, pix.scale(0.5));
+        assert_eq!(Pixel16::default(), pix.scale(0.0));
+    }
+
+    #[test]
+    fn correction_parse() {
+        assert!("none".parse::<Correction>().is_ok());
+        assert!("srgb".parse::<Correction>().is_ok());
+        assert!("srgb:255,240,230".parse::<Correction>().is_ok());
+        assert!("srgb:255,240".parse::<Correction>().is_err());
+        assert!("srgb:255,240,256".parse::<Correction>().is_err());
+        assert!("cie1931".parse::<Correction>().is_ok());
+        assert!("gamma:2.2".parse::<Correction>().is_ok());
+        assert!("gamma".parse::<Correction>().is_err());
+        assert!("gamma:-1".parse::<Correction>().is_err());
+        assert!("gamma:x".parse::<Correction>().is_err());
+        assert!("foo".parse::<Correction>().is_err());
+    }
+
+    #[test]
+    fn correction_gamma() {
+        let c = Correction::gamma(2.0);
+        assert_eq!(Pixel { r: 0, g: 64, b: 255 }, c.correct(Pixel { r: 0, g: 128, b: 255 }));
+        let c = Correction::gamma(1.0);
+        assert_eq!(Pixel { r: 1, g: 100, b: 200 }, c.correct(Pixel { r: 1, g: 100, b: 200 }));
+    }
+
+    #[test]
+    fn correction_lut() {
+        let lut: String = (0..256).map(|i| format!("{}, {}, {}\n", i, 255 - i, i / 2)).collect();
+        let c = Correction::parse_lut(&lut).unwrap();
+        assert_eq!(Pixel { r: 10, g: 235, b: 100 }, c.correct(Pixel { r: 10, g: 20, b: 200 }));
+        let lut: String = (0..256).map(|i| format!("{}\n", 255 - i)).collect();
+        let c = Correction::parse_lut(&lut).unwrap();
+        assert_eq!(Pixel { r: 255, g: 0, b: 155 }, c.correct(Pixel { r: 0, g: 255, b: 100 }));
+        assert!(Correction::parse_lut("1\n2\n").is_err());
+        assert!(Correction::parse_lut(&lut.replace("100\n", "1000\n")).is_err());
+        assert!(Correction::parse_lut(&lut.replace("100\n", "1, 2\n")).is_err());
+    }
+
+    #[test]
+    fn color_order() {
+        let pix = Pixel { r: 1, g: 2, b: 3 };
+        assert_eq!(pix, "RGB".parse::<ColorOrder>().unwrap().reorder(&pix));
+        assert_eq!(Pixel { r: 2, g: 1, b: 3 }, "grb".parse::<ColorOrder>().unwrap().reorder(&pix));
+        assert_eq!(Pixel { r: 3, g: 1, b: 2 }, "BRG".parse::<ColorOrder>().unwrap().reorder(&pix));
+        assert!("RRB".parse::<ColorOrder>().is_err());
+        assert!("RG".parse::<ColorOrder>().is_err());
+        assert!("RGBW".parse::<ColorOrder>().is_err());
+        assert!("XYZ".parse::<ColorOrder>().is_err());
+    }
+
+    #[test]
+    fn correction_dither() {
+        // A value between two 8 bit levels is shown as a mix of both over 16 frames.
+        let c = Correction::gamma(2.2).with_dither(8);
+        let color = [40.0 / 255.0, 0.0, 1.0];
+        let wide = c.correct_wide(color).r as f32 / 257.0;
+        let frames: Vec<Pixel> = (0..16).map(|f| c.correct_at(color, 3, f).narrow()).collect();
+        let avg = frames.iter().map(|p| p.r as f32).sum::<f32>() / 16.0;
+        assert!((avg - wide).abs() < 1.0 / 16.0);
+        assert!(frames.iter().any(|p| p.r != frames[0].r));
+        assert!(frames.iter().all(|p| p.g == 0 && p.b == 255));
+
+        // Devices with fewer bits get the dithered value in the most significant bits.
+        let c = Correction::none().with_dither(7);
+        for f in 0..16 {
+            let p = c.correct_at([3.0 / 255.0, 254.0 / 255.0, 128.0 / 255.0], 0, f).narrow();
+            assert_eq!(0, p.r & 1);
+            assert!(p.r == 2 || p.r == 4);
+            assert!(p.g == 252 || p.g == 254);
+        }
+    }
+
+    #[test]
+    fn correction_planar() {
+        let corrections = vec![
+            Correction::none(),
+            Correction::srgb(255, 240, 230).then(Correction::gamma(1.1)).with_brightness(0.8),
+            Correction::cie1931().with_dither(6),
+        ];
+        let colors: Vec<[f32; 3]> = (0..300)
+            .map(|i| [i as f32 / 299.0, (i * 7 % 300) as f32 / 299.0, 1.0 - i as f32 / 299.0])
+            .collect();
+        for c in corrections {
+            let mut planes = planar::Planes::default();
+            planes.reset(colors.len());
+            for (i, color) in colors.iter().enumerate() {
+                planes.set(i, *color);
+            }
+            let mut out = vec![Pixel16::default(); colors.len()];
+            c.correct_planar(&mut planes, &mut out, 5);
+            for (i, color) in colors.iter().enumerate() {
+                assert_eq!(c.correct_at(*color, i, 5), out[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn reorder_frame() {
+        let pixels: Vec<Pixel16> = (0..10).map(|i| Pixel16 { r: i, g: i * 2, b: i * 3 }).collect();
+        for order in &["RGB", "RBG", "GRB", "GBR", "BRG", "BGR"] {
+            let order: ColorOrder = order.parse().unwrap();
+            let mut out = Vec::new();
+            order.reorder_frame_wide(&pixels, &mut out);
+            let expected: Vec<Pixel16> = pixels.iter().map(|p| order.reorder_wide(p)).collect();
+            assert_eq!(expected, out);
+        }
+    }
+
+    #[test]
+    fn dither_thresholds() {
+        let mut t: Vec<f32> = (0..16).map(dither_threshold).collect();
+        t.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (i, v) in t.iter().enumerate() {
+            assert_eq!((i as f32 + 0.5) / 16.0, *v);
+        }
+    }
+
+    #[test]
+    fn correction_brightness() {
+        // Dimming after the correction keeps dark colors from being crushed to black.
+        let c = Correction::srgb(255, 255, 255).with_brightness(0.5);
+        assert_eq!(Pixel { r: 128, g: 0, b: 16 }, c.correct(Pixel { r: 255, g: 0, b: 100 }));
+        let c = Correction::srgb(255, 255, 255).with_brightness(0.1);
+        assert_eq!(Pixel { r: 26, g: 0, b: 0 }, c.correct(Pixel { r: 255, g: 0, b: 30 }));
+        assert_ne!(0, c.correct_wide([0.0, 0.0, 30.0 / 255.0]).b);
+    }
+
+    #[test]
+    fn correction_cie1931() {
+        let c = Correction::cie1931();
+        assert_eq!(Pixel { r: 0, g: 255, b: 47 }, c.correct(Pixel { r: 0, g: 255, b: 128 }));
+        // The low end rises linearly.
+        assert_eq!(Pixel { r: 0, g: 0, b: 1 }, c.correct(Pixel { r: 1, g: 2, b: 5 }));
+    }
+
+    #[test]
+    fn correction_srgb_white_point() {
+        let c: Correction = "srgb:255,128,0".parse().unwrap();
+        assert_eq!(Pixel { r: 255, g: 128, b: 0 }, c.correct(Pixel { r: 255, g: 255, b: 255 }));
+    }
+
+    #[test]
+    fn correction_chain() {
+        let c = "balance:1,0.5,0".parse::<Correction>().unwrap()
+            .then("gamma:2".parse().unwrap());
+        assert_eq!(Pixel { r: 64, g: 16, b: 0 }, c.correct(Pixel { r: 128, g: 128, b: 128 }));
+        // The order of the stages matters.
+        let c = Correction::gamma(2.0).then(Correction::balance(1.0, 0.5, 0.0));
+        assert_eq!(Pixel { r: 64, g: 32, b: 0 }, c.correct(Pixel { r: 128, g: 128, b: 128 }));
+        assert!("balance:1,2,1".parse::<Correction>().is_err());
+        assert!("balance:1,1".parse::<Correction>().is_err());
+    }
 }
@@ -0,0 +1,53 @@
+use color::Pixel;
+
+
+/// The colors of a frame with each channel in a plane of its own, indexed by the output index of
+/// the pixels. The values are between 0 and 1.0.
+///
+/// Steps that treat the channels independently are loops over a single plane, which the compiler
+/// can vectorize, unlike a loop over pixels that handles the three channels of each in turn.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Planes {
+    pub channels: [Vec<f32>; 3],
+}
+
+impl Planes {
+    /// Sets the number of pixels and makes all of them black.
+    pub fn reset(&mut self, len: usize) {
+        for plane in self.channels.iter_mut() {
+            plane.clear();
+            plane.resize(len, 0.0);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> [f32; 3] {
+        [self.channels[0][index], self.channels[1][index], self.channels[2][index]]
+    }
+
+    pub fn set(&mut self, index: usize, color: [f32; 3]) {
+        for (plane, v) in self.channels.iter_mut().zip(&color) {
+            plane[index] = *v;
+        }
+    }
+
+    /// Stores an 8 bit color at the index.
+    pub fn set_pixel(&mut self, index: usize, pix: &Pixel) {
+        self.set(index, [f32::from(pix.r) / 255.0, f32::from(pix.g) / 255.0, f32::from(pix.b) / 255.0]);
+    }
+
+    /// Applies a function to the color of each pixel, for steps that mix the channels.
+    pub fn map<F: FnMut([f32; 3]) -> [f32; 3]>(&mut self, mut f: F) {
+        for i in 0..self.len() {
+            let color = f(self.get(i));
+            self.set(i, color);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::mem;
+use std::str;
+use std::time;
+use nix::libc;
+
+
+/// A schedule that sets the brightness of the display depending on the time of day.
+///
+/// A schedule is parsed from a list of `HH:MM=LEVEL` entries separated by commas. Each level is
+/// in effect from its time until the time of the next entry, the last entry of the day continues
+/// after midnight until the first entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    // Seconds since midnight and the level from then on, ordered by time.
+    entries: Vec<(u32, f32)>,
+}
+
+impl Schedule {
+    /// Returns the level at the specified number of seconds since midnight.
+    pub fn level_at(&self, seconds: u32) -> f32 {
+        self.entries.iter()
+            .rev()
+            .find(|&&(t, _)| t <= seconds)
+            .or_else(|| self.entries.last())
+            .map(|&(_, level)| level)
+            .unwrap()
+    }
+
+    /// Returns the level at the specified time in the local timezone.
+    pub fn level(&self, now: time::SystemTime) -> f32 {
+        self.level_at(local_seconds(now))
+    }
+}
+
+/// Returns the number of seconds since midnight in the local timezone.
+fn local_seconds(now: time::SystemTime) -> u32 {
+    let unix = now.duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs() as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&unix, &mut tm).is_null() {
+            return (unix % 86400) as u32;
+        }
+        (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u32
+    }
+}
+
+impl str::FromStr for Schedule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = s.split(',')
+            .map(|entry| {
+                let mut split = entry.trim().splitn(2, '=');
+                let at = split.next().unwrap();
+                let level = split.next()
+                    .ok_or_else(|| format!("invalid schedule entry: \"{}\", expected HH:MM=LEVEL", entry))?;
+                let mut hm = at.splitn(2, ':').map(|v| v.parse::<u32>());
+                let seconds = match (hm.next(), hm.next()) {
+                    (Some(Ok(h)), Some(Ok(m))) if h < 24 && m < 60 => h * 3600 + m * 60,
+                    _ => return Err(format!("invalid time: \"{}\", expected HH:MM", at)),
+                };
+                match level.parse::<f32>() {
+                    Ok(l) if (0.0..=1.0).contains(&l) => Ok((seconds, l)),
+                    _ => Err(format!("invalid level: \"{}\", expected 0 to 1.0", level)),
+                }
+            })
+            .collect::<Result<Vec<(u32, f32)>, String>>()?;
+        entries.sort_by_key(|&(t, _)| t);
+        if entries.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err("the schedule contains the same time more than once".to_string());
+        }
+        Ok(Schedule { entries })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_parse() {
+        assert!("22:00=0.2,07:00=1.0".parse::<Schedule>().is_ok());
+        assert!("22:00=0.2".parse::<Schedule>().is_ok());
+        assert!("".parse::<Schedule>().is_err());
+        assert!("22:00".parse::<Schedule>().is_err());
+        assert!("24:00=1".parse::<Schedule>().is_err());
+        assert!("12:60=1".parse::<Schedule>().is_err());
+        assert!("12:00=1.5".parse::<Schedule>().is_err());
+        assert!("12:00=1,12:00=0".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn schedule_level() {
+        let schedule: Schedule = "22:00=0.2,07:00=1.0,12:30=0.8".parse().unwrap();
+        assert_eq!(0.2, schedule.level_at(0));
+        assert_eq!(0.2, schedule.level_at(7 * 3600 - 1));
+        assert_eq!(1.0, schedule.level_at(7 * 3600));
+        assert_eq!(0.8, schedule.level_at(12 * 3600 + 30 * 60));
+        assert_eq!(0.8, schedule.level_at(21 * 3600 + 59 * 60));
+        assert_eq!(0.2, schedule.level_at(23 * 3600));
+    }
+}
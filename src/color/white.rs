@@ -0,0 +1,136 @@
+use std::str;
+use color::Pixel;
+
+
+/// The method used to derive the value of the white channel of RGBW devices.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum White {
+    /// The white channel is not used.
+    None,
+    /// The part of the color that all channels have in common is shown by the white LED.
+    Min,
+    /// Like Min, but the white channel is driven by the perceived brightness of the common part.
+    Luma,
+    /// The white LED has the specified tint, given as the RGB color it is equivalent to. As much
+    /// of the color as possible is shown by the white LED.
+    Calibrated([u8; 3]),
+}
+
+impl White {
+    /// Splits a color into the values of the red, green, blue and white channels.
+    pub fn split(&self, pix: &Pixel) -> [u8; 4] {
+        let (r, g, b) = (pix.r, pix.g, pix.b);
+        let min = r.min(g).min(b);
+        match *self {
+            White::None => [r, g, b, 0],
+            White::Min => [r - min, g - min, b - min, min],
+            White::Luma => {
+                let max = r.max(g).max(b);
+                if max == 0 {
+                    return [0, 0, 0, 0];
+                }
+                let luma = 0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b);
+                let w = (luma * f32::from(min) / f32::from(max)).round().min(f32::from(min)) as u8;
+                [r - w, g - w, b - w, w]
+            },
+            White::Calibrated(tint) => {
+                let channels = [r, g, b];
+                // The largest amount of white light that does not exceed any of the channels.
+                let w = (0..3)
+                    .filter(|&i| tint[i] > 0)
+                    .map(|i| f32::from(channels[i]) * 255.0 / f32::from(tint[i]))
+                    .fold(255.0f32, f32::min);
+                let rest = |i: usize| {
+                    (f32::from(channels[i]) - w * f32::from(tint[i]) / 255.0).round().max(0.0) as u8
+                };
+                [rest(0), rest(1), rest(2), w.round() as u8]
+            },
+        }
+    }
+
+    /// Combines the values of the red, green, blue and white channels back into a color, the
+    /// inverse of `split` up to rounding.
+    pub fn join(&self, channels: [u8; 4]) -> Pixel {
+        let [r, g, b, w] = channels;
+        let tint = match *self {
+            White::None => [0, 0, 0],
+            White::Min | White::Luma => [255, 255, 255],
+            White::Calibrated(tint) => tint,
+        };
+        let add = |v: u8, t: u8| {
+            (f32::from(v) + f32::from(w) * f32::from(t) / 255.0).round().min(255.0) as u8
+        };
+        Pixel { r: add(r, tint[0]), g: add(g, tint[1]), b: add(b, tint[2]) }
+    }
+}
+
+impl str::FromStr for White {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        match (split.next().unwrap(), split.next()) {
+            ("none", None) => Ok(White::None),
+            ("min", None) => Ok(White::Min),
+            ("luma", None) => Ok(White::Luma),
+            ("calibrated", Some(arg)) => {
+                let tint = arg.split(',')
+                    .map(|v| v.trim().parse::<u8>().map_err(|_| format!("invalid tint value: \"{}\"", v)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                match tint.as_slice() {
+                    [0, 0, 0] => Err("the tint of the white LED can not be black".to_string()),
+                    [r, g, b] => Ok(White::Calibrated([*r, *g, *b])),
+                    _ => Err(format!("the tint requires 3 values, got {}", tint.len())),
+                }
+            },
+            _ => Err(format!("unknown white extraction: {}, expected none, min, luma or calibrated:R,G,B", s)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_parse() {
+        assert_eq!(Ok(White::None), "none".parse());
+        assert_eq!(Ok(White::Min), "min".parse());
+        assert_eq!(Ok(White::Luma), "luma".parse());
+        assert_eq!(Ok(White::Calibrated([255, 200, 150])), "calibrated:255,200,150".parse());
+        assert!("calibrated".parse::<White>().is_err());
+        assert!("calibrated:1,2".parse::<White>().is_err());
+        assert!("calibrated:0,0,0".parse::<White>().is_err());
+        assert!("foo".parse::<White>().is_err());
+    }
+
+    #[test]
+    fn white_split() {
+        let pix = Pixel { r: 200, g: 100, b: 50 };
+        assert_eq!([200, 100, 50, 0], White::None.split(&pix));
+        assert_eq!([150, 50, 0, 50], White::Min.split(&pix));
+        // luma = 42.52 + 71.52 + 3.61 = 117.65, scaled by 50 / 200.
+        assert_eq!([171, 71, 21, 29], White::Luma.split(&pix));
+        assert_eq!([0, 0, 0, 255], White::Min.split(&Pixel { r: 255, g: 255, b: 255 }));
+        assert_eq!([0, 0, 0, 0], White::Luma.split(&Pixel { r: 0, g: 0, b: 0 }));
+    }
+
+    #[test]
+    fn white_split_calibrated() {
+        // A warm white LED.
+        let white = White::Calibrated([255, 200, 100]);
+        assert_eq!([0, 0, 0, 255], white.split(&Pixel { r: 255, g: 200, b: 100 }));
+        assert_eq!([0, 55, 0, 255], white.split(&Pixel { r: 255, g: 255, b: 100 }));
+        assert_eq!([101, 60, 0, 99], white.split(&Pixel { r: 200, g: 138, b: 39 }));
+    }
+
+    #[test]
+    fn white_join() {
+        let pix = Pixel { r: 200, g: 100, b: 50 };
+        for white in &[White::None, White::Min, White::Luma] {
+            assert_eq!(pix, white.join(white.split(&pix)));
+        }
+        let white = White::Calibrated([255, 200, 100]);
+        assert_eq!(Pixel { r: 200, g: 138, b: 39 }, white.join([101, 60, 0, 99]));
+    }
+}
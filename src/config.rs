@@ -0,0 +1,307 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::iter;
+use std::path;
+use std::str;
+use clap;
+use toml;
+use ::profile::{self, Value};
+
+
+/// The name of the directory in the user's configuration directory.
+const DIR_NAME: &str = "ledcat";
+
+/// The system wide configuration directory, used if the user has no configuration.
+const SYSTEM_DIR: &str = "/etc/ledcat";
+
+/// The file that is loaded when no configuration is set with `--config`.
+const FILE_NAME: &str = "ledcat.toml";
+
+/// The prefix of the environment variables that set global options.
+const ENV_PREFIX: &str = "LEDCAT_";
+
+
+/// A configuration file holding the command line options of an installation.
+///
+/// Configurations are stored as TOML. The global options are set at the top of the file, followed
+/// by a `[name]` table with the options of each subcommand. The keys are the names of the command
+/// line options without the leading dashes, `device` selects the subcommand that is used if none
+/// is given on the command line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub options: Vec<(String, Value)>,
+    pub device: Option<String>,
+    pub sections: Vec<(String, Vec<(String, Value)>)>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Config> {
+        parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Returns the options of the section of a subcommand.
+    pub fn section(&self, name: &str) -> &[(String, Value)] {
+        self.sections.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, options)| &options[..])
+            .unwrap_or(&[])
+    }
+
+    /// Adds the options of the configuration to the command line arguments. `matches` are the
+    /// command line arguments parsed without the configuration, options that are set on the
+    /// command line take precedence.
+    pub fn apply(&self, args: &[String], matches: &clap::ArgMatches) -> Vec<String> {
+        let mut out: Vec<String> = args.iter().take(1).cloned().collect();
+        out.extend(profile::to_args(&self.options, |key| matches.occurrences_of(key) > 0));
+        out.extend(args.iter().skip(1).cloned());
+        match matches.subcommand() {
+            ("", _) => if let Some(ref device) = self.device {
+                out.push(device.clone());
+                out.extend(profile::to_args(self.section(device), |_| false));
+            },
+            (name, Some(sub_matches)) => {
+                out.extend(profile::to_args(self.section(name), |key| sub_matches.occurrences_of(key) > 0));
+            },
+            (_, None) => (),
+        }
+        out
+    }
+}
+
+/// Returns the directories that hold the configuration of ledcat in order of precedence: the
+/// user's `ledcat` directory in `$XDG_CONFIG_HOME` or `~/.config` and `/etc/ledcat`.
+pub fn dirs() -> Vec<path::PathBuf> {
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(path::PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| path::Path::new(&home).join(".config")));
+    user_dir.map(|dir| dir.join(DIR_NAME)).into_iter()
+        .chain(iter::once(path::PathBuf::from(SYSTEM_DIR)))
+        .collect()
+}
+
+/// Returns the path of the configuration that is loaded by default: the first `ledcat.toml` in
+/// the configuration directories. Returns None if there is none.
+pub fn default_path() -> Option<path::PathBuf> {
+    dirs().into_iter()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Collects the global options that are set by `LEDCAT_*` environment variables, such as
+/// `LEDCAT_DIM=0.5` for `--dim 0.5`. Flags are set with `true` or `false` and empty variables are
+/// ignored.
+pub fn from_env() -> Vec<(String, Value)> {
+    from_vars(env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))))
+}
+
+fn from_vars<I>(vars: I) -> Vec<(String, Value)>
+    where I: IntoIterator<Item = (String, String)> {
+    let mut options: Vec<(String, Value)> = vars.into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .filter_map(|(k, v)| {
+            let key = k.strip_prefix(ENV_PREFIX)?.to_lowercase().replace('_', "-");
+            let value = match v.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => Value::String(v),
+            };
+            Some((key, value))
+        })
+        .filter(|(key, _)| valid_key(key))
+        .collect();
+    options.sort_by(|a, b| a.0.cmp(&b.0));
+    options
+}
+
+/// The name of the environment variable that sets an option.
+pub fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('-', "_"))
+}
+
+impl str::FromStr for Config {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+fn parse(s: &str) -> Result<Config, String> {
+    let table: toml::Table = s.parse().map_err(|err: toml::de::Error| err.to_string())?;
+    let mut config = Config::default();
+    for (key, value) in table {
+        if !valid_key(&key) {
+            return Err(format!("invalid key: {}", key));
+        }
+        match value {
+            toml::Value::Table(section) => {
+                let options = section.into_iter()
+                    .map(|(k, v)| option(k, v).map_err(|err| format!("{}.{}", key, err)))
+                    .collect::<Result<_, _>>()?;
+                config.sections.push((key, options));
+            },
+            toml::Value::String(device) if key == "device" => config.device = Some(device),
+            _ if key == "device" => return Err("the device should be a string".to_string()),
+            _ if key == "config" => return Err("a configuration can not load another configuration".to_string()),
+            value => config.options.push(option(key, value)?),
+        }
+    }
+    Ok(config)
+}
+
+fn option(key: String, value: toml::Value) -> Result<(String, Value), String> {
+    if !valid_key(&key) {
+        return Err(format!("invalid key: {}", key));
+    }
+    let value = profile::from_toml(value).map_err(|err| format!("{}: {}", key, err))?;
+    Ok((key, value))
+}
+
+/// Keys are the names of options without the leading dashes.
+fn valid_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli<'a, 'b>() -> clap::App<'a, 'b> {
+        clap::App::new("ledcat")
+            .arg(clap::Arg::with_name("geometry")
+                .long("geometry")
+                .takes_value(true))
+            .arg(clap::Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .min_values(1)
+                .multiple(true))
+            .subcommand(clap::SubCommand::with_name("artnet")
+                .arg(clap::Arg::with_name("target")
+                    .long("target")
+                    .takes_value(true)
+                    .multiple(true))
+                .arg(clap::Arg::with_name("artsync")
+                    .long("artsync")))
+    }
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn config_parse() {
+        let config = parse("# Stage\n\
+                            geometry = \"32x16\"\n\
+                            input = [\"/tmp/a.fifo\", \"/tmp/b.fifo\"]\n\
+                            device = \"artnet\"\n\
+                            \n\
+                            [artnet] # The nodes\n\
+                            target = [\n\
+                            \x20   \"10.0.0.2\", # Stage left\n\
+                            ]\n\
+                            artsync = true\n").unwrap();
+        assert_eq!(Config {
+            options: vec![
+                ("geometry".to_string(), Value::String("32x16".to_string())),
+                ("input".to_string(), Value::Array(vec![
+                    Value::String("/tmp/a.fifo".to_string()),
+                    Value::String("/tmp/b.fifo".to_string()),
+                ])),
+            ],
+            device: Some("artnet".to_string()),
+            sections: vec![
+                ("artnet".to_string(), vec![
+                    ("target".to_string(), Value::Array(vec![Value::String("10.0.0.2".to_string())])),
+                    ("artsync".to_string(), Value::Bool(true)),
+                ]),
+            ],
+        }, config);
+    }
+
+    #[test]
+    fn config_parse_invalid() {
+        assert!(parse("geometry 10").is_err());
+        assert!(parse("--geometry = 10").is_err());
+        assert!(parse("device = 1").is_err());
+        assert!(parse("config = \"other.toml\"").is_err());
+        assert!(parse("[artnet").is_err());
+        assert!(parse("[artnet]\n[artnet]").is_err());
+        assert!(parse("[artnet] x").is_err());
+        assert!(parse("[artnet.node]\ntarget = \"10.0.0.2\"").is_err());
+        assert!(parse("input = [[\"a\"]]").is_err());
+    }
+
+    #[test]
+    fn config_parse_toml() {
+        let config = parse("geometry = \"\"\"\n32x16\"\"\"\n\
+                            input = ['C:\\a.fifo', \"\\u00e9.fifo\"]\n\
+                            dim = 5e-1\n\
+                            start = 2024-01-01T00:00:00Z\n\
+                            artnet = { target = \"10.0.0.2\" }\n").unwrap();
+        assert_eq!(Config {
+            options: vec![
+                ("geometry".to_string(), Value::String("32x16".to_string())),
+                ("input".to_string(), Value::Array(vec![
+                    Value::String("C:\\a.fifo".to_string()),
+                    Value::String("\u{e9}.fifo".to_string()),
+                ])),
+                ("dim".to_string(), Value::Number("0.5".to_string())),
+                ("start".to_string(), Value::String("2024-01-01T00:00:00Z".to_string())),
+            ],
+            device: None,
+            sections: vec![
+                ("artnet".to_string(), vec![
+                    ("target".to_string(), Value::String("10.0.0.2".to_string())),
+                ]),
+            ],
+        }, config);
+    }
+
+    #[test]
+    fn config_apply() {
+        let config = parse("geometry = 150\n\
+                            input = [\"a\", \"b\"]\n\
+                            device = \"artnet\"\n\
+                            [artnet]\n\
+                            target = [\"10.0.0.2\", \"10.0.0.3\"]\n\
+                            artsync = true\n").unwrap();
+
+        let cli_args = args("ledcat");
+        let matches = cli().get_matches_from(&cli_args);
+        assert_eq!(args("ledcat --geometry=150 --input=a --input=b artnet --target=10.0.0.2 --target=10.0.0.3 --artsync"),
+                   config.apply(&cli_args, &matches));
+
+        let cli_args = args("ledcat --geometry 10 artnet --target 10.0.0.4");
+        let matches = cli().get_matches_from(&cli_args);
+        let applied = config.apply(&cli_args, &matches);
+        assert_eq!(args("ledcat --input=a --input=b --geometry 10 artnet --target 10.0.0.4 --artsync"), applied);
+        let matches = cli().get_matches_from(&applied);
+        assert_eq!(Some("10"), matches.value_of("geometry"));
+        let artnet = matches.subcommand_matches("artnet").unwrap();
+        assert_eq!(vec!["10.0.0.4"], artnet.values_of("target").unwrap().collect::<Vec<_>>());
+        assert!(artnet.is_present("artsync"));
+    }
+
+    #[test]
+    fn env_options() {
+        let vars = vec![
+            ("LEDCAT_GEOMETRY", "32x16"),
+            ("LEDCAT_SKIP_FRAMES", "true"),
+            ("LEDCAT_BLANK_ON_EXIT", "false"),
+            ("LEDCAT_DIM", ""),
+            ("LEDCAT_", "x"),
+            ("HOME", "/root"),
+        ];
+        let options = from_vars(vars.into_iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(vec![
+            ("blank-on-exit".to_string(), Value::Bool(false)),
+            ("geometry".to_string(), Value::String("32x16".to_string())),
+            ("skip-frames".to_string(), Value::Bool(true)),
+        ], options);
+        assert_eq!("LEDCAT_SKIP_FRAMES", env_var("skip-frames"));
+    }
+}
@@ -0,0 +1,558 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net;
+use std::path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::thread;
+use nix::sys::stat;
+use nix::unistd;
+use serde_json;
+use input::select;
+use input::spec;
+
+
+/// The settings of the output that can be changed while ledcat is running. The state is shared
+/// between the control socket and the thread that writes the frames.
+#[derive(Debug)]
+pub struct State {
+    /// The global brightness between 0 and 1.0, stored as the bits of an f32.
+    dim: AtomicU32,
+    /// Whether the dimming is applied to the light output rather than to the input values.
+    pub linear_dim: bool,
+    /// While paused, frames are read from the inputs but not written to the output.
+    paused: AtomicBool,
+    /// Set when the output should be turned off once, after which it stays paused.
+    blank: AtomicBool,
+    /// The number of frames written to the output.
+    frames: AtomicUsize,
+}
+
+impl State {
+    pub fn new(dim: f32, linear_dim: bool) -> State {
+        State {
+            dim: AtomicU32::new(dim.to_bits()),
+            linear_dim,
+            paused: AtomicBool::new(false),
+            blank: AtomicBool::new(false),
+            frames: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn dim(&self) -> f32 {
+        f32::from_bits(self.dim.load(Ordering::Relaxed))
+    }
+
+    /// Sets the brightness, which is clamped between 0 and 1.0.
+    pub fn set_dim(&self, dim: f32) {
+        self.dim.store(dim.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Adds `step` to the brightness in a single atomic update, so concurrent steps are not lost.
+    /// The result is clamped between 0 and 1.0.
+    pub fn step_dim(&self, step: f32) {
+        let _ = self.dim.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f32::from_bits(bits) + step).clamp(0.0, 1.0).to_bits())
+        });
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Pauses the output and has it turn all pixels off.
+    pub fn blank(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+        self.blank.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the output should be turned off. Returns true only once for each call to `blank`.
+    pub fn take_blank(&self) -> bool {
+        self.blank.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Records a frame that has been written to the output.
+    pub fn add_frame(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+
+/// The control socket allows ledcat to be reconfigured while it is running.
+///
+/// Clients send commands as lines of text. Each command is answered with zero or more lines of
+/// output followed by a line starting with either "ok" or "error". Commands can also be sent as
+/// JSON objects like `{"command": "dim", "arg": 0.5}`, which are answered with a JSON object on a
+/// single line.
+///
+/// Commands:
+///   list          List the current inputs with their index
+///   add SPEC      Add an input, SPEC is in the same format as accepted by --input
+///   remove INDEX  Remove the input at the specified index
+///   next          Show the next input, with --input-mode cycle
+///   select INDEX  Show the input at the specified index, with --input-mode cycle
+///   dim [VALUE]   Print or set the brightness, VALUE may be relative, e.g. +0.1
+///   pause         Stop writing frames to the output, the display keeps the last frame
+///   blank         Turn all pixels off and stop writing frames to the output
+///   resume        Continue writing frames to the output
+///   status        Print the brightness, whether the output is paused, the number of inputs and
+///                 the number of frames written
+pub struct Server<F> {
+    inputs: Vec<String>,
+    controller: select::Controller,
+    state: Arc<State>,
+    open: F,
+}
+
+impl<F> Server<F>
+    where F: Fn(&spec::Spec) -> io::Result<fs::File> + Send + 'static {
+    /// Creates a new server, `inputs` is the list of inputs that the Reader has been initialized
+    /// with and `open` is used to open newly added inputs.
+    pub fn new(inputs: Vec<String>, controller: select::Controller, state: Arc<State>, open: F) -> Server<F> {
+        Server { inputs, controller, state, open }
+    }
+
+    /// Starts accepting connections on a unix socket at the specified path in the background.
+    /// Only the owner and the group of ledcat may connect.
+    pub fn listen<P: AsRef<path::Path>>(self, path: P) -> io::Result<()> {
+        // Remove stale sockets left behind by a previous instance.
+        if let Ok(meta) = fs::metadata(&path) {
+            if meta.file_type().is_socket() {
+                fs::remove_file(&path)?;
+            }
+        }
+        let listener = net::UnixListener::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o660))?;
+        self.serve_listener(listener);
+        Ok(())
+    }
+
+    /// Starts accepting connections on a socket that is already listening in the background, e.g.
+    /// one that has been passed by the service manager.
+    ///
+    /// Each connection is served by its own thread, so a client that keeps its connection open
+    /// does not lock out the others.
+    pub fn serve_listener(self, listener: net::UnixListener) {
+        let server = Arc::new(Mutex::new(self));
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let conn = match conn {
+                    Ok(c) => c,
+                    Err(_) => continue,
+                };
+                let server = server.clone();
+                thread::spawn(move || {
+                    if let Err(err) = Server::serve(&server, conn) {
+                        warn!("control: {}", err);
+                    }
+                });
+            }
+        });
+    }
+
+    fn serve(server: &Mutex<Server<F>>, conn: net::UnixStream) -> io::Result<()> {
+        let mut out = conn.try_clone()?;
+        for line in io::BufReader::new(conn).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            // Commands of different connections are executed one at a time. The lock is released
+            // before replying, so a client that does not read its replies can not hold it. A
+            // command that panicked does not leave the server unusable for the other clients.
+            if line.starts_with('{') {
+                let reply = match parse_json_command(line) {
+                    Ok((command, arg)) => {
+                        let mut server = server.lock().unwrap_or_else(|err| err.into_inner());
+                        match server.handle(&command, &arg) {
+                            Ok(output) if command == "status" => json!({"ok": true, "output": output, "status": server.status_json()}),
+                            Ok(output) => json!({"ok": true, "output": output}),
+                            Err(err) => json!({"ok": false, "error": err}),
+                        }
+                    },
+                    Err(err) => json!({"ok": false, "error": err}),
+                };
+                writeln!(out, "{}", reply)?;
+                continue;
+            }
+            let mut split = line.splitn(2, ' ');
+            let command = split.next().unwrap();
+            let arg = split.next().map(|a| a.trim()).unwrap_or("");
+            let result = server.lock().unwrap_or_else(|err| err.into_inner()).handle(command, arg);
+            match result {
+                Ok(output) => {
+                    for line in output {
+                        writeln!(out, "{}", line)?;
+                    }
+                    writeln!(out, "ok")?
+                },
+                Err(err) => writeln!(out, "error: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a command and returns the lines of its output.
+    fn handle(&mut self, command: &str, arg: &str) -> Result<Vec<String>, String> {
+        match command {
+            "list" => {
+                Ok(self.inputs.iter().enumerate()
+                    .map(|(i, input)| format!("{} {}", i, input))
+                    .collect())
+            },
+            "add" => {
+                let spec: spec::Spec = arg.parse()?;
+                let input = (self.open)(&spec)
+                    .map_err(|err| format!("could not open {}: {}", spec.path, err))?;
+                self.controller.send(select::Change::Add {
+                    input,
+                    placement: spec.placement,
+                    format: spec.format,
+                    weight: spec.weight,
+                }).map_err(|err| err.to_string())?;
+                self.inputs.push(arg.to_string());
+                Ok(Vec::new())
+            },
+            "remove" => {
+                let index: usize = arg.parse()
+                    .map_err(|_| format!("invalid index: {}", arg))?;
+                if index >= self.inputs.len() {
+                    return Err(format!("no input at index {}", index));
+                }
+                self.controller.send(select::Change::Remove(index))
+                    .map_err(|err| err.to_string())?;
+                self.inputs.remove(index);
+                Ok(Vec::new())
+            },
+            "next" => {
+                self.controller.send(select::Change::Cycle)
+                    .map_err(|err| err.to_string())?;
+                Ok(Vec::new())
+            },
+            "select" => {
+                let index: usize = arg.parse()
+                    .map_err(|_| format!("invalid index: {}", arg))?;
+                if index >= self.inputs.len() {
+                    return Err(format!("no input at index {}", index));
+                }
+                self.controller.send(select::Change::Select(index))
+                    .map_err(|err| err.to_string())?;
+                Ok(Vec::new())
+            },
+            "dim" | "pause" | "blank" | "resume" => handle_output(&self.state, command, arg),
+            "status" => {
+                Ok(vec![
+                    format!("dim: {}", self.state.dim()),
+                    format!("paused: {}", self.state.paused()),
+                    format!("inputs: {}", self.inputs.len()),
+                    format!("frames: {}", self.state.frames()),
+                ])
+            },
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+
+    fn status_json(&self) -> serde_json::Value {
+        json!({
+            "dim": self.state.dim(),
+            "paused": self.state.paused(),
+            "inputs": self.inputs.len(),
+            "frames": self.state.frames(),
+        })
+    }
+}
+
+/// Executes the commands that change the output, which are shared by the control socket and the
+/// control FIFO.
+fn handle_output(state: &State, command: &str, arg: &str) -> Result<Vec<String>, String> {
+    match command {
+        "dim" => {
+            if !arg.is_empty() {
+                match parse_dim(arg)? {
+                    Dim::Absolute(dim) => state.set_dim(dim),
+                    Dim::Relative(step) => state.step_dim(step),
+                }
+            }
+            Ok(vec![state.dim().to_string()])
+        },
+        "pause" => {
+            state.set_paused(true);
+            Ok(Vec::new())
+        },
+        "blank" => {
+            state.blank();
+            Ok(Vec::new())
+        },
+        "resume" => {
+            state.set_paused(false);
+            Ok(Vec::new())
+        },
+        _ => Err(format!("unknown command: {}", command)),
+    }
+}
+
+
+/// The control FIFO is a simpler alternative to the control socket for scripts that only need to
+/// change the output, e.g. `echo pause > /run/ledcat.fifo`.
+///
+/// Commands are read one per line and are not answered, errors are logged:
+///   dim VALUE     Set the brightness, VALUE may be relative, e.g. +0.1
+///   pause         Stop writing frames to the output, the display keeps the last frame
+///   blank         Turn all pixels off and stop writing frames to the output
+///   resume        Continue writing frames to the output
+pub struct Fifo {
+    file: fs::File,
+}
+
+impl Fifo {
+    /// Opens the FIFO at the specified path, which is created if it does not exist.
+    pub fn open<P: AsRef<path::Path>>(path: P) -> io::Result<Fifo> {
+        let path = path.as_ref();
+        match fs::metadata(path) {
+            Ok(ref meta) if meta.file_type().is_fifo() => (),
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the file exists and is not a FIFO")),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                io_err!(unistd::mkfifo(path, stat::Mode::from_bits_truncate(0o620)))?;
+            },
+            Err(err) => return Err(err),
+        }
+        // Also opening the FIFO for writing keeps it from reaching the end when the writers close
+        // it and makes opening it not block until a writer appears.
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Fifo { file })
+    }
+
+    /// Starts reading commands in the background.
+    pub fn serve(self, state: Arc<State>) {
+        thread::spawn(move || {
+            for line in io::BufReader::new(self.file).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        warn!("control fifo: {}", err);
+                        break;
+                    },
+                };
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let mut split = line.splitn(2, ' ');
+                let command = split.next().unwrap();
+                let arg = split.next().map(|a| a.trim()).unwrap_or("");
+                if command == "dim" && arg.is_empty() {
+                    warn!("control fifo: dim requires a value");
+                    continue;
+                }
+                match handle_output(&state, command, arg) {
+                    Ok(_) => debug!("control fifo: {}", line),
+                    Err(err) => warn!("control fifo: {}", err),
+                }
+            }
+        });
+    }
+}
+
+
+/// The argument of the dim command.
+#[derive(Debug, PartialEq)]
+enum Dim {
+    Absolute(f32),
+    /// A step that is added to the current brightness.
+    Relative(f32),
+}
+
+/// Parses the argument of the dim command, which is either an absolute value or a value relative
+/// to the current brightness when prefixed with a sign.
+fn parse_dim(arg: &str) -> Result<Dim, String> {
+    let value: f32 = arg.parse()
+        .map_err(|_| format!("invalid brightness: {}", arg))?;
+    if !value.is_finite() {
+        return Err(format!("invalid brightness: {}", arg));
+    }
+    if arg.starts_with('+') || arg.starts_with('-') {
+        Ok(Dim::Relative(value))
+    } else if (0.0..=1.0).contains(&value) {
+        Ok(Dim::Absolute(value))
+    } else {
+        Err(format!("brightness should be between 0 and 1.0: {}", arg))
+    }
+}
+
+/// Parses a command sent as a JSON object with a "command" and an optional "arg" field.
+fn parse_json_command(line: &str) -> Result<(String, String), String> {
+    let value: serde_json::Value = serde_json::from_str(line)
+        .map_err(|err| format!("invalid JSON: {}", err))?;
+    let command = value.get("command")
+        .and_then(|c| c.as_str())
+        .ok_or_else(|| "missing command".to_string())?;
+    let arg = match value.get("arg") {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Number(n)) => n.to_string(),
+        Some(_) => return Err("arg should be a string or a number".to_string()),
+    };
+    Ok((command.to_string(), arg))
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use super::*;
+
+    #[test]
+    fn state_dim() {
+        let state = State::new(1.0, false);
+        state.set_dim(0.5);
+        assert_eq!(0.5, state.dim());
+        state.set_dim(1.5);
+        assert_eq!(1.0, state.dim());
+        state.set_dim(-1.0);
+        assert_eq!(0.0, state.dim());
+    }
+
+    #[test]
+    fn state_step_dim() {
+        let state = State::new(0.5, false);
+        state.step_dim(0.25);
+        assert_eq!(0.75, state.dim());
+        state.step_dim(0.5);
+        assert_eq!(1.0, state.dim());
+        state.step_dim(-2.0);
+        assert_eq!(0.0, state.dim());
+
+        // Steps from several threads at once all take effect.
+        let state = Arc::new(State::new(0.0, false));
+        let threads: Vec<_> = (0..4).map(|_| {
+            let state = state.clone();
+            thread::spawn(move || for _ in 0..100 {
+                state.step_dim(0.001);
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!((state.dim() - 0.4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn output_commands() {
+        let state = State::new(1.0, false);
+        assert_eq!(Ok(vec!["0.5".to_string()]), handle_output(&state, "dim", "-0.5"));
+        handle_output(&state, "blank", "").unwrap();
+        assert!(state.paused());
+        assert!(state.take_blank());
+        assert!(!state.take_blank());
+        handle_output(&state, "resume", "").unwrap();
+        assert!(!state.paused());
+        assert!(handle_output(&state, "status", "").is_err());
+    }
+
+    #[test]
+    fn fifo_commands() {
+        let dir = tempdir::TempDir::new("ledcat-control").unwrap();
+        let path = dir.path().join("control");
+        let state = Arc::new(State::new(1.0, false));
+        Fifo::open(&path).unwrap().serve(state.clone());
+        let mut writer = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        writeln!(writer, "bogus\ndim 0.25\npause").unwrap();
+        let start = ::std::time::Instant::now();
+        while !state.paused() && start.elapsed() < ::std::time::Duration::from_secs(5) {
+            thread::sleep(::std::time::Duration::from_millis(10));
+        }
+        assert!(state.paused());
+        assert_eq!(0.25, state.dim());
+        // A regular file is not taken over.
+        fs::write(dir.path().join("file"), "").unwrap();
+        assert!(Fifo::open(dir.path().join("file")).is_err());
+    }
+
+    #[test]
+    fn socket_commands() {
+        use std::io::Read;
+        use std::os::unix::io::FromRawFd;
+        use std::time;
+        use input::geometry::Dimensions;
+
+        let (rd_a, wr_a) = unistd::pipe().unwrap();
+        let (rd_b, wr_b) = unistd::pipe().unwrap();
+        let (mut wr_a, mut wr_b) = unsafe { (fs::File::from_raw_fd(wr_a), fs::File::from_raw_fd(wr_b)) };
+        let mut reader = select::Reader::from(
+            vec![
+                Box::new(unsafe { fs::File::from_raw_fd(rd_a) }),
+                Box::new(unsafe { fs::File::from_raw_fd(rd_b) }),
+            ],
+            3,
+            select::WhenEOF::Close,
+            None,
+        ).unwrap();
+        reader.cycle();
+        let controller = reader.controller(Dimensions::One(1)).unwrap();
+
+        let dir = tempdir::TempDir::new("ledcat-control").unwrap();
+        let path = dir.path().join("control.sock");
+        let state = Arc::new(State::new(1.0, false));
+        Server::new(vec!["a".to_string(), "b".to_string()], controller, state, |_| {
+            Err(io::Error::other("not supported"))
+        }).listen(&path).unwrap();
+        assert_eq!(0o660, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        // An idle client does not keep others from being served.
+        let _idle = net::UnixStream::connect(&path).unwrap();
+        let mut conn = net::UnixStream::connect(&path).unwrap();
+        conn.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
+        let mut replies = io::BufReader::new(conn.try_clone().unwrap()).lines();
+        let mut command = |line: &str| {
+            writeln!(conn, "{}", line).unwrap();
+            replies.next().unwrap().unwrap()
+        };
+        assert_eq!("error: no input at index 2", command("select 2"));
+        assert_eq!("error: invalid index: b", command("select b"));
+        assert_eq!("error: can not parse \"99999999999999999999x0\" into a position",
+                   command("add c.fifo:geometry=1x1,at=99999999999999999999x0"));
+        let mut rd_buf = vec![0; 3];
+
+        assert_eq!("ok", command("select 1"));
+        wr_a.write_all(&[1; 3]).unwrap();
+        wr_b.write_all(&[2; 3]).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![2; 3], rd_buf);
+
+        assert_eq!("ok", command("next"));
+        wr_b.write_all(&[3; 3]).unwrap();
+        wr_a.write_all(&[4; 3]).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![4; 3], rd_buf);
+    }
+
+    #[test]
+    fn dim_arg() {
+        assert_eq!(Ok(Dim::Absolute(0.25)), parse_dim("0.25"));
+        assert_eq!(Ok(Dim::Relative(0.25)), parse_dim("+0.25"));
+        assert_eq!(Ok(Dim::Relative(-0.25)), parse_dim("-0.25"));
+        assert!(parse_dim("2").is_err());
+        assert!(parse_dim("bright").is_err());
+        assert!(parse_dim("NaN").is_err());
+    }
+
+    #[test]
+    fn json_command() {
+        assert_eq!(Ok(("dim".to_string(), "0.5".to_string())), parse_json_command(r#"{"command": "dim", "arg": 0.5}"#));
+        assert_eq!(Ok(("add".to_string(), "/tmp/a.fifo".to_string())), parse_json_command(r#"{"command": "add", "arg": "/tmp/a.fifo"}"#));
+        assert_eq!(Ok(("status".to_string(), String::new())), parse_json_command(r#"{"command": "status"}"#));
+        assert!(parse_json_command(r#"{"arg": 1}"#).is_err());
+        assert!(parse_json_command(r#"{"command": "dim", "arg": [1]}"#).is_err());
+        assert!(parse_json_command("{").is_err());
+    }
+}
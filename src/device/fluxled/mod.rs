@@ -19,6 +19,12 @@ use self::bulb::*;
 const DISCOVERY_PORT: u16 = 48899;
 const DISCOVERY_MAGIC: &[u8] = b"HF-A11ASSISTHREAD";
 
+pub const INFO: Info = Info {
+    protocol: "network, Flux LED",
+    drivers: &[],
+    rate: None,
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("fluxled")
         .about("TODO")
@@ -47,7 +53,7 @@ pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
             .help("The network range of where to look for devices in CIDR format"))
 }
 
-pub fn from_command(args: &clap::ArgMatches, _gargs: &GlobalArgs) -> io::Result<FromCommand> {
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
     if args.is_present("discover") {
         let network_range_rs = args.value_of("network")
             .map(|cidr| {
@@ -58,25 +64,28 @@ pub fn from_command(args: &clap::ArgMatches, _gargs: &GlobalArgs) -> io::Result<
         let network_range = match network_range_rs {
             Ok(cidr) => cidr,
             Err(err) => {
-                eprintln!("Could not guess which interface to use for discovery: {}", err);
-                eprintln!("Please set one using --net <cidr>");
+                error!("Could not guess which interface to use for discovery: {}", err);
+                error!("Please set one using --net <cidr>");
                 return Ok(FromCommand::SubcommandHandled);
             },
         };
 
         if let Err(err) = tui_discover(network_range) {
-            eprintln!("{}", err);
+            error!("{}", err);
         }
         return Ok(FromCommand::SubcommandHandled);
     }
 
+    let dev = Box::new(generic::Generic::default());
+    if gargs.check {
+        return Ok(FromCommand::Output(Box::new((dev, io::sink()))));
+    }
+
     let bulbs: Vec<_> = args.values_of("target").unwrap()
         .map(|addr| {
             Bulb::connect(addr.parse().unwrap()).unwrap()
         })
         .collect();
-
-    let dev = Box::new(generic::Generic{});
     let output = Display {
         bulbs,
         buf: Vec::new(),
@@ -3,7 +3,32 @@ use device::*;
 use clap;
 
 
-pub struct Generic { }
+pub struct Generic {
+    /// Set for RGBW output, four bytes are written per pixel.
+    white: Option<white::White>,
+    /// Set to write each channel as a 16-bit value.
+    wide: bool,
+    byte_order: ByteOrder,
+}
+
+impl Default for Generic {
+    fn default() -> Generic {
+        Generic { white: None, wide: false, byte_order: ByteOrder::Big }
+    }
+}
+
+impl Generic {
+    fn write_wide(&self, writer: &mut dyn io::Write, pixels: impl Iterator<Item = Pixel16>) -> io::Result<()> {
+        with_encode_buffer(|buf| {
+            for pix in pixels {
+                for &v in &[pix.r, pix.g, pix.b] {
+                    buf.extend_from_slice(&self.byte_order.bytes(v));
+                }
+            }
+            writer.write_all(buf)
+        })
+    }
+}
 
 impl Device for Generic {
     fn color_correction(&self) -> Correction {
@@ -11,19 +36,111 @@ impl Device for Generic {
     }
 
     fn write_frame(&self, writer: &mut io::Write, pixels: &[Pixel]) -> io::Result<()> {
-        let buf: Vec<u8> = pixels.iter()
-            .flat_map(|pix| vec![pix.r, pix.g, pix.b])
+        if self.wide {
+            return self.write_wide(writer, pixels.iter().cloned().map(Pixel16::from));
+        }
+        with_encode_buffer(|buf| {
+            for pix in pixels {
+                match self.white {
+                    Some(white) => buf.extend_from_slice(&white.split(pix)),
+                    None => buf.extend_from_slice(&[pix.r, pix.g, pix.b]),
+                }
+            }
+            writer.write_all(buf)
+        })
+    }
+
+    fn write_frame_wide(&self, writer: &mut dyn io::Write, pixels: &[Pixel16]) -> io::Result<()> {
+        if !self.wide {
+            return with_narrowed(pixels, |narrow| self.write_frame(writer, narrow));
+        }
+        self.write_wide(writer, pixels.iter().cloned())
+    }
+
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        Some(match (self.wide, self.white) {
+            (true, _) => 6,
+            (false, Some(_)) => 4,
+            (false, None) => 3,
+        })
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        let per_pixel = self.bytes_per_pixel().unwrap();
+        if !data.len().is_multiple_of(per_pixel) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame ends in an incomplete pixel"));
+        }
+        let pixels = data.chunks(per_pixel)
+            .map(|pix| match (self.wide, self.white) {
+                (true, _) => {
+                    let v = |i: usize| self.byte_order.value([pix[i], pix[i + 1]]);
+                    Pixel16 { r: v(0), g: v(2), b: v(4) }.narrow()
+                },
+                (false, Some(white)) => white.join([pix[0], pix[1], pix[2], pix[3]]),
+                (false, None) => Pixel { r: pix[0], g: pix[1], b: pix[2] },
+            })
             .collect();
-        writer.write_all(&buf)?;
-        Ok(())
+        Ok(pixels)
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "raw RGB",
+    drivers: &["none", "serial"],
+    rate: None,
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("generic")
         .about("Output data as RGB24")
+        .arg(clap::Arg::with_name("rgbw")
+            .long("rgbw")
+            .takes_value(true)
+            .validator(|v| v.parse::<white::White>().map(|_| ()))
+            .help("Output RGBW data, deriving the white channel using one of none, min, luma or \
+                   calibrated:R,G,B"))
+        .arg(clap::Arg::with_name("depth")
+            .long("depth")
+            .takes_value(true)
+            .possible_values(&["8", "16"])
+            .conflicts_with("rgbw")
+            .help("The number of bits per channel, 8 by default. With 16, each channel is written \
+                   as a 16-bit value, big endian unless --byte-order is set"))
+}
+
+pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let white = args.value_of("rgbw").map(|v| v.parse().unwrap());
+    let wide = args.value_of("depth") == Some("16");
+    let byte_order = gargs.byte_order.unwrap_or(ByteOrder::Big);
+    Ok(FromCommand::Device(Box::new(Generic { white, wide, byte_order })))
 }
 
-pub fn from_command(_: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
-    Ok(FromCommand::Device(Box::new(Generic {})))
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_frame_wide() {
+        let pixels = [Pixel16 { r: 0x1234, g: 0xffff, b: 0x0080 }];
+        let mut out = Vec::new();
+        Generic { white: None, wide: true, byte_order: ByteOrder::Big }.write_frame_wide(&mut out, &pixels).unwrap();
+        assert_eq!(vec![0x12, 0x34, 0xff, 0xff, 0x00, 0x80], out);
+        let mut out = Vec::new();
+        Generic { white: None, wide: true, byte_order: ByteOrder::Little }.write_frame_wide(&mut out, &pixels).unwrap();
+        assert_eq!(vec![0x34, 0x12, 0xff, 0xff, 0x80, 0x00], out);
+        let mut out = Vec::new();
+        Generic::default().write_frame_wide(&mut out, &pixels).unwrap();
+        assert_eq!(vec![0x12, 0xff, 0x00], out);
+    }
+
+    #[test]
+    fn decode_frame() {
+        let dev = Generic { white: None, wide: true, byte_order: ByteOrder::Little };
+        assert_eq!(vec![Pixel { r: 0x12, g: 0xff, b: 0x00 }],
+                   dev.decode_frame(&[0x34, 0x12, 0xff, 0xff, 0x80, 0x00]).unwrap());
+        let dev = Generic { white: Some(white::White::Min), wide: false, byte_order: ByteOrder::Big };
+        assert_eq!(vec![Pixel { r: 200, g: 100, b: 50 }], dev.decode_frame(&[150, 50, 0, 50]).unwrap());
+        assert!(dev.decode_frame(&[150, 50, 0]).is_err());
+    }
 }
@@ -3,7 +3,9 @@ use device::*;
 use clap;
 
 
-pub struct HexWS2811 {}
+pub struct HexWS2811 {
+    byte_order: ByteOrder,
+}
 
 impl Device for HexWS2811 {
     fn color_correction(&self) -> Correction {
@@ -11,24 +13,48 @@ impl Device for HexWS2811 {
     }
 
     fn write_frame(&self, writer: &mut io::Write, pixels: &[Pixel]) -> io::Result<()> {
-        for pix in pixels.iter().rev() {
-            writer.write_all(&[
-                ((u16::from(pix.g) * 256) & 0xff) as u8,
-                ((u16::from(pix.g) * 256) >> 8) as u8,
-                ((u16::from(pix.r) * 256) & 0xff) as u8,
-                ((u16::from(pix.r) * 256) >> 8) as u8,
-                ((u16::from(pix.b) * 256) & 0xff) as u8,
-                ((u16::from(pix.b) * 256) >> 8) as u8,
-            ])?;
+        with_encode_buffer(|leds| {
+            for pix in pixels.iter().rev() {
+                for &v in &[pix.g, pix.r, pix.b] {
+                    leds.extend_from_slice(&self.byte_order.bytes(u16::from(v) * 256));
+                }
+            }
+            write_frame_vectored(writer, &[leds, &[0xff, 0xff, 0xff, 0xf0]])
+        })
+    }
+
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        Some(6)
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        if data.len() < 4 || data[data.len() - 4..] != [0xff, 0xff, 0xff, 0xf0] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame has no end marker"));
+        }
+        if !(data.len() - 4).is_multiple_of(6) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame ends in an incomplete pixel"));
         }
-        writer.write_all(&[0xff, 0xff, 0xff, 0xf0])
+        let pixels = data[..data.len() - 4].chunks(6).rev()
+            .map(|led| {
+                let v = |i: usize| (self.byte_order.value([led[i], led[i + 1]]) >> 8) as u8;
+                Pixel { r: v(2), g: v(0), b: v(4) }
+            })
+            .collect();
+        Ok(pixels)
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "serial",
+    drivers: &["serial"],
+    rate: Some(Rate::Baud(1_152_000)),
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("hexws2811")
 }
 
-pub fn from_command(_: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
-    Ok(FromCommand::Device(Box::new(HexWS2811 {})))
+pub fn from_command(_: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let byte_order = gargs.byte_order.unwrap_or(ByteOrder::Little);
+    Ok(FromCommand::Device(Box::new(HexWS2811 { byte_order })))
 }
@@ -86,7 +86,7 @@ pub struct Hub75 {
 
 impl Output for Hub75 {
     fn color_correction(&self) -> Correction {
-        Correction::srgb(255, 255, 255)
+        color_correction()
     }
 
     fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
@@ -100,6 +100,16 @@ impl Output for Hub75 {
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "HUB75 over GPIO",
+    drivers: &[],
+    rate: None,
+};
+
+fn color_correction() -> Correction {
+    Correction::srgb(255, 255, 255)
+}
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("hub75")
         .about("Drive HUB75 LED-panels using GPIO")
@@ -151,15 +161,31 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
 
     let pwm_cycles = args.value_of("pwm").unwrap()
         .parse().unwrap();
-    let pins = |name: &str| -> io::Result<Vec<SysFsGpioOutput>> {
+    let numbers = |name: &str| -> Vec<u16> {
         args.value_of(name).unwrap()
             .split(',')
             .map(|s| s.parse().unwrap())
+            .collect()
+    };
+    let level_select = numbers("level-select");
+    if height % (1 << level_select.len()) != 0 {
+        return Err(io::Error::other("The height must be a multiple of 2^len(level-select-pins)"));
+    }
+    let (red, green, blue) = (numbers("red"), numbers("green"), numbers("blue"));
+    if red.len() != green.len() || green.len() != blue.len() {
+        return Err(io::Error::other("The number of red, green and blue pins must be all equal"));
+    }
+    if gargs.check {
+        return Ok(FromCommand::Output(Box::new(Unopened(color_correction))));
+    }
+
+    let pins = |numbers: Vec<u16>| -> io::Result<Vec<SysFsGpioOutput>> {
+        numbers.into_iter()
             .map(SysFsGpioOutput::new)
             .collect()
     };
     let pin = |name: &str| -> io::Result<SysFsGpioOutput> {
-        Ok(pins(name)?.pop().unwrap())
+        Ok(pins(numbers(name))?.pop().unwrap())
     };
 
     let (frame_tx, frame_rx) = mpsc::sync_channel(0);
@@ -172,20 +198,11 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
         frame_rx,
         cur_frame: vec![Pixel::default(); width * height],
         err_tx,
-        level_select: {
-            let p = pins("level-select")?;
-            if height % (1 << p.len()) != 0 {
-                return Err(io::Error::new(io::ErrorKind::Other, "The height must be a multiple of 2^len(level-select-pins)"));
-            }
-            p
-        },
+        level_select: pins(level_select)?,
         rgb: {
-            let r = pins("red")?;
-            let g = pins("green")?;
-            let b = pins("blue")?;
-            if r.len() != g.len() || g.len() != b.len() {
-                return Err(io::Error::new(io::ErrorKind::Other, "The number of red, green and blue pins must be all equal"));
-            }
+            let r = pins(red)?;
+            let g = pins(green)?;
+            let b = pins(blue)?;
             r.into_iter().zip(g).zip(b)
                 .map(|a| [(a.0).0, (a.0).1, a.1])
                 .collect()
@@ -4,6 +4,9 @@ use color::*;
 use device::*;
 
 
+/// The number of zero bytes that is written after the pixels.
+const TRAILER: usize = 50;
+
 pub struct Lpd8806 {
     pub spidev_clock: u32,
 }
@@ -22,16 +25,52 @@ impl Device for Lpd8806 {
         })
     }
 
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        Some(3)
+    }
+
+    fn constraints(&self) -> Constraints {
+        Constraints {
+            // Each zero byte of the trailer latches the data of 32 pixels.
+            max_pixels: Some(TRAILER * 32),
+            latch: None,
+            bit_rate: Some(self.spidev_clock),
+        }
+    }
+
     fn write_frame(&self, writer: &mut io::Write, pixels: &[Pixel]) -> io::Result<()> {
-        // FIXME: The number of zero bytes in the header and trailer should not be magic.
-        writer.write_all(&[0x00; 10])?;
-        for pix in pixels.iter().rev() {
-            writer.write_all(&[(pix.g >> 1) | 0x80, (pix.r >> 1) | 0x80, (pix.b >> 1) | 0x80])?;
+        // FIXME: The number of zero bytes in the header should not be magic.
+        with_encode_buffer(|leds| {
+            for pix in pixels.iter().rev() {
+                leds.extend_from_slice(&[(pix.g >> 1) | 0x80, (pix.r >> 1) | 0x80, (pix.b >> 1) | 0x80]);
+            }
+            write_frame_vectored(writer, &[&[0x00; 10], leds, &[0x00; TRAILER]])
+        })
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        if data.len() < 10 + TRAILER || data[..10].iter().chain(&data[data.len() - TRAILER..]).any(|&b| b != 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame has no header or trailer"));
         }
-        writer.write_all(&[0x00; 50])
+        if !(data.len() - 10 - TRAILER).is_multiple_of(3) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame ends in an incomplete pixel"));
+        }
+        // Only the upper 7 bits of each channel are sent.
+        data[10..data.len() - TRAILER].chunks(3).rev()
+            .map(|led| match *led {
+                [g, r, b] if g & r & b & 0x80 != 0 => Ok(Pixel { r: r << 1, g: g << 1, b: b << 1 }),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "a pixel has its high bit cleared")),
+            })
+            .collect()
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "SPI",
+    drivers: &["spidev"],
+    rate: Some(Rate::Clock(500_000)),
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("lpd8806")
         .arg(clap::Arg::with_name("spidev-clock")
@@ -1,9 +1,13 @@
+use std::cell;
+use std::fmt;
 use std::io;
 use std::ops::{Deref, DerefMut};
+use std::str;
+use std::time;
 use clap;
 use color::*;
 use driver::*;
-use geometry::*;
+use input::geometry::*;
 
 pub mod apa102;
 pub mod fluxled;
@@ -26,6 +30,12 @@ pub trait Output: Send {
     fn color_correction(&self) -> Correction;
 
     fn output_frame(&mut self, &[Pixel]) -> io::Result<()>;
+
+    /// Outputs a frame with 16 bits per channel. Outputs that can not show more than 8 bits per
+    /// channel may rely on the default implementation, which rounds the colors.
+    fn output_frame_wide(&mut self, pixels: &[Pixel16]) -> io::Result<()> {
+        with_narrowed(pixels, |narrow| self.output_frame(narrow))
+    }
 }
 
 impl<D, W> Output for (D, W)
@@ -38,6 +48,10 @@ impl<D, W> Output for (D, W)
     fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
         self.0.write_frame(&mut self.1, frame)
     }
+
+    fn output_frame_wide(&mut self, frame: &[Pixel16]) -> io::Result<()> {
+        self.0.write_frame_wide(&mut self.1, frame)
+    }
 }
 
 impl Output for Box<Output> {
@@ -48,6 +62,102 @@ impl Output for Box<Output> {
     fn output_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
         self.deref_mut().output_frame(pixels)
     }
+
+    fn output_frame_wide(&mut self, pixels: &[Pixel16]) -> io::Result<()> {
+        self.deref_mut().output_frame_wide(pixels)
+    }
+}
+
+
+/// Repeat shows each frame on a number of identical segments of a longer chain of pixels.
+pub struct Repeat<O> {
+    pub output: O,
+    pub times: usize,
+    // Hold the repeated frame, reused between frames.
+    buffer: Vec<Pixel>,
+    buffer_wide: Vec<Pixel16>,
+}
+
+impl<O: Output> Repeat<O> {
+    pub fn new(output: O, times: usize) -> Repeat<O> {
+        Repeat { output, times, buffer: Vec::new(), buffer_wide: Vec::new() }
+    }
+}
+
+impl<O: Output> Output for Repeat<O> {
+    fn color_correction(&self) -> Correction {
+        self.output.color_correction()
+    }
+
+    fn output_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        self.buffer.clear();
+        for _ in 0..self.times {
+            self.buffer.extend_from_slice(pixels);
+        }
+        self.output.output_frame(&self.buffer)
+    }
+
+    fn output_frame_wide(&mut self, pixels: &[Pixel16]) -> io::Result<()> {
+        self.buffer_wide.clear();
+        for _ in 0..self.times {
+            self.buffer_wide.extend_from_slice(pixels);
+        }
+        self.output.output_frame_wide(&self.buffer_wide)
+    }
+}
+
+
+/// Reorder sends the channels of each pixel in a different order, for devices that are wired
+/// differently than the standard chip they are compatible with.
+pub struct Reorder<O> {
+    pub output: O,
+    pub order: ColorOrder,
+    // Hold the reordered frame, reused between frames.
+    buffer: Vec<Pixel>,
+    buffer_wide: Vec<Pixel16>,
+}
+
+impl<O: Output> Reorder<O> {
+    pub fn new(output: O, order: ColorOrder) -> Reorder<O> {
+        Reorder { output, order, buffer: Vec::new(), buffer_wide: Vec::new() }
+    }
+}
+
+impl<O: Output> Output for Reorder<O> {
+    fn color_correction(&self) -> Correction {
+        self.output.color_correction()
+    }
+
+    fn output_frame(&mut self, pixels: &[Pixel]) -> io::Result<()> {
+        if self.order.is_identity() {
+            return self.output.output_frame(pixels);
+        }
+        self.order.reorder_frame(pixels, &mut self.buffer);
+        self.output.output_frame(&self.buffer)
+    }
+
+    fn output_frame_wide(&mut self, pixels: &[Pixel16]) -> io::Result<()> {
+        if self.order.is_identity() {
+            return self.output.output_frame_wide(pixels);
+        }
+        self.order.reorder_frame_wide(pixels, &mut self.buffer_wide);
+        self.output.output_frame_wide(&self.buffer_wide)
+    }
+}
+
+
+/// Unopened stands in for an output that drives hardware directly when the arguments are only
+/// checked. It reports the color correction of the real output but can not show frames.
+pub struct Unopened(pub fn() -> Correction);
+
+impl Output for Unopened {
+    fn color_correction(&self) -> Correction {
+        (self.0)()
+    }
+
+    fn output_frame(&mut self, _: &[Pixel]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "the output has not been opened"))
+    }
 }
 
 
@@ -59,10 +169,23 @@ pub trait Device: Send {
     fn color_correction(&self) -> Correction;
     fn write_frame(&self, &mut io::Write, &[Pixel]) -> io::Result<()>;
 
+    /// Writes a frame with 16 bits per channel. Devices that can not show more than 8 bits per
+    /// channel may rely on the default implementation, which rounds the colors.
+    fn write_frame_wide(&self, out: &mut dyn io::Write, frame: &[Pixel16]) -> io::Result<()> {
+        with_narrowed(frame, |narrow| self.write_frame(out, narrow))
+    }
+
     fn spidev_config(&self) -> Option<spidev::Config> {
         None
     }
 
+    /// The number of bytes sent for each pixel with the options the device is configured with,
+    /// not counting the start and end of a frame. None if the pixels are not sent as a stream of
+    /// bytes.
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        None
+    }
+
     fn written_frame_size(&self, num_pixels: usize) -> usize {
         let mut buf = Vec::new();
         let dummy_frame: Vec<Pixel> = (0..num_pixels)
@@ -71,6 +194,19 @@ pub trait Device: Send {
         self.write_frame(&mut buf, dummy_frame.as_slice()).unwrap();
         buf.len()
     }
+
+    /// The physical limits of the device, which are checked against the geometry and the frame
+    /// rate.
+    fn constraints(&self) -> Constraints {
+        Constraints::default()
+    }
+
+    /// Reads the pixels back from a frame that was written by this device, so the output can be
+    /// captured and verified. Channels that the device stores with less precision are returned
+    /// as they are shown.
+    fn decode_frame(&self, _: &[u8]) -> io::Result<Vec<Pixel>> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "the device does not support decoding frames"))
+    }
 }
 
 impl<T> Device for Box<T>
@@ -82,11 +218,193 @@ impl<T> Device for Box<T>
     fn write_frame(&self, out: &mut io::Write, frame: &[Pixel]) -> io::Result<()> {
         self.deref().write_frame(out, frame)
     }
+
+    fn write_frame_wide(&self, out: &mut dyn io::Write, frame: &[Pixel16]) -> io::Result<()> {
+        self.deref().write_frame_wide(out, frame)
+    }
+
+    fn spidev_config(&self) -> Option<spidev::Config> {
+        self.deref().spidev_config()
+    }
+
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        self.deref().bytes_per_pixel()
+    }
+
+    fn written_frame_size(&self, num_pixels: usize) -> usize {
+        self.deref().written_frame_size(num_pixels)
+    }
+
+    fn constraints(&self) -> Constraints {
+        self.deref().constraints()
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        self.deref().decode_frame(data)
+    }
+}
+
+thread_local! {
+    // The encoded pixels of the last frame written by the thread, kept to reuse the allocation.
+    static ENCODED: cell::RefCell<Vec<u8>> = const { cell::RefCell::new(Vec::new()) };
+    // The last frame rounded to 8 bits per channel by the thread, kept to reuse the allocation.
+    static NARROWED: cell::RefCell<Vec<Pixel>> = const { cell::RefCell::new(Vec::new()) };
+}
+
+/// Calls `f` with the frame rounded to 8 bits per channel. Like the encode buffer, the buffer
+/// holding the rounded frame is reused for the next frame of the same thread.
+pub fn with_narrowed<T, F>(frame: &[Pixel16], f: F) -> T
+    where F: FnOnce(&[Pixel]) -> T {
+    NARROWED.with(|narrowed| match narrowed.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            buf.extend(frame.iter().map(Pixel16::narrow));
+            f(&buf)
+        },
+        // An output that wraps another one gets a buffer of its own.
+        Err(_) => f(&frame.iter().map(Pixel16::narrow).collect::<Vec<_>>()),
+    })
+}
+
+/// Calls `f` with an empty buffer to encode the pixels of a frame into. The buffer is reused for
+/// the next frame written by the same thread, so devices do not allocate for each frame.
+pub fn with_encode_buffer<T, F>(f: F) -> T
+    where F: FnOnce(&mut Vec<u8>) -> T {
+    ENCODED.with(|encoded| match encoded.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            f(&mut buf)
+        },
+        // A device that wraps another one gets a buffer of its own.
+        Err(_) => f(&mut Vec::new()),
+    })
+}
+
+/// The maximum number of parts that write_frame_vectored accepts.
+const MAX_FRAME_PARTS: usize = 4;
+
+/// Writes the parts of a frame, such as a header, the encoded pixels and a footer, with a single
+/// vectored write if the writer accepts it. This sends the frame in one go without copying the
+/// parts together first.
+pub fn write_frame_vectored(writer: &mut dyn io::Write, parts: &[&[u8]]) -> io::Result<()> {
+    assert!(parts.len() <= MAX_FRAME_PARTS);
+    let mut slices = [io::IoSlice::new(&[]); MAX_FRAME_PARTS];
+    // The part and the offset into it that is to be written next.
+    let (mut first, mut offset) = (0, 0);
+    loop {
+        // Skip the parts that have been written, and empty ones, which can not be written.
+        while first < parts.len() && offset == parts[first].len() {
+            first += 1;
+            offset = 0;
+        }
+        let remaining = &parts[first..];
+        if remaining.is_empty() {
+            return Ok(());
+        }
+        for (slice, part) in slices.iter_mut().zip(remaining) {
+            *slice = io::IoSlice::new(part);
+        }
+        slices[0] = io::IoSlice::new(&remaining[0][offset..]);
+        match writer.write_vectored(&slices[..remaining.len()]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole frame")),
+            Ok(mut n) => {
+                while n > 0 {
+                    let left = parts[first].len() - offset;
+                    if n < left {
+                        offset += n;
+                        n = 0;
+                    } else {
+                        n -= left;
+                        first += 1;
+                        offset = 0;
+                    }
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+
+/// Capture decodes each frame that a device writes back into pixels and writes them as RGB to a
+/// file, so what is sent to the hardware can be checked end to end.
+pub struct Capture<D, W> {
+    dev: D,
+    capture: cell::RefCell<W>,
+}
+
+impl<D, W> Capture<D, W>
+    where D: Device,
+          W: io::Write + Send {
+    /// Fails if the device does not support decoding its frames.
+    pub fn new(dev: D, capture: W) -> io::Result<Capture<D, W>> {
+        let mut buf = Vec::new();
+        dev.write_frame(&mut buf, &[Pixel::default()])?;
+        dev.decode_frame(&buf)?;
+        Ok(Capture { dev, capture: cell::RefCell::new(capture) })
+    }
+
+    fn write_encoded(&self, writer: &mut dyn io::Write, buf: &[u8]) -> io::Result<()> {
+        writer.write_all(buf)?;
+        let rgb: Vec<u8> = self.dev.decode_frame(buf)?.iter()
+            .flat_map(|pix| vec![pix.r, pix.g, pix.b])
+            .collect();
+        let mut capture = self.capture.borrow_mut();
+        capture.write_all(&rgb)?;
+        capture.flush()
+    }
+}
+
+impl<D, W> Device for Capture<D, W>
+    where D: Device,
+          W: io::Write + Send {
+    fn color_correction(&self) -> Correction {
+        self.dev.color_correction()
+    }
+
+    fn write_frame(&self, writer: &mut dyn io::Write, pixels: &[Pixel]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.dev.write_frame(&mut buf, pixels)?;
+        self.write_encoded(writer, &buf)
+    }
+
+    fn write_frame_wide(&self, writer: &mut dyn io::Write, pixels: &[Pixel16]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.dev.write_frame_wide(&mut buf, pixels)?;
+        self.write_encoded(writer, &buf)
+    }
+
+    fn spidev_config(&self) -> Option<spidev::Config> {
+        self.dev.spidev_config()
+    }
+
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        self.dev.bytes_per_pixel()
+    }
+
+    fn written_frame_size(&self, num_pixels: usize) -> usize {
+        self.dev.written_frame_size(num_pixels)
+    }
+
+    fn constraints(&self) -> Constraints {
+        self.dev.constraints()
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        self.dev.decode_frame(data)
+    }
 }
 
 
 pub struct GlobalArgs {
     pub dimensions: Option<Dimensions>,
+    /// Set by --check. Devices should validate their arguments without opening any hardware or
+    /// network connections, e.g. by writing to an `io::sink()` or returning an `Unopened`.
+    pub check: bool,
+    /// Set by --byte-order. Devices that write 16-bit values use their own byte order unless it
+    /// is set.
+    pub byte_order: Option<ByteOrder>,
 }
 
 impl GlobalArgs {
@@ -104,6 +422,78 @@ impl GlobalArgs {
     }
 }
 
+/// The order in which the bytes of 16-bit values are written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    pub fn bytes(self, v: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Big => v.to_be_bytes(),
+            ByteOrder::Little => v.to_le_bytes(),
+        }
+    }
+
+    pub fn value(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        }
+    }
+}
+
+impl str::FromStr for ByteOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "be" => Ok(ByteOrder::Big),
+            "le" => Ok(ByteOrder::Little),
+            _ => Err(format!("unknown byte order: {}, expected be or le", s)),
+        }
+    }
+}
+
+/// The physical limits of a device. Exceeding them does not break ledcat, but the display will
+/// not show what is expected.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Constraints {
+    /// The number of pixels a frame can be shown on.
+    pub max_pixels: Option<usize>,
+    /// The time the data line has to be idle after a frame before the pixels show it.
+    pub latch: Option<time::Duration>,
+    /// The number of bits per second that are sent to the device.
+    pub bit_rate: Option<u32>,
+}
+
+impl Constraints {
+    /// The shortest time in which a frame of `frame_bytes` can be sent and shown.
+    pub fn frame_time(&self, frame_bytes: usize) -> Option<time::Duration> {
+        self.bit_rate.map(|rate| {
+            time::Duration::from_secs_f64(frame_bytes as f64 * 8.0 / f64::from(rate))
+                + self.latch.unwrap_or_default()
+        })
+    }
+
+    /// Checks frames of `num_pixels`, which the device writes as `frame_bytes`, and the interval
+    /// at which they are sent against the constraints. Returns a message for each violation.
+    pub fn check(&self, num_pixels: usize, frame_bytes: usize, interval: Option<time::Duration>) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(max) = self.max_pixels.filter(|&max| num_pixels > max) {
+            violations.push(format!("the device can show at most {} pixels, but the display has {}", max, num_pixels));
+        }
+        if let (Some(interval), Some(time)) = (interval, self.frame_time(frame_bytes)) {
+            if time > interval {
+                violations.push(format!("sending {} pixels takes {:.1}ms, which allows at most {:.1} frames per second",
+                                        num_pixels, time.as_secs_f64() * 1000.0, 1.0 / time.as_secs_f64()));
+            }
+        }
+        violations
+    }
+}
+
 /// Device implemetations are expected to be accompanied by a function that constructs and
 /// configures a new instance from a set of command line arguments.
 pub enum FromCommand {
@@ -117,19 +507,207 @@ pub enum FromCommand {
 
 pub type FromCommandFn = fn(&clap::ArgMatches, &GlobalArgs) -> io::Result<FromCommand>;
 
-pub fn devices<'a, 'b>() -> Vec<(clap::App<'a, 'b>, FromCommandFn)> {
+/// Describes the protocol of a device, as listed by `ledcat list-devices`. Each device module
+/// declares its own as `INFO`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Info {
+    /// The family of the protocol, e.g. SPI for chips with a clock and data line.
+    pub protocol: &'static str,
+    /// The drivers that can write to the device. Empty for devices that do their own IO.
+    pub drivers: &'static [&'static str],
+    /// The clock frequency or baud rate that is used unless it is overridden.
+    pub rate: Option<Rate>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rate {
+    /// The clock of a synchronous bus in Hz.
+    Clock(u32),
+    /// The baud rate of a serial port.
+    Baud(u32),
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rate::Clock(hz) if hz >= 1_000_000 => write!(f, "{}MHz", f64::from(hz) / 1e6),
+            Rate::Clock(hz) if hz >= 1_000 => write!(f, "{}kHz", f64::from(hz) / 1e3),
+            Rate::Clock(hz) => write!(f, "{}Hz", hz),
+            Rate::Baud(baud) => write!(f, "{} baud", baud),
+        }
+    }
+}
+
+pub fn devices<'a, 'b>() -> Vec<(clap::App<'a, 'b>, FromCommandFn, Info)> {
     vec![
-        (apa102::command(), apa102::from_command),
-        (artnet::command(), artnet::from_command),
-        (fluxled::command(), fluxled::from_command),
-        (generic::command(), generic::from_command),
-        (hexws2811::command(), hexws2811::from_command),
-        (hub75::command(), hub75::from_command),
-        (lpd8806::command(), lpd8806::from_command),
+        (apa102::command(), apa102::from_command, apa102::INFO),
+        (artnet::command(), artnet::from_command, artnet::INFO),
+        (fluxled::command(), fluxled::from_command, fluxled::INFO),
+        (generic::command(), generic::from_command, generic::INFO),
+        (hexws2811::command(), hexws2811::from_command, hexws2811::INFO),
+        (hub75::command(), hub75::from_command, hub75::INFO),
+        (lpd8806::command(), lpd8806::from_command, lpd8806::INFO),
         #[cfg(all(not(feature = "ci"), target_arch = "arm", target_os = "linux"))]
-        (rpi_led_matrix::command(), rpi_led_matrix::from_command),
-        (simulator::command(), simulator::from_command),
-        (sk9822::command(), sk9822::from_command),
-        (ws2812::command(), ws2812::from_command),
+        (rpi_led_matrix::command(), rpi_led_matrix::from_command, rpi_led_matrix::INFO),
+        (simulator::command(), simulator::from_command, simulator::INFO),
+        (sk9822::command(), sk9822::from_command, sk9822::INFO),
+        (ws2812::command(), ws2812::from_command, ws2812::INFO),
     ]
 }
+
+/// Returns the number of bytes that a device sends per pixel when it is configured with the
+/// arguments of its subcommand, e.g. `["ws2812", "--rgbw", "min"]`. The device is constructed
+/// like with --check, so no hardware is opened.
+pub fn bytes_per_pixel(command: clap::App, from_command: FromCommandFn, args: &[&str]) -> io::Result<Option<usize>> {
+    let matches = command.get_matches_from_safe(args)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.message))?;
+    let gargs = GlobalArgs { dimensions: None, check: true, byte_order: None };
+    match from_command(&matches, &gargs)? {
+        FromCommand::Device(dev) => Ok(dev.bytes_per_pixel()),
+        _ => Ok(None),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use super::*;
+
+    #[test]
+    fn device_bytes_per_pixel() {
+        let gargs = GlobalArgs { dimensions: None, check: false, byte_order: None };
+        let options: &[&[&str]] = &[&[], &["--rgbw", "min"], &["--depth", "16"]];
+        // Devices without a driver take arguments that can not be defaulted, like a target host.
+        for (command, from_command, _) in devices().into_iter().filter(|d| !d.2.drivers.is_empty()) {
+            let name = command.get_name().to_string();
+            for opts in options {
+                let args: Vec<&str> = iter::once(name.as_str()).chain(opts.iter().cloned()).collect();
+                let matches = match command.clone().get_matches_from_safe(&args) {
+                    Ok(matches) => matches,
+                    Err(_) => continue,
+                };
+                let dev = match from_command(&matches, &gargs) {
+                    Ok(FromCommand::Device(dev)) => dev,
+                    _ => continue,
+                };
+                let per_pixel = dev.written_frame_size(11) - dev.written_frame_size(10);
+                assert_eq!(Some(per_pixel), dev.bytes_per_pixel(), "{:?}", args);
+            }
+        }
+        assert_eq!(Some(12), bytes_per_pixel(ws2812::command(), ws2812::from_command, &["ws2812", "--rgbw", "min"]).unwrap());
+        assert_eq!(Some(6), bytes_per_pixel(generic::command(), generic::from_command, &["generic", "--depth", "16"]).unwrap());
+        assert!(bytes_per_pixel(generic::command(), generic::from_command, &["generic", "--bogus"]).is_err());
+    }
+
+    #[test]
+    fn decode_frame() {
+        let gargs = GlobalArgs { dimensions: None, check: false, byte_order: None };
+        // Even values survive devices that drop the lowest bit.
+        let pixels = vec![
+            Pixel { r: 254, g: 0, b: 128 },
+            Pixel { r: 2, g: 100, b: 200 },
+            Pixel { r: 0, g: 0, b: 0 },
+        ];
+        for (command, from_command, _) in devices().into_iter().filter(|d| !d.2.drivers.is_empty()) {
+            let name = command.get_name().to_string();
+            let args = command.get_matches_from(vec![name.clone()]);
+            let dev = match from_command(&args, &gargs) {
+                Ok(FromCommand::Device(dev)) => dev,
+                _ => continue,
+            };
+            let mut buf = Vec::new();
+            dev.write_frame(&mut buf, &pixels).unwrap();
+            assert_eq!(pixels, dev.decode_frame(&buf).unwrap(), "{}", name);
+            buf.pop();
+            assert!(dev.decode_frame(&buf).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let pixels = [Pixel { r: 1, g: 2, b: 3 }, Pixel { r: 4, g: 5, b: 6 }];
+        let dev = Capture::new(apa102::Apa102 { grayscale: 31, spidev_clock: 500_000 }, Vec::new()).unwrap();
+        let mut out = Vec::new();
+        dev.write_frame(&mut out, &pixels).unwrap();
+        assert_eq!(vec![0, 0, 0, 0, 0xff, 3, 2, 1, 0xff, 6, 5, 4], out);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], dev.capture.into_inner());
+    }
+
+    #[test]
+    fn write_vectored() {
+        // Accepts at most a few bytes of the first part per call, like a congested socket.
+        struct Partial(Vec<u8>);
+        impl io::Write for Partial {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let parts: [&[u8]; 4] = [&[1, 2], &[], &[3, 4, 5, 6, 7], &[8]];
+        let mut partial = Partial(Vec::new());
+        write_frame_vectored(&mut partial, &parts).unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], partial.0);
+        let mut buf = Vec::new();
+        write_frame_vectored(&mut buf, &parts).unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], buf);
+        assert_eq!(io::ErrorKind::WriteZero, write_frame_vectored(&mut &mut [0u8; 4][..], &parts).unwrap_err().kind());
+    }
+
+    #[test]
+    fn narrow_reuses_buffer() {
+        // Remembers where the pixels of the last frame it was handed are stored.
+        struct Frames(cell::Cell<usize>);
+        impl Device for Frames {
+            fn color_correction(&self) -> Correction {
+                Correction::none()
+            }
+            fn write_frame(&self, _: &mut dyn io::Write, pixels: &[Pixel]) -> io::Result<()> {
+                self.0.set(pixels.as_ptr() as usize);
+                Ok(())
+            }
+        }
+        let frame = vec![Pixel16 { r: 0x1212, g: 0, b: 0xffff }; 64];
+        let mut output = (Frames(cell::Cell::new(0)), io::sink());
+        output.output_frame_wide(&frame).unwrap();
+        let first = (output.0).0.get();
+        for _ in 0..3 {
+            output.output_frame_wide(&frame).unwrap();
+            assert_eq!(first, (output.0).0.get());
+        }
+        with_narrowed(&frame, |narrow| {
+            assert_eq!(first, narrow.as_ptr() as usize);
+            assert_eq!(Pixel { r: 0x12, g: 0, b: 0xff }, narrow[0]);
+        });
+    }
+
+    #[test]
+    fn check_constraints() {
+        let ms = time::Duration::from_millis;
+        let constraints = Constraints {
+            max_pixels: Some(100),
+            latch: Some(time::Duration::from_micros(50)),
+            bit_rate: Some(2_400_000),
+        };
+        // 9000 bytes take 30ms at 2.4MHz.
+        assert_eq!(Some(ms(30) + time::Duration::from_micros(50)), constraints.frame_time(9000));
+        assert!(constraints.check(100, 9000, Some(ms(40))).is_empty());
+        assert!(constraints.check(100, 9000, None).is_empty());
+        assert_eq!(vec!["sending 100 pixels takes 30.1ms, which allows at most 33.3 frames per second"],
+                   constraints.check(100, 9000, Some(ms(16))));
+        assert_eq!(vec!["the device can show at most 100 pixels, but the display has 101"],
+                   constraints.check(101, 9090, Some(ms(40))));
+        assert!(Constraints::default().check(1000, 9000, Some(ms(1))).is_empty());
+    }
+
+    #[test]
+    fn rate_display() {
+        assert_eq!("500kHz", Rate::Clock(500_000).to_string());
+        assert_eq!("2.4MHz", Rate::Clock(2_400_000).to_string());
+        assert_eq!("1152000 baud", Rate::Baud(1_152_000).to_string());
+    }
+}
@@ -53,6 +53,12 @@ impl Drop for LedMatrix {
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "HUB75 over GPIO",
+    drivers: &[],
+    rate: None,
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("rpi-led-matrix")
         .about("Hzeller's Raspberry Pi LED Matrix library")
@@ -207,6 +213,9 @@ pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<F
             options.led_rgb_sequence = s.as_ptr() as _;
         }
 
+        if gargs.check {
+            return Ok(FromCommand::Output(Box::new(Unopened(Correction::none))));
+        }
         let led_matrix = led_matrix_create_from_options(&options, &0, ptr::null());
         if led_matrix.is_null() {
             return Err(io::Error::new(io::ErrorKind::Other, "could not initialize LED Matrix driver"));
@@ -53,6 +53,12 @@ impl Output for AnsiDisplay {
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "terminal",
+    drivers: &[],
+    rate: None,
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("show")
         .about("Visualize 2D frames using a text based display")
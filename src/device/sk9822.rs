@@ -24,16 +24,45 @@ impl Device for Sk9822 {
         })
     }
 
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        Some(4)
+    }
+
+    fn constraints(&self) -> Constraints {
+        Constraints { bit_rate: Some(self.spidev_clock), ..Constraints::default() }
+    }
+
     fn write_frame(&self, writer: &mut io::Write, pixels: &[Pixel]) -> io::Result<()> {
-        writer.write_all(&[0x00; 4])?;
-        for pix in pixels {
-            writer.write_all(&[0b1110_0000 | self.grayscale, pix.b, pix.g, pix.r])?;
+        with_encode_buffer(|leds| {
+            for pix in pixels {
+                leds.extend_from_slice(&[0b1110_0000 | self.grayscale, pix.b, pix.g, pix.r]);
+            }
+            write_frame_vectored(writer, &[&[0x00; 4], leds, &[0xff; 4]])
+        })
+    }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        if data.len() < 8 || data[..4] != [0x00; 4] || data[data.len() - 4..] != [0xff; 4] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame has no start or end frame"));
         }
-        writer.write_all(&[0xff; 4])?;
-        Ok(())
+        if !data.len().is_multiple_of(4) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame ends in an incomplete pixel"));
+        }
+        data[4..data.len() - 4].chunks(4)
+            .map(|led| match *led {
+                [head, b, g, r] if head & 0b1110_0000 == 0b1110_0000 => Ok(Pixel { r, g, b }),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "a pixel has an invalid header")),
+            })
+            .collect()
     }
 }
 
+pub const INFO: Info = Info {
+    protocol: "SPI",
+    drivers: &["spidev"],
+    rate: Some(Rate::Clock(500_000)),
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("sk9822")
         .arg(clap::Arg::with_name("grayscale")
@@ -6,7 +6,13 @@ use color::*;
 use device::*;
 
 
-pub struct Ws2812 { }
+/// The time the data line has to be low for the pixels to show the frame that was sent.
+const RESET: time::Duration = time::Duration::from_micros(50);
+
+pub struct Ws2812 {
+    /// Set for RGBW strips such as the SK6812RGBW, which expect a fourth byte for white.
+    white: Option<white::White>,
+}
 
 impl Device for Ws2812 {
     fn color_correction(&self) -> Correction {
@@ -22,6 +28,19 @@ impl Device for Ws2812 {
         })
     }
 
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        // Each byte of a channel is encoded as three bytes.
+        Some(if self.white.is_some() { 4 * 3 } else { 3 * 3 })
+    }
+
+    fn constraints(&self) -> Constraints {
+        Constraints {
+            max_pixels: None,
+            latch: Some(RESET),
+            bit_rate: Some(2_400_000),
+        }
+    }
+
     fn write_frame(&self, writer: &mut io::Write, pixels: &[Pixel]) -> io::Result<()> {
         // 1.25 µs are required to transmit a single bit to the WS2812.
         // The value of a bit is determined by the duty cycle of a single period which
@@ -30,7 +49,13 @@ impl Device for Ws2812 {
         // A single period is transmitted as 3 SPI bits of which the second bit determines the
         // duty cycle.
         let buf: Vec<u8> = pixels.iter()
-            .flat_map(|pix| vec![ pix.g, pix.r, pix.b ])
+            .flat_map(|pix| match self.white {
+                Some(white) => {
+                    let [r, g, b, w] = white.split(pix);
+                    vec![ g, r, b, w ]
+                },
+                None => vec![ pix.g, pix.r, pix.b ],
+            })
             .flat_map(|b| {
                 let mut obits: u32 = 0;
                 for i in 0..8 {
@@ -45,15 +70,56 @@ impl Device for Ws2812 {
             })
             .collect();
         writer.write_all(&buf)?;
-        thread::sleep(time::Duration::new(0, 50_000)); // Sleep for 50µs to reset.
+        thread::sleep(RESET);
         Ok(())
     }
+
+    fn decode_frame(&self, data: &[u8]) -> io::Result<Vec<Pixel>> {
+        let channels = if self.white.is_some() { 4 } else { 3 };
+        if !data.len().is_multiple_of(channels * 3) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the frame ends in an incomplete pixel"));
+        }
+        let bytes = data.chunks(3)
+            .map(|encoded| {
+                let obits = encoded.iter().fold(0u32, |acc, &b| acc << 8 | u32::from(b));
+                let mut b = 0;
+                for i in 0..8 {
+                    let period = (obits >> (i * 3)) & 0b111;
+                    if period & 0b101 != 0b100 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "a bit is not a valid period"));
+                    }
+                    b |= (((period >> 1) & 1) as u8) << i;
+                }
+                Ok(b)
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+        let pixels = bytes.chunks(channels)
+            .map(|c| match self.white {
+                Some(white) => white.join([c[1], c[0], c[2], c[3]]),
+                None => Pixel { r: c[1], g: c[0], b: c[2] },
+            })
+            .collect();
+        Ok(pixels)
+    }
 }
 
+pub const INFO: Info = Info {
+    protocol: "one-wire, encoded as SPI",
+    drivers: &["spidev"],
+    rate: Some(Rate::Clock(2_400_000)),
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("ws2812")
+        .arg(clap::Arg::with_name("rgbw")
+            .long("rgbw")
+            .takes_value(true)
+            .validator(|v| v.parse::<white::White>().map(|_| ()))
+            .help("Drive RGBW strips such as the SK6812RGBW, deriving the white channel using one \
+                   of none, min, luma or calibrated:R,G,B"))
 }
 
-pub fn from_command(_: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
-    Ok(FromCommand::Device(Box::new(Ws2812 { })))
+pub fn from_command(args: &clap::ArgMatches, _: &GlobalArgs) -> io::Result<FromCommand> {
+    let white = args.value_of("rgbw").map(|v| v.parse().unwrap());
+    Ok(FromCommand::Device(Box::new(Ws2812 { white })))
 }
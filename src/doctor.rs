@@ -0,0 +1,383 @@
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path;
+use clap;
+use nix::libc;
+use nix::sys::termios;
+use device::{Info, Rate};
+use driver::{self, serial, spidev};
+
+
+/// A spidev buffer size that fits the frames of a few thousand pixels.
+const RECOMMENDED_BUFSIZ: usize = 65536;
+
+/// The number of bits that are sent on a serial line for each byte, including the start and stop
+/// bits.
+const BITS_PER_SERIAL_BYTE: u32 = 10;
+
+pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name("doctor")
+        .about("Inspect the output for common problems, such as missing permissions, a small SPI \
+                buffer or a slow USB serial adapter, and suggest how to fix them")
+        .arg(clap::Arg::with_name("device")
+            .long("device")
+            .takes_value(true)
+            .help("The device that is driven along with its options, e.g. \"ws2812 --rgbw min\", to \
+                   check whether its frames fit the output and to inspect the GPIO pins of devices \
+                   like hub75"))
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warning,
+    /// Prevents ledcat from working.
+    Problem,
+}
+
+/// Something that was found while inspecting the output, with a suggestion on how to fix it if
+/// it is not ok.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok<S: Into<String>>(message: S) -> Finding {
+        Finding { severity: Severity::Ok, message: message.into(), fix: None }
+    }
+
+    fn warning<S: Into<String>, F: Into<String>>(message: S, fix: F) -> Finding {
+        Finding { severity: Severity::Warning, message: message.into(), fix: Some(fix.into()) }
+    }
+
+    fn problem<S: Into<String>, F: Into<String>>(message: S, fix: F) -> Finding {
+        Finding { severity: Severity::Problem, message: message.into(), fix: Some(fix.into()) }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Problem => "problem",
+        };
+        write!(f, "{}: {}", label, self.message)?;
+        if let Some(ref fix) = self.fix {
+            write!(f, "\n  fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// What is inspected, as set by the global options.
+pub struct Target<'a> {
+    pub output: &'a str,
+    pub driver: Option<&'a str>,
+    pub baudrate: u32,
+    /// The name and protocol of the device, if set with --device.
+    pub device: Option<(&'a str, Info)>,
+    /// The number of bytes the device sends per pixel with the options set with --device.
+    pub bytes_per_pixel: Option<usize>,
+    /// The number of pixels of the display, if the geometry is set.
+    pub num_pixels: Option<usize>,
+}
+
+impl<'a> Target<'a> {
+    /// The minimal number of bytes in a frame, not counting the start and end of a frame.
+    fn frame_size(&self) -> Option<usize> {
+        Some(self.num_pixels? * self.bytes_per_pixel?)
+    }
+}
+
+/// Inspects the output, driver and device.
+pub fn examine(target: &Target) -> Vec<Finding> {
+    if let Some((name, info)) = target.device {
+        if name == "hub75" {
+            return gpio(path::Path::new("/sys/class/gpio"), path::Path::new("/etc/group"));
+        }
+        if info.drivers.is_empty() {
+            return vec![Finding::ok(format!("the {} device does its own IO, there is no output to inspect", name))];
+        }
+    }
+    if target.output == "-" {
+        return vec![Finding::ok("frames are written to stdout")];
+    }
+
+    let mut findings = Vec::new();
+    let port = match target.output.parse::<serial::Port>() {
+        Ok(port) => port,
+        Err(err) => return vec![Finding::problem(err, "set --output to a path or usb:VID:PID")],
+    };
+    let path = match port.resolve() {
+        Ok(path) => path,
+        Err(err) => {
+            return vec![Finding::problem(format!("{} was not found: {}", port, err),
+                                         "check that the adapter is plugged in with lsusb")];
+        },
+    };
+    let meta = match fs::metadata(&path) {
+        Ok(meta) => meta,
+        Err(err) => {
+            let fix = if spidev::is_spidev(&path) {
+                "enable SPI, on a Raspberry Pi with dtparam=spi=on in /boot/config.txt"
+            } else if path.to_string_lossy().contains("tty") {
+                "check that the adapter is plugged in and that its driver has been loaded with dmesg"
+            } else {
+                "check --output"
+            };
+            return vec![Finding::problem(format!("{} can not be used: {}", path.display(), err), fix)];
+        },
+    };
+    findings.push(Finding::ok(format!("{} exists", path.display())));
+    findings.extend(permission(&path, meta.gid(), path::Path::new("/etc/group")));
+
+    let driver = target.driver.map(String::from)
+        .or_else(|| driver::detect(&path));
+    match driver.as_deref() {
+        Some("spidev") => {
+            findings.push(Finding::ok("using the spidev driver"));
+            findings.extend(spi(&path, target));
+        },
+        Some("serial") => {
+            findings.push(Finding::ok("using the serial driver"));
+            findings.extend(serial(&path, target, path::Path::new("/sys/class/tty")));
+        },
+        Some(driver) => findings.push(Finding::ok(format!("using the {} driver", driver))),
+        None => {
+            findings.push(Finding::warning(format!("no driver was detected for {}", path.display()),
+                                           "set --driver to spidev or serial if it is a SPI or serial device, or to none"));
+        },
+    }
+    findings
+}
+
+/// Checks whether the file can be written to, and suggests joining the group that owns it if it
+/// can not.
+fn permission(path: &path::Path, gid: u32, group_file: &path::Path) -> Option<Finding> {
+    let cpath = ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    if unsafe { libc::access(cpath.as_ptr(), libc::W_OK) } == 0 {
+        return Some(Finding::ok(format!("{} is writable", path.display())));
+    }
+    let err = io::Error::last_os_error();
+    let fix = match group_name(group_file, gid) {
+        Some(ref group) if gid != 0 => {
+            format!("add the user to the {} group with `sudo usermod -aG {} $USER` and log in again", group, group)
+        },
+        _ => format!("run ledcat as a user that may write to {}", path.display()),
+    };
+    Some(Finding::problem(format!("{} is not writable: {}", path.display(), err), fix))
+}
+
+/// Looks up the name of a group in a file in the format of /etc/group.
+fn group_name(group_file: &path::Path, gid: u32) -> Option<String> {
+    let groups = fs::read_to_string(group_file).ok()?;
+    groups.lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[2].parse() == Ok(gid))
+        .map(|fields| fields[0].to_string())
+}
+
+fn spi(path: &path::Path, target: &Target) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if let Ok(file) = fs::File::open(path) {
+        match spidev::max_speed_hz(&file) {
+            Ok(hz) => findings.push(Finding::ok(format!("the maximum clock of {} is {}", path.display(), Rate::Clock(hz)))),
+            Err(err) => {
+                let err: io::Error = err.into();
+                findings.push(Finding::warning(format!("the maximum clock could not be read: {}", err),
+                                               "check that the output is a SPI device"));
+            },
+        }
+    }
+    let bufsiz = match spidev::bufsiz() {
+        Ok(bufsiz) => bufsiz,
+        Err(err) => {
+            findings.push(Finding::warning(format!("the spidev buffer size could not be read: {}", err),
+                                           "check that the spidev kernel module is loaded"));
+            return findings;
+        },
+    };
+    findings.push(spi_buffer(bufsiz, target.frame_size()));
+    findings
+}
+
+/// Checks whether frames fit in the buffer of spidev.
+fn spi_buffer(bufsiz: usize, frame_size: Option<usize>) -> Finding {
+    let fix = format!("raise the buffer size by adding spidev.bufsiz={} to the kernel command line, \
+                       on a Raspberry Pi in /boot/cmdline.txt", RECOMMENDED_BUFSIZ);
+    match frame_size {
+        Some(size) if size > bufsiz => {
+            Finding::problem(format!("frames of at least {} bytes do not fit the spidev buffer of {} bytes", size, bufsiz), fix)
+        },
+        Some(size) => Finding::ok(format!("frames of {} bytes fit the spidev buffer of {} bytes", size, bufsiz)),
+        None if bufsiz < RECOMMENDED_BUFSIZ => {
+            Finding::warning(format!("the spidev buffer of {} bytes limits the size of frames", bufsiz), fix)
+        },
+        None => Finding::ok(format!("the spidev buffer is {} bytes", bufsiz)),
+    }
+}
+
+fn serial(path: &path::Path, target: &Target, tty_class: &path::Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // Without O_NONBLOCK, opening a serial port may wait for the carrier detect line.
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    if let Ok(file) = file {
+        if termios::tcgetattr(file.as_raw_fd()).is_err() {
+            findings.push(Finding::problem(format!("{} is not a serial port", path.display()),
+                                           "set --driver none if frames should be written as is"));
+            return findings;
+        }
+    }
+    let name = fs::canonicalize(path).ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
+    if let Some(name) = name {
+        findings.extend(usb_serial(tty_class, &name));
+    }
+    let mut rate = format!("the baud rate is {}", target.baudrate);
+    if let Some(size) = target.frame_size() {
+        let secs = (size as f64) * f64::from(BITS_PER_SERIAL_BYTE) / f64::from(target.baudrate);
+        rate += &format!(", sending a frame takes at least {:.1}ms", secs * 1000.0);
+    }
+    findings.push(Finding::ok(rate));
+    findings
+}
+
+/// Reports the driver of a USB serial adapter and whether it holds back data.
+fn usb_serial(tty_class: &path::Path, name: &str) -> Vec<Finding> {
+    let device = tty_class.join(name).join("device");
+    let link_name = |link: &str| {
+        fs::read_link(device.join(link)).ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+    };
+    // Other ports, such as the UART of the board, are not USB adapters.
+    match link_name("subsystem") {
+        Some(ref subsystem) if subsystem == "usb-serial" || subsystem == "usb" => (),
+        _ => return Vec::new(),
+    }
+    let driver = link_name("driver").unwrap_or_else(|| "unknown".to_string());
+    let mut findings = vec![Finding::ok(format!("{} uses the {} driver", name, driver))];
+    let latency_timer = device.join("latency_timer");
+    let timer = fs::read_to_string(&latency_timer).ok()
+        .and_then(|t| t.trim().parse::<u32>().ok());
+    match timer {
+        Some(ms) if ms > 1 => {
+            findings.push(Finding::warning(
+                format!("the adapter holds back data for up to {}ms before sending it", ms),
+                format!("lower its latency timer with `echo 1 | sudo tee {}`", latency_timer.display())));
+        },
+        Some(ms) => findings.push(Finding::ok(format!("the latency timer of the adapter is {}ms", ms))),
+        None => (),
+    }
+    findings
+}
+
+/// Lists the GPIO chips and checks whether pins can be exported through sysfs.
+fn gpio(gpio_class: &path::Path, group_file: &path::Path) -> Vec<Finding> {
+    let entries = match fs::read_dir(gpio_class) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return vec![Finding::problem(format!("the sysfs GPIO interface is not available: {}", err),
+                                         "use a kernel that is built with CONFIG_GPIO_SYSFS")];
+        },
+    };
+    let mut chips: Vec<path::PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("gpiochip"))
+        .map(|entry| entry.path())
+        .collect();
+    chips.sort();
+    let mut findings = Vec::new();
+    if chips.is_empty() {
+        findings.push(Finding::problem("no GPIO chips were found", "check that the GPIO driver of the board is loaded"));
+    }
+    for chip in chips {
+        let read = |name: &str| fs::read_to_string(chip.join(name)).map(|s| s.trim().to_string()).ok();
+        let first = read("base").and_then(|b| b.parse::<u32>().ok());
+        let count = read("ngpio").and_then(|n| n.parse::<u32>().ok());
+        let name = chip.file_name().unwrap().to_string_lossy().into_owned();
+        let label = read("label").unwrap_or_else(|| "unknown".to_string());
+        match (first, count) {
+            (Some(first), Some(count)) if count > 0 => {
+                findings.push(Finding::ok(format!("{} ({}) has lines {} to {}", name, label, first, first + count - 1)));
+            },
+            _ => findings.push(Finding::ok(format!("{} ({}) has no lines", name, label))),
+        }
+    }
+    let export = gpio_class.join("export");
+    if let Ok(meta) = fs::metadata(&export) {
+        findings.extend(permission(&export, meta.gid(), group_file));
+    }
+    findings
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use std::os::unix;
+    use super::*;
+
+    #[test]
+    fn doctor_group_name() {
+        let dir = tempdir::TempDir::new("ledcat").unwrap();
+        let groups = dir.path().join("group");
+        fs::write(&groups, "root:x:0:\nspi:x:999:pi\ndialout:x:20:pi,alice\n").unwrap();
+        assert_eq!(Some("dialout".to_string()), group_name(&groups, 20));
+        assert_eq!(Some("spi".to_string()), group_name(&groups, 999));
+        assert_eq!(None, group_name(&groups, 1000));
+    }
+
+    #[test]
+    fn doctor_spi_buffer() {
+        assert_eq!(Severity::Problem, spi_buffer(4096, Some(300 * 4 * 4)).severity);
+        assert_eq!(Severity::Ok, spi_buffer(4096, Some(300 * 4)).severity);
+        assert_eq!(Severity::Warning, spi_buffer(4096, None).severity);
+        assert_eq!(Severity::Ok, spi_buffer(RECOMMENDED_BUFSIZ, None).severity);
+    }
+
+    #[test]
+    fn doctor_usb_serial() {
+        let dir = tempdir::TempDir::new("ledcat").unwrap();
+        let device = dir.path().join("ttyUSB0").join("device");
+        fs::create_dir_all(&device).unwrap();
+        unix::fs::symlink("/sys/bus/usb-serial", device.join("subsystem")).unwrap();
+        unix::fs::symlink("/sys/bus/usb-serial/drivers/ftdi_sio", device.join("driver")).unwrap();
+        fs::write(device.join("latency_timer"), "16\n").unwrap();
+        let findings = usb_serial(dir.path(), "ttyUSB0");
+        assert_eq!("ttyUSB0 uses the ftdi_sio driver", findings[0].message);
+        assert_eq!(Severity::Warning, findings[1].severity);
+        assert!(findings[1].fix.as_ref().unwrap().contains("ttyUSB0/device/latency_timer"));
+        // The UART of a board is not a USB adapter.
+        let device = dir.path().join("ttyAMA0").join("device");
+        fs::create_dir_all(&device).unwrap();
+        unix::fs::symlink("/sys/bus/amba", device.join("subsystem")).unwrap();
+        assert!(usb_serial(dir.path(), "ttyAMA0").is_empty());
+    }
+
+    #[test]
+    fn doctor_gpio() {
+        let dir = tempdir::TempDir::new("ledcat").unwrap();
+        let chip = dir.path().join("gpiochip0");
+        fs::create_dir(&chip).unwrap();
+        fs::write(chip.join("base"), "0\n").unwrap();
+        fs::write(chip.join("ngpio"), "54\n").unwrap();
+        fs::write(chip.join("label"), "pinctrl-bcm2835\n").unwrap();
+        let findings = gpio(dir.path(), path::Path::new("/nonexistent"));
+        assert_eq!(vec![Finding::ok("gpiochip0 (pinctrl-bcm2835) has lines 0 to 53")], findings);
+        assert_eq!(Severity::Problem, gpio(&dir.path().join("missing"), path::Path::new("/nonexistent"))[0].severity);
+    }
+}
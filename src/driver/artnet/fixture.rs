@@ -0,0 +1,122 @@
+use std::io;
+use std::str;
+use ::color::*;
+use ::color::white::White;
+use ::device::Device;
+
+
+/// The function of a DMX channel of a pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    /// The part of the color that all of red, green and blue have in common.
+    White,
+    /// A master dimmer, which is kept at full brightness.
+    Dimmer,
+    /// A channel that is not used and always set to 0.
+    Unused,
+}
+
+
+/// The channels that make up a pixel, in the order in which they are addressed, such as `rgb`
+/// or `drgbw`. Writes each pixel of a frame as the channels of the layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelLayout {
+    channels: Vec<Channel>,
+}
+
+impl ChannelLayout {
+    /// The number of channels of each pixel.
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn expand(&self, pix: &Pixel, out: &mut Vec<u8>) {
+        let has_white = self.channels.contains(&Channel::White);
+        let [r, g, b, w] = if has_white {
+            White::Min.split(pix)
+        } else {
+            [pix.r, pix.g, pix.b, 0]
+        };
+        out.extend(self.channels.iter().map(|ch| match *ch {
+            Channel::Red => r,
+            Channel::Green => g,
+            Channel::Blue => b,
+            Channel::White => w,
+            Channel::Dimmer => 0xff,
+            Channel::Unused => 0,
+        }));
+    }
+}
+
+impl Default for ChannelLayout {
+    fn default() -> ChannelLayout {
+        ChannelLayout { channels: vec![Channel::Red, Channel::Green, Channel::Blue] }
+    }
+}
+
+impl str::FromStr for ChannelLayout {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let channels = s.chars()
+            .map(|c| match c.to_ascii_lowercase() {
+                'r' => Ok(Channel::Red),
+                'g' => Ok(Channel::Green),
+                'b' => Ok(Channel::Blue),
+                'w' => Ok(Channel::White),
+                'd' => Ok(Channel::Dimmer),
+                'x' => Ok(Channel::Unused),
+                c => Err(format!("unknown channel \"{}\", expected one of r, g, b, w, d or x", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if channels.is_empty() {
+            return Err("the channel layout is empty".to_string());
+        }
+        Ok(ChannelLayout { channels })
+    }
+}
+
+impl Device for ChannelLayout {
+    fn color_correction(&self) -> Correction {
+        Correction::none()
+    }
+
+    fn write_frame(&self, writer: &mut dyn io::Write, pixels: &[Pixel]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(pixels.len() * self.len());
+        for pix in pixels {
+            self.expand(pix, &mut buf);
+        }
+        writer.write_all(&buf)
+    }
+
+    fn bytes_per_pixel(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_layout_parse() {
+        assert_eq!(Ok(ChannelLayout::default()), "rgb".parse());
+        assert_eq!(5, "DRGBW".parse::<ChannelLayout>().unwrap().len());
+        assert!("".parse::<ChannelLayout>().is_err());
+        assert!("rgbq".parse::<ChannelLayout>().is_err());
+    }
+
+    #[test]
+    fn channel_layout_write() {
+        let pixels = [Pixel { r: 255, g: 100, b: 50 }, Pixel { r: 1, g: 2, b: 3 }];
+        let mut buf = Vec::new();
+        "drgbx".parse::<ChannelLayout>().unwrap().write_frame(&mut buf, &pixels).unwrap();
+        assert_eq!(vec![255, 255, 100, 50, 0, 255, 1, 2, 3, 0], buf);
+        buf.clear();
+        "rgbw".parse::<ChannelLayout>().unwrap().write_frame(&mut buf, &pixels).unwrap();
+        assert_eq!(vec![205, 50, 0, 50, 0, 1, 2, 1], buf);
+    }
+}
@@ -6,18 +6,55 @@ use std::sync;
 use std::thread;
 use std::time;
 use clap;
+use regex::Regex;
 use ::device::*;
 
-mod unicast;
-mod target;
+mod fixture;
+mod rdm;
+pub mod unicast;
+pub mod target;
+use self::fixture::ChannelLayout;
 use self::unicast::*;
 use self::target::*;
 
 
+pub const INFO: Info = Info {
+    protocol: "network, Art-Net",
+    drivers: &[],
+    rate: None,
+};
+
 pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
     clap::SubCommand::with_name("artnet")
         .about("Control artnet DMX nodes via unicast and broadcast")
-        .arg(clap::Arg::with_name("target")
+        .args(&output_args())
+        .arg(clap::Arg::with_name("discover")
+            .short("d")
+            .long("discover")
+            .conflicts_with_all(&["target", "target-list", "broadcast", "target-discover"])
+            .help("Discover artnet nodes"))
+        .arg(clap::Arg::with_name("rdm-discover")
+            .long("rdm-discover")
+            .conflicts_with_all(&["target", "target-list", "broadcast", "target-discover", "discover"])
+            .help("List the RDM responders connected to the outputs of the nodes in the network, \
+                   along with their device info"))
+        .arg(clap::Arg::with_name("json")
+            .long("json")
+            .requires("discover")
+            .help("Print each discovered node as a JSON object on a separate line"))
+        .arg(clap::Arg::with_name("blackout-on-exit")
+            .long("blackout-on-exit")
+            .help("Set all channels of the targets to 0 when the input ends"))
+        .subcommand(clap::SubCommand::with_name("blackout")
+            .about("Set all channels of the targets to 0 and exit, without reading any input")
+            .args(&output_args()))
+}
+
+/// The arguments that select the targets and describe how frames are sent to them, shared by the
+/// output and the blackout subcommand.
+fn output_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    vec![
+        clap::Arg::with_name("target")
             .short("t")
             .long("target")
             .takes_value(true)
@@ -27,55 +64,235 @@ pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
                 Ok(_) => Ok(()),
                 Err(err) => Err(format!("{} ({})", err, addr)),
             })
-            .conflicts_with_all(&["discover", "target-list", "broadcast"])
-            .help("One or more target IP addresses"))
-        .arg(clap::Arg::with_name("target-list")
+            .conflicts_with_all(&["discover", "target-list", "broadcast", "target-discover"])
+            .help("One or more target IP addresses"),
+        clap::Arg::with_name("target-list")
             .long("target-list")
             .takes_value(true)
-            .conflicts_with_all(&["target", "discover", "broadcast"])
+            .conflicts_with_all(&["target", "discover", "broadcast", "target-discover"])
             .help("Specify a file containing 1 IP address per line to unicast to. \
-                   Changes to the file are read automatically"))
-        .arg(clap::Arg::with_name("broadcast")
+                   Changes to the file are read automatically"),
+        clap::Arg::with_name("broadcast")
             .short("b")
             .long("broadcast")
-            .conflicts_with_all(&["target", "target-list", "discover"])
-            .help("Broadcast to all devices in the network"))
-        .arg(clap::Arg::with_name("discover")
-            .short("d")
-            .long("discover")
-            .conflicts_with_all(&["target", "target-list", "broadcast"])
-            .help("Discover artnet nodes"))
+            .conflicts_with_all(&["target", "target-list", "discover", "target-discover"])
+            .help("Broadcast to all devices in the network"),
+        clap::Arg::with_name("target-discover")
+            .long("target-discover")
+            .conflicts_with_all(&["target", "target-list", "broadcast", "discover"])
+            .help("Discover nodes in the background and unicast to all nodes that reply"),
+        clap::Arg::with_name("node-filter")
+            .long("node-filter")
+            .takes_value(true)
+            .requires("target-discover")
+            .validator(|s| Regex::new(&s).map(|_| ()).map_err(|err| err.to_string()))
+            .help("Only target discovered nodes of which the short or long name matches this \
+                   regular expression"),
+        clap::Arg::with_name("pixels-per-universe")
+            .long("pixels-per-universe")
+            .takes_value(true)
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .help("The number of pixels sent in each universe, frames with more pixels are split \
+                   across consecutive universes. Defaults to as many pixels as fit in a universe"),
+        clap::Arg::with_name("start-universe")
+            .long("start-universe")
+            .takes_value(true)
+            .validator(|s| match s.parse::<u16>() {
+                Ok(n) if n <= MAX_UNIVERSE => Ok(()),
+                _ => Err(format!("the universe must be between 0 and {}", MAX_UNIVERSE)),
+            })
+            .conflicts_with_all(&["net", "subnet", "universe"])
+            .help("The universe that receives the first pixels of each frame, as a port-address \
+                   that combines the net, subnet and universe. 0 by default"),
+        clap::Arg::with_name("net")
+            .long("net")
+            .takes_value(true)
+            .validator(|s| validate_address_part(&s, 127))
+            .help("The net of the first universe, 0 by default"),
+        clap::Arg::with_name("subnet")
+            .long("subnet")
+            .takes_value(true)
+            .validator(|s| validate_address_part(&s, 15))
+            .help("The subnet of the first universe, 0 by default"),
+        clap::Arg::with_name("universe")
+            .long("universe")
+            .takes_value(true)
+            .validator(|s| validate_address_part(&s, 15))
+            .help("The universe within the subnet that receives the first pixels of each frame, \
+                   0 by default"),
+        clap::Arg::with_name("dmx-start")
+            .long("dmx-start")
+            .takes_value(true)
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(n) if (1..=UNIVERSE_CHANNELS).contains(&n) => Ok(()),
+                _ => Err(format!("the start channel must be between 1 and {}", UNIVERSE_CHANNELS)),
+            })
+            .help("The channel of the first universe that receives the first pixel, so the pixels \
+                   can follow the channels of other fixtures. The channels before it are sent as 0"),
+        clap::Arg::with_name("channel-layout")
+            .long("channel-layout")
+            .takes_value(true)
+            .default_value("rgb")
+            .validator(|s| s.parse::<ChannelLayout>().map(|_| ()))
+            .help("The DMX channels of each pixel in order, using r, g and b for the colors, w for \
+                   a white channel, d for a dimmer, which is kept at full brightness, and x for \
+                   unused channels. E.g. rgbw or drgb"),
+        clap::Arg::with_name("artsync")
+            .long("artsync")
+            .help("Send an ArtSync packet after each frame, so nodes show all universes at once"),
+        clap::Arg::with_name("sequence")
+            .long("sequence")
+            .help("Number the packets of each universe, so nodes can drop packets that arrive out \
+                   of order. Some nodes do not handle this well, so it is disabled by default"),
+        clap::Arg::with_name("keep-alive")
+            .long("keep-alive")
+            .takes_value(true)
+            .default_value("1000")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("the keep-alive must be a number of milliseconds, got \"{}\"", s)),
+            })
+            .help("Send the last frame again after this number of milliseconds without new \
+                   frames, so nodes keep showing it. 0 disables this"),
+        clap::Arg::with_name("bind")
+            .long("bind")
+            .takes_value(true)
+            .default_value("0.0.0.0")
+            .validator(|addr| match net::Ipv4Addr::from_str(addr.as_str()) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(format!("{} ({})", err, addr)),
+            })
+            .help("The IPv4 address of the network interface to send from. Broadcasts are sent to \
+                   the broadcast address of the network of this interface"),
+        clap::Arg::with_name("broadcast-address")
+            .long("broadcast-address")
+            .takes_value(true)
+            .validator(|addr| match net::Ipv4Addr::from_str(addr.as_str()) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(format!("{} ({})", err, addr)),
+            })
+            .conflicts_with_all(&["target", "target-list"])
+            .help("The address to send broadcasts and discovery polls to, such as the directed \
+                   broadcast address of a subnet like 2.255.255.255. Implies --broadcast"),
+        clap::Arg::with_name("limit-rate")
+            .long("limit-rate")
+            .help("Send at most 44 frames per second, the maximum rate of the Art-Net spec"),
+    ]
 }
 
+/// How long to wait for nodes to reply to the polls when blacking out discovered targets.
+const BLACKOUT_DISCOVER_WAIT: time::Duration = time::Duration::from_secs(2);
+
 pub fn from_command(args: &clap::ArgMatches, gargs: &GlobalArgs) -> io::Result<FromCommand> {
+    let blackout_args = args.subcommand_matches("blackout");
+    let args = blackout_args.unwrap_or(args);
+    let bind_addr: net::Ipv4Addr = args.value_of("bind").unwrap().parse().unwrap();
+    let broadcast_addr = || match args.value_of("broadcast-address") {
+        Some(ip) => Ok(net::SocketAddr::new(ip.parse().unwrap(), PORT)),
+        None => unicast::broadcast_addr(bind_addr),
+    };
     if args.is_present("discover") {
-        if let Err(err) = artnet_discover() {
-            eprintln!("{}", err);
+        if let Err(err) = broadcast_addr().and_then(|broadcast| artnet_discover(bind_addr, broadcast, args.is_present("json"))) {
+            error!("{}", err);
+        }
+        return Ok(FromCommand::SubcommandHandled);
+    }
+    if args.is_present("rdm-discover") {
+        if let Err(err) = broadcast_addr().and_then(|broadcast| rdm_discover(bind_addr, broadcast)) {
+            error!("{}", err);
         }
         return Ok(FromCommand::SubcommandHandled);
     }
 
-    let dev = Box::new(generic::Generic {});
-    let artnet_target: Box<Target> = if args.is_present("broadcast") {
-        Box::new(Broadcast{})
+    let channel_layout: ChannelLayout = args.value_of("channel-layout").unwrap().parse().unwrap();
+    if gargs.check {
+        gargs.dimensions()?;
+        if !["broadcast", "broadcast-address", "target-list", "target-discover", "target"].iter().any(|&name| args.is_present(name)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Missing artnet target. Please set --target IP, --target-discover or --broadcast"));
+        }
+        return Ok(FromCommand::Output(Box::new((channel_layout, io::sink()))));
+    }
+    let start_universe = if ["net", "subnet", "universe"].iter().any(|&name| args.is_present(name)) {
+        let part = |name| args.value_of(name).map(|s| s.parse::<u16>().unwrap()).unwrap_or(0);
+        part("net") << 8 | part("subnet") << 4 | part("universe")
+    } else {
+        args.value_of("start-universe").map(|s| s.parse().unwrap()).unwrap_or(0)
+    };
+    let layout = Layout {
+        pixel_channels: channel_layout.len(),
+        pixels_per_universe: args.value_of("pixels-per-universe")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or(UNIVERSE_CHANNELS / channel_layout.len()),
+        start_universe,
+        offset: args.value_of("dmx-start").unwrap().parse::<usize>().unwrap() - 1,
+    };
+    let frame_size = gargs.dimensions()?.size() * channel_layout.len();
+    let socket = unicast::bind(bind_addr)?;
+    let artnet_target: Box<dyn Target> = if args.is_present("broadcast") || args.is_present("broadcast-address") {
+        Box::new(Broadcast { address: broadcast_addr()? })
     } else if let Some(list_path) = args.value_of("target-list") {
-        Box::new(ListFile::new(list_path))
+        Box::new(ListFile::new(list_path, gargs.dimensions()?.size(), layout.clone()))
+    } else if args.is_present("target-discover") {
+        let filter = args.value_of("node-filter").map(|re| Regex::new(re).unwrap());
+        Box::new(Discovered::new(unicast::discover(socket.try_clone()?, broadcast_addr()?), filter))
     } else if args.is_present("target") {
         let addresses: Vec<_> = args.values_of("target").unwrap().map(|addr| {
             net::SocketAddr::new(addr.parse().unwrap(), PORT)
         }).collect();
         Box::new(addresses)
     } else {
-        eprintln!("Missing artnet target. Please set --target IP or --broadcast");
+        error!("Missing artnet target. Please set --target IP, --target-discover or --broadcast");
         return Ok(FromCommand::SubcommandHandled);
     };
 
-    let output = Unicast::to(artnet_target, gargs.dimensions()?.size() * 3)?;
-    Ok(FromCommand::Output(Box::new((dev, output))))
+    let output = Unicast::to(socket, artnet_target, frame_size, layout)?
+        .with_sync(args.is_present("artsync"))
+        .with_sequence(args.is_present("sequence"))
+        .with_max_rate(if args.is_present("limit-rate") { Some(MAX_RATE) } else { None })
+        .with_keep_alive(match args.value_of("keep-alive").unwrap().parse().unwrap() {
+            0 => None,
+            ms => Some(time::Duration::from_millis(ms)),
+        })
+        .with_blackout_on_exit(args.is_present("blackout-on-exit"));
+    if blackout_args.is_some() {
+        let mut output = output.with_blackout_on_exit(false);
+        if args.is_present("target-discover") {
+            thread::sleep(BLACKOUT_DISCOVER_WAIT);
+        }
+        if let Err(err) = output.blackout() {
+            error!("{}", err);
+        }
+        return Ok(FromCommand::SubcommandHandled);
+    }
+    Ok(FromCommand::Output(Box::new((channel_layout, output))))
+}
+
+fn validate_address_part(s: &str, max: u16) -> Result<(), String> {
+    match s.parse::<u16>() {
+        Ok(n) if n <= max => Ok(()),
+        _ => Err(format!("must be between 0 and {}", max)),
+    }
+}
+
+fn rdm_discover(bind_addr: net::Ipv4Addr, broadcast: net::SocketAddr) -> io::Result<()> {
+    let socket = unicast::bind(bind_addr)?;
+    let responders = rdm::discover(&socket, broadcast)?;
+    if responders.is_empty() {
+        eprintln!("No RDM responders found");
+    }
+    for responder in responders {
+        let ip_str = format!("{}", responder.node); // Padding only works with strings. :(
+        match responder.info {
+            Some(info) => println!("{: <15} universe {: <5} {} -> {}", ip_str, responder.universe, responder.uid, info),
+            None => println!("{: <15} universe {: <5} {} -> no device info", ip_str, responder.universe, responder.uid),
+        }
+    }
+    Ok(())
 }
 
-fn artnet_discover() -> io::Result<()> {
-    let discovery_stream = unicast::discover();
+fn artnet_discover(bind_addr: net::Ipv4Addr, broadcast: net::SocketAddr, json: bool) -> io::Result<()> {
+    let discovery_stream = unicast::discover(unicast::bind(bind_addr)?, broadcast);
     let mut discovered: collections::HashSet<net::SocketAddr> = collections::HashSet::new();
 
     let (close_tx, close_rx) = sync::mpsc::sync_channel(0);
@@ -100,14 +317,27 @@ fn artnet_discover() -> io::Result<()> {
                 return Err(err);
             }
         };
-        if !discovered.contains(&node.0) {
-            let ip_str = format!("{}", node.0.ip()); // Padding only works with strings. :(
-            match node.1 {
-                Some(name) => eprintln!("\r{: <15} -> {}", ip_str, name),
-                None => eprintln!("\r{: <15}", ip_str),
-            };
+        if !discovered.contains(&node.address) {
+            if json {
+                // Printed to stdout, the spinner and everything else go to stderr.
+                eprint!("\r");
+                println!("{}", json!({
+                    "address": node.address.ip().to_string(),
+                    "short_name": node.short_name,
+                    "long_name": node.long_name,
+                    "firmware": node.firmware,
+                    "ports": node.num_ports,
+                    "universes": node.universes,
+                }));
+            } else {
+                let ip_str = format!("{}", node.address.ip()); // Padding only works with strings. :(
+                let universes: Vec<String> = node.universes.iter().map(|u| u.to_string()).collect();
+                eprintln!("\r{: <15} -> {} ({}), firmware: {}, ports: {}, universes: {}",
+                          ip_str, node.short_name, node.long_name, node.firmware,
+                          node.num_ports, universes.join(","));
+            }
         }
-        discovered.insert(node.0);
+        discovered.insert(node.address);
     }
     Ok(())
 }
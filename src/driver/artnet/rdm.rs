@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::net;
+use std::time;
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian, LittleEndian};
+use super::unicast::{self, Node};
+
+/// The UID that ledcat uses as the source of RDM requests, from the range of manufacturer IDs
+/// reserved for prototyping.
+const SOURCE_UID: Uid = Uid([0x7f, 0xf0, 0x00, 0x00, 0x00, 0x01]);
+
+/// The start code of RDM packets, which is left out of the packets sent over Art-Net.
+const START_CODE: u8 = 0xcc;
+const SUB_START_CODE: u8 = 0x01;
+
+const GET_COMMAND: u8 = 0x20;
+const GET_COMMAND_RESPONSE: u8 = 0x21;
+const RESPONSE_TYPE_ACK: u8 = 0x00;
+const PID_DEVICE_INFO: u16 = 0x0060;
+
+/// How long to wait for replies to polls and requests.
+const REPLY_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+
+/// The unique ID of an RDM responder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Uid(pub [u8; 6]);
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let u = &self.0;
+        write!(f, "{:02x}{:02x}:{:02x}{:02x}{:02x}{:02x}", u[0], u[1], u[2], u[3], u[4], u[5])
+    }
+}
+
+
+/// The response to a GET DEVICE_INFO request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub model: u16,
+    pub category: u16,
+    pub software_version: u32,
+    pub footprint: u16,
+    pub personality: u8,
+    pub personality_count: u8,
+    /// The DMX start address, 0xffff if the responder does not use DMX.
+    pub start_address: u16,
+    pub sub_devices: u16,
+    pub sensors: u8,
+}
+
+impl DeviceInfo {
+    fn parse(data: &[u8]) -> Option<DeviceInfo> {
+        if data.len() < 19 {
+            return None;
+        }
+        let mut rdr = io::Cursor::new(&data[2..]);
+        Some(DeviceInfo {
+            model: rdr.read_u16::<BigEndian>().ok()?,
+            category: rdr.read_u16::<BigEndian>().ok()?,
+            software_version: rdr.read_u32::<BigEndian>().ok()?,
+            footprint: rdr.read_u16::<BigEndian>().ok()?,
+            personality: rdr.read_u8().ok()?,
+            personality_count: rdr.read_u8().ok()?,
+            start_address: rdr.read_u16::<BigEndian>().ok()?,
+            sub_devices: rdr.read_u16::<BigEndian>().ok()?,
+            sensors: rdr.read_u8().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "model: {:#06x}, category: {:#06x}, software: {:#010x}, footprint: {}, personality: {}/{}, ",
+               self.model, self.category, self.software_version, self.footprint,
+               self.personality, self.personality_count)?;
+        if self.start_address == 0xffff {
+            write!(f, "start address: none")?;
+        } else {
+            write!(f, "start address: {}", self.start_address)?;
+        }
+        write!(f, ", sub-devices: {}, sensors: {}", self.sub_devices, self.sensors)
+    }
+}
+
+
+/// An RDM responder that is connected to a port of a node.
+#[derive(Clone, Debug)]
+pub struct Responder {
+    pub node: net::IpAddr,
+    pub universe: u16,
+    pub uid: Uid,
+    /// None if the responder did not answer the request for its device info.
+    pub info: Option<DeviceInfo>,
+}
+
+
+/// Finds the nodes in the network, asks them for the RDM responders connected to their outputs
+/// and requests the device info of each responder.
+pub fn discover(socket: &net::UdpSocket, broadcast: net::SocketAddr) -> io::Result<Vec<Responder>> {
+    socket.set_read_timeout(Some(time::Duration::from_millis(100)))?;
+
+    let mut packet = Vec::new();
+    unicast::art_poll_packet(&mut packet)?;
+    socket.send_to(&packet, broadcast)?;
+    let mut nodes: BTreeMap<net::IpAddr, Node> = BTreeMap::new();
+    receive(socket, |addr, packet| {
+        if let Some(node) = Node::from_poll_reply(addr, packet) {
+            nodes.insert(addr.ip(), node);
+        }
+    })?;
+
+    for node in nodes.values() {
+        for &universe in &node.universes {
+            let mut packet = Vec::new();
+            art_tod_request_packet(&mut packet, universe)?;
+            socket.send_to(&packet, net::SocketAddr::new(node.address.ip(), unicast::PORT))?;
+        }
+    }
+    let mut tables: BTreeMap<(net::IpAddr, u16, Uid), ()> = BTreeMap::new();
+    receive(socket, |addr, packet| {
+        if let Some((universe, uids)) = parse_art_tod_data(packet) {
+            for uid in uids {
+                tables.insert((addr.ip(), universe, uid), ());
+            }
+        }
+    })?;
+
+    let mut responders = Vec::new();
+    for (transaction, &(node, universe, uid)) in tables.keys().enumerate() {
+        let mut packet = Vec::new();
+        art_rdm_packet(&mut packet, universe, &rdm_get_request(uid, transaction as u8, PID_DEVICE_INFO)?)?;
+        socket.send_to(&packet, net::SocketAddr::new(node, unicast::PORT))?;
+        let mut info = None;
+        receive_until(socket, |addr, packet| {
+            if addr.ip() != node {
+                return false;
+            }
+            info = parse_art_rdm(packet)
+                .and_then(|rdm| parse_rdm_get_response(rdm, uid, PID_DEVICE_INFO))
+                .and_then(DeviceInfo::parse);
+            info.is_some()
+        })?;
+        responders.push(Responder { node, universe, uid, info });
+    }
+    Ok(responders)
+}
+
+/// Passes all packets received within the reply timeout to the handler.
+fn receive<F>(socket: &net::UdpSocket, mut handle: F) -> io::Result<()>
+    where F: FnMut(net::SocketAddr, &[u8]) {
+    receive_until(socket, |addr, packet| {
+        handle(addr, packet);
+        false
+    })
+}
+
+/// Passes received packets to the handler until it returns true or the reply timeout expires.
+fn receive_until<F>(socket: &net::UdpSocket, mut handle: F) -> io::Result<()>
+    where F: FnMut(net::SocketAddr, &[u8]) -> bool {
+    let start = time::Instant::now();
+    let mut buf = [0; 1024];
+    while start.elapsed() < REPLY_TIMEOUT {
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(rs) => rs,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        };
+        if handle(addr, &buf[..len]) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn art_tod_request_packet<W>(mut wr: W, universe: u16) -> io::Result<()>
+    where W: io::Write {
+    wr.write_all(b"Art-Net\0")?; // Artnet Header
+    wr.write_u16::<LittleEndian>(0x8000)?; // OpCode
+    wr.write_u8(0)?; // ProtVerHi
+    wr.write_u8(14)?; // ProtVerLo
+    wr.write_all(&[0; 9])?; // Filler and Spare
+    wr.write_u8((universe >> 8) as u8 & 0x7f)?; // Net
+    wr.write_u8(0)?; // Command, TodFull
+    wr.write_u8(1)?; // AdCount
+    wr.write_u8(universe as u8)?; // Address
+    wr.write_all(&[0; 31])?; // Unused addresses
+    Ok(())
+}
+
+/// Returns the port-address and the UIDs in an ArtTodData packet.
+fn parse_art_tod_data(packet: &[u8]) -> Option<(u16, Vec<Uid>)> {
+    if packet.len() < 28 || &packet[0..8] != b"Art-Net\0" || packet[8..10] != [0x00, 0x81] {
+        return None;
+    }
+    let universe = u16::from(packet[21] & 0x7f) << 8 | u16::from(packet[23]);
+    let count = usize::from(packet[27]);
+    let uids = packet[28..].chunks(6)
+        .take(count)
+        .filter(|chunk| chunk.len() == 6)
+        .map(|chunk| {
+            let mut uid = [0; 6];
+            uid.copy_from_slice(chunk);
+            Uid(uid)
+        })
+        .collect();
+    Some((universe, uids))
+}
+
+fn art_rdm_packet<W>(mut wr: W, universe: u16, rdm: &[u8]) -> io::Result<()>
+    where W: io::Write {
+    wr.write_all(b"Art-Net\0")?; // Artnet Header
+    wr.write_u16::<LittleEndian>(0x8300)?; // OpCode
+    wr.write_u8(0)?; // ProtVerHi
+    wr.write_u8(14)?; // ProtVerLo
+    wr.write_u8(1)?; // RdmVer
+    wr.write_all(&[0; 8])?; // Filler and Spare
+    wr.write_u8((universe >> 8) as u8 & 0x7f)?; // Net
+    wr.write_u8(0)?; // Command, ArProcess
+    wr.write_u8(universe as u8)?; // Address
+    wr.write_all(rdm)?; // RDM packet without the start code
+    Ok(())
+}
+
+/// Returns the RDM packet in an ArtRdm packet.
+fn parse_art_rdm(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < 24 || &packet[0..8] != b"Art-Net\0" || packet[8..10] != [0x00, 0x83] {
+        return None;
+    }
+    Some(&packet[24..])
+}
+
+/// Builds an RDM GET request for the parameter without the start code.
+fn rdm_get_request(dest: Uid, transaction: u8, pid: u16) -> io::Result<Vec<u8>> {
+    let mut p = Vec::new();
+    p.write_u8(SUB_START_CODE)?;
+    p.write_u8(24)?; // Message length
+    p.write_all(&dest.0)?;
+    p.write_all(&SOURCE_UID.0)?;
+    p.write_u8(transaction)?;
+    p.write_u8(1)?; // Port ID
+    p.write_u8(0)?; // Message count
+    p.write_u16::<BigEndian>(0)?; // Sub-device, the root device
+    p.write_u8(GET_COMMAND)?;
+    p.write_u16::<BigEndian>(pid)?;
+    p.write_u8(0)?; // Parameter data length
+    let checksum = checksum(&p);
+    p.write_u16::<BigEndian>(checksum)?;
+    Ok(p)
+}
+
+/// Returns the parameter data of an acknowledged GET response from the responder.
+fn parse_rdm_get_response(p: &[u8], source: Uid, pid: u16) -> Option<&[u8]> {
+    if p.len() < 25 || p[0] != SUB_START_CODE {
+        return None;
+    }
+    let len = usize::from(p[1]);
+    if len < 24 || p.len() < len + 1 || checksum(&p[..len - 1]) != io::Cursor::new(&p[len - 1..]).read_u16::<BigEndian>().ok()? {
+        return None;
+    }
+    if p[8..14] != source.0 || p[15] != RESPONSE_TYPE_ACK || p[19] != GET_COMMAND_RESPONSE {
+        return None;
+    }
+    if io::Cursor::new(&p[20..22]).read_u16::<BigEndian>().ok()? != pid {
+        return None;
+    }
+    let data = &p[23..len - 1];
+    if data.len() != usize::from(p[22]) {
+        return None;
+    }
+    Some(data)
+}
+
+/// The sum of all bytes of an RDM packet, including the start code.
+fn checksum(p: &[u8]) -> u16 {
+    p.iter().fold(u16::from(START_CODE), |sum, &b| sum.wrapping_add(u16::from(b)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: Uid = Uid([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+
+    #[test]
+    fn uid_display() {
+        assert_eq!("1234:56789abc", UID.to_string());
+    }
+
+    #[test]
+    fn get_request() {
+        let p = rdm_get_request(UID, 7, PID_DEVICE_INFO).unwrap();
+        assert_eq!(25, p.len());
+        assert_eq!([0x01, 24], p[..2]);
+        assert_eq!(UID.0, p[2..8]);
+        assert_eq!([7, 1, 0, 0, 0, GET_COMMAND, 0x00, 0x60, 0], p[14..23]);
+        let sum = p[..23].iter().map(|&b| u32::from(b)).sum::<u32>() + 0xcc;
+        assert_eq!([(sum >> 8) as u8, sum as u8], p[23..]);
+    }
+
+    fn device_info_response() -> Vec<u8> {
+        let data = [0x01, 0x00, 0x01, 0x02, 0x05, 0x08, 0x00, 0x00, 0x01, 0x04, 0x00, 0x04, 0x02, 0x03, 0x00, 0x11, 0x00, 0x00, 0x01];
+        let mut p = vec![SUB_START_CODE, 24 + data.len() as u8];
+        p.extend_from_slice(&SOURCE_UID.0);
+        p.extend_from_slice(&UID.0);
+        p.extend_from_slice(&[7, RESPONSE_TYPE_ACK, 0, 0, 0, GET_COMMAND_RESPONSE, 0x00, 0x60, data.len() as u8]);
+        p.extend_from_slice(&data);
+        let sum = checksum(&p);
+        p.extend_from_slice(&[(sum >> 8) as u8, sum as u8]);
+        p
+    }
+
+    #[test]
+    fn get_response() {
+        let p = device_info_response();
+        let info = parse_rdm_get_response(&p, UID, PID_DEVICE_INFO).and_then(DeviceInfo::parse);
+        assert_eq!(Some(DeviceInfo {
+            model: 0x0102,
+            category: 0x0508,
+            software_version: 0x104,
+            footprint: 4,
+            personality: 2,
+            personality_count: 3,
+            start_address: 17,
+            sub_devices: 0,
+            sensors: 1,
+        }), info);
+
+        assert_eq!(None, parse_rdm_get_response(&p, SOURCE_UID, PID_DEVICE_INFO));
+        assert_eq!(None, parse_rdm_get_response(&p, UID, 0x0082));
+        let mut corrupt = p.clone();
+        corrupt[30] ^= 1;
+        assert_eq!(None, parse_rdm_get_response(&corrupt, UID, PID_DEVICE_INFO));
+    }
+
+    #[test]
+    fn tod_data() {
+        let mut p = b"Art-Net\0\x00\x81\x00\x0e".to_vec();
+        p.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0, 1, 0x02, 0, 0x34, 0, 2, 0, 2]);
+        p.extend_from_slice(&UID.0);
+        p.extend_from_slice(&SOURCE_UID.0);
+        assert_eq!(Some((0x234, vec![UID, SOURCE_UID])), parse_art_tod_data(&p));
+        assert_eq!(None, parse_art_tod_data(&p[..20]));
+    }
+
+    #[test]
+    fn rdm_packet() {
+        let mut p = Vec::new();
+        art_rdm_packet(&mut p, 0x234, &[1, 2, 3]).unwrap();
+        assert_eq!(27, p.len());
+        assert_eq!([0x02, 0, 0x34, 1, 2, 3], p[21..]);
+        assert_eq!(Some(&[1, 2, 3][..]), parse_art_rdm(&[&b"Art-Net\0\x00\x83"[..], &p[10..]].concat()));
+    }
+}
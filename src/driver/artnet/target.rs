@@ -1,15 +1,99 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, BufRead};
 use std::net;
+use std::ops;
 use std::path;
-use std::sync::{Arc, RwLock};
+use std::str;
+use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
 use std::time;
+use regex::Regex;
+use super::unicast::{Layout, Node};
 
 
 pub trait Target: Send {
     fn addresses(&self) -> Cow<[net::SocketAddr]>;
+
+    /// Returns the nodes along with the part of each frame that is sent to them. By default,
+    /// every address receives all pixels.
+    fn routes(&self) -> Cow<'_, [Route]> {
+        let routes = self.addresses().iter()
+            .map(|&address| Route { address, pixels: None, universe: None })
+            .collect();
+        Cow::Owned(routes)
+    }
+}
+
+
+/// A node along with the pixels that are sent to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub address: net::SocketAddr,
+    /// The pixels of the frame that are sent to the node, all pixels if None.
+    pub pixels: Option<ops::Range<usize>>,
+    /// The universe that receives the first pixel, overriding the start universe of the output.
+    pub universe: Option<u16>,
+}
+
+/// Parses a line of a target list: an IP address, optionally followed by an inclusive range of
+/// pixels and the universe that receives the first pixel of the range, e.g.
+/// `10.0.0.2 170-339 1`.
+impl str::FromStr for Route {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let address = match fields.next() {
+            Some(addr) => addr.parse()
+                .or_else(|_| addr.parse().map(|ip| net::SocketAddr::new(ip, super::PORT)))
+                .map_err(|_| format!("invalid address: \"{}\"", addr))?,
+            None => return Err("missing address".to_string()),
+        };
+        let pixels = match fields.next() {
+            Some(range) => {
+                let mut bounds = range.splitn(2, '-').map(|v| v.parse::<usize>());
+                let (first, last) = match (bounds.next(), bounds.next()) {
+                    (Some(Ok(first)), Some(Ok(last))) if first <= last => (first, last),
+                    (Some(Ok(pixel)), None) => (pixel, pixel),
+                    _ => return Err(format!("invalid pixel range: \"{}\"", range)),
+                };
+                match last.checked_add(1) {
+                    Some(end) => Some(first..end),
+                    None => return Err(format!("invalid pixel range: \"{}\"", range)),
+                }
+            },
+            None => None,
+        };
+        let universe = match fields.next() {
+            Some(u) => match u.parse::<u16>() {
+                Ok(n) if n <= super::MAX_UNIVERSE => Some(n),
+                _ => return Err(format!("invalid universe: \"{}\"", u)),
+            },
+            None => None,
+        };
+        if let Some(extra) = fields.next() {
+            return Err(format!("unexpected \"{}\"", extra));
+        }
+        Ok(Route { address, pixels, universe })
+    }
+}
+
+
+impl Route {
+    /// Checks that the pixels of the route are part of a frame of `num_pixels` and fit in the
+    /// universes from the first universe of the route on.
+    fn check(&self, num_pixels: usize, layout: &Layout) -> Result<(), String> {
+        let pixels = self.pixels.clone().unwrap_or(0..num_pixels);
+        if pixels.end > num_pixels {
+            return Err(format!("pixels {}-{} are not all part of the frame of {} pixels", pixels.start, pixels.end - 1, num_pixels));
+        }
+        let layout = Layout {
+            start_universe: self.universe.unwrap_or(layout.start_universe),
+            ..layout.clone()
+        };
+        layout.check(pixels.len() * layout.pixel_channels).map_err(|err| err.to_string())
+    }
 }
 
 
@@ -20,25 +104,33 @@ impl Target for Vec<net::SocketAddr> {
 }
 
 
-pub struct Broadcast {}
+pub struct Broadcast {
+    pub address: net::SocketAddr,
+}
 
 impl Target for Broadcast {
     fn addresses(&self) -> Cow<[net::SocketAddr]> {
-        let ip = net::Ipv4Addr::new(255, 255, 255, 255);
-        let addrs = vec![net::SocketAddrV4::new(ip, super::PORT).into()];
-        Cow::Owned(addrs)
+        Cow::Owned(vec![self.address])
     }
 }
 
 
 pub struct ListFile {
-    cache: Arc<RwLock<Vec<net::SocketAddr>>>,
+    cache: Arc<RwLock<Vec<Route>>>,
 }
 
 impl ListFile {
-    pub fn new<T: Into<path::PathBuf>>(p: T) -> ListFile {
+    /// The file is read once before returning, so the first frame is sent to the listed nodes.
+    /// Lines that can not be parsed and routes that do not fit a frame of `num_pixels` divided
+    /// over universes as described by the layout are reported and left out.
+    pub fn new<T: Into<path::PathBuf>>(p: T, num_pixels: usize, layout: Layout) -> ListFile {
         let path = p.into();
-        let cache = Arc::new(RwLock::new(Vec::new()));
+        let mut prev_mod_time = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+        let routes = read_routes(&path, num_pixels, &layout).unwrap_or_else(|err| {
+            warn!("Could not read {}: {}", path.display(), err);
+            Vec::new()
+        });
+        let cache = Arc::new(RwLock::new(routes));
 
         let cache_weak = Arc::downgrade(&cache);
         thread::spawn(move || {
@@ -51,8 +143,9 @@ impl ListFile {
                 }}
             }
 
-            let mut prev_mod_time = None;
             loop {
+                thread::sleep(time::Duration::new(1, 0));
+
                 let meta = try_or_continue!(fs::metadata(&path));
                 let mod_time = try_or_continue!(meta.modified());
                 let reload = prev_mod_time != Some(mod_time);
@@ -63,26 +156,13 @@ impl ListFile {
                         Some(c) => c,
                         None => return,
                     };
-                    let mut v = cache.write().unwrap();
-                    v.clear();
-
-                    let file = try_or_continue!(fs::File::open(&path));
-                    let addrs = io::BufReader::new(file).lines()
-                        .filter_map(|rs| {
-                            rs.ok()
-                        })
-                        .filter_map(|line| {
-                            line.parse().ok()
-                                .or_else(|| {
-                                    line.parse().ok()
-                                        .map(|ip| net::SocketAddr::new(ip, super::PORT))
-                                })
-                        });
-                    v.extend(addrs);
-                    v.dedup();
+                    let routes = read_routes(&path, num_pixels, &layout).unwrap_or_else(|err| {
+                        warn!("Could not read {}: {}", path.display(), err);
+                        Vec::new()
+                    });
+                    debug!("Reloaded {} routes from {}", routes.len(), path.display());
+                    *cache.write().unwrap() = routes;
                 }
-
-                thread::sleep(time::Duration::new(1, 0));
             }
         });
 
@@ -90,8 +170,197 @@ impl ListFile {
     }
 }
 
+fn read_routes(path: &path::Path, num_pixels: usize, layout: &Layout) -> io::Result<Vec<Route>> {
+    let file = fs::File::open(path)?;
+    let mut routes = Vec::new();
+    for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match line.parse::<Route>().and_then(|route| route.check(num_pixels, layout).map(|_| route)) {
+            Ok(route) => routes.push(route),
+            Err(err) => warn!("{}:{}: {}, ignoring the line", path.display(), lineno + 1, err),
+        }
+    }
+    routes.dedup();
+    Ok(routes)
+}
+
 impl Target for ListFile {
     fn addresses(&self) -> Cow<[net::SocketAddr]> {
+        let mut addrs: Vec<net::SocketAddr> = self.cache.read().unwrap().iter()
+            .map(|route| route.address)
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+        Cow::Owned(addrs)
+    }
+
+    fn routes(&self) -> Cow<'_, [Route]> {
         Cow::Owned(self.cache.read().unwrap().clone())
     }
 }
+
+
+/// Nodes that have not replied to a poll for this long are no longer sent to.
+const NODE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Targets the nodes that reply to the polls of a discovery stream.
+pub struct Discovered {
+    last_seen: Arc<RwLock<HashMap<net::SocketAddr, time::Instant>>>,
+    timeout: time::Duration,
+}
+
+impl Discovered {
+    /// Only nodes of which the short or long name matches the filter are targeted.
+    pub fn new(discovery: mpsc::Receiver<io::Result<Node>>, filter: Option<Regex>) -> Discovered {
+        Discovered::with_timeout(discovery, filter, NODE_TIMEOUT)
+    }
+
+    fn with_timeout(discovery: mpsc::Receiver<io::Result<Node>>, filter: Option<Regex>, timeout: time::Duration) -> Discovered {
+        let last_seen = Arc::new(RwLock::new(HashMap::new()));
+
+        let last_seen_weak = Arc::downgrade(&last_seen);
+        thread::spawn(move || {
+            for result in discovery {
+                let node = match result {
+                    Ok(node) => node,
+                    Err(err) => {
+                        warn!("Art-Net discovery failed: {}", err);
+                        return;
+                    },
+                };
+                if let Some(ref re) = filter {
+                    if !re.is_match(&node.short_name) && !re.is_match(&node.long_name) {
+                        continue;
+                    }
+                }
+                let last_seen = match last_seen_weak.upgrade() {
+                    Some(l) => l,
+                    None => return,
+                };
+                let mut map = last_seen.write().unwrap();
+                let address = net::SocketAddr::new(node.address.ip(), super::PORT);
+                if map.insert(address, time::Instant::now()).is_none() {
+                    event!(::log::Level::Debug, "node_discovered", {"address": address.to_string(), "name": node.short_name},
+                           "Discovered Art-Net node {} ({})", address, node.short_name);
+                }
+                map.retain(|_, seen| seen.elapsed() < timeout);
+            }
+        });
+
+        Discovered { last_seen, timeout }
+    }
+}
+
+impl Target for Discovered {
+    fn addresses(&self) -> Cow<'_, [net::SocketAddr]> {
+        let addrs = self.last_seen.read().unwrap().iter()
+            .filter(|&(_, seen)| seen.elapsed() < self.timeout)
+            .map(|(&addr, _)| addr)
+            .collect();
+        Cow::Owned(addrs)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use std::io::Write;
+    use super::*;
+
+    #[test]
+    fn route_parse() {
+        let address: net::SocketAddr = "10.0.0.2:6454".parse().unwrap();
+        assert_eq!(Ok(Route { address, pixels: None, universe: None }), "10.0.0.2".parse());
+        assert_eq!(Ok(Route { address, pixels: None, universe: None }), "10.0.0.2:6454".parse());
+        assert_eq!(Ok(Route { address, pixels: Some(170..340), universe: None }), "10.0.0.2 170-339".parse());
+        assert_eq!(Ok(Route { address, pixels: Some(3..4), universe: Some(12) }), " 10.0.0.2\t3  12".parse());
+        assert!("10.0.0.2 339-170".parse::<Route>().is_err());
+        assert!("10.0.0.2 0-10 32768".parse::<Route>().is_err());
+        assert!("10.0.0.2 0-10 1 2".parse::<Route>().is_err());
+        assert!("foo".parse::<Route>().is_err());
+        assert!(format!("10.0.0.2 {}", usize::MAX).parse::<Route>().is_err());
+        assert!(format!("10.0.0.2 0-{}", usize::MAX).parse::<Route>().is_err());
+    }
+
+    #[test]
+    fn read_routes_skips_invalid_lines() {
+        let dir = tempdir::TempDir::new("ledcat-targets").unwrap();
+        let path = dir.path().join("nodes.txt");
+        let mut file = fs::File::create(&path).unwrap();
+        writeln!(file, "# comment").unwrap();
+        writeln!(file, "10.0.0.2 0-169").unwrap();
+        writeln!(file, "10.0.0.3 170-199 32767").unwrap();
+        writeln!(file, "10.0.0.4 10.20").unwrap();
+        writeln!(file, "10.0.0.5 190-209").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "10.0.0.6 0-199 32767").unwrap();
+        drop(file);
+
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 0 };
+        let routes = read_routes(&path, 200, &layout).unwrap();
+        let addresses: Vec<_> = routes.iter().map(|route| route.address.ip().to_string()).collect();
+        assert_eq!(vec!["10.0.0.2", "10.0.0.3"], addresses);
+    }
+
+    fn node(ip: &str, short_name: &str) -> Node {
+        Node {
+            address: net::SocketAddr::new(ip.parse().unwrap(), 1234),
+            short_name: short_name.to_string(),
+            long_name: format!("{} long name", short_name),
+            firmware: 0,
+            num_ports: 1,
+            universes: vec![0],
+        }
+    }
+
+    /// Waits for the discovery thread to target the address, since the nodes that were sent before
+    /// it are handled by then, and returns all targeted addresses.
+    fn wait_for_address(target: &Discovered, ip: &str) -> Vec<String> {
+        let start = time::Instant::now();
+        loop {
+            let mut addrs: Vec<_> = target.addresses().iter().map(|addr| addr.to_string()).collect();
+            addrs.sort();
+            if addrs.iter().any(|addr| addr.starts_with(&format!("{}:", ip))) {
+                return addrs;
+            }
+            assert!(start.elapsed() < time::Duration::from_secs(1), "{} was not discovered", ip);
+            thread::sleep(time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn discovered_filter() {
+        let (tx, rx) = mpsc::channel();
+        let target = Discovered::new(rx, Some(Regex::new("^stage").unwrap()));
+        tx.send(Ok(node("10.0.0.2", "stage left"))).unwrap();
+        tx.send(Ok(node("10.0.0.3", "bar"))).unwrap();
+        tx.send(Ok(node("10.0.0.4", "stage right"))).unwrap();
+        // Nodes are sent to at the Art-Net port, whichever port they replied from.
+        assert_eq!(vec!["10.0.0.2:6454", "10.0.0.4:6454"], wait_for_address(&target, "10.0.0.4"));
+        // The long name is matched as well.
+        tx.send(Ok(Node { long_name: "stage center".to_string(), ..node("10.0.0.5", "baz") })).unwrap();
+        assert_eq!(vec!["10.0.0.2:6454", "10.0.0.4:6454", "10.0.0.5:6454"], wait_for_address(&target, "10.0.0.5"));
+    }
+
+    #[test]
+    fn discovered_timeout() {
+        let timeout = time::Duration::from_millis(100);
+        let (tx, rx) = mpsc::channel();
+        let target = Discovered::with_timeout(rx, None, timeout);
+        tx.send(Ok(node("10.0.0.2", "a"))).unwrap();
+        assert_eq!(vec!["10.0.0.2:6454"], wait_for_address(&target, "10.0.0.2"));
+        thread::sleep(timeout);
+        assert!(target.addresses().is_empty());
+        // The node that has not replied for too long is dropped when another one replies.
+        tx.send(Ok(node("10.0.0.3", "b"))).unwrap();
+        assert_eq!(vec!["10.0.0.3:6454"], wait_for_address(&target, "10.0.0.3"));
+        assert_eq!(1, target.last_seen.read().unwrap().len());
+        // Nodes that reply again are targeted again.
+        tx.send(Ok(node("10.0.0.2", "a"))).unwrap();
+        assert_eq!(vec!["10.0.0.2:6454", "10.0.0.3:6454"], wait_for_address(&target, "10.0.0.2"));
+    }
+}
@@ -1,35 +1,187 @@
+use std::collections::HashMap;
 use std::io;
-use std::net::ToSocketAddrs;
+use std::mem;
 use std::net;
-use std::os::unix::io::FromRawFd;
-use std::str;
-use std::sync;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{self, Arc, Mutex};
 use std::thread;
 use std::time;
+use nix::ifaddrs;
+use nix::libc;
 use nix::sys::socket;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian, LittleEndian};
 use super::target::*;
 
 pub const PORT: u16 = 6454;
 
+/// The number of channels in a DMX universe.
+pub const UNIVERSE_CHANNELS: usize = 512;
+
+/// The highest port-address, made up of the net, subnet and universe.
+pub const MAX_UNIVERSE: u16 = 0x7fff;
+
+
+/// Describes how the pixels of a frame are divided over universes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    /// The number of channels used by each pixel.
+    pub pixel_channels: usize,
+    /// The number of pixels in each universe.
+    pub pixels_per_universe: usize,
+    /// The port-address of the universe that receives the first pixels.
+    pub start_universe: u16,
+    /// The number of channels in the first universe that come before the first pixel. Whole
+    /// pixels that do not fit in the remainder of the universe move to the next universe.
+    pub offset: usize,
+}
+
+impl Layout {
+    /// Splits a frame into the data for each universe, starting with the first universe. Pixels
+    /// are never split across universes.
+    pub fn split(&self, frame: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        let first_size = self.first_pixels() * self.pixel_channels;
+        let (first, rest) = frame.split_at(first_size.min(frame.len()));
+        let mut universes = vec![(self.start_universe, vec![0; self.offset])];
+        universes[0].1.extend_from_slice(first);
+        for (universe, data) in (self.start_universe + 1..).zip(rest.chunks(self.pixels_per_universe * self.pixel_channels)) {
+            universes.push((universe, data.to_vec()));
+        }
+        universes
+    }
+
+    /// The number of pixels that fit in the first universe after the offset.
+    fn first_pixels(&self) -> usize {
+        self.pixels_per_universe.min(UNIVERSE_CHANNELS.saturating_sub(self.offset) / self.pixel_channels)
+    }
+
+    pub(crate) fn check(&self, frame_size: usize) -> io::Result<()> {
+        if self.pixel_channels == 0 || self.pixels_per_universe * self.pixel_channels > UNIVERSE_CHANNELS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("at most {} pixels fit in a universe", UNIVERSE_CHANNELS / self.pixel_channels.max(1))));
+        }
+        if self.pixels_per_universe == 0 || self.first_pixels() == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no pixels fit in the first universe"));
+        }
+        let rest = frame_size.saturating_sub(self.first_pixels() * self.pixel_channels);
+        let num_universes = 1 + rest.div_ceil(self.pixels_per_universe * self.pixel_channels);
+        if usize::from(self.start_universe) + num_universes > usize::from(MAX_UNIVERSE) + 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("a frame requires {} universes, which exceeds the last universe", num_universes)));
+        }
+        Ok(())
+    }
+}
+
+
+/// The maximum rate at which nodes should be sent data according to the Art-Net spec.
+pub const MAX_RATE: u32 = 44;
+
 pub struct Unicast {
-    socket: net::UdpSocket,
-    target: Box<Target>,
     frame_size: usize,
     frame_buffer: Vec<u8>,
+    sender: Arc<Mutex<Sender>>,
+    blackout_on_exit: bool,
 }
 
 impl Unicast {
-    pub fn to(target: Box<Target>, frame_size: usize) -> io::Result<Unicast> {
-        let socket = reuse_bind(("0.0.0.0", PORT))?;
-        socket.set_broadcast(true)?;
+    /// Frames that do not fit in a single universe are split across consecutive universes as
+    /// described by the layout.
+    pub fn to(socket: net::UdpSocket, target: Box<dyn Target>, frame_size: usize, layout: Layout) -> io::Result<Unicast> {
+        layout.check(frame_size)?;
+        let sender = Sender {
+            socket,
+            target,
+            layout,
+            sync: false,
+            sequence: None,
+            min_interval: None,
+            last_frame: Vec::new(),
+            last_sent: None,
+        };
         Ok(Unicast {
-               socket,
-               target,
                frame_size,
                frame_buffer: Vec::with_capacity(frame_size),
+               sender: Arc::new(Mutex::new(sender)),
+               blackout_on_exit: false,
            })
     }
+
+    /// Sends an ArtSync packet after the ArtDmx packets of each frame, so nodes output the data
+    /// of all universes at the same time.
+    pub fn with_sync(self, sync: bool) -> Unicast {
+        self.sender.lock().unwrap().sync = sync;
+        self
+    }
+
+    /// Numbers the ArtDmx packets for each universe of each node, so nodes can drop packets that
+    /// arrive out of order. Without it, the sequence field of all packets is 0, which disables
+    /// the check.
+    pub fn with_sequence(self, enabled: bool) -> Unicast {
+        self.sender.lock().unwrap().sequence = if enabled { Some(HashMap::new()) } else { None };
+        self
+    }
+
+    /// Delays frames so they are sent at most at the specified rate.
+    pub fn with_max_rate(self, rate: Option<u32>) -> Unicast {
+        self.sender.lock().unwrap().min_interval = rate.map(|r| time::Duration::new(1, 0) / r);
+        self
+    }
+
+    /// Sends the last frame again when no new frame has been sent for the specified interval, so
+    /// nodes do not consider the data lost and switch to their fallback.
+    pub fn with_keep_alive(self, interval: Option<time::Duration>) -> Unicast {
+        let interval = match interval {
+            Some(i) => i,
+            None => return self,
+        };
+        let sender_weak = Arc::downgrade(&self.sender);
+        thread::spawn(move || loop {
+            let wait = {
+                let sender = match sender_weak.upgrade() {
+                    Some(s) => s,
+                    None => return,
+                };
+                let mut sender = sender.lock().unwrap();
+                match sender.last_sent.map(|t| t.elapsed()) {
+                    Some(elapsed) if elapsed >= interval => {
+                        if let Err(err) = sender.send() {
+                            warn!("Could not resend the last Art-Net frame: {}", err);
+                        }
+                        interval
+                    },
+                    Some(elapsed) => interval - elapsed,
+                    None => interval,
+                }
+            };
+            thread::sleep(wait);
+        });
+        self
+    }
+
+    /// Sends a frame with all channels set to 0 when the output is dropped, e.g. when the input
+    /// ends.
+    pub fn with_blackout_on_exit(mut self, enabled: bool) -> Unicast {
+        self.blackout_on_exit = enabled;
+        self
+    }
+
+    /// Immediately sends a frame with all channels set to 0 to all targets. The keep-alive
+    /// resends this frame until a new frame is written.
+    pub fn blackout(&mut self) -> io::Result<()> {
+        let mut sender = self.sender.lock().unwrap();
+        sender.last_frame = vec![0; self.frame_size];
+        sender.send()
+    }
+}
+
+impl Drop for Unicast {
+    fn drop(&mut self) {
+        if self.blackout_on_exit {
+            if let Err(err) = self.blackout() {
+                error!("Could not send the Art-Net blackout: {}", err);
+            }
+        }
+    }
 }
 
 impl io::Write for Unicast {
@@ -44,17 +196,149 @@ impl io::Write for Unicast {
             return Ok(());
         }
         let new_buf = self.frame_buffer.split_off(self.frame_size);
-        let mut packet = Vec::new();
-        art_dmx_packet(&mut packet, &self.frame_buffer)?;
-        self.frame_buffer = new_buf;
-        for addr in self.target.addresses().iter() {
-            self.socket.send_to(&packet, addr)?;
+        let frame = mem::replace(&mut self.frame_buffer, new_buf);
+        let wait = {
+            let sender = self.sender.lock().unwrap();
+            match (sender.min_interval, sender.last_sent) {
+                (Some(min), Some(t)) => min.checked_sub(t.elapsed()),
+                _ => None,
+            }
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
         }
+        let mut sender = self.sender.lock().unwrap();
+        sender.last_frame = frame;
+        sender.send()
+    }
+}
+
+/// The part of the output that is shared with the keep-alive thread.
+struct Sender {
+    socket: net::UdpSocket,
+    target: Box<dyn Target>,
+    layout: Layout,
+    sync: bool,
+    /// The sequence number of the last packet sent to each universe of each node.
+    sequence: Option<HashMap<(net::SocketAddr, u16), u8>>,
+    min_interval: Option<time::Duration>,
+    last_frame: Vec<u8>,
+    last_sent: Option<time::Instant>,
+}
+
+impl Sender {
+    fn send(&mut self) -> io::Result<()> {
+        // All packets of a frame are sent at once to reduce the number of system calls when
+        // sending to many nodes and universes.
+        let mut packets = Vec::new();
+        for route in self.target.routes().iter() {
+            let layout = Layout {
+                start_universe: route.universe.unwrap_or(self.layout.start_universe),
+                ..self.layout.clone()
+            };
+            let frame = match route.pixels {
+                Some(ref pixels) => {
+                    let len = self.last_frame.len();
+                    let start = (pixels.start * layout.pixel_channels).min(len);
+                    let end = (pixels.end * layout.pixel_channels).min(len);
+                    // The routes of a list file are checked when it is read, this only guards
+                    // against routes of other targets.
+                    if start == end || layout.check(end - start).is_err() {
+                        continue;
+                    }
+                    &self.last_frame[start..end]
+                },
+                None => &self.last_frame[..],
+            };
+            for (universe, data) in layout.split(frame) {
+                let mut packet = Vec::new();
+                let sequence = match self.sequence {
+                    Some(ref mut counters) => {
+                        let counter = counters.entry((route.address, universe)).or_insert(0);
+                        // 0 disables sequencing, so the counter wraps around to 1.
+                        *counter = if *counter == 0xff { 1 } else { *counter + 1 };
+                        *counter
+                    },
+                    None => 0,
+                };
+                art_dmx_packet(&mut packet, universe, sequence, &data)?;
+                trace!("ArtDmx to {}, universe {}, sequence {}, {} channels", route.address, universe, sequence, data.len());
+                packets.push((packet, route.address));
+            }
+        }
+        if self.sync {
+            let mut packet = Vec::new();
+            art_sync_packet(&mut packet)?;
+            for &addr in self.target.addresses().iter() {
+                trace!("ArtSync to {}", addr);
+                packets.push((packet.clone(), addr));
+            }
+        }
+        send_all(&self.socket, &packets)?;
+        self.last_sent = Some(time::Instant::now());
         Ok(())
     }
 }
 
-pub fn discover() -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<String>)>> {
+/// A node that answered an ArtPoll.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub address: net::SocketAddr,
+    pub short_name: String,
+    pub long_name: String,
+    /// The firmware revision of the node.
+    pub firmware: u16,
+    /// The number of ports of the node.
+    pub num_ports: u16,
+    /// The port-addresses of the universes that are output by the node.
+    pub universes: Vec<u16>,
+}
+
+impl Node {
+    /// Decodes an ArtPollReply packet. Returns None if the packet is not an ArtPollReply.
+    pub fn from_poll_reply(address: net::SocketAddr, packet: &[u8]) -> Option<Node> {
+        if packet.len() < 194 || &packet[0..8] != b"Art-Net\0" {
+            return None;
+        }
+        if io::Cursor::new(&packet[8..10]).read_u16::<LittleEndian>().ok()? != 0x2100 {
+            return None;
+        }
+        let string = |field: &[u8]| {
+            let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+        let (net, subnet) = (u16::from(packet[18] & 0x7f), u16::from(packet[19] & 0x0f));
+        let num_ports = io::Cursor::new(&packet[172..174]).read_u16::<BigEndian>().ok()?;
+        let universes = (0..num_ports.min(4) as usize)
+            // Bit 7 of the port type is set if the port can output DMX.
+            .filter(|&i| packet[174 + i] & 0x80 != 0)
+            .map(|i| net << 8 | subnet << 4 | u16::from(packet[190 + i] & 0x0f))
+            .collect();
+        Some(Node {
+            address,
+            short_name: string(&packet[26..44]),
+            long_name: string(&packet[44..108]),
+            firmware: io::Cursor::new(&packet[16..18]).read_u16::<BigEndian>().ok()?,
+            num_ports,
+            universes,
+        })
+    }
+}
+
+/// Binds a socket to the Art-Net port from which packets can be broadcast. Binding to the address
+/// of a network interface makes sure all packets are sent from that interface.
+pub fn bind(addr: net::Ipv4Addr) -> io::Result<net::UdpSocket> {
+    let socket = reuse_bind((addr, PORT))?;
+    socket.set_broadcast(true)?;
+    Ok(socket)
+}
+
+/// Polls for nodes in the background by broadcasting from the socket, which should be bound with
+/// `bind()`.
+///
+/// Nodes reply to the port the poll was sent from, so the socket should be shared with the
+/// output to the nodes instead of binding another socket to the same port.
+pub fn discover(socket: net::UdpSocket, broadcast: net::SocketAddr) -> sync::mpsc::Receiver<io::Result<Node>> {
     let (tx, rx) = sync::mpsc::channel();
 
     thread::spawn(move || {
@@ -70,30 +354,22 @@ pub fn discover() -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<St
             )
         }
 
-        let socket = try_or_send!(reuse_bind(("0.0.0.0", PORT)));
-        try_or_send!(socket.set_broadcast(true));
         try_or_send!(socket.set_read_timeout(Some(time::Duration::new(1, 0))));
 
         loop {
             // Send out an ArtPoll packet to elicit an ArtPollReply from all devices in the network.
             let mut buf = Vec::new();
             try_or_send!(art_poll_packet(&mut buf));
-            try_or_send!(socket.send_to(&buf, broadcast_addr()));
+            try_or_send!(socket.send_to(&buf, broadcast));
 
             loop {
-                let mut recv_buf = [0; 231];
-                let (_, sender_addr) = match socket.recv_from(&mut recv_buf) {
+                let mut recv_buf = [0; 512];
+                let (len, sender_addr) = match socket.recv_from(&mut recv_buf) {
                     Err(_) => break,
                     Ok(rs) => rs,
                 };
-                if &recv_buf[0..8] != b"Art-Net\0" {
-                    continue;
-                }
-                let mut rdr = io::Cursor::new(&recv_buf[8..10]);
-                let opcode = try_or_send!(rdr.read_u16::<LittleEndian>());
-                if opcode == 0x2100 {
-                    let short_name = str::from_utf8(&recv_buf[19..38]).map(String::from).ok();
-                    tx.send(Ok((sender_addr, short_name))).unwrap();
+                if let Some(node) = Node::from_poll_reply(sender_addr, &recv_buf[..len]) {
+                    tx.send(Ok(node)).unwrap();
                 }
             }
         }
@@ -102,15 +378,23 @@ pub fn discover() -> sync::mpsc::Receiver<io::Result<(net::SocketAddr, Option<St
     rx
 }
 
-pub fn broadcast_addr() -> net::SocketAddr {
-    ("255.255.255.255", PORT)
-        .to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
+/// Returns the broadcast address of the network interface with the specified address. If the
+/// address is unspecified, the broadcast address of the default interface is returned.
+pub fn broadcast_addr(local: net::Ipv4Addr) -> io::Result<net::SocketAddr> {
+    if local.is_unspecified() {
+        return Ok(net::SocketAddr::new(net::Ipv4Addr::new(255, 255, 255, 255).into(), PORT));
+    }
+    for ifaddr in io_err!(ifaddrs::getifaddrs())? {
+        if let (Some(socket::SockAddr::Inet(addr)), Some(socket::SockAddr::Inet(broadcast))) = (ifaddr.address, ifaddr.broadcast) {
+            if addr.to_std().ip() == net::IpAddr::V4(local) {
+                return Ok(net::SocketAddr::new(broadcast.to_std().ip(), PORT));
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("no network interface with address {} that supports broadcast", local)))
 }
 
-fn art_poll_packet<W>(mut wr: W) -> io::Result<()>
+pub fn art_poll_packet<W>(mut wr: W) -> io::Result<()>
     where W: io::Write {
     wr.write_all(b"Art-Net\0")?; // Artnet Header
     wr.write_u16::<LittleEndian>(0x2000)?; // OpCode
@@ -121,21 +405,86 @@ fn art_poll_packet<W>(mut wr: W) -> io::Result<()>
     Ok(())
 }
 
-fn art_dmx_packet<W>(mut wr: W, data: &[u8]) -> io::Result<()>
+fn art_dmx_packet<W>(mut wr: W, universe: u16, sequence: u8, data: &[u8]) -> io::Result<()>
     where W: io::Write {
-    if data.len() >= 0xffff {
+    if data.len() > UNIVERSE_CHANNELS {
         return Err(io::Error::new(io::ErrorKind::Other, "data exceeds max dmx packet length"));
     }
+    // The length of the data must be even and at least 2.
+    let padding = if data.len() < 2 { 2 - data.len() } else { data.len() % 2 };
     wr.write_all(b"Art-Net\0")?; // Artnet Header
     wr.write_u16::<LittleEndian>(0x5000)?; // OpCode
     wr.write_u8(4)?; // ProtVerHi
     wr.write_u8(14)?; // ProtVerLo
-    wr.write_u8(0)?; // Sequence
+    wr.write_u8(sequence)?; // Sequence
     wr.write_u8(0)?; // Physical
-    wr.write_u8(0)?; // SubUni
-    wr.write_u8(0)?; // Net
-    wr.write_u16::<BigEndian>(data.len() as u16)?; // Length
+    wr.write_u8(universe as u8)?; // SubUni
+    wr.write_u8((universe >> 8) as u8 & 0x7f)?; // Net
+    wr.write_u16::<BigEndian>((data.len() + padding) as u16)?; // Length
     wr.write_all(data)?; // Data
+    wr.write_all(&[0; 2][..padding])?;
+    Ok(())
+}
+
+fn art_sync_packet<W>(mut wr: W) -> io::Result<()>
+    where W: io::Write {
+    wr.write_all(b"Art-Net\0")?; // Artnet Header
+    wr.write_u16::<LittleEndian>(0x5200)?; // OpCode
+    wr.write_u8(0)?; // ProtVerHi
+    wr.write_u8(14)?; // ProtVerLo
+    wr.write_u8(0)?; // Aux1
+    wr.write_u8(0)?; // Aux2
+    Ok(())
+}
+
+/// Sends each packet to its address using as few system calls as possible.
+#[cfg(target_os = "linux")]
+fn send_all(socket: &net::UdpSocket, packets: &[(Vec<u8>, net::SocketAddr)]) -> io::Result<()> {
+    let addrs = packets.iter()
+        .map(|&(_, addr)| match addr {
+            net::SocketAddr::V4(addr) => {
+                let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                Ok(sin)
+            },
+            net::SocketAddr::V6(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Artnet does not support IPv6 :(")),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut iovecs: Vec<libc::iovec> = packets.iter()
+        .map(|(packet, _)| libc::iovec {
+            iov_base: packet.as_ptr() as *mut libc::c_void,
+            iov_len: packet.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = addrs.iter().zip(iovecs.iter_mut())
+        .map(|(addr, iov)| {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_name = addr as *const libc::sockaddr_in as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg
+        })
+        .collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        let rest = &mut msgs[sent..];
+        let n = unsafe { libc::sendmmsg(socket.as_raw_fd(), rest.as_mut_ptr(), rest.len() as libc::c_uint, 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        sent += n as usize;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_all(socket: &net::UdpSocket, packets: &[(Vec<u8>, net::SocketAddr)]) -> io::Result<()> {
+    for (packet, addr) in packets {
+        socket.send_to(packet, addr)?;
+    }
     Ok(())
 }
 
@@ -161,3 +510,202 @@ fn reuse_bind<A: net::ToSocketAddrs>(to_addr: A) -> io::Result<net::UdpSocket> {
 
     Ok(unsafe { net::UdpSocket::from_raw_fd(fd) })
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dmx_packet() {
+        let mut packet = Vec::new();
+        art_dmx_packet(&mut packet, 0x1234, 9, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(b"Art-Net\0\x00\x50\x04\x0e\x09\x00\x34\x12\x00\x04\x01\x02\x03\x04"[..], packet[..]);
+    }
+
+    #[test]
+    fn dmx_packet_padding() {
+        let mut packet = Vec::new();
+        art_dmx_packet(&mut packet, 0, 0, &[1, 2, 3]).unwrap();
+        assert_eq!([0, 4, 1, 2, 3, 0], packet[16..]);
+        packet.clear();
+        art_dmx_packet(&mut packet, 0, 0, &[]).unwrap();
+        assert_eq!([0, 2, 0, 0], packet[16..]);
+        assert!(art_dmx_packet(&mut packet, 0, 0, &[0; 513]).is_err());
+    }
+
+    #[test]
+    fn layout_split() {
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 2, start_universe: 7, offset: 0 };
+        assert_eq!(vec![(7, vec![1, 2, 3, 4, 5, 6]), (8, vec![7, 8, 9])],
+                   layout.split(&[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 2, start_universe: 7, offset: 2 };
+        assert_eq!(vec![(7, vec![0, 0, 1, 2, 3, 4, 5, 6]), (8, vec![7, 8, 9])],
+                   layout.split(&[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+    }
+
+    #[test]
+    fn layout_split_spillover() {
+        // Only 2 pixels fit after the offset, the third moves to the next universe.
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 505 };
+        let frame: Vec<u8> = (0..9).collect();
+        let universes = layout.split(&frame);
+        assert_eq!(2, universes.len());
+        assert_eq!(505 + 6, universes[0].1.len());
+        assert_eq!(&frame[..6], &universes[0].1[505..]);
+        assert_eq!((1, frame[6..].to_vec()), universes[1]);
+    }
+
+    #[test]
+    fn layout_check() {
+        assert!(Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 509 }.check(3).is_ok());
+        assert!(Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 510 }.check(3).is_err());
+        assert!(Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: MAX_UNIVERSE, offset: 0 }.check(510).is_ok());
+        assert!(Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: MAX_UNIVERSE, offset: 0 }.check(513).is_err());
+        assert!(Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: MAX_UNIVERSE - 1, offset: 3 }.check(510).is_ok());
+        assert!(Layout { pixel_channels: 4, pixels_per_universe: 128, start_universe: 0, offset: 0 }.check(512).is_ok());
+        assert!(Layout { pixel_channels: 4, pixels_per_universe: 129, start_universe: 0, offset: 0 }.check(512).is_err());
+    }
+
+    #[test]
+    fn poll_reply() {
+        let mut packet = vec![0; 239];
+        packet[0..8].copy_from_slice(b"Art-Net\0");
+        packet[8..10].copy_from_slice(&[0x00, 0x21]);
+        packet[16..18].copy_from_slice(&[0x01, 0x02]);
+        packet[18] = 1; // NetSwitch
+        packet[19] = 2; // SubSwitch
+        packet[26..30].copy_from_slice(b"node");
+        packet[44..53].copy_from_slice(b"Long name");
+        packet[173] = 3; // NumPorts
+        packet[174..177].copy_from_slice(&[0x80, 0x40, 0x85]);
+        packet[190..193].copy_from_slice(&[4, 5, 6]);
+        let address = "10.0.0.1:6454".parse().unwrap();
+        assert_eq!(Some(Node {
+            address,
+            short_name: "node".to_string(),
+            long_name: "Long name".to_string(),
+            firmware: 0x0102,
+            num_ports: 3,
+            universes: vec![0x124, 0x126],
+        }), Node::from_poll_reply(address, &packet));
+
+        packet[9] = 0x20;
+        assert_eq!(None, Node::from_poll_reply(address, &packet));
+        assert_eq!(None, Node::from_poll_reply(address, &packet[..100]));
+    }
+
+    #[test]
+    fn send_all_packets() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(time::Duration::new(1, 0))).unwrap();
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let packets: Vec<(Vec<u8>, net::SocketAddr)> = (0..100u8).map(|i| (vec![i; 1 + i as usize], addr)).collect();
+        send_all(&sender, &packets).unwrap();
+        let mut buf = [0; 128];
+        for i in 0..100u8 {
+            let (len, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(vec![i; 1 + i as usize], buf[..len].to_vec());
+        }
+    }
+
+    #[test]
+    fn sync_packet() {
+        let mut packet = Vec::new();
+        art_sync_packet(&mut packet).unwrap();
+        assert_eq!(b"Art-Net\0\x00\x52\x00\x0e\x00\x00"[..], packet[..]);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(time::Duration::new(1, 0))).unwrap();
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 0 };
+        let target: Box<dyn Target> = Box::new(vec![receiver.local_addr().unwrap()]);
+        let interval = time::Duration::from_millis(50);
+        let mut output = Unicast::to(socket, target, 6, layout).unwrap()
+            .with_keep_alive(Some(interval));
+        io::Write::write_all(&mut output, &[1, 2, 3, 4, 5, 6]).unwrap();
+        let sent = time::Instant::now();
+        let mut buf = [0; 32];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!([1, 2, 3, 4, 5, 6], buf[18..len]);
+        // Without a new frame, the last one is sent again once the interval has passed.
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert!(sent.elapsed() >= interval);
+        assert_eq!([1, 2, 3, 4, 5, 6], buf[18..len]);
+        // Nothing is resent once the output is gone, except for a frame that was on its way.
+        drop(output);
+        receiver.set_read_timeout(Some(interval * 3)).unwrap();
+        let resent = (0..5).take_while(|_| receiver.recv_from(&mut buf).is_ok()).count();
+        assert!(resent <= 1);
+    }
+
+    #[test]
+    fn sequence_per_universe() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(time::Duration::new(1, 0))).unwrap();
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 1, start_universe: 0, offset: 0 };
+        let target: Box<dyn Target> = Box::new(vec![receiver.local_addr().unwrap()]);
+        let mut output = Unicast::to(socket, target, 6, layout).unwrap()
+            .with_sequence(true);
+        let mut buf = [0; 32];
+        // The counter of each universe runs from 1 to 0xff and then wraps around to 1, skipping
+        // the 0 that disables sequencing.
+        for expected in (1..=0xff).chain(1..=2) {
+            io::Write::write_all(&mut output, &[0; 6]).unwrap();
+            let mut universes = Vec::new();
+            for _ in 0..2 {
+                let (len, _) = receiver.recv_from(&mut buf).unwrap();
+                assert!(len > 18);
+                assert_eq!(expected, buf[12]);
+                universes.push(u16::from(buf[14]) | u16::from(buf[15]) << 8);
+            }
+            universes.sort();
+            assert_eq!(vec![0, 1], universes);
+        }
+    }
+
+    #[test]
+    fn limit_rate() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(time::Duration::new(1, 0))).unwrap();
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 0 };
+        let target: Box<dyn Target> = Box::new(vec![receiver.local_addr().unwrap()]);
+        let mut output = Unicast::to(socket, target, 6, layout).unwrap()
+            .with_max_rate(Some(20));
+        let start = time::Instant::now();
+        for i in 0..3 {
+            io::Write::write_all(&mut output, &[i; 6]).unwrap();
+        }
+        // The second and third frame each wait for 50ms after the one before.
+        assert!(start.elapsed() >= time::Duration::from_millis(100));
+        let mut buf = [0; 32];
+        for i in 0..3 {
+            let (len, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!([i; 6], buf[18..len]);
+        }
+    }
+
+    #[test]
+    fn blackout_on_exit() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(time::Duration::new(1, 0))).unwrap();
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let layout = Layout { pixel_channels: 3, pixels_per_universe: 170, start_universe: 0, offset: 0 };
+        let target: Box<dyn Target> = Box::new(vec![receiver.local_addr().unwrap()]);
+        let mut output = Unicast::to(socket, target, 6, layout).unwrap()
+            .with_blackout_on_exit(true);
+        io::Write::write_all(&mut output, &[1, 2, 3, 4, 5, 6]).unwrap();
+        drop(output);
+        let mut buf = [0; 32];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!([1, 2, 3, 4, 5, 6], buf[18..len]);
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!([0; 6], buf[18..len]);
+    }
+}
@@ -14,15 +14,20 @@ const DRIVER_DETECTORS: &[(&str, fn(&path::Path) -> bool)] = &[
 ];
 
 pub fn detect<P: AsRef<path::Path>>(file: P) -> Option<String> {
-    let real_file = match read_link_recursive(file) {
+    let real_file = match read_link_recursive(&file) {
         Ok(p) => p,
-        Err(_) => return None,
+        Err(err) => {
+            debug!("Could not resolve {}: {}", file.as_ref().display(), err);
+            return None;
+        },
     };
     for dr in DRIVER_DETECTORS {
         if dr.1(&real_file) {
+            debug!("Detected the {} driver for {}", dr.0, real_file.display());
             return Some(dr.0.to_string());
         }
     }
+    debug!("None of the drivers support {}", real_file.display());
     None
 }
 
@@ -45,3 +50,14 @@ pub enum Error {
     Io(io::Error),
     Nix(nix::Error),
 }
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::DeviceNotSupported => io::Error::new(io::ErrorKind::InvalidInput, "the device is not supported by the driver"),
+            Error::Io(err) => err,
+            Error::Nix(nix::Error::Sys(errno)) => io::Error::from_raw_os_error(errno as i32),
+            Error::Nix(err) => io::Error::other(err),
+        }
+    }
+}
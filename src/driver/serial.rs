@@ -1,8 +1,22 @@
+use std::ffi;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read, Write};
 use std::os::unix::io::AsRawFd;
 use std::path;
+use std::str;
+use std::thread;
+use std::time;
+use nix::libc;
 use nix::sys::termios;
+use color::*;
+use device::*;
 use driver;
+use log::Level;
+
+
+/// How often to look for a serial port that has disappeared.
+const RECONNECT_INTERVAL: time::Duration = time::Duration::from_secs(1);
 
 pub fn open<P: AsRef<path::Path>>(path: P, baudrate: u32) -> Result<fs::File, driver::Error> {
     let tty = fs::OpenOptions::new()
@@ -19,6 +33,227 @@ pub fn open<P: AsRef<path::Path>>(path: P, baudrate: u32) -> Result<fs::File, dr
     Ok(tty)
 }
 
+/// A serial port, either a path like /dev/serial/by-id/... or a USB serial adapter that is
+/// identified by its vendor and product ID, e.g. usb:0403:6001.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Port {
+    Path(path::PathBuf),
+    Usb { vendor: u16, product: u16 },
+}
+
+impl Port {
+    /// Finds the device node the port currently refers to, which may change each time the
+    /// adapter is plugged in.
+    pub fn resolve(&self) -> io::Result<path::PathBuf> {
+        match *self {
+            Port::Path(ref path) => Ok(path.clone()),
+            Port::Usb { vendor, product } => {
+                let name = find_usb_tty(path::Path::new("/sys/class/tty"), vendor, product)?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such USB serial adapter is connected"))?;
+                Ok(path::Path::new("/dev").join(name))
+            },
+        }
+    }
+}
+
+impl str::FromStr for Port {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with("usb:") {
+            return Ok(Port::Path(path::PathBuf::from(s)));
+        }
+        let mut ids = s["usb:".len()..].splitn(2, ':')
+            .map(|id| u16::from_str_radix(id, 16));
+        match (ids.next(), ids.next()) {
+            (Some(Ok(vendor)), Some(Ok(product))) => Ok(Port::Usb { vendor, product }),
+            _ => Err(format!("invalid USB ID, expected usb:VID:PID in hexadecimal: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Port::Path(ref path) => write!(f, "{}", path.display()),
+            Port::Usb { vendor, product } => write!(f, "usb:{:04x}:{:04x}", vendor, product),
+        }
+    }
+}
+
+/// Looks up the name of the tty of a USB serial adapter in sysfs. The IDs are read from the USB
+/// device that the tty is a descendant of.
+fn find_usb_tty(class_dir: &path::Path, vendor: u16, product: u16) -> io::Result<Option<ffi::OsString>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(class_dir)? {
+        let entry = entry?;
+        let mut dir = match fs::canonicalize(entry.path().join("device")) {
+            Ok(dir) => dir,
+            Err(_) => continue, // Not backed by any hardware, e.g. a virtual console.
+        };
+        loop {
+            if dir.join("idVendor").exists() {
+                if read_usb_id(&dir.join("idVendor")) == Some(vendor)
+                    && read_usb_id(&dir.join("idProduct")) == Some(product) {
+                    names.push(entry.file_name());
+                }
+                break;
+            }
+            if !dir.pop() {
+                break;
+            }
+        }
+    }
+    // Adapters with more than one port are resolved to the first.
+    names.sort();
+    Ok(names.into_iter().next())
+}
+
+fn read_usb_id(path: &path::Path) -> Option<u16> {
+    fs::read_to_string(path).ok()
+        .and_then(|s| u16::from_str_radix(s.trim(), 16).ok())
+}
+
+
+/// Writes frames to a serial port and waits for the port to come back when it disappears, e.g.
+/// because a USB adapter has been re-enumerated by a flaky hub.
+pub struct Reconnect<D> {
+    dev: D,
+    port: Port,
+    baudrate: u32,
+    tty: Option<fs::File>,
+}
+
+impl<D: Device> Reconnect<D> {
+    pub fn new(dev: D, port: Port, baudrate: u32, tty: fs::File) -> Reconnect<D> {
+        Reconnect { dev, port, baudrate, tty: Some(tty) }
+    }
+
+    /// Writes a frame, which is written again in its entirety after reconnecting so the device
+    /// does not receive a partial frame.
+    fn write_with<F>(&mut self, write: F) -> io::Result<()>
+        where F: Fn(&D, &mut fs::File) -> io::Result<()> {
+        loop {
+            if self.tty.is_none() {
+                self.tty = Some(self.reopen());
+            }
+            match write(&self.dev, self.tty.as_mut().unwrap()) {
+                Err(ref err) if is_disconnect(err) => {
+                    warn!("Lost the connection to {}: {}, waiting for it to come back", self.port, err);
+                    self.tty = None;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn reopen(&self) -> fs::File {
+        loop {
+            thread::sleep(RECONNECT_INTERVAL);
+            let path = match self.port.resolve() {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            if let Ok(tty) = open(&path, self.baudrate) {
+                event!(Level::Info, "output_reconnected", {"port": self.port.to_string(), "path": path.display().to_string()},
+                       "Reconnected to {}", path.display());
+                return tty;
+            }
+        }
+    }
+}
+
+impl<D> Output for Reconnect<D>
+    where D: Device + Send {
+    fn color_correction(&self) -> Correction {
+        self.dev.color_correction()
+    }
+
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        self.write_with(|dev, tty| dev.write_frame(tty, frame))
+    }
+
+    fn output_frame_wide(&mut self, frame: &[Pixel16]) -> io::Result<()> {
+        self.write_with(|dev, tty| dev.write_frame_wide(tty, frame))
+    }
+}
+
+/// Writes frames to a serial port and waits until the device has echoed them back, so the time it
+/// takes to write a frame includes the time the device takes to receive it.
+pub struct Echo<D> {
+    dev: D,
+    tty: fs::File,
+    timeout: time::Duration,
+}
+
+impl<D: Device> Echo<D> {
+    pub fn new(dev: D, tty: fs::File, timeout: time::Duration) -> Echo<D> {
+        Echo { dev, tty, timeout }
+    }
+
+    fn write_encoded(&mut self, buf: &[u8]) -> io::Result<()> {
+        let fd = self.tty.as_raw_fd();
+        // Anything that was received before belongs to an earlier frame.
+        io_err!(termios::tcflush(fd, termios::FlushArg::TCIFLUSH))?;
+        self.tty.write_all(buf)?;
+        let mut echo = vec![0; buf.len()];
+        let mut received = 0;
+        let deadline = time::Instant::now() + self.timeout;
+        while received < echo.len() {
+            let now = time::Instant::now();
+            let readable = match wait_readable(fd, deadline.saturating_duration_since(now)) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => result?,
+            };
+            if !readable {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                    format!("the device echoed {} of {} bytes", received, buf.len())));
+            }
+            match self.tty.read(&mut echo[received..])? {
+                0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the serial port was closed")),
+                n => received += n,
+            }
+        }
+        if echo != buf {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the echo does not match the frame"));
+        }
+        Ok(())
+    }
+}
+
+impl<D> Output for Echo<D>
+    where D: Device + Send {
+    fn color_correction(&self) -> Correction {
+        self.dev.color_correction()
+    }
+
+    fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.dev.write_frame(&mut buf, frame)?;
+        self.write_encoded(&buf)
+    }
+
+    fn output_frame_wide(&mut self, frame: &[Pixel16]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.dev.write_frame_wide(&mut buf, frame)?;
+        self.write_encoded(&buf)
+    }
+}
+
+/// Waits until data can be read from the file descriptor, returns false on a timeout.
+fn wait_readable(fd: libc::c_int, timeout: time::Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+    match unsafe { libc::poll(&mut pollfd, 1, ms) } {
+        n if n < 0 => Err(io::Error::last_os_error()),
+        n => Ok(n > 0),
+    }
+}
+
+/// Whether an error means that the port has gone away, rather than that the data was rejected.
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EIO) | Some(libc::ENXIO) | Some(libc::ENODEV))
+}
+
 pub fn is_serial(path: &path::Path) -> bool {
     path.to_str()
         .map(|p| p.starts_with("/dev/tty"))
@@ -85,3 +320,44 @@ fn map_baudrate(b: u32) -> termios::BaudRate {
     }
     termios::BaudRate::B0
 }
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use std::os::unix::fs::symlink;
+    use super::*;
+
+    #[test]
+    fn parse_port() {
+        assert_eq!(Ok(Port::Path(path::PathBuf::from("/dev/serial/by-id/usb-FTDI-if00-port0"))),
+                   "/dev/serial/by-id/usb-FTDI-if00-port0".parse());
+        assert_eq!(Ok(Port::Usb { vendor: 0x0403, product: 0x6001 }), "usb:0403:6001".parse());
+        assert!("usb:0403".parse::<Port>().is_err());
+        assert!("usb:0403:xyz".parse::<Port>().is_err());
+        assert_eq!("usb:0403:6001", Port::Usb { vendor: 0x0403, product: 0x6001 }.to_string());
+    }
+
+    #[test]
+    fn find_usb_tty_in_sysfs() {
+        let tmp = tempdir::TempDir::new("serial_sysfs").unwrap();
+        let root = tmp.path();
+        let add_adapter = |tty: &str, bus: &str, vendor: &str, product: &str| {
+            let usb = root.join("devices").join(bus);
+            let iface = usb.join(format!("{}:1.0", bus)).join(tty);
+            fs::create_dir_all(&iface).unwrap();
+            fs::write(usb.join("idVendor"), vendor).unwrap();
+            fs::write(usb.join("idProduct"), product).unwrap();
+            fs::create_dir_all(root.join("class").join(tty)).unwrap();
+            symlink(&iface, root.join("class").join(tty).join("device")).unwrap();
+        };
+        add_adapter("ttyUSB0", "1-1", "10c4\n", "ea60\n");
+        add_adapter("ttyUSB1", "1-2", "0403\n", "6001\n");
+        fs::create_dir_all(root.join("class/tty0")).unwrap();
+
+        let class = root.join("class");
+        assert_eq!(Some(ffi::OsString::from("ttyUSB1")), find_usb_tty(&class, 0x0403, 0x6001).unwrap());
+        assert_eq!(Some(ffi::OsString::from("ttyUSB0")), find_usb_tty(&class, 0x10c4, 0xea60).unwrap());
+        assert_eq!(None, find_usb_tty(&class, 0x0403, 0x6015).unwrap());
+    }
+}
@@ -1,4 +1,5 @@
 use std::fs;
+use std::io;
 use std::os::unix::io::AsRawFd;
 use std::path;
 use regex;
@@ -9,6 +10,10 @@ use driver;
 ioctl!(write_ptr spi_ioc_wr_mode with b'k', 1; u8);
 ioctl!(write_ptr spi_ioc_wr_lsb_first with b'k', 2; u8);
 ioctl!(write_ptr spi_ioc_wr_max_speed_hz with b'k', 4; u32);
+ioctl!(read spi_ioc_rd_max_speed_hz with b'k', 4; u32);
+
+/// The parameter of the spidev kernel module that limits the size of a single write.
+const BUFSIZ_PARAM: &str = "/sys/module/spidev/parameters/bufsiz";
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug)]
@@ -45,6 +50,22 @@ pub fn open(path: &path::Path, dev: &Device) -> Result<fs::File, driver::Error>
     Ok(spidev)
 }
 
+/// Reads the maximum clock frequency in Hz that is configured for a SPI device.
+pub fn max_speed_hz(spidev: &fs::File) -> Result<u32, driver::Error> {
+    let mut speed = 0;
+    unsafe { spi_ioc_rd_max_speed_hz(spidev.as_raw_fd(), &mut speed)? };
+    Ok(speed)
+}
+
+/// Reads the maximum number of bytes that can be written to a SPI device at once. Larger frames
+/// fail with EMSGSIZE.
+pub fn bufsiz() -> io::Result<usize> {
+    fs::read_to_string(BUFSIZ_PARAM)?
+        .trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
 pub fn is_spidev(path: &path::Path) -> bool {
     let devs = regex::RegexSet::new(&[r"^/dev/spidev\d+\.\d+$",
                                       r"^/sys/devices/.+/spi\d\.\d$",
@@ -0,0 +1,93 @@
+use std::str;
+use regex::Regex;
+use input::geometry::Dimensions;
+
+
+/// A sub-rectangle of a 2D frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub at: (usize, usize),
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// The dimensions of the cropped frame.
+    pub fn dimensions(&self) -> Dimensions {
+        Dimensions::Two(self.width, self.height)
+    }
+
+    /// Checks whether the rectangle fits in frames of the specified dimensions.
+    pub fn check(&self, source: Dimensions) -> Result<(), String> {
+        let (w, h) = match source {
+            Dimensions::One(_) => return Err("cropping requires 2D geometry to be specified".to_string()),
+            Dimensions::Two(w, h) => (w, h),
+        };
+        let fits = |at: usize, len, max| at.checked_add(len).is_some_and(|end| end <= max);
+        if !fits(self.at.0, self.width, w) || !fits(self.at.1, self.height, h) {
+            return Err(format!("the crop rectangle does not fit in {}", source));
+        }
+        Ok(())
+    }
+
+    /// Copies the pixels inside the rectangle of a frame with the specified width to a new
+    /// buffer.
+    pub fn apply(&self, source_width: usize, frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.width * self.height * 3);
+        for y in self.at.1..self.at.1 + self.height {
+            let start = (y * source_width + self.at.0) * 3;
+            out.extend_from_slice(&frame[start..start + self.width * 3]);
+        }
+        out
+    }
+}
+
+impl str::FromStr for Crop {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(r"^(\d+),(\d+),([1-9]\d*)x([1-9]\d*)$").unwrap();
+        let err = || format!("can not parse \"{}\" into a crop rectangle, expected x,y,WxH", s);
+        let cap = re.captures(s).ok_or_else(err)?;
+        let num = |i: usize| cap[i].parse::<usize>().map_err(|e| format!("{}: {}", err(), e));
+        Ok(Crop {
+            at: (num(1)?, num(2)?),
+            width: num(3)?,
+            height: num(4)?,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_parse() {
+        assert_eq!(Crop { at: (1, 2), width: 3, height: 4 }, "1,2,3x4".parse().unwrap());
+        assert!("1,2".parse::<Crop>().is_err());
+        assert!("1,2,0x4".parse::<Crop>().is_err());
+        assert!("-1,2,3x4".parse::<Crop>().is_err());
+        assert!("99999999999999999999,0,1x1".parse::<Crop>().is_err());
+    }
+
+    #[test]
+    fn crop_check() {
+        let crop = Crop { at: (1, 1), width: 2, height: 2 };
+        assert!(crop.check(Dimensions::Two(3, 3)).is_ok());
+        assert!(crop.check(Dimensions::Two(2, 3)).is_err());
+        assert!(crop.check(Dimensions::One(9)).is_err());
+        let crop = Crop { at: (usize::MAX, 0), width: 1, height: 1 };
+        assert!(crop.check(Dimensions::Two(3, 3)).is_err());
+    }
+
+    #[test]
+    fn crop_apply() {
+        // 0 1 2
+        // 3 4 5
+        let frame: Vec<u8> = (0..6).flat_map(|i| vec![i; 3]).collect();
+        let crop = Crop { at: (1, 0), width: 2, height: 2 };
+        let expected: Vec<u8> = [1, 2, 4, 5].iter().flat_map(|&i| vec![i; 3]).collect();
+        assert_eq!(expected, crop.apply(3, &frame));
+    }
+}
@@ -0,0 +1,131 @@
+use std::io;
+use byteorder::{ReadBytesExt, BigEndian};
+
+
+/// The size of the header of a run: a 32-bit pixel offset followed by a 16-bit pixel count.
+const RUN_HEADER_SIZE: usize = 6;
+
+
+/// Decodes a stream of delta frames.
+///
+/// Instead of sending full frames, producers send runs of pixels that have changed since the
+/// previous frame. Each run consists of a big endian u32 pixel offset, a big endian u16 number of
+/// pixels and the RGB24 data of those pixels. A run with a length of 0 marks the end of a frame.
+pub struct Decoder {
+    frame: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(frame_size: usize) -> Decoder {
+        Decoder {
+            frame: vec![0; frame_size],
+            pending: Vec::new(),
+        }
+    }
+
+    /// Discards any partially received run.
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Appends data received from the producer.
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+    }
+
+    /// Applies the pending runs to the current frame until the end of a frame is reached, in
+    /// which case the frame is copied into `out` and true is returned.
+    ///
+    /// A run that does not fit in the frame is an error, after which the pending data is
+    /// discarded and the runs that were applied before it are kept.
+    pub fn next_frame(&mut self, out: &mut Vec<u8>) -> io::Result<bool> {
+        let mut complete = false;
+        let mut pos = 0;
+        loop {
+            let rest = &self.pending[pos..];
+            if rest.len() < RUN_HEADER_SIZE {
+                break;
+            }
+            let mut rdr = io::Cursor::new(rest);
+            let offset = (rdr.read_u32::<BigEndian>()? as usize).checked_mul(3);
+            let length = rdr.read_u16::<BigEndian>()? as usize * 3;
+            if length == 0 {
+                out.clear();
+                out.extend_from_slice(&self.frame);
+                complete = true;
+                pos += RUN_HEADER_SIZE;
+                break;
+            }
+            if rest.len() < RUN_HEADER_SIZE + length {
+                break;
+            }
+            let end = match offset.and_then(|o| o.checked_add(length)) {
+                Some(end) if end <= self.frame.len() => end,
+                _ => {
+                    self.pending.clear();
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "delta run exceeds the frame size"));
+                },
+            };
+            self.frame[end - length..end]
+                .copy_from_slice(&rest[RUN_HEADER_SIZE..RUN_HEADER_SIZE + length]);
+            pos += RUN_HEADER_SIZE + length;
+        }
+        self.pending.drain(..pos);
+        Ok(complete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_runs() {
+        let mut dec = Decoder::new(4 * 3);
+        dec.push(&[
+            0, 0, 0, 1, 0, 2, 1, 2, 3, 4, 5, 6,
+            0, 0, 0, 0, 0, 0,
+            0, 0, 0, 3, 0, 1, 7, 8, 9,
+            0, 0, 0, 0, 0, 0,
+        ]);
+        let mut frame = Vec::new();
+        assert!(dec.next_frame(&mut frame).unwrap());
+        assert_eq!(vec![0, 0, 0, 1, 2, 3, 4, 5, 6, 0, 0, 0], frame);
+        // The state of the previous frame is retained.
+        assert!(dec.next_frame(&mut frame).unwrap());
+        assert_eq!(vec![0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9], frame);
+        assert!(!dec.next_frame(&mut frame).unwrap());
+    }
+
+    #[test]
+    fn decode_partial() {
+        let mut dec = Decoder::new(2 * 3);
+        let mut frame = Vec::new();
+        dec.push(&[0, 0, 0, 0, 0, 1, 1]);
+        assert!(!dec.next_frame(&mut frame).unwrap());
+        dec.push(&[2, 3, 0, 0]);
+        assert!(!dec.next_frame(&mut frame).unwrap());
+        dec.push(&[0, 0, 0, 0]);
+        assert!(dec.next_frame(&mut frame).unwrap());
+        assert_eq!(vec![1, 2, 3, 0, 0, 0], frame);
+    }
+
+    #[test]
+    fn decode_out_of_bounds() {
+        let mut dec = Decoder::new(2 * 3);
+        let mut frame = Vec::new();
+        dec.push(&[0, 0, 0, 2, 0, 1, 1, 2, 3]);
+        assert!(dec.next_frame(&mut frame).is_err());
+        // An offset that overflows when it is multiplied by the size of a pixel.
+        dec.push(&[0x55, 0x55, 0x55, 0x56, 0, 1, 1, 2, 3]);
+        assert!(dec.next_frame(&mut frame).is_err());
+        dec.push(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        dec.push(&vec![0; 0xffff * 3]);
+        assert!(dec.next_frame(&mut frame).is_err());
+        // The decoder recovers with the next valid run.
+        dec.push(&[0, 0, 0, 1, 0, 1, 1, 2, 3, 0, 0, 0, 0, 0, 0]);
+        assert!(dec.next_frame(&mut frame).unwrap());
+        assert_eq!(vec![0, 0, 0, 1, 2, 3], frame);
+    }
+}
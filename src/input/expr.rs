@@ -0,0 +1,243 @@
+use std::str;
+
+
+/// An integer expression such as `y % 2 == 0 ? y * w + x : y * w + (w - 1 - x)`.
+///
+/// Supported are integer literals, variables, parentheses, the arithmetic operators `+ - * / %`,
+/// comparisons, `&&`, `||`, `!` and the conditional operator `?:`. Booleans are represented as 0
+/// and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Var(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add, Sub, Mul, Div, Rem,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+}
+
+impl Expr {
+    /// Evaluates the expression, `vars` is used to look up the value of variables.
+    ///
+    /// Addition, subtraction and multiplication wrap around, a negation, division or remainder
+    /// that overflows is an error like a division by zero.
+    pub fn eval<F>(&self, vars: &F) -> Result<i64, String>
+        where F: Fn(&str) -> Option<i64> {
+        Ok(match *self {
+            Expr::Num(n) => n,
+            Expr::Var(ref name) => vars(name).ok_or_else(|| format!("unknown variable: {}", name))?,
+            Expr::Neg(ref e) => e.eval(vars)?.checked_neg().ok_or_else(overflow)?,
+            Expr::Not(ref e) => (e.eval(vars)? == 0) as i64,
+            Expr::Cond(ref c, ref a, ref b) => {
+                if c.eval(vars)? != 0 { a.eval(vars)? } else { b.eval(vars)? }
+            },
+            Expr::Binary(op, ref a, ref b) => {
+                let a = a.eval(vars)?;
+                // Short circuit so guarded divisions can be written.
+                match op {
+                    Op::And if a == 0 => return Ok(0),
+                    Op::Or if a != 0 => return Ok(1),
+                    _ => (),
+                }
+                let b = b.eval(vars)?;
+                match op {
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    Op::Mul => a.wrapping_mul(b),
+                    Op::Div | Op::Rem if b == 0 => return Err("division by zero".to_string()),
+                    Op::Div => a.checked_div(b).ok_or_else(overflow)?,
+                    Op::Rem => a.checked_rem(b).ok_or_else(overflow)?,
+                    Op::Eq => (a == b) as i64,
+                    Op::Ne => (a != b) as i64,
+                    Op::Lt => (a < b) as i64,
+                    Op::Le => (a <= b) as i64,
+                    Op::Gt => (a > b) as i64,
+                    Op::Ge => (a >= b) as i64,
+                    Op::And | Op::Or => (b != 0) as i64,
+                }
+            },
+        })
+    }
+}
+
+fn overflow() -> String {
+    "integer overflow".to_string()
+}
+
+impl str::FromStr for Expr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        let expr = parser.cond()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(tok) => Err(format!("unexpected \"{}\"", tok)),
+        }
+    }
+}
+
+
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            if ["==", "!=", "<=", ">=", "&&", "||"].contains(&two.as_str()) {
+                tokens.push(two);
+                i += 2;
+            } else if "+-*/%<>!?:()".contains(c) {
+                tokens.push(c.to_string());
+                i += 1;
+            } else {
+                return Err(format!("unexpected character: '{}'", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn expect(&mut self, tok: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(t) if t == tok => {
+                self.pos += 1;
+                Ok(())
+            },
+            Some(t) => Err(format!("expected \"{}\", got \"{}\"", tok, t)),
+            None => Err(format!("expected \"{}\" at the end of the expression", tok)),
+        }
+    }
+
+    fn cond(&mut self) -> Result<Expr, String> {
+        let c = self.binary(0)?;
+        if self.peek() != Some("?") {
+            return Ok(c);
+        }
+        self.pos += 1;
+        let a = self.cond()?;
+        self.expect(":")?;
+        let b = self.cond()?;
+        Ok(Expr::Cond(Box::new(c), Box::new(a), Box::new(b)))
+    }
+
+    /// Parses binary operators of at least the specified precedence level.
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        const LEVELS: &[&[(&str, Op)]] = &[
+            &[("||", Op::Or)],
+            &[("&&", Op::And)],
+            &[("==", Op::Eq), ("!=", Op::Ne), ("<", Op::Lt), ("<=", Op::Le), (">", Op::Gt), (">=", Op::Ge)],
+            &[("+", Op::Add), ("-", Op::Sub)],
+            &[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek().and_then(|t| LEVELS[level].iter().find(|&&(s, _)| s == t)) {
+                Some(&(_, op)) => op,
+                None => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let tok = match self.peek() {
+            Some(t) => t.to_string(),
+            None => return Err("unexpected end of the expression".to_string()),
+        };
+        self.pos += 1;
+        match tok.as_str() {
+            "-" => Ok(Expr::Neg(Box::new(self.unary()?))),
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let e = self.cond()?;
+                self.expect(")")?;
+                Ok(e)
+            },
+            t if t.chars().all(|c| c.is_ascii_digit()) => {
+                t.parse().map(Expr::Num).map_err(|_| format!("invalid number: {}", t))
+            },
+            t if t.chars().next().unwrap().is_ascii_alphabetic() || t.starts_with('_') => {
+                Ok(Expr::Var(t.to_string()))
+            },
+            t => Err(format!("unexpected \"{}\"", t)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str) -> Result<i64, String> {
+        s.parse::<Expr>()?.eval(&|name| match name {
+            "x" => Some(3),
+            "w" => Some(8),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn expr_arithmetic() {
+        assert_eq!(Ok(7), eval("1 + 2 * 3"));
+        assert_eq!(Ok(9), eval("(1 + 2) * 3"));
+        assert_eq!(Ok(1), eval("10 % 3"));
+        assert_eq!(Ok(-2), eval("-x + 1"));
+        assert_eq!(Ok(4), eval("w - 1 - x"));
+        assert_eq!(Ok(2), eval("8 - 4 - 2"));
+    }
+
+    #[test]
+    fn expr_logic() {
+        assert_eq!(Ok(1), eval("x < w && !(x == 4)"));
+        assert_eq!(Ok(0), eval("x >= w || x != 3"));
+        assert_eq!(Ok(10), eval("x % 2 == 1 ? 10 : 20"));
+        assert_eq!(Ok(3), eval("0 ? 1 : 0 ? 2 : 3"));
+        assert_eq!(Ok(0), eval("0 && 1 / 0"));
+    }
+
+    #[test]
+    fn expr_invalid() {
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 $ 2").is_err());
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("-(0-9223372036854775807-1)").is_err());
+        assert!(eval("(0-9223372036854775807-1)/(0-1)").is_err());
+        assert!(eval("(0-9223372036854775807-1)%(0-1)").is_err());
+        assert!(eval("z").is_err());
+        assert!(eval("1 2").is_err());
+    }
+}
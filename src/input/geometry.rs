@@ -1,5 +1,10 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
 use std::str;
 use regex::Regex;
+use super::expr::Expr;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Dimensions {
@@ -16,6 +21,15 @@ impl Dimensions {
     }
 }
 
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Dimensions::One(size) => write!(f, "{}", size),
+            Dimensions::Two(w, h) => write!(f, "{}x{}", w, h),
+        }
+    }
+}
+
 impl str::FromStr for Dimensions {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -37,6 +51,14 @@ pub enum Axis {
     Y,
 }
 
+/// A clockwise rotation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Angle {
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
 
 pub trait Transposition {
     fn transpose(&self, index: usize) -> usize;
@@ -113,6 +135,501 @@ impl Transposition for Zigzag {
 }
 
 
+/// Rotates a 2D image clockwise. The width and height are those of the input image, a rotation by
+/// 90 or 270 degrees results in an image that is `height` pixels wide and `width` pixels high.
+pub struct Rotate {
+    pub width: usize,
+    pub height: usize,
+    pub angle: Angle,
+}
+
+impl Transposition for Rotate {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.width * self.height);
+        let x = index % self.width;
+        let y = index / self.width;
+        match self.angle {
+            Angle::Deg90 => x * self.height + (self.height - y - 1),
+            Angle::Deg180 => self.width * self.height - index - 1,
+            Angle::Deg270 => (self.width - x - 1) * self.height + y,
+        }
+    }
+}
+
+
+/// Moves all pixels by some amount, pixels that are moved past the end wrap around to the start.
+pub struct Shift {
+    pub length: usize,
+    pub amount: isize,
+}
+
+impl Transposition for Shift {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.length);
+        wrap(index as isize + self.amount, self.length)
+    }
+}
+
+
+/// Like Shift, but moves the pixels of a 2D image along a single axis.
+pub struct AxisShift {
+    pub width: usize,
+    pub height: usize,
+    pub axis: Axis,
+    pub amount: isize,
+}
+
+impl Transposition for AxisShift {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.width * self.height);
+        let x = index % self.width;
+        let y = index / self.width;
+        match self.axis {
+            Axis::X => self.width * y + wrap(x as isize + self.amount, self.width),
+            Axis::Y => self.width * wrap(y as isize + self.amount, self.height) + x,
+        }
+    }
+}
+
+fn wrap(index: isize, length: usize) -> usize {
+    index.rem_euclid(length as isize) as usize
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Start {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+/// Maps a 2D image onto a display that is wired as a spiral, e.g. a circular disc.
+///
+/// A spiral starting in a corner runs along the edges towards the center. A spiral starting in
+/// the center runs outward and ends in the top left corner.
+pub struct Spiral {
+    table: Vec<usize>,
+}
+
+impl Spiral {
+    pub fn new(width: usize, height: usize, direction: Direction, start: Start) -> Spiral {
+        // An outward spiral is an inward spiral in the opposite direction, walked backwards.
+        let (inward_direction, corner) = match (start, direction) {
+            (Start::Center, Direction::Clockwise) => (Direction::CounterClockwise, Start::TopLeft),
+            (Start::Center, Direction::CounterClockwise) => (Direction::Clockwise, Start::TopLeft),
+            (corner, direction) => (direction, corner),
+        };
+        let (mut x, mut y) = match corner {
+            Start::TopLeft | Start::Center => (0, 0),
+            Start::TopRight => (width as isize - 1, 0),
+            Start::BottomLeft => (0, height as isize - 1),
+            Start::BottomRight => (width as isize - 1, height as isize - 1),
+        };
+        // The initial direction runs along the edge of the display.
+        let (mut dx, mut dy): (isize, isize) = match (corner, inward_direction) {
+            (Start::TopLeft, Direction::Clockwise) | (Start::Center, Direction::Clockwise) => (1, 0),
+            (Start::TopRight, Direction::Clockwise) => (0, 1),
+            (Start::BottomRight, Direction::Clockwise) => (-1, 0),
+            (Start::BottomLeft, Direction::Clockwise) => (0, -1),
+            (Start::TopLeft, Direction::CounterClockwise) | (Start::Center, Direction::CounterClockwise) => (0, 1),
+            (Start::BottomLeft, Direction::CounterClockwise) => (1, 0),
+            (Start::BottomRight, Direction::CounterClockwise) => (0, -1),
+            (Start::TopRight, Direction::CounterClockwise) => (-1, 0),
+        };
+
+        let size = width * height;
+        let mut order = Vec::with_capacity(size);
+        let mut visited = vec![false; size];
+        while order.len() < size {
+            let index = y as usize * width + x as usize;
+            visited[index] = true;
+            order.push(index);
+            let free = |x: isize, y: isize| {
+                x >= 0 && y >= 0 && x < width as isize && y < height as isize
+                    && !visited[y as usize * width + x as usize]
+            };
+            if !free(x + dx, y + dy) {
+                // Turn at the edge or when reaching the part that has already been walked.
+                let (ndx, ndy) = match inward_direction {
+                    Direction::Clockwise => (-dy, dx),
+                    Direction::CounterClockwise => (dy, -dx),
+                };
+                dx = ndx;
+                dy = ndy;
+            }
+            x += dx;
+            y += dy;
+        }
+        if start == Start::Center {
+            order.reverse();
+        }
+
+        let mut table = vec![0; size];
+        for (output, &input) in order.iter().enumerate() {
+            table[input] = output;
+        }
+        Spiral { table }
+    }
+}
+
+impl Transposition for Spiral {
+    fn transpose(&self, index: usize) -> usize {
+        self.table[index]
+    }
+}
+
+
+/// Maps an image onto a display that is made of a chain of panels. The pixels in each panel are
+/// ordered from left to right, top to bottom. The chain runs through the first row of panels from
+/// left to right and snakes back through the next row. Panels in the reversed rows may be mounted
+/// upside down, in which case `rotate` should be set.
+pub struct Panels {
+    pub width: usize,
+    pub height: usize,
+    pub panel_width: usize,
+    pub panel_height: usize,
+    pub rotate: bool,
+}
+
+impl Transposition for Panels {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.width * self.height);
+        let x = index % self.width;
+        let y = index / self.width;
+        let cols = self.width / self.panel_width;
+        let (col, row) = (x / self.panel_width, y / self.panel_height);
+        let (lx, ly) = (x % self.panel_width, y % self.panel_height);
+        let reversed = row % 2 == 1;
+        let panel = row * cols + if reversed { cols - col - 1 } else { col };
+        let local = if reversed && self.rotate {
+            (self.panel_height - ly - 1) * self.panel_width + (self.panel_width - lx - 1)
+        } else {
+            ly * self.panel_width + lx
+        };
+        panel * self.panel_width * self.panel_height + local
+    }
+}
+
+
+/// Swaps the X- and Y-axis, converting a row-major image into a column-major one. The result is
+/// an image that is `height` pixels wide and `width` pixels high.
+pub struct SwapXY {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Transposition for SwapXY {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.width * self.height);
+        let x = index % self.width;
+        let y = index / self.width;
+        x * self.height + y
+    }
+}
+
+
+/// Splits interleaved pixels into separate runs: with 2 ways, all pixels with an even index come
+/// first, followed by all odd pixels.
+pub struct Interleave {
+    pub length: usize,
+    pub ways: usize,
+}
+
+impl Transposition for Interleave {
+    fn transpose(&self, index: usize) -> usize {
+        assert!(index < self.length);
+        let run = index % self.ways;
+        let run_start: usize = (0..run)
+            .map(|r| (self.length - r).div_ceil(self.ways))
+            .sum();
+        run_start + index / self.ways
+    }
+}
+
+
+/// An arbitrary mapping of pixels for displays that are wired in a way that can not be expressed
+/// using the other transpositions.
+///
+/// A map is parsed from a list of indices, either as a JSON array or separated by commas or
+/// newlines as in a CSV file. The Nth value is the index of the input pixel that should be shown
+/// at output pixel N. Every input pixel should be mapped exactly once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Map {
+    // The output index of each input index, the inverse of the parsed list.
+    table: Vec<usize>,
+}
+
+impl Map {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Map> {
+        fs::read_to_string(path)?.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Returns the number of pixels in the map.
+    pub fn size(&self) -> usize {
+        self.table.len()
+    }
+}
+
+impl Transposition for Map {
+    fn transpose(&self, index: usize) -> usize {
+        self.table[index]
+    }
+}
+
+impl str::FromStr for Map {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = if s.starts_with('[') && s.ends_with(']') {
+            &s[1..s.len() - 1]
+        } else {
+            s
+        };
+        let sources = s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<usize>().map_err(|_| format!("invalid pixel index: \"{}\"", v)))
+            .collect::<Result<Vec<usize>, String>>()?;
+        let mut table = vec![None; sources.len()];
+        for (output, &source) in sources.iter().enumerate() {
+            match table.get_mut(source) {
+                Some(&mut Some(_)) => return Err(format!("pixel {} is mapped more than once", source)),
+                Some(entry) => *entry = Some(output),
+                None => return Err(format!("pixel {} is out of bounds", source)),
+            }
+        }
+        Ok(Map { table: table.into_iter().map(Option::unwrap).collect() })
+    }
+}
+
+
+/// A transposition of which the output index of each pixel is computed by an expression.
+///
+/// The expression is evaluated once for every pixel to build a table. The variables `x`, `y` and
+/// `i` hold the position and index of the input pixel, `w`, `h` and `n` the width, height and
+/// number of pixels of the display. 1D displays have a height of 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    table: Vec<usize>,
+}
+
+impl Expression {
+    pub fn new(expr: &Expr, width: usize, height: usize) -> Result<Expression, String> {
+        let size = width * height;
+        let mut table = Vec::with_capacity(size);
+        let mut used = vec![false; size];
+        for i in 0..size {
+            let output = expr.eval(&|name| match name {
+                "x" => Some((i % width) as i64),
+                "y" => Some((i / width) as i64),
+                "i" => Some(i as i64),
+                "w" => Some(width as i64),
+                "h" => Some(height as i64),
+                "n" => Some(size as i64),
+                _ => None,
+            })?;
+            if output < 0 || output as usize >= size {
+                return Err(format!("pixel {} is mapped to {}, which is out of bounds", i, output));
+            }
+            let output = output as usize;
+            if used[output] {
+                return Err(format!("more than one pixel is mapped to {}", output));
+            }
+            used[output] = true;
+            table.push(output);
+        }
+        Ok(Expression { table })
+    }
+}
+
+impl Transposition for Expression {
+    fn transpose(&self, index: usize) -> usize {
+        self.table[index]
+    }
+}
+
+
+/// The names of the transpositions accepted by --transpose and whether they take an argument,
+/// which is separated from the name by a colon.
+pub const TRANSPOSITIONS: &[(&str, bool)] = &[
+    ("reverse", false),
+    ("zigzag_x", false),
+    ("zigzag_y", false),
+    ("mirror_x", false),
+    ("mirror_y", false),
+    ("rotate90", false),
+    ("rotate180", false),
+    ("rotate270", false),
+    ("shift", true),
+    ("shift_x", true),
+    ("shift_y", true),
+    ("spiral", true),
+    ("panels", true),
+    ("tile", true),
+    ("rotate", true),
+    ("interleave", true),
+    ("swap_xy", false),
+    ("expr", true),
+];
+
+fn parse_spiral(arg: &str) -> Result<(Direction, Start), String> {
+    let mut split = arg.splitn(2, ',');
+    let direction = match split.next().unwrap() {
+        "cw" => Direction::Clockwise,
+        "ccw" => Direction::CounterClockwise,
+        d => return Err(format!("invalid spiral direction \"{}\", expected cw or ccw", d)),
+    };
+    let start = match split.next().unwrap_or("") {
+        "tl" => Start::TopLeft,
+        "tr" => Start::TopRight,
+        "bl" => Start::BottomLeft,
+        "br" => Start::BottomRight,
+        "center" => Start::Center,
+        s => return Err(format!("invalid spiral start \"{}\", expected tl, tr, bl, br or center", s)),
+    };
+    Ok((direction, start))
+}
+
+/// Builds the table that maps each pixel of the display to its index in the output from the
+/// operations of --transpose, in order, followed by the pixel map.
+pub fn transposition_table(dimensions: &Dimensions,
+                           operations: Vec<&str>,
+                           map: Option<Map>)
+                           -> Result<Vec<usize>, String> {
+    // Rotating by 90 or 270 degrees and swapping the axes swaps the width and height for
+    // subsequent operations.
+    let mut dims = *dimensions;
+    let mut transpositions: Vec<Box<dyn Transposition>> = operations.into_iter()
+        .map(|op| -> Result<Box<dyn Transposition>, String> {
+            let mut split = op.splitn(2, ':');
+            let name = split.next().unwrap();
+            if name == "expr" {
+                // Expressions may contain colons, so their argument is passed on as is.
+                let expr: Expr = split.next().unwrap_or("").parse()
+                    .map_err(|err| format!("invalid expression: {}", err))?;
+                let (w, h) = match dims {
+                    Dimensions::One(n) => (n, 1),
+                    Dimensions::Two(w, h) => (w, h),
+                };
+                return Ok(Box::from(Expression::new(&expr, w, h)?));
+            }
+            // Multiple arguments may be separated by commas or colons.
+            let arg = split.next().unwrap_or("").replace(':', ",");
+            let arg = arg.as_str();
+            // Some operations can be written both with and without arguments.
+            let name = match (name, arg) {
+                ("rotate", "90") => "rotate90",
+                ("rotate", "180") => "rotate180",
+                ("rotate", "270") => "rotate270",
+                ("rotate", _) => return Err(format!("invalid rotation: \"{}\", expected 90, 180 or 270", arg)),
+                ("tile", _) => "panels",
+                _ => name,
+            };
+            let amount = || arg.parse::<isize>()
+                .map_err(|_| format!("{} requires a number, got \"{}\"", name, arg));
+            match (name, dims) {
+                ("reverse", dim) => Ok(Box::from(Reverse { length: dim.size() })),
+                ("zigzag_x", Dimensions::Two(w, h)) | ("zigzag_y", Dimensions::Two(w, h)) => {
+                    Ok(Box::from(Zigzag {
+                        width: w,
+                        height: h,
+                        major_axis: match name.chars().last().unwrap() {
+                            'x' => Axis::X,
+                            'y' => Axis::Y,
+                            _ => unreachable!(),
+                        },
+                    }))
+                },
+                ("mirror_x", Dimensions::Two(w, h)) | ("mirror_y", Dimensions::Two(w, h)) => {
+                    Ok(Box::from(Mirror {
+                        width: w,
+                        height: h,
+                        axis: match name.chars().last().unwrap() {
+                            'x' => Axis::X,
+                            'y' => Axis::Y,
+                            _ => unreachable!(),
+                        },
+                    }))
+                },
+                ("rotate90", Dimensions::Two(w, h)) | ("rotate270", Dimensions::Two(w, h)) => {
+                    dims = Dimensions::Two(h, w);
+                    Ok(Box::from(Rotate {
+                        width: w,
+                        height: h,
+                        angle: if name == "rotate90" { Angle::Deg90 } else { Angle::Deg270 },
+                    }))
+                },
+                ("swap_xy", Dimensions::Two(w, h)) => {
+                    dims = Dimensions::Two(h, w);
+                    Ok(Box::from(SwapXY { width: w, height: h }))
+                },
+                ("rotate180", Dimensions::Two(w, h)) => {
+                    Ok(Box::from(Rotate { width: w, height: h, angle: Angle::Deg180 }))
+                },
+                ("interleave", dim) => {
+                    match arg.parse::<usize>() {
+                        Ok(ways) if ways > 0 => Ok(Box::from(Interleave { length: dim.size(), ways })),
+                        _ => Err(format!("interleave requires a positive number, got \"{}\"", arg)),
+                    }
+                },
+                ("shift", dim) => Ok(Box::from(Shift { length: dim.size(), amount: amount()? })),
+                ("shift_x", Dimensions::Two(w, h)) | ("shift_y", Dimensions::Two(w, h)) => {
+                    Ok(Box::from(AxisShift {
+                        width: w,
+                        height: h,
+                        axis: match name.chars().last().unwrap() {
+                            'x' => Axis::X,
+                            'y' => Axis::Y,
+                            _ => unreachable!(),
+                        },
+                        amount: amount()?,
+                    }))
+                },
+                ("spiral", Dimensions::Two(w, h)) => {
+                    let (direction, start) = parse_spiral(arg)?;
+                    Ok(Box::from(Spiral::new(w, h, direction, start)))
+                },
+                ("panels", Dimensions::Two(w, h)) => {
+                    let mut split = arg.splitn(2, ',');
+                    let (pw, ph) = match split.next().unwrap().parse::<Dimensions>() {
+                        Ok(Dimensions::Two(pw, ph)) => (pw, ph),
+                        _ => return Err(format!("invalid panel size: \"{}\", expected WxH", arg)),
+                    };
+                    if w % pw != 0 || h % ph != 0 {
+                        return Err(format!("the display can not be divided into {}x{} panels", pw, ph));
+                    }
+                    let rotate = match split.next() {
+                        None => false,
+                        Some("rotate") | Some("rot180") => true,
+                        Some(opt) => return Err(format!("unknown panels option: {}", opt)),
+                    };
+                    Ok(Box::from(Panels { width: w, height: h, panel_width: pw, panel_height: ph, rotate }))
+                },
+                (name, Dimensions::One(_)) => Err(format!("{} requires 2D geometry to be specified", name)),
+                (name, _) => Err(format!("Unknown transposition: {}", name)),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    if let Some(map) = map {
+        if map.size() != dimensions.size() {
+            return Err(format!("the pixel map has {} pixels while the display has {}", map.size(), dimensions.size()));
+        }
+        transpositions.push(Box::new(map));
+    }
+    Ok((0..dimensions.size())
+        .map(|index| transpositions.transpose(index))
+        .collect())
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::*;
@@ -142,6 +659,12 @@ mod tests {
         assert_eq!(Dimensions::Two(4, 20), "4x20".parse::<Dimensions>().unwrap());
     }
 
+    #[test]
+    fn dimensions_display() {
+        assert_eq!("42", Dimensions::One(42).to_string());
+        assert_eq!("4x20", Dimensions::Two(4, 20).to_string());
+    }
+
     #[test]
     fn dimensions_size() {
         assert_eq!(42, Dimensions::One(42).size());
@@ -221,4 +744,117 @@ mod tests {
         assert_eq!(vec![0, 1, 2, 3, 7, 6, 5, 4, 8, 9, 10, 11],
                    transpose_all(&zz, 0..12));
     }
+
+    #[test]
+    fn rotate() {
+        // 0 1 2      3 0      5 4 3      2 5
+        // 3 4 5  ->  4 1  or  2 1 0  or  1 4
+        //            5 2                 0 3
+        let rot = |angle| Rotate { width: 3, height: 2, angle };
+        assert_eq!(vec![1, 3, 5, 0, 2, 4], transpose_all(&rot(Angle::Deg90), 0..6));
+        assert_eq!(vec![5, 4, 3, 2, 1, 0], transpose_all(&rot(Angle::Deg180), 0..6));
+        assert_eq!(vec![4, 2, 0, 5, 3, 1], transpose_all(&rot(Angle::Deg270), 0..6));
+    }
+
+    #[test]
+    fn map_parse() {
+        let expected = Map { table: vec![1, 2, 0] };
+        assert_eq!(expected, "2,0,1".parse().unwrap());
+        assert_eq!(expected, "2\n0\n1\n".parse().unwrap());
+        assert_eq!(expected, "[2, 0, 1]".parse().unwrap());
+        assert!("2,0".parse::<Map>().is_err());
+        assert!("1,1,0".parse::<Map>().is_err());
+        assert!("0,x".parse::<Map>().is_err());
+    }
+
+    #[test]
+    fn map() {
+        let map: Map = "2,0,1".parse().unwrap();
+        assert_eq!(vec![1, 2, 0], transpose_all(&map, 0..3));
+    }
+
+    #[test]
+    fn shift() {
+        assert_eq!(vec![2, 3, 4, 0, 1], transpose_all(&Shift { length: 5, amount: 2 }, 0..5));
+        assert_eq!(vec![4, 0, 1, 2, 3], transpose_all(&Shift { length: 5, amount: -6 }, 0..5));
+    }
+
+    #[test]
+    fn shift_axis() {
+        let sx = AxisShift { width: 3, height: 2, axis: Axis::X, amount: 1 };
+        assert_eq!(vec![1, 2, 0, 4, 5, 3], transpose_all(&sx, 0..6));
+        let sy = AxisShift { width: 3, height: 2, axis: Axis::Y, amount: -1 };
+        assert_eq!(vec![3, 4, 5, 0, 1, 2], transpose_all(&sy, 0..6));
+    }
+
+    #[test]
+    fn spiral_corner() {
+        // 0 1 2
+        // 7 8 3
+        // 6 5 4
+        let sp = Spiral::new(3, 3, Direction::Clockwise, Start::TopLeft);
+        assert_eq!(vec![0, 1, 2, 7, 8, 3, 6, 5, 4], transpose_all(&sp, 0..9));
+        // 2 1 0
+        // 3 8 7
+        // 4 5 6
+        let sp = Spiral::new(3, 3, Direction::CounterClockwise, Start::TopRight);
+        assert_eq!(vec![2, 1, 0, 3, 8, 7, 4, 5, 6], transpose_all(&sp, 0..9));
+        // 7  8  9 0
+        // 6 11 10 1
+        // 5  4  3 2
+        let sp = Spiral::new(4, 3, Direction::Clockwise, Start::TopRight);
+        assert_eq!(vec![7, 8, 9, 0, 6, 11, 10, 1, 5, 4, 3, 2], transpose_all(&sp, 0..12));
+    }
+
+    #[test]
+    fn spiral_center() {
+        // 8 7 6
+        // 1 0 5
+        // 2 3 4
+        let sp = Spiral::new(3, 3, Direction::CounterClockwise, Start::Center);
+        assert_eq!(vec![8, 7, 6, 1, 0, 5, 2, 3, 4], transpose_all(&sp, 0..9));
+    }
+
+    #[test]
+    fn panels() {
+        // Four 2x1 panels on a 4x2 display, the second row of panels runs right to left.
+        let p = Panels { width: 4, height: 2, panel_width: 2, panel_height: 1, rotate: false };
+        assert_eq!(vec![0, 1, 2, 3, 6, 7, 4, 5], transpose_all(&p, 0..8));
+        let p = Panels { width: 4, height: 2, panel_width: 2, panel_height: 1, rotate: true };
+        assert_eq!(vec![0, 1, 2, 3, 7, 6, 5, 4], transpose_all(&p, 0..8));
+        // Two 2x2 panels on a 2x4 display.
+        let p = Panels { width: 2, height: 4, panel_width: 2, panel_height: 2, rotate: true };
+        assert_eq!(vec![0, 1, 2, 3, 7, 6, 5, 4], transpose_all(&p, 0..8));
+    }
+
+    #[test]
+    fn interleave() {
+        assert_eq!(vec![0, 3, 1, 4, 2], transpose_all(&Interleave { length: 5, ways: 2 }, 0..5));
+        assert_eq!(vec![0, 3, 5, 1, 4, 6, 2], transpose_all(&Interleave { length: 7, ways: 3 }, 0..7));
+    }
+
+    #[test]
+    fn swap_xy() {
+        // 0 1 2  ->  0 2 4
+        // 3 4 5      1 3 5
+        let sw = SwapXY { width: 3, height: 2 };
+        assert_eq!(vec![0, 2, 4, 1, 3, 5], transpose_all(&sw, 0..6));
+    }
+
+    #[test]
+    fn expression() {
+        let e = "y % 2 == 0 ? y * w + x : y * w + (w - 1 - x)".parse().unwrap();
+        let ex = Expression::new(&e, 3, 2).unwrap();
+        assert_eq!(vec![0, 1, 2, 5, 4, 3], transpose_all(&ex, 0..6));
+        let e = "n - 1 - i".parse().unwrap();
+        let ex = Expression::new(&e, 4, 1).unwrap();
+        assert_eq!(vec![3, 2, 1, 0], transpose_all(&ex, 0..4));
+    }
+
+    #[test]
+    fn expression_invalid() {
+        assert!(Expression::new(&"x".parse().unwrap(), 2, 2).is_err());
+        assert!(Expression::new(&"i + 1".parse().unwrap(), 2, 2).is_err());
+        assert!(Expression::new(&"-i".parse().unwrap(), 2, 2).is_err());
+    }
 }
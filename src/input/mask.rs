@@ -0,0 +1,171 @@
+use std::fs;
+use std::io;
+use std::path;
+use std::str;
+use input::geometry::Dimensions;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Masked pixels are left out of the output, for displays on which they are absent.
+    Skip,
+    /// Masked pixels are turned off, for displays on which they are broken.
+    Black,
+}
+
+impl str::FromStr for Mode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Mode::Skip),
+            "black" => Ok(Mode::Black),
+            _ => Err(format!("unknown mask mode: {}", s)),
+        }
+    }
+}
+
+
+/// A Mask marks pixels of a rectangular display that are physically absent or broken.
+///
+/// A mask is parsed from either a list of the indices of the masked pixels, separated by commas
+/// or newlines, or from a bitmap with a line of characters for each row of the display where `#`
+/// is a present pixel and `.` a masked one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mask {
+    // Whether each pixel of the display is masked. May be shorter than the display if the mask
+    // was parsed from a list of indices.
+    masked: Vec<bool>,
+    // The number of pixels if the mask was parsed from a bitmap.
+    size: Option<usize>,
+}
+
+impl Mask {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Mask> {
+        fs::read_to_string(path)?.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Checks whether the mask fits a display with the specified dimensions.
+    pub fn check(&self, dimensions: Dimensions) -> Result<(), String> {
+        match self.size {
+            Some(size) if size != dimensions.size() => {
+                Err(format!("the mask has {} pixels while the display has {}", size, dimensions.size()))
+            },
+            None if self.masked.len() > dimensions.size() => {
+                Err(format!("pixel {} is out of bounds", self.masked.len() - 1))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_masked(&self, index: usize) -> bool {
+        self.masked.get(index).cloned().unwrap_or(false)
+    }
+
+    /// Returns the number of pixels that are not masked on a display of the specified size.
+    pub fn num_present(&self, size: usize) -> usize {
+        (0..size).filter(|&i| !self.is_masked(i)).count()
+    }
+
+    /// Sets all masked pixels of a frame to black.
+    pub fn apply_black(&self, frame: &[u8]) -> Vec<u8> {
+        let mut out = frame.to_vec();
+        for (i, pix) in out.chunks_mut(3).enumerate() {
+            if self.is_masked(i) {
+                pix.copy_from_slice(&[0, 0, 0]);
+            }
+        }
+        out
+    }
+
+    /// Removes masked pixels from a transposition table. The output indices of the remaining
+    /// pixels are shifted to close the gaps.
+    pub fn skip(&self, transposition: &[usize]) -> Vec<Option<usize>> {
+        let mut removed = vec![false; transposition.len()];
+        for (i, &out) in transposition.iter().enumerate() {
+            removed[out] = self.is_masked(i);
+        }
+        let mut shifted = Vec::with_capacity(removed.len());
+        let mut num_removed = 0;
+        for r in removed {
+            shifted.push(num_removed);
+            if r {
+                num_removed += 1;
+            }
+        }
+        transposition.iter().enumerate()
+            .map(|(i, &out)| if self.is_masked(i) { None } else { Some(out - shifted[out]) })
+            .collect()
+    }
+}
+
+impl str::FromStr for Mask {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.chars().any(|c| c == '#' || c == '.') {
+            let mut masked = Vec::new();
+            let mut width = None;
+            for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+                if *width.get_or_insert(line.len()) != line.len() {
+                    return Err("all rows of the mask should have the same width".to_string());
+                }
+                for c in line.chars() {
+                    masked.push(match c {
+                        '#' => false,
+                        '.' => true,
+                        _ => return Err(format!("invalid character in mask: '{}'", c)),
+                    });
+                }
+            }
+            return Ok(Mask { size: Some(masked.len()), masked });
+        }
+
+        let mut masked = Vec::new();
+        for v in s.split(|c: char| c == ',' || c.is_whitespace()).filter(|v| !v.is_empty()) {
+            let index: usize = v.parse()
+                .map_err(|_| format!("invalid pixel index: \"{}\"", v))?;
+            if index >= masked.len() {
+                masked.resize(index + 1, false);
+            }
+            masked[index] = true;
+        }
+        Ok(Mask { masked, size: None })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_parse() {
+        let mask: Mask = "1, 3".parse().unwrap();
+        assert_eq!(vec![false, true, false, true, false], (0..5).map(|i| mask.is_masked(i)).collect::<Vec<_>>());
+        assert!(mask.check(Dimensions::One(4)).is_ok());
+        assert!(mask.check(Dimensions::One(3)).is_err());
+
+        let mask: Mask = "#.#\n.##\n".parse().unwrap();
+        assert_eq!(vec![false, true, false, true, false, false], (0..6).map(|i| mask.is_masked(i)).collect::<Vec<_>>());
+        assert!(mask.check(Dimensions::Two(3, 2)).is_ok());
+        assert!(mask.check(Dimensions::Two(3, 3)).is_err());
+
+        assert!("#.#\n.#".parse::<Mask>().is_err());
+        assert!("1,x".parse::<Mask>().is_err());
+    }
+
+    #[test]
+    fn mask_black() {
+        let mask: Mask = "1".parse().unwrap();
+        assert_eq!(vec![1, 1, 1, 0, 0, 0, 3, 3, 3], mask.apply_black(&[1, 1, 1, 2, 2, 2, 3, 3, 3]));
+    }
+
+    #[test]
+    fn mask_skip() {
+        let mask: Mask = "1".parse().unwrap();
+        assert_eq!(vec![Some(0), None, Some(1)], mask.skip(&[0, 1, 2]));
+        // Input pixel 1 is at output 0, so the other pixels move down.
+        assert_eq!(vec![Some(1), None, Some(0)], mask.skip(&[2, 0, 1]));
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time;
+
+
+/// Inputs that have not produced a frame for this long are left out of the mix.
+const STALE_AFTER_MS: u64 = 1000;
+
+
+/// The weight of each input in the mix. The weights can be changed while ledcat is running.
+#[derive(Clone)]
+pub struct Weights {
+    inner: Arc<RwLock<Vec<f32>>>,
+}
+
+impl Weights {
+    pub fn fixed(weights: Vec<f32>) -> Weights {
+        Weights { inner: Arc::new(RwLock::new(weights)) }
+    }
+
+    /// Loads the weights from a file containing one weight per line in the order of the inputs.
+    /// Changes to the file are read automatically. Until the file has been read, the specified
+    /// defaults are used.
+    pub fn watch<T: Into<path::PathBuf>>(p: T, defaults: Vec<f32>) -> Weights {
+        let path = p.into();
+        let weights = Weights::fixed(defaults);
+
+        let inner_weak = Arc::downgrade(&weights.inner);
+        thread::spawn(move || {
+            macro_rules! try_or_continue {
+                ($expr:expr) => {{
+                    match $expr {
+                        Ok(t) => t,
+                        Err(_) => {
+                            thread::sleep(time::Duration::new(1, 0));
+                            continue;
+                        },
+                    }
+                }}
+            }
+
+            let mut prev_mod_time = None;
+            loop {
+                let meta = try_or_continue!(fs::metadata(&path));
+                let mod_time = try_or_continue!(meta.modified());
+                let reload = prev_mod_time != Some(mod_time);
+                prev_mod_time = Some(mod_time);
+
+                if reload {
+                    let inner = match inner_weak.upgrade() {
+                        Some(c) => c,
+                        None => return,
+                    };
+                    let file = try_or_continue!(fs::File::open(&path));
+                    let loaded: Vec<f32> = io::BufReader::new(file).lines()
+                        .map_while(Result::ok)
+                        .filter_map(|line| line.trim().parse().ok())
+                        .filter(|w: &f32| *w >= 0.0)
+                        .collect();
+                    let mut w = inner.write().unwrap();
+                    for (cur, new) in w.iter_mut().zip(loaded) {
+                        *cur = new;
+                    }
+                }
+
+                thread::sleep(time::Duration::new(1, 0));
+            }
+        });
+
+        weights
+    }
+
+    pub fn get(&self) -> Vec<f32> {
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Adds the weight of an input that was added after the others.
+    fn push(&self, weight: f32) {
+        self.inner.write().unwrap().push(weight);
+    }
+
+    /// Removes the weight of the input at the index, so the inputs after it keep their weight.
+    fn remove(&self, index: usize) {
+        let mut weights = self.inner.write().unwrap();
+        if index < weights.len() {
+            weights.remove(index);
+        }
+    }
+}
+
+
+/// The Mixer blends the most recent frames of all inputs that are currently producing into a
+/// single frame.
+pub struct Mixer {
+    weights: Weights,
+    layers: Vec<Option<(time::Instant, Vec<u8>)>>,
+}
+
+impl Mixer {
+    pub fn new(num_inputs: usize, weights: Weights) -> Mixer {
+        Mixer {
+            weights,
+            layers: vec![None; num_inputs],
+        }
+    }
+
+    /// Adds an input after the others. Without a weight, the input is mixed with a weight of 1.
+    pub fn add_input(&mut self, weight: Option<f32>) {
+        self.layers.push(None);
+        self.weights.push(weight.unwrap_or(1.0));
+    }
+
+    pub fn remove_input(&mut self, index: usize) {
+        self.layers.remove(index);
+        self.weights.remove(index);
+    }
+
+    /// Stores the frame as the most recent one of the input at the specified index and writes
+    /// the weighted average of all live inputs to out, which has the size of a frame.
+    pub fn mix(&mut self, index: usize, frame: &[u8], out: &mut [u8]) {
+        let now = time::Instant::now();
+        // The buffer of a layer is reused for the next frame of its input.
+        if let Some((ref mut t, ref mut layer)) = self.layers[index] {
+            *t = now;
+            layer.clear();
+            layer.extend_from_slice(frame);
+        } else {
+            self.layers[index] = Some((now, frame.to_vec()));
+        }
+
+        let weights = self.weights.get();
+        let stale_after = time::Duration::from_millis(STALE_AFTER_MS);
+        let live: Vec<(f32, &Vec<u8>)> = self.layers.iter()
+            .enumerate()
+            .filter_map(|(i, layer)| match *layer {
+                Some((t, ref f)) if now.duration_since(t) < stale_after => {
+                    Some((weights.get(i).cloned().unwrap_or(1.0), f))
+                },
+                _ => None,
+            })
+            .collect();
+
+        let total: f32 = live.iter().map(|l| l.0).sum();
+        if total <= 0.0 {
+            for v in out.iter_mut() {
+                *v = 0;
+            }
+            return;
+        }
+        for (j, v) in out.iter_mut().enumerate() {
+            let sum: f32 = live.iter().map(|&(w, f)| w * f32::from(f[j])).sum();
+            *v = (sum / total).round().min(255.0) as u8;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_average() {
+        let mut mixer = Mixer::new(2, Weights::fixed(vec![1.0, 1.0]));
+        let mut out = vec![0; 2];
+        mixer.mix(0, &[10, 20], &mut out);
+        assert_eq!(vec![10, 20], out);
+        mixer.mix(1, &[30, 100], &mut out);
+        assert_eq!(vec![20, 60], out);
+    }
+
+    #[test]
+    fn mix_weighted() {
+        let mut mixer = Mixer::new(2, Weights::fixed(vec![3.0, 1.0]));
+        let mut out = vec![0; 2];
+        mixer.mix(0, &[100, 0], &mut out);
+        mixer.mix(1, &[0, 200], &mut out);
+        assert_eq!(vec![75, 50], out);
+    }
+
+    #[test]
+    fn mix_removed_input() {
+        let mut mixer = Mixer::new(3, Weights::fixed(vec![1.0, 2.0, 3.0]));
+        let mut out = vec![0; 1];
+        mixer.mix(0, &[0], &mut out);
+        mixer.mix(1, &[100], &mut out);
+        mixer.mix(2, &[200], &mut out);
+        mixer.remove_input(1);
+        // The last input keeps its weight of 3.
+        mixer.mix(1, &[200], &mut out);
+        assert_eq!(vec![150], out);
+    }
+
+    #[test]
+    fn mix_added_input() {
+        let mut mixer = Mixer::new(1, Weights::fixed(vec![1.0]));
+        mixer.add_input(Some(3.0));
+        let mut out = vec![0; 1];
+        mixer.mix(0, &[0], &mut out);
+        mixer.mix(1, &[100], &mut out);
+        assert_eq!(vec![75], out);
+    }
+
+    #[test]
+    fn mix_zero_weights() {
+        let mut mixer = Mixer::new(1, Weights::fixed(vec![0.0]));
+        let mut out = vec![9; 3];
+        mixer.mix(0, &[1, 2, 3], &mut out);
+        assert_eq!(vec![0, 0, 0], out);
+    }
+}
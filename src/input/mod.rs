@@ -1,2 +1,131 @@
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time;
+use nix::{fcntl, unistd};
+use self::geometry::Dimensions;
+
+pub mod crop;
+pub mod delta;
+pub mod expr;
 pub mod geometry;
+pub mod mask;
+pub mod mix;
+pub mod overlay;
+pub mod playlist;
+pub mod points;
+mod poll;
+pub mod rings;
+pub mod scale;
+#[cfg(feature = "script")]
+pub mod script;
 pub mod select;
+pub mod spec;
+pub mod tiles;
+
+
+/// The rate at which generated inputs produce frames if no framerate has been set.
+pub const DEFAULT_FRAMERATE: u32 = 30;
+
+/// Opens the file, FIFO or script of an input. Scripts generate frames of the dimensions of the
+/// placement of the input, if it has one.
+pub fn open(spec: &spec::Spec,
+            dimensions: Dimensions,
+            when_eof: select::WhenEOF,
+            generator_interval: time::Duration)
+            -> io::Result<fs::File> {
+    #[cfg(feature = "script")]
+    {
+        if spec.path.starts_with("script:") {
+            let geometry = spec.placement
+                .map(|p| p.geometry)
+                .unwrap_or(dimensions);
+            let script = script::Script::load(&spec.path["script:".len()..], geometry)?;
+            return script.run(generator_interval);
+        }
+    }
+    #[cfg(not(feature = "script"))]
+    let _ = (dimensions, generator_interval);
+
+    match spec.path.as_str() {
+        "-" => select::open_file("/dev/stdin", when_eof),
+        path => select::open_file(path, when_eof),
+    }
+}
+
+/// Checks that an input can be opened without opening it, since opening a FIFO blocks until it is
+/// opened for writing as well.
+pub fn check(spec: &spec::Spec, dimensions: Dimensions) -> io::Result<()> {
+    #[cfg(feature = "script")]
+    {
+        if spec.path.starts_with("script:") {
+            let geometry = spec.placement
+                .map(|p| p.geometry)
+                .unwrap_or(dimensions);
+            return script::Script::load(&spec.path["script:".len()..], geometry).map(|_| ());
+        }
+    }
+    #[cfg(not(feature = "script"))]
+    let _ = dimensions;
+
+    match spec.path.as_str() {
+        "-" => Ok(()),
+        path => fs::metadata(path).map(|_| ()),
+    }
+}
+
+
+/// Runs a function producing frames in the background. The function is handed the writing end
+/// of a pipe of which the reading end is returned so it can be used like any other input.
+pub fn spawn_generator<F>(generate: F) -> io::Result<fs::File>
+    where F: FnOnce(fs::File) + Send + 'static {
+    let (rd, wr) = io_err!(unistd::pipe())?;
+    let (rd, wr) = unsafe { (fs::File::from_raw_fd(rd), fs::File::from_raw_fd(wr)) };
+    // Commands that are started by the generator should not hold on to the pipe, or the end of
+    // the input would wait for them to exit.
+    for file in &[&rd, &wr] {
+        io_err!(fcntl::fcntl(file.as_raw_fd(), fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::FD_CLOEXEC)))?;
+    }
+    thread::spawn(move || generate(wr));
+    Ok(rd)
+}
+
+
+/// Reads full frames of a fixed size from the inner reader and passes each through a function
+/// before yielding it, e.g. to crop or scale it.
+pub struct MapFrames<R, F> {
+    inner: R,
+    map: F,
+    frame: Vec<u8>,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<R, F> MapFrames<R, F>
+    where R: io::Read,
+          F: FnMut(&[u8]) -> Vec<u8> {
+    pub fn new(inner: R, frame_size: usize, map: F) -> MapFrames<R, F> {
+        MapFrames {
+            inner,
+            map,
+            frame: vec![0; frame_size],
+            current: io::Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl<R, F> io::Read for MapFrames<R, F>
+    where R: io::Read,
+          F: FnMut(&[u8]) -> Vec<u8> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.current.position() == self.current.get_ref().len() as u64 {
+            match self.inner.read_exact(&mut self.frame) {
+                Ok(()) => (),
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(err) => return Err(err),
+            }
+            self.current = io::Cursor::new((self.map)(&self.frame));
+        }
+        self.current.read(buf)
+    }
+}
@@ -0,0 +1,52 @@
+/// The number of pixels that show the frame counter.
+pub const COUNTER_PIXELS: usize = 8;
+
+/// The colors of the set and cleared bits of the frame counter.
+const ONE: [u8; 3] = [0, 255, 0];
+const ZERO: [u8; 3] = [255, 0, 0];
+
+
+/// Draws the number of each frame over the first pixels of the frame, so dropped or frozen frames
+/// can be spotted on the display itself.
+///
+/// The number is shown in binary with the least significant bit first, as green pixels for the
+/// bits that are set and red ones for those that are not. The first pixel changes with every
+/// frame.
+#[derive(Clone, Debug, Default)]
+pub struct Counter {
+    frame: usize,
+}
+
+impl Counter {
+    pub fn apply(&mut self, frame: &[u8]) -> Vec<u8> {
+        let mut out = frame.to_vec();
+        for (bit, pix) in out.chunks_mut(3).take(COUNTER_PIXELS).enumerate() {
+            let color = if self.frame >> bit & 1 == 1 { ONE } else { ZERO };
+            pix.copy_from_slice(&color);
+        }
+        self.frame = self.frame.wrapping_add(1);
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_overlay() {
+        let mut counter = Counter::default();
+        let frame = vec![7; 10 * 3];
+        let out = counter.apply(&frame);
+        assert_eq!(ZERO.repeat(8), out[..24].to_vec());
+        assert_eq!(vec![7; 6], out[24..].to_vec());
+        let out = counter.apply(&frame);
+        assert_eq!(ONE.to_vec(), out[..3].to_vec());
+        assert_eq!(ZERO.repeat(7), out[3..24].to_vec());
+        let out = counter.apply(&frame);
+        assert_eq!([ZERO, ONE, ZERO].concat(), out[..9].to_vec());
+        // Frames with less pixels than the counter show the lowest bits.
+        assert_eq!(ONE.repeat(2), counter.apply(&[0; 6]));
+    }
+}
@@ -0,0 +1,374 @@
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path;
+use std::process;
+use std::str;
+use std::thread;
+use std::time;
+use regex::Regex;
+use input::geometry::*;
+use input::poll::Poller;
+use input::spawn_generator;
+
+/// Where the frames of a playlist item come from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// A file containing frames. The file is repeated if it ends before the item's duration has
+    /// passed.
+    File(path::PathBuf),
+    /// A shell command which writes frames to its stdout.
+    Command(String),
+    /// A solid color.
+    Color(u8, u8, u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub duration: time::Duration,
+    /// The time to crossfade from the previous item into this one.
+    pub fade: Option<time::Duration>,
+    pub source: Source,
+}
+
+
+/// A Playlist cycles through a list of sources, each shown for some duration.
+///
+/// A playlist is defined in a file with one item per line:
+///
+/// ```text
+/// # DURATION [fade=DURATION] SOURCE ARGS...
+/// 30s file /home/pi/fire.bin
+/// 5m fade=2s command python3 plasma.py
+/// 10s color ff8800
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Playlist {
+    pub items: Vec<Item>,
+}
+
+impl Playlist {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Playlist> {
+        let file = fs::File::open(path)?;
+        let mut items = Vec::new();
+        for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let item = line.parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", lineno + 1, err)))?;
+            items.push(item);
+        }
+        if items.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the playlist is empty"));
+        }
+        Ok(Playlist { items })
+    }
+
+    /// Starts playing the playlist in the background. The frames are written to a pipe of which
+    /// the reading end is returned.
+    ///
+    /// Frames are produced at a fixed interval so the durations of the items are not skewed by
+    /// frames buffered in the pipe.
+    ///
+    /// An item that fails is skipped once its duration has passed. If none of the items produced
+    /// a frame during a full pass, playback stops and the pipe is closed.
+    pub fn play(self, dimensions: Dimensions, frame_interval: time::Duration) -> io::Result<fs::File> {
+        spawn_generator(move |mut wr| {
+            let frame_size = dimensions.size() * 3;
+            let mut last_frame = vec![0; frame_size];
+            loop {
+                let mut num_frames = 0;
+                for item in &self.items {
+                    let start = time::Instant::now();
+                    let result = play_item(item, dimensions, frame_interval, &mut last_frame, &mut num_frames, &mut wr);
+                    match result {
+                        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return,
+                        Err(err) => {
+                            warn!("playlist: {:?}: {}", item.source, err);
+                            // Wait out the rest of the item instead of retrying it right away.
+                            let el = start.elapsed();
+                            if item.duration > el {
+                                thread::sleep(item.duration - el);
+                            }
+                        },
+                        Ok(_) => (),
+                    }
+                }
+                if num_frames == 0 {
+                    error!("playlist: none of the items produced a frame, stopping");
+                    return;
+                }
+            }
+        })
+    }
+}
+
+/// The running counterpart of a Source.
+enum Producer {
+    File(path::PathBuf, fs::File),
+    /// The output of the command is polled, so a command that stops writing does not hold up the
+    /// playlist past the end of the item.
+    Command(process::Child, process::ChildStdout, Poller),
+    Color(u8, u8, u8),
+}
+
+impl Producer {
+    fn start(source: &Source, dimensions: Dimensions) -> io::Result<Producer> {
+        Ok(match *source {
+            Source::File(ref path) => Producer::File(path.clone(), fs::File::open(path)?),
+            Source::Command(ref cmd) => {
+                let mut child = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(cmd)
+                    .env("LEDCAT_GEOMETRY", dimensions.to_string())
+                    .stdout(process::Stdio::piped())
+                    .spawn()?;
+                let stdout = child.stdout.take().unwrap();
+                let mut poller = Poller::new()?;
+                if !poller.add(stdout.as_raw_fd(), 0) {
+                    return Err(io::Error::other("can not poll the output of the command"));
+                }
+                Producer::Command(child, stdout, poller)
+            },
+            Source::Color(r, g, b) => Producer::Color(r, g, b),
+        })
+    }
+
+    /// Reads a frame into the buffer. Returns an error of kind `TimedOut` if a command has not
+    /// written a full frame by the deadline. Without a deadline, the command is waited for
+    /// indefinitely.
+    fn read_frame(&mut self, buf: &mut [u8], deadline: Option<time::Instant>) -> io::Result<()> {
+        match *self {
+            Producer::File(ref path, ref mut file) => {
+                match file.read_exact(buf) {
+                    Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                        // Repeat the file from the start.
+                        *file = fs::File::open(path)?;
+                        file.read_exact(buf)
+                    },
+                    rs => rs,
+                }
+            },
+            Producer::Command(_, ref mut stdout, ref mut poller) => {
+                let mut events = Vec::new();
+                let mut filled = 0;
+                while filled < buf.len() {
+                    let now = time::Instant::now();
+                    let timeout = match deadline {
+                        Some(deadline) if now >= deadline => {
+                            return Err(io::Error::new(io::ErrorKind::TimedOut, "the command did not write a frame in time"));
+                        },
+                        Some(deadline) => (deadline - now).as_millis().min(isize::MAX as u128) as isize,
+                        None => -1,
+                    };
+                    match poller.wait(&mut events, timeout) {
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        rs => rs?,
+                    }
+                    if events.is_empty() {
+                        continue;
+                    }
+                    match stdout.read(&mut buf[filled..]) {
+                        Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                        Ok(n) => filled += n,
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(())
+            },
+            Producer::Color(r, g, b) => {
+                for pix in buf.chunks_mut(3) {
+                    pix.copy_from_slice(&[r, g, b]);
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        if let Producer::Command(ref mut child, _, _) = *self {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn play_item(item: &Item,
+             dimensions: Dimensions,
+             frame_interval: time::Duration,
+             last_frame: &mut [u8],
+             num_frames: &mut usize,
+             out: &mut fs::File)
+             -> io::Result<()> {
+    let mut producer = Producer::start(&item.source, dimensions)?;
+    let start = time::Instant::now();
+    let prev_frame = last_frame.to_vec();
+    let mut frame = vec![0; dimensions.size() * 3];
+    while start.elapsed() < item.duration {
+        let frame_start = time::Instant::now();
+        match producer.read_frame(&mut frame, start.checked_add(item.duration)) {
+            // The item ended while waiting for the command, which is stopped when the producer
+            // is dropped.
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => {
+                debug!("playlist: {:?}: {}", item.source, err);
+                break;
+            },
+            rs => rs?,
+        }
+        last_frame.copy_from_slice(&frame);
+
+        let fade_progress = item.fade
+            .map(|fade| duration_fraction(start.elapsed(), fade))
+            .unwrap_or(1.0);
+        if fade_progress < 1.0 {
+            for (cur, &prev) in frame.iter_mut().zip(prev_frame.iter()) {
+                *cur = (f32::from(prev) * (1.0 - fade_progress) + f32::from(*cur) * fade_progress).round() as u8;
+            }
+        }
+        out.write_all(&frame)?;
+        *num_frames += 1;
+
+        let el = frame_start.elapsed();
+        if frame_interval >= el {
+            thread::sleep(frame_interval - el);
+        }
+    }
+    Ok(())
+}
+
+fn duration_fraction(elapsed: time::Duration, total: time::Duration) -> f32 {
+    let ms = |d: time::Duration| d.as_secs() as f32 * 1000.0 + d.subsec_nanos() as f32 / 1_000_000.0;
+    (ms(elapsed) / ms(total)).min(1.0)
+}
+
+/// Parses a duration such as "500ms", "30s", "5m" or "1h".
+pub fn parse_duration(s: &str) -> Result<time::Duration, String> {
+    let re = Regex::new(r"^(\d+)(ms|s|m|h)$").unwrap();
+    let cap = re.captures(s)
+        .ok_or_else(|| format!("can not parse \"{}\" into a duration", s))?;
+    let n: u64 = cap[1].parse().map_err(|err| format!("{}", err))?;
+    let secs = |unit: u64| n.checked_mul(unit)
+        .map(time::Duration::from_secs)
+        .ok_or_else(|| format!("the duration \"{}\" is too long", s));
+    match &cap[2] {
+        "ms" => Ok(time::Duration::from_millis(n)),
+        "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        _ => unreachable!(),
+    }
+}
+
+impl str::FromStr for Item {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let duration = parse_duration(fields.next().ok_or("missing duration")?)?;
+        let mut kind = fields.next().ok_or("missing source")?;
+        let mut fade = None;
+        if kind.starts_with("fade=") {
+            fade = Some(parse_duration(&kind["fade=".len()..])?);
+            kind = fields.next().ok_or("missing source")?;
+        }
+        let args: Vec<&str> = fields.collect();
+        if args.is_empty() {
+            return Err(format!("missing arguments for {}", kind));
+        }
+        let source = match kind {
+            "file" => Source::File(path::PathBuf::from(args.join(" "))),
+            "command" => Source::Command(args.join(" ")),
+            "color" => {
+                let re = Regex::new(r"^#?([0-9a-fA-F]{2})([0-9a-fA-F]{2})([0-9a-fA-F]{2})$").unwrap();
+                let cap = re.captures(args[0])
+                    .ok_or_else(|| format!("can not parse \"{}\" into a color", args[0]))?;
+                let hex = |i: usize| u8::from_str_radix(&cap[i], 16).unwrap();
+                Source::Color(hex(1), hex(2), hex(3))
+            },
+            _ => return Err(format!("unknown source: {}", kind)),
+        };
+        Ok(Item { duration, fade, source })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_parse() {
+        assert_eq!(time::Duration::from_millis(500), parse_duration("500ms").unwrap());
+        assert_eq!(time::Duration::from_secs(30), parse_duration("30s").unwrap());
+        assert_eq!(time::Duration::from_secs(300), parse_duration("5m").unwrap());
+        assert_eq!(time::Duration::from_secs(7200), parse_duration("2h").unwrap());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("9999999999999999999h").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn item_parse() {
+        assert_eq!(Item {
+            duration: time::Duration::from_secs(30),
+            fade: None,
+            source: Source::File(path::PathBuf::from("/tmp/fire.bin")),
+        }, "30s file /tmp/fire.bin".parse().unwrap());
+        assert_eq!(Item {
+            duration: time::Duration::from_secs(300),
+            fade: Some(time::Duration::from_secs(2)),
+            source: Source::Command("python3 plasma.py --fast".to_string()),
+        }, "5m fade=2s command python3 plasma.py --fast".parse().unwrap());
+        assert_eq!(Item {
+            duration: time::Duration::from_secs(10),
+            fade: None,
+            source: Source::Color(0xff, 0x88, 0x00),
+        }, "10s color ff8800".parse().unwrap());
+    }
+
+    #[test]
+    fn play_failing_items() {
+        let playlist = Playlist {
+            items: vec![
+                "50ms file /nonexistent/a.bin".parse().unwrap(),
+                "50ms command exit 1".parse().unwrap(),
+            ],
+        };
+        let start = time::Instant::now();
+        let mut out = playlist.play(Dimensions::One(4), time::Duration::from_millis(10)).unwrap();
+        let mut buf = Vec::new();
+        out.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert!(start.elapsed() >= time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn play_stalled_command() {
+        let playlist = Playlist {
+            items: vec!["100ms command sleep 60".parse().unwrap()],
+        };
+        let start = time::Instant::now();
+        let mut out = playlist.play(Dimensions::One(4), time::Duration::from_millis(10)).unwrap();
+        let mut buf = Vec::new();
+        out.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert!(start.elapsed() >= time::Duration::from_millis(100));
+        assert!(start.elapsed() < time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn item_parse_invalid() {
+        assert!("".parse::<Item>().is_err());
+        assert!("10s".parse::<Item>().is_err());
+        assert!("10s file".parse::<Item>().is_err());
+        assert!("10s video foo.mp4".parse::<Item>().is_err());
+        assert!("10s color red".parse::<Item>().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path;
+use std::str;
+use input::geometry::Dimensions;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    /// Looks at the points from the front, along the z-axis.
+    Planar,
+    /// Wraps the frame around the vertical y-axis, the left and right edges of the frame meet
+    /// behind the object.
+    Cylindrical,
+}
+
+impl str::FromStr for Projection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "planar" => Ok(Projection::Planar),
+            "cylindrical" => Ok(Projection::Cylindrical),
+            _ => Err(format!("unknown projection: {}", s)),
+        }
+    }
+}
+
+
+/// The positions of the LEDs of a display in 3D space.
+///
+/// Points are loaded from a file with the x, y and z coordinates of one LED per line, separated
+/// by commas or whitespace. The y-axis points up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Points {
+    pub points: Vec<(f32, f32, f32)>,
+}
+
+impl Points {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Points> {
+        let file = fs::File::open(path)?;
+        let mut points = Vec::new();
+        for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let point = parse_point(line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", lineno + 1, err)))?;
+            points.push(point);
+        }
+        if points.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the file contains no points"));
+        }
+        Ok(Points { points })
+    }
+
+    /// Projects the points onto a frame with the specified dimensions. The index of the pixel
+    /// nearest to each point is returned.
+    pub fn project(&self, projection: Projection, frame: Dimensions) -> Vec<usize> {
+        let (w, h) = match frame {
+            Dimensions::One(len) => (len, 1),
+            Dimensions::Two(w, h) => (w, h),
+        };
+        let (min, max) = self.bounds();
+        // Normalize a coordinate to 0..1, a flat axis is mapped to the center.
+        let norm = |v: f32, min: f32, max: f32| if max > min { (v - min) / (max - min) } else { 0.5 };
+        let (cx, cz) = ((min.0 + max.0) / 2.0, (min.2 + max.2) / 2.0);
+        self.points.iter()
+            .map(|&(x, y, z)| {
+                let u = match projection {
+                    Projection::Planar => norm(x, min.0, max.0),
+                    Projection::Cylindrical => ((z - cz).atan2(x - cx) + PI) / (2.0 * PI),
+                };
+                // The y-axis points up while the rows of a frame go down.
+                let v = 1.0 - norm(y, min.1, max.1);
+                let px = match projection {
+                    Projection::Planar => ((u * w as f32) as usize).min(w - 1),
+                    // The angle wraps around, so the right edge continues at the left.
+                    Projection::Cylindrical => (u * w as f32) as usize % w,
+                };
+                let py = ((v * h as f32) as usize).min(h - 1);
+                py * w + px
+            })
+            .collect()
+    }
+
+    /// Returns the dimensions of the bounding box of the points if they lie on a grid of whole
+    /// numbers, such as the pixel positions of an image.
+    pub fn grid_dimensions(&self) -> Option<Dimensions> {
+        if self.points.iter().any(|&(x, y, _)| x.fract() != 0.0 || y.fract() != 0.0) {
+            return None;
+        }
+        let (min, max) = self.bounds();
+        let (w, h) = ((max.0 - min.0) as usize + 1, (max.1 - min.1) as usize + 1);
+        Some(if h == 1 { Dimensions::One(w) } else { Dimensions::Two(w, h) })
+    }
+
+    fn bounds(&self) -> ((f32, f32, f32), (f32, f32, f32)) {
+        let first = self.points[0];
+        self.points.iter().fold((first, first), |(min, max), &p| {
+            ((min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+             (max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)))
+        })
+    }
+}
+
+fn parse_point(line: &str) -> Result<(f32, f32, f32), String> {
+    let coords = line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f32>().map_err(|_| format!("invalid coordinate: \"{}\"", v)))
+        .collect::<Result<Vec<f32>, String>>()?;
+    match coords.as_slice() {
+        [x, y, z] => Ok((*x, *y, *z)),
+        _ => Err(format!("expected 3 coordinates, got {}", coords.len())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_parse() {
+        assert_eq!(Ok((1.0, -2.5, 3.0)), parse_point("1, -2.5, 3"));
+        assert_eq!(Ok((1.0, 2.0, 3.0)), parse_point("1 2\t3"));
+        assert!(parse_point("1, 2").is_err());
+        assert!(parse_point("1, 2, x").is_err());
+    }
+
+    #[test]
+    fn project_planar() {
+        let points = Points {
+            points: vec![(0.0, 1.0, 0.0), (1.0, 1.0, 5.0), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0)],
+        };
+        assert_eq!(vec![0, 3, 12, 15], points.project(Projection::Planar, Dimensions::Two(4, 4)));
+    }
+
+    #[test]
+    fn project_cylindrical() {
+        // Four points around the y-axis at the same height.
+        let points = Points {
+            points: vec![(-1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)],
+        };
+        assert_eq!(vec![4, 5, 6, 7], points.project(Projection::Cylindrical, Dimensions::Two(4, 2)));
+    }
+
+    #[test]
+    fn grid_dimensions() {
+        let points = Points { points: vec![(2.0, 1.0, 0.0), (5.0, 3.0, 0.0)] };
+        assert_eq!(Some(Dimensions::Two(4, 3)), points.grid_dimensions());
+        let points = Points { points: vec![(0.0, 0.0, 0.0), (7.0, 0.0, 1.0)] };
+        assert_eq!(Some(Dimensions::One(8)), points.grid_dimensions());
+        let points = Points { points: vec![(0.0, 0.0, 0.0), (0.5, 1.0, 0.0)] };
+        assert_eq!(None, points.grid_dimensions());
+    }
+}
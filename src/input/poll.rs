@@ -0,0 +1,149 @@
+//! Waits for any of a set of inputs to become readable. Linux and Android use epoll, other
+//! platforms fall back to poll(2).
+
+use std::io;
+use std::os::unix::io::RawFd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::fs;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::sys::epoll;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::mem;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use nix::libc;
+
+
+/// The maximum number of events handled per call to wait().
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MAX_EVENTS: usize = 64;
+
+/// An event of a registered file descriptor, identified by its token.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub token: u64,
+    /// Whether there is data to read. If not, the other end hung up.
+    pub readable: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub struct Poller {
+    epoll: fs::File,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Poller {
+    pub fn new() -> io::Result<Poller> {
+        let epoll = io_err!(epoll::epoll_create1(epoll::EpollCreateFlags::EPOLL_CLOEXEC))?;
+        Ok(Poller { epoll: unsafe { fs::File::from_raw_fd(epoll) } })
+    }
+
+    /// Registers a file descriptor, returns whether that succeeded. Regular files can not be
+    /// registered, they are always readable.
+    pub fn add(&mut self, fd: RawFd, token: u64) -> bool {
+        let mut event = epoll::EpollEvent::new(epoll::EpollFlags::EPOLLIN, token);
+        epoll::epoll_ctl(self.epoll.as_raw_fd(), epoll::EpollOp::EpollCtlAdd, fd, &mut event).is_ok()
+    }
+
+    pub fn remove(&mut self, fd: RawFd) {
+        let _ = epoll::epoll_ctl(self.epoll.as_raw_fd(), epoll::EpollOp::EpollCtlDel, fd, None);
+    }
+
+    /// Waits for events for up to `timeout` milliseconds, or indefinitely if it is negative. The
+    /// events replace the contents of `events`.
+    pub fn wait(&mut self, events: &mut Vec<Event>, timeout: isize) -> io::Result<()> {
+        let mut buf = [epoll::EpollEvent::empty(); MAX_EVENTS];
+        events.clear();
+        let num_events = match epoll::epoll_wait(self.epoll.as_raw_fd(), &mut buf, timeout) {
+            Err(::nix::Error::Sys(::nix::errno::Errno::EINTR)) => {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            },
+            rs => io_err!(rs)?,
+        };
+        events.extend(buf[..num_events].iter().map(|ev| Event {
+            token: ev.data(),
+            readable: ev.events().contains(epoll::EpollFlags::EPOLLIN),
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub struct Poller {
+    fds: Vec<libc::pollfd>,
+    tokens: Vec<u64>,
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl Poller {
+    pub fn new() -> io::Result<Poller> {
+        Ok(Poller { fds: Vec::new(), tokens: Vec::new() })
+    }
+
+    /// Registers a file descriptor, returns whether that succeeded. Regular files are not
+    /// registered, like with epoll, they are always readable.
+    pub fn add(&mut self, fd: RawFd, token: u64) -> bool {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT == libc::S_IFREG {
+            return false;
+        }
+        self.fds.push(libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+        self.tokens.push(token);
+        true
+    }
+
+    pub fn remove(&mut self, fd: RawFd) {
+        if let Some(i) = self.fds.iter().position(|p| p.fd == fd) {
+            self.fds.remove(i);
+            self.tokens.remove(i);
+        }
+    }
+
+    /// Waits for events for up to `timeout` milliseconds, or indefinitely if it is negative. The
+    /// events replace the contents of `events`.
+    pub fn wait(&mut self, events: &mut Vec<Event>, timeout: isize) -> io::Result<()> {
+        events.clear();
+        let timeout = timeout.clamp(-1, libc::c_int::MAX as isize) as libc::c_int;
+        if unsafe { libc::poll(self.fds.as_mut_ptr(), self.fds.len() as libc::nfds_t, timeout) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        events.extend(self.fds.iter().zip(&self.tokens)
+            .filter(|&(p, _)| p.revents != 0)
+            .map(|(p, &token)| Event { token, readable: p.revents & libc::POLLIN != 0 }));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use nix::unistd;
+
+    #[test]
+    fn pipe_events() {
+        let (rd, wr) = unistd::pipe().unwrap();
+        let (rd, mut wr) = unsafe { (fs::File::from_raw_fd(rd), fs::File::from_raw_fd(wr)) };
+        let mut poller = Poller::new().unwrap();
+        assert!(poller.add(rd.as_raw_fd(), 7));
+        let mut events = Vec::new();
+        poller.wait(&mut events, 0).unwrap();
+        assert!(events.is_empty());
+        wr.write_all(&[1]).unwrap();
+        poller.wait(&mut events, -1).unwrap();
+        assert_eq!(vec![Event { token: 7, readable: true }], events);
+        poller.remove(rd.as_raw_fd());
+        poller.wait(&mut events, 0).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn regular_file() {
+        let file = fs::File::open("Cargo.toml").unwrap();
+        assert!(!Poller::new().unwrap().add(file.as_raw_fd(), 0));
+    }
+}
@@ -0,0 +1,85 @@
+use std::f32::consts::PI;
+use std::str;
+use input::geometry::Dimensions;
+
+
+/// The layout of a display made of concentric rings of pixels, such as the popular 241 pixel
+/// discs.
+///
+/// The rings are listed from the innermost ring outward and are evenly spaced from the center to
+/// the edge of the frame. A ring of a single pixel is placed at the center. The first pixel of
+/// each ring is at the top, the pixels run clockwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rings {
+    pub counts: Vec<usize>,
+}
+
+impl Rings {
+    /// Returns the total number of pixels.
+    pub fn size(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns for each pixel of the rings the index of the pixel of a frame with the specified
+    /// dimensions that it should show.
+    pub fn project(&self, frame: Dimensions) -> Vec<usize> {
+        let (w, h) = match frame {
+            Dimensions::One(len) => (len, 1),
+            Dimensions::Two(w, h) => (w, h),
+        };
+        let n = self.counts.len();
+        let center_pixel = self.counts[0] == 1;
+        let mut pixels = Vec::with_capacity(self.size());
+        for (k, &count) in self.counts.iter().enumerate() {
+            let radius = if center_pixel {
+                if n > 1 { k as f32 / (n - 1) as f32 } else { 0.0 }
+            } else {
+                (k + 1) as f32 / n as f32
+            };
+            for j in 0..count {
+                let angle = 2.0 * PI * j as f32 / count as f32;
+                let u = 0.5 + 0.5 * radius * angle.sin();
+                let v = 0.5 - 0.5 * radius * angle.cos();
+                let px = ((u * w as f32) as usize).min(w - 1);
+                let py = ((v * h as f32) as usize).min(h - 1);
+                pixels.push(py * w + px);
+            }
+        }
+        pixels
+    }
+}
+
+impl str::FromStr for Rings {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let counts = s.split(',')
+            .map(|v| match v.trim().parse::<usize>() {
+                Ok(c) if c > 0 => Ok(c),
+                _ => Err(format!("invalid number of pixels in a ring: \"{}\"", v)),
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+        Ok(Rings { counts })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rings_parse() {
+        assert_eq!(Rings { counts: vec![1, 8, 12] }, "1,8,12".parse().unwrap());
+        assert_eq!(21, "1,8,12".parse::<Rings>().unwrap().size());
+        assert!("".parse::<Rings>().is_err());
+        assert!("1,0".parse::<Rings>().is_err());
+        assert!("1,x".parse::<Rings>().is_err());
+    }
+
+    #[test]
+    fn rings_project() {
+        // A center pixel and a ring of 4 at the edges of a 5x5 frame: top, right, bottom, left.
+        let rings = Rings { counts: vec![1, 4] };
+        assert_eq!(vec![12, 2, 14, 22, 10], rings.project(Dimensions::Two(5, 5)));
+    }
+}
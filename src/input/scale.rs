@@ -0,0 +1,123 @@
+use std::str;
+use input::geometry::Dimensions;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Take the pixel nearest to the center of each output pixel.
+    Nearest,
+    /// Average all pixels that are covered by an output pixel.
+    Box,
+}
+
+impl str::FromStr for Filter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "box" => Ok(Filter::Box),
+            _ => Err(format!("unknown scaling filter: {}", s)),
+        }
+    }
+}
+
+
+/// Resamples frames to a different resolution.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Scale {
+    pub source: Dimensions,
+    pub target: Dimensions,
+    pub filter: Filter,
+}
+
+impl Scale {
+    pub fn apply(&self, frame: &[u8]) -> Vec<u8> {
+        let (sw, sh) = extent(self.source);
+        let (tw, th) = extent(self.target);
+        assert_eq!(sw * sh * 3, frame.len());
+        let mut out = Vec::with_capacity(tw * th * 3);
+        for ty in 0..th {
+            for tx in 0..tw {
+                match self.filter {
+                    Filter::Nearest => {
+                        let sx = (2 * tx + 1) * sw / (2 * tw);
+                        let sy = (2 * ty + 1) * sh / (2 * th);
+                        let i = (sy * sw + sx) * 3;
+                        out.extend_from_slice(&frame[i..i + 3]);
+                    },
+                    Filter::Box => {
+                        let (x0, x1) = span(tx, sw, tw);
+                        let (y0, y1) = span(ty, sh, th);
+                        let mut sum = [0u32; 3];
+                        for sy in y0..y1 {
+                            for sx in x0..x1 {
+                                let i = (sy * sw + sx) * 3;
+                                for c in 0..3 {
+                                    sum[c] += u32::from(frame[i + c]);
+                                }
+                            }
+                        }
+                        let n = ((x1 - x0) * (y1 - y0)) as u32;
+                        out.extend(sum.iter().map(|&s| ((s + n / 2) / n) as u8));
+                    },
+                }
+            }
+        }
+        out
+    }
+}
+
+fn extent(dim: Dimensions) -> (usize, usize) {
+    match dim {
+        Dimensions::One(len) => (len, 1),
+        Dimensions::Two(w, h) => (w, h),
+    }
+}
+
+/// Returns the range of source pixels covered by target pixel i. At least one pixel is covered,
+/// so upscaling falls back to nearest neighbour.
+fn span(i: usize, source: usize, target: usize) -> (usize, usize) {
+    let start = i * source / target;
+    let end = ((i + 1) * source / target).max(start + 1);
+    (start, end)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(values: &[u8]) -> Vec<u8> {
+        values.iter().flat_map(|&v| vec![v; 3]).collect()
+    }
+
+    #[test]
+    fn scale_nearest() {
+        let scale = Scale {
+            source: Dimensions::Two(4, 2),
+            target: Dimensions::Two(2, 1),
+            filter: Filter::Nearest,
+        };
+        assert_eq!(gray(&[50, 70]), scale.apply(&gray(&[0, 10, 20, 30, 40, 50, 60, 70])));
+    }
+
+    #[test]
+    fn scale_box() {
+        let scale = Scale {
+            source: Dimensions::Two(4, 2),
+            target: Dimensions::Two(2, 1),
+            filter: Filter::Box,
+        };
+        assert_eq!(gray(&[25, 45]), scale.apply(&gray(&[0, 10, 20, 30, 40, 50, 60, 70])));
+    }
+
+    #[test]
+    fn scale_1d() {
+        let scale = Scale {
+            source: Dimensions::One(6),
+            target: Dimensions::One(3),
+            filter: Filter::Box,
+        };
+        assert_eq!(gray(&[5, 25, 45]), scale.apply(&gray(&[0, 10, 20, 30, 40, 50])));
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path;
+use std::thread;
+use std::time;
+use rhai;
+use input::geometry::*;
+use input::spawn_generator;
+
+
+/// The number of operations a single call of `pixel()` may take, so a script that loops forever
+/// stops with an error instead of hanging the generator.
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// The depth to which functions may call each other.
+const MAX_CALL_LEVELS: usize = 64;
+/// The depth to which expressions may be nested, at the top level and in functions.
+const MAX_EXPR_DEPTHS: (usize, usize) = (64, 32);
+
+/// A Script generates frames by evaluating a function written in Rhai for each pixel.
+///
+/// The script should define a function `pixel(x, y, t)` where `t` is the time in seconds since
+/// the script was started. The function should return the color of the pixel either as a
+/// 0xRRGGBB integer or as an `[r, g, b]` array. The functions `width()` and `height()` return
+/// the size of the display.
+pub struct Script {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    dimensions: Dimensions,
+}
+
+impl Script {
+    pub fn load<P: AsRef<path::Path>>(path: P, dimensions: Dimensions) -> io::Result<Script> {
+        let mut source = String::new();
+        fs::File::open(path)?.read_to_string(&mut source)?;
+        Script::compile(&source, dimensions)
+    }
+
+    pub fn compile(source: &str, dimensions: Dimensions) -> io::Result<Script> {
+        let (width, height) = match dimensions {
+            Dimensions::One(len) => (len as rhai::INT, 1),
+            Dimensions::Two(w, h) => (w as rhai::INT, h as rhai::INT),
+        };
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_expr_depths(MAX_EXPR_DEPTHS.0, MAX_EXPR_DEPTHS.1);
+        engine.register_fn("width", move || width);
+        engine.register_fn("height", move || height);
+        let ast = engine.compile(source)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        Ok(Script { engine, ast, dimensions })
+    }
+
+    /// Renders the frame at time t into the buffer.
+    pub fn render(&self, t: f64, frame: &mut [u8]) -> io::Result<()> {
+        let width = match self.dimensions {
+            Dimensions::One(len) => len,
+            Dimensions::Two(w, _) => w,
+        };
+        let mut scope = rhai::Scope::new();
+        for (i, pix) in frame.chunks_mut(3).enumerate() {
+            let (x, y) = ((i % width) as rhai::INT, (i / width) as rhai::INT);
+            let color: rhai::Dynamic = self.engine.call_fn(&mut scope, &self.ast, "pixel", (x, y, t))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            pix.copy_from_slice(&to_rgb(color)?);
+        }
+        Ok(())
+    }
+
+    /// Starts rendering frames in the background at the specified interval. The frames are
+    /// written to a pipe of which the reading end is returned.
+    pub fn run(self, frame_interval: time::Duration) -> io::Result<fs::File> {
+        spawn_generator(move |mut wr| {
+            let start = time::Instant::now();
+            let mut frame = vec![0; self.dimensions.size() * 3];
+            loop {
+                let frame_start = time::Instant::now();
+                let el = start.elapsed();
+                let t = el.as_secs() as f64 + f64::from(el.subsec_nanos()) / 1_000_000_000.0;
+                if let Err(err) = self.render(t, &mut frame) {
+                    error!("script: {}", err);
+                    return;
+                }
+                if wr.write_all(&frame).is_err() {
+                    return;
+                }
+
+                let el = frame_start.elapsed();
+                if frame_interval >= el {
+                    thread::sleep(frame_interval - el);
+                }
+            }
+        })
+    }
+}
+
+fn to_rgb(color: rhai::Dynamic) -> io::Result<[u8; 3]> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if let Ok(i) = color.as_int() {
+        return Ok([(i >> 16) as u8, (i >> 8) as u8, i as u8]);
+    }
+    let type_name = color.type_name();
+    let arr = color.into_array()
+        .map_err(|_| invalid(format!("pixel() should return an int or an array, got {}", type_name)))?;
+    if arr.len() != 3 {
+        return Err(invalid(format!("pixel() should return 3 color components, got {}", arr.len())));
+    }
+    let mut rgb = [0; 3];
+    for (c, v) in rgb.iter_mut().zip(arr) {
+        let f = v.as_int().map(|i| i as rhai::FLOAT)
+            .or_else(|_| v.as_float())
+            .map_err(|t| invalid(format!("color components should be numbers, got {}", t)))?;
+        *c = f.round().clamp(0.0, 255.0) as u8;
+    }
+    Ok(rgb)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_int() {
+        let script = Script::compile("fn pixel(x, y, t) { 0x010203 * (x + y * width() + 1) }",
+                                     Dimensions::Two(2, 2)).unwrap();
+        let mut frame = vec![0; 2 * 2 * 3];
+        script.render(0.0, &mut frame).unwrap();
+        assert_eq!(vec![1, 2, 3, 2, 4, 6, 3, 6, 9, 4, 8, 12], frame);
+    }
+
+    #[test]
+    fn render_array() {
+        let script = Script::compile("fn pixel(x, y, t) { [x * 255, t * 100.0, 300] }",
+                                     Dimensions::One(2)).unwrap();
+        let mut frame = vec![0; 2 * 3];
+        script.render(0.5, &mut frame).unwrap();
+        assert_eq!(vec![0, 50, 255, 255, 50, 255], frame);
+    }
+
+    #[test]
+    fn render_endless() {
+        let script = Script::compile("fn pixel(x, y, t) { loop {} }", Dimensions::One(1)).unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, script.render(0.0, &mut [0; 3]).unwrap_err().kind());
+        let script = Script::compile("fn f(n) { f(n + 1) } fn pixel(x, y, t) { f(0) }", Dimensions::One(1)).unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, script.render(0.0, &mut [0; 3]).unwrap_err().kind());
+    }
+
+    #[test]
+    fn render_invalid() {
+        let script = Script::compile("fn pixel(x, y, t) { \"red\" }", Dimensions::One(1)).unwrap();
+        assert!(script.render(0.0, &mut [0; 3]).is_err());
+        assert!(Script::compile("fn pixel(x, y, t) {", Dimensions::One(1)).is_err());
+    }
+}
@@ -1,154 +1,750 @@
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
+use std::mem;
 use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path;
-use std::thread;
+use std::str;
+use std::sync::{mpsc, Arc};
 use std::time;
-use nix::{fcntl, poll};
+use nix::{self, fcntl, unistd};
+use input::delta;
+use input::geometry::Dimensions;
+use input::mix::Mixer;
+use input::poll::{Event, Poller};
+use input::spec::{Format, Placement};
+use stats::Stats;
 
 
+/// The poll token of the pipe that signals changes to the set of inputs.
+const WAKE_TOKEN: u64 = u64::MAX;
+
+/// The time an input has to stay empty for the data it sent before to be considered a single frame
+/// when checking the size of frames.
+const FRAME_GAP: time::Duration = time::Duration::from_millis(5);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum WhenEOF {
     Close,
     Retry,
 }
 
+/// What to do with frames that are smaller or larger than the display. Frames are delimited by
+/// pauses in the input, so the bytes beyond a frame are not taken to be the start of the next one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SizeMismatch {
+    /// Fill up short frames with black, cut off long frames.
+    Pad,
+    /// Discard short frames, cut off long frames.
+    Truncate,
+    /// Stop reading with an error.
+    Error,
+}
+
+impl str::FromStr for SizeMismatch {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pad" => Ok(SizeMismatch::Pad),
+            "truncate" => Ok(SizeMismatch::Truncate),
+            "error" => Ok(SizeMismatch::Error),
+            _ => Err(format!("unknown size mismatch mode: {}, expected pad, truncate or error", s)),
+        }
+    }
+}
+
 pub trait ReadFd: io::Read + AsRawFd { }
 
 impl<T> ReadFd for T
     where T: io::Read + AsRawFd { }
 
+/// Opens a file for use as an input of the Reader.
+pub fn open_file<P: AsRef<path::Path>>(filename: P, when_eof: WhenEOF) -> io::Result<fs::File> {
+    let mut open_opts = fs::OpenOptions::new();
+    open_opts.read(true);
+
+    let is_fifo = fs::metadata(&filename)?.file_type().is_fifo();
+    if is_fifo {
+        // A FIFO will block the call to open() until the other end has been opened. This
+        // means that when multiple FIFO's are used, they all have to be open at once
+        // before this program can continue.
+        // Opening the file with O_NONBLOCK will ensure that we don't have to wait.
+        open_opts.custom_flags(fcntl::OFlag::O_NONBLOCK.bits());
+
+        if when_eof == WhenEOF::Retry {
+            // When the first program writing to the FIFO closes the writing end, polling will
+            // immediately return with a hangup for the respective reading end because all
+            // writing ends have been closed. If we open the FIFO for writing ourselves,
+            // there will always be writers. This ensures that polling never reports a hangup.
+            open_opts.write(true);
+        }
+    }
+
+    open_opts.open(&filename)
+}
+
+fn set_nonblocking(fd: RawFd) -> nix::Result<()> {
+    let flags = fcntl::OFlag::from_bits_truncate(fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFL)?);
+    fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFL(flags | fcntl::OFlag::O_NONBLOCK))?;
+    Ok(())
+}
+
+/// A change to the set of inputs of a running Reader.
+pub enum Change {
+    Add {
+        input: fs::File,
+        placement: Option<Placement>,
+        format: Format,
+        /// The weight of the input when inputs are mixed.
+        weight: Option<f32>,
+    },
+    Remove(usize),
+    /// Shows the next input, if the Reader cycles through its inputs.
+    Cycle,
+    /// Shows the input at the index, if the Reader cycles through its inputs.
+    Select(usize),
+}
+
+/// A Controller is used to add and remove inputs of a Reader from another thread.
+pub struct Controller {
+    tx: mpsc::Sender<Change>,
+    wake: fs::File,
+}
+
+impl Controller {
+    /// Returns another Controller for the same Reader, to be used from another thread.
+    pub fn try_clone(&self) -> io::Result<Controller> {
+        Ok(Controller { tx: self.tx.clone(), wake: self.wake.try_clone()? })
+    }
+
+    pub fn send(&mut self, change: Change) -> io::Result<()> {
+        self.tx.send(change)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the reader has been closed"))?;
+        // Interrupt the wait of the Reader so the change is applied immediately.
+        self.wake.write_all(&[0])
+    }
+}
+
+/// The outcome of reading from an input once.
+enum Progress {
+    Frame,
+    Partial,
+    Idle,
+    Eof,
+}
+
+struct Input {
+    reader: Box<dyn ReadFd + Send>,
+    // Identifies the input in poll events, indices can not be used because they change when
+    // inputs are removed.
+    token: u64,
+    // Regular files can not be polled, they are considered to always be readable.
+    pollable: bool,
+    // Whether the input is currently registered with the poller.
+    registered: bool,
+    // The number of bytes in a frame. Inputs that are placed at an offset in the display may
+    // have frames that are smaller than a full frame.
+    frame_size: usize,
+    // Set if the input does not cover the full display.
+    placement: Option<Placement>,
+    // Set if the input sends delta frames instead of full frames.
+    decoder: Option<delta::Decoder>,
+    // Partially received content.
+    buffer: Vec<u8>,
+    // The number of bytes received since the input last paused, used to check the size of frames.
+    burst: usize,
+    // The first and the last time a read found the input empty since it last sent data. The input
+    // has paused once it has been empty for FRAME_GAP. Time during which the input was not read
+    // from does not count, data may have been waiting in the pipe.
+    idle: Option<(time::Instant, time::Instant)>,
+    // Whether a frame of the wrong size has been reported, so it is not reported for every frame.
+    mismatch_reported: bool,
+    // The time at which EOF was reached. With WhenEOF::Retry, the input is read from again after
+    // the retry interval has passed.
+    eof_at: Option<time::Instant>,
+}
+
+impl Input {
+    /// Registers the input with the poller, returns whether that succeeded.
+    fn register(&mut self, poller: &mut Poller) -> bool {
+        self.registered = poller.add(self.reader.as_raw_fd(), self.token);
+        self.registered
+    }
+
+    fn deregister(&mut self, poller: &mut Poller) {
+        if self.registered {
+            poller.remove(self.reader.as_raw_fd());
+            self.registered = false;
+        }
+    }
+
+    /// Marks the input as having reached EOF. It is removed from the poller so a closed pipe does
+    /// not cause the wait to return immediately. Returns whether the input was alive before.
+    fn set_eof(&mut self, poller: &mut Poller, now: time::Instant) -> bool {
+        let was_alive = self.eof_at.is_none();
+        self.eof_at = Some(now);
+        self.deregister(poller);
+        was_alive
+    }
+
+    /// Decodes the next frame of a delta input into the buffer, returns whether there was one. Data
+    /// that can not be decoded is discarded with a warning, so a misbehaving producer does not stop
+    /// the other inputs.
+    fn decode_frame(&mut self, i: usize) -> bool {
+        let dec = match self.decoder {
+            Some(ref mut dec) => dec,
+            None => return false,
+        };
+        match dec.next_frame(&mut self.buffer) {
+            Ok(complete) => complete,
+            Err(err) => {
+                event!(::log::Level::Warn, "delta_invalid", {"input": i, "error": err.to_string()},
+                       "Discarded the delta frames of input {}: {}", i, err);
+                false
+            },
+        }
+    }
+
+    /// Marks the input as alive after it was read from successfully. Returns whether the input
+    /// had reached EOF before.
+    fn set_alive(&mut self, poller: &mut Poller) -> bool {
+        if self.eof_at.take().is_none() {
+            return false;
+        }
+        if self.pollable {
+            self.register(poller);
+        }
+        true
+    }
+}
+
 pub struct Reader {
     when_eof: WhenEOF,
 
-    inputs: Vec<Box<ReadFd + Send>>,
+    inputs: Vec<Input>,
+    // Waits for the pollable inputs and the pipe of the controller.
+    poller: Poller,
+    next_token: u64,
     // The number of bytes after which another input is selected.
     switch_after: usize,
-    // A buffer for each input to be used for partially received content.
-    buffers: Vec<Vec<u8>>,
-    // The current buffer selected for output.
-    current: io::Cursor<Vec<u8>>,
+    // The geometry of the full display, required for placing inputs.
+    display: Option<Dimensions>,
+    // The last full frame, used to composite placed inputs into. This is also the frame that is
+    // read from.
+    canvas: Vec<u8>,
+    // A black frame that placed inputs are composited onto before mixing.
+    layer: Vec<u8>,
+    // If set, frames of all live inputs are mixed together instead of selecting one by priority.
+    mixer: Option<Mixer>,
+    // If set, only the frames of the input at this index are shown, those of the other inputs
+    // are read and discarded.
+    active: Option<usize>,
+    // The position in the canvas up to which it has been read.
+    position: usize,
     // The time after which a partially received frame should be discarded.
     clear_timeout: Option<time::Duration>,
+    // The last time any data was received, used to determine when the clear timeout expires.
+    last_data: time::Instant,
+    // Receives changes to the set of inputs along with the reading end of a pipe that is written
+    // to for each change.
+    changes: Option<(mpsc::Receiver<Change>, fs::File)>,
+    // Counts the partial frames that are discarded and the frames of each input.
+    stats: Option<Arc<Stats>>,
+    // The input of which a frame was selected last, used to report switches between inputs.
+    selected: Option<usize>,
+    // If set, frames are delimited by pauses in the input and those of the wrong size are
+    // handled accordingly.
+    size_mismatch: Option<SizeMismatch>,
 }
 
 impl Reader {
     pub fn from_files<P>(filenames: Vec<P>, switch_after: usize, when_eof: WhenEOF, clear_timeout: Option<time::Duration>) -> io::Result<Reader>
         where P: AsRef<path::Path> {
         let files: io::Result<Vec<Box<ReadFd + Send>>> = filenames.into_iter().map(|filename| {
-            let mut open_opts = fs::OpenOptions::new();
-            open_opts.read(true);
-
-            let is_fifo = fs::metadata(&filename)?.file_type().is_fifo();
-            if is_fifo {
-                // A FIFO will block the call to open() until the other end has been opened. This
-                // means that when multiple FIFO's are used, they all have to be open at once
-                // before this program can continue.
-                // Opening the file with O_NONBLOCK will ensure that we don't have to wait.
-                // After the file has been opened, there is no need to make reads block again since
-                // poll(2) is used to check whether data is available.
-                open_opts.custom_flags(fcntl::OFlag::O_NONBLOCK.bits());
-
-                if when_eof == WhenEOF::Retry {
-                    // When the first program writing to the FIFO closes the writing end, poll will
-                    // immediately return with a POLLHUP for the respective reading end because all
-                    // writing ends have been closed. If we open the FIFO for writing ourselves,
-                    // there will always be writers. This ensures that poll never returnes POLLHUP.
-                    open_opts.write(true);
-                }
-            }
-
-            let file = open_opts.open(&filename)?;
+            let file = open_file(filename, when_eof)?;
             Ok(Box::<ReadFd + Send>::from(Box::new(file)))
         }).collect();
-        Ok(Reader::from(files?, switch_after, when_eof, clear_timeout))
+        Reader::from(files?, switch_after, when_eof, clear_timeout)
     }
 
-    pub fn from(inputs: Vec<Box<ReadFd + Send>>, switch_after: usize, when_eof: WhenEOF, clear_timeout: Option<time::Duration>) -> Reader {
+    pub fn from(inputs: Vec<Box<dyn ReadFd + Send>>, switch_after: usize, when_eof: WhenEOF, clear_timeout: Option<time::Duration>) -> io::Result<Reader> {
         assert_ne!(inputs.len(), 0);
-        let buffers = (0..inputs.len())
-            .map(|_| Vec::with_capacity(switch_after))
-            .collect();
-        Reader {
-            switch_after,
-            buffers,
+        let poller = Poller::new()?;
+        let mut reader = Reader {
             when_eof,
-            inputs,
-            current: io::Cursor::new(Vec::new()),
+            inputs: Vec::with_capacity(inputs.len()),
+            poller,
+            next_token: 0,
+            switch_after,
+            display: None,
+            canvas: vec![0; switch_after],
+            layer: Vec::new(),
+            mixer: None,
+            active: None,
+            position: switch_after,
             clear_timeout,
+            last_data: time::Instant::now(),
+            changes: None,
+            stats: None,
+            selected: None,
+            size_mismatch: None,
+        };
+        for input in inputs {
+            reader.add_input(input, None, Format::Raw);
         }
+        Ok(reader)
+    }
+
+    /// Places the frames of the input at the specified index somewhere in the display instead of
+    /// having them cover the display entirely.
+    pub fn place(&mut self, index: usize, display: Dimensions, placement: Placement) {
+        assert_eq!(display.size() * 3, self.switch_after);
+        let input = &mut self.inputs[index];
+        input.frame_size = placement.frame_size();
+        input.placement = Some(placement);
+        if input.decoder.is_some() {
+            input.decoder = Some(delta::Decoder::new(placement.frame_size()));
+        }
+        self.display = Some(display);
+    }
+
+    /// Read delta frames from the input at the specified index instead of full frames.
+    pub fn delta(&mut self, index: usize) {
+        let input = &mut self.inputs[index];
+        input.decoder = Some(delta::Decoder::new(input.frame_size));
+    }
+
+    /// Mix the frames of all inputs that are producing instead of selecting the input with the
+    /// highest priority.
+    pub fn mix(&mut self, mixer: Mixer) {
+        self.mixer = Some(mixer);
+    }
+
+    /// Only show the frames of the first input instead of selecting the input with the highest
+    /// priority. `Change::Cycle` moves on to the next input and `Change::Select` to a specific one.
+    pub fn cycle(&mut self) {
+        self.active = Some(0);
+    }
+
+    /// Checks that the frames of raw inputs match the size of the display. The end of a frame is
+    /// detected by a short pause in the input.
+    pub fn size_mismatch(&mut self, mode: SizeMismatch) {
+        self.size_mismatch = Some(mode);
+    }
+
+    /// Counts the partially received frames that are discarded after the clear timeout.
+    pub fn stats(&mut self, stats: Arc<Stats>) {
+        self.stats = Some(stats);
+    }
+
+    /// Returns a Controller which can be used to add and remove inputs while the Reader is being
+    /// read from.
+    pub fn controller(&mut self, display: Dimensions) -> io::Result<Controller> {
+        assert_eq!(display.size() * 3, self.switch_after);
+        let (rd, wr) = io_err!(unistd::pipe())?;
+        let (rd, wr) = unsafe { (fs::File::from_raw_fd(rd), fs::File::from_raw_fd(wr)) };
+        if !self.poller.add(rd.as_raw_fd(), WAKE_TOKEN) {
+            return Err(io::Error::other("could not poll the pipe of the controller"));
+        }
+        let (tx, rx) = mpsc::channel();
+        self.display = Some(display);
+        self.changes = Some((rx, rd));
+        Ok(Controller { tx, wake: wr })
     }
 }
 
-impl io::Read for Reader {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.current.position() == self.current.get_ref().len() as u64 {
-            // The end of the current buffer has been reached, fetch more data.
-            loop {
-                // Perform a poll to see if there are any inputs ready for reading.
-                let mut poll_fds: Vec<_> = self.inputs.iter()
-                    .map(|inp| {
-                        poll::PollFd::new(inp.as_raw_fd(), poll::EventFlags::POLLIN)
-                    })
-                    .collect();
-                let timeout = self.clear_timeout.as_ref()
-                    .map(|t| t.as_secs() as i32 * 1_000 + t.subsec_nanos() as i32 / 1_000_000)
-                    .unwrap_or(-1);
-                if io_err!(poll::poll(&mut poll_fds, timeout))? == 0 {
-                    assert!(self.clear_timeout.is_some());
-                    // Timeout expired, clear the input buffers.
-                    for buf in &mut self.buffers {
-                        buf.clear();
+impl Reader {
+    fn add_input(&mut self, reader: Box<dyn ReadFd + Send>, placement: Option<Placement>, format: Format) {
+        // Reads must never block, poll may report inputs as readable while they are not, e.g.
+        // after a spurious wakeup.
+        let _ = set_nonblocking(reader.as_raw_fd());
+        let token = self.next_token;
+        self.next_token += 1;
+        let frame_size = placement
+            .map(|p| p.frame_size())
+            .unwrap_or(self.switch_after);
+        let mut input = Input {
+            reader,
+            token,
+            pollable: true,
+            registered: false,
+            frame_size,
+            placement,
+            decoder: match format {
+                Format::Raw => None,
+                Format::Delta => Some(delta::Decoder::new(frame_size)),
+            },
+            buffer: Vec::with_capacity(frame_size),
+            burst: 0,
+            idle: None,
+            mismatch_reported: false,
+            eof_at: None,
+        };
+        // Regular files can not be polled, they are always readable.
+        input.pollable = input.register(&mut self.poller);
+        self.inputs.push(input);
+    }
+
+    /// Applies all pending changes to the set of inputs.
+    fn apply_changes(&mut self) -> io::Result<()> {
+        let changes: Vec<Change> = match self.changes {
+            Some((ref rx, ref mut wake)) => {
+                // Each change is accompanied by a single byte. Reading less than all of them is
+                // fine, the remainder just causes another wakeup.
+                let _ = wake.read(&mut [0; 64])?;
+                rx.try_iter().collect()
+            },
+            None => return Ok(()),
+        };
+        for change in changes {
+            match change {
+                Change::Add { input, placement, format, weight } => {
+                    self.add_input(Box::new(input), placement, format);
+                    event!(::log::Level::Debug, "input_added", {"input": self.inputs.len() - 1}, "Added input {}", self.inputs.len() - 1);
+                    if let Some(ref mut mixer) = self.mixer {
+                        mixer.add_input(weight);
                     }
+                },
+                Change::Remove(i) if i < self.inputs.len() => {
+                    self.inputs.remove(i).deregister(&mut self.poller);
+                    event!(::log::Level::Debug, "input_removed", {"input": i}, "Removed input {}", i);
+                    self.selected = None;
+                    if let Some(ref stats) = self.stats {
+                        stats.remove_input(i);
+                    }
+                    if let Some(ref mut mixer) = self.mixer {
+                        mixer.remove_input(i);
+                    }
+                    if let Some(ref mut active) = self.active {
+                        if i < *active {
+                            *active -= 1;
+                        } else if *active >= self.inputs.len() {
+                            *active = 0;
+                        }
+                    }
+                },
+                Change::Remove(_) => (),
+                Change::Cycle => if let Some(ref mut active) = self.active {
+                    *active = (*active + 1) % self.inputs.len().max(1);
+                    event!(::log::Level::Info, "input_cycled", {"input": *active}, "Showing input {}", *active);
+                },
+                Change::Select(i) => if let Some(ref mut active) = self.active {
+                    if i < self.inputs.len() {
+                        *active = i;
+                        event!(::log::Level::Info, "input_cycled", {"input": i}, "Showing input {}", i);
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index of a delta input that received more than one frame in a single read.
+    /// Those frames have to be consumed before waiting for more data.
+    fn next_decoded(&mut self) -> Option<usize> {
+        self.inputs.iter_mut()
+            .enumerate()
+            .position(|(i, input)| input.decode_frame(i))
+    }
+
+    /// The time after which an input that reached EOF is read from again.
+    fn retry_interval(&self) -> time::Duration {
+        self.clear_timeout
+            .unwrap_or_else(|| time::Duration::from_millis(10))
+    }
+
+    /// Computes the timeout in milliseconds for waiting on the inputs so that the Reader wakes up in time
+    /// for the clear timeout and retries of inputs that reached EOF.
+    fn wait_timeout(&self, now: time::Instant) -> isize {
+        if self.inputs.iter().any(|inp| !inp.pollable && inp.eof_at.is_none()) {
+            return 0;
+        }
+        let retry_interval = self.retry_interval();
+        let retries = self.inputs.iter()
+            .filter(|_| self.when_eof == WhenEOF::Retry)
+            .filter_map(|inp| inp.eof_at)
+            .map(|t| t + retry_interval);
+        let clear = self.clear_timeout.map(|t| self.last_data + t);
+        let gaps = self.inputs.iter()
+            .filter(|inp| self.size_mismatch.is_some() && inp.burst > 0)
+            .map(|inp| inp.idle.map(|(first, _)| first + FRAME_GAP).unwrap_or(now));
+        match retries.chain(clear).chain(gaps).min() {
+            Some(deadline) if deadline <= now => 0,
+            Some(deadline) => {
+                let t = deadline - now;
+                // Round up to prevent waking up just before the deadline.
+                (t.as_secs() * 1_000 + u64::from(t.subsec_nanos() + 999_999) / 1_000_000) as isize
+            },
+            None => -1,
+        }
+    }
+
+    /// Reads from the input at the specified index once.
+    fn read_input(&mut self, i: usize, now: time::Instant) -> io::Result<Progress> {
+        let input = &mut self.inputs[i];
+        let result = if let Some(ref mut dec) = input.decoder {
+            let mut chunk = [0; 4096];
+            input.reader.read(&mut chunk)
+                .inspect(|&nread| dec.push(&chunk[..nread]))
+        } else {
+            let frame_size = input.frame_size;
+            let buf = &mut input.buffer;
+            let buf_used = buf.len();
+            assert_ne!(buf_used, frame_size);
+            // Resize the buffer so there is just enough space for the remainder of the frame.
+            buf.resize(frame_size, 0);
+            let result = input.reader.read(&mut buf[buf_used..]);
+            buf.resize(buf_used + *result.as_ref().unwrap_or(&0), 0);
+            input.burst += *result.as_ref().unwrap_or(&0);
+            result
+        };
+        match result {
+            Ok(0) => Ok(Progress::Eof),
+            Ok(_) => {
+                input.idle = None;
+                if input.decoder.is_some() {
+                    // The decoded frame is stored like a regular full frame.
+                    return Ok(if input.decode_frame(i) {
+                        Progress::Frame
+                    } else {
+                        Progress::Partial
+                    });
+                }
+                assert!(input.buffer.len() <= input.frame_size);
+                Ok(if input.buffer.len() == input.frame_size {
+                    Progress::Frame
+                } else {
+                    Progress::Partial
+                })
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if input.burst > 0 {
+                    let first = input.idle.map(|(first, _)| first).unwrap_or(now);
+                    input.idle = Some((first, now));
                 }
+                Ok(Progress::Idle)
+            },
+            Err(err) => Err(err),
+        }
+    }
 
-                let mut num_open = poll_fds.len();
-                let mut ready_index = None;
-                for (i, p) in poll_fds.iter().enumerate() {
-                    let rev = p.revents().unwrap();
-                    if rev.contains(poll::EventFlags::POLLIN) {
-                        let buf = &mut self.buffers[i];
-                        let buf_used = buf.len();
-                        assert_ne!(buf_used, self.switch_after);
-                        // Resize the buffer so there is just enough space for the remainder of the
-                        // frame.
-                        buf.resize(self.switch_after, 0);
-
-                        let nread = self.inputs[i].read(&mut buf[buf_used..])?;
-                        buf.resize(buf_used + nread, 0);
-                        assert!(buf.len() <= self.switch_after);
-                        if nread == 0 { // EOF
-                            num_open -= 1;
-                        } else if buf.len() == self.switch_after {
-                            ready_index = Some(i);
-                            break;
+    /// Blocks until one of the inputs has received a full frame and returns its index. None is
+    /// returned if all inputs have been closed.
+    fn wait_frame(&mut self) -> io::Result<Option<usize>> {
+        let mut events: Vec<Event> = Vec::new();
+        loop {
+            if let Some(i) = self.end_bursts()? {
+                return Ok(Some(i));
+            }
+            if self.when_eof == WhenEOF::Close && self.inputs.iter().all(|inp| inp.eof_at.is_some()) {
+                return Ok(None);
+            }
+
+            let timeout = self.wait_timeout(time::Instant::now());
+            match self.poller.wait(&mut events, timeout) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                rs => rs?,
+            }
+            let num_events = events.len();
+            let now = time::Instant::now();
+
+            let mut ready = Vec::with_capacity(num_events);
+            let mut hangups = Vec::new();
+            for ev in &events {
+                if ev.token == WAKE_TOKEN {
+                    // The indices of the inputs may change, so start over.
+                    self.apply_changes()?;
+                    ready.clear();
+                    hangups.clear();
+                    break;
+                }
+                let i = match self.inputs.iter().position(|inp| inp.token == ev.token) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if ev.readable {
+                    ready.push(i);
+                } else {
+                    hangups.push(i);
+                }
+            }
+            for i in hangups {
+                if self.inputs[i].set_eof(&mut self.poller, now) {
+                    event!(::log::Level::Debug, "input_eof", {"input": i}, "Input {} was closed", i);
+                }
+            }
+
+            // Inputs that can not be polled, inputs that are due for a retry and inputs of which it
+            // is checked whether they have paused are read from without having been reported by
+            // the poller.
+            let retry_interval = self.retry_interval();
+            for (i, inp) in self.inputs.iter().enumerate() {
+                let unpollable = !inp.pollable && inp.eof_at.is_none();
+                let retry = self.when_eof == WhenEOF::Retry && inp.eof_at.map(|t| t + retry_interval <= now).unwrap_or(false);
+                let gap = self.size_mismatch.is_some() && inp.burst > 0 && inp.eof_at.is_none()
+                    && inp.idle.map(|(first, _)| first + FRAME_GAP <= now).unwrap_or(true);
+                if unpollable || retry || gap {
+                    ready.push(i);
+                }
+            }
+            ready.sort();
+            ready.dedup();
+
+            if num_events == 0 && ready.is_empty() {
+                if let Some(clear_timeout) = self.clear_timeout {
+                    if now >= self.last_data + clear_timeout {
+                        // Timeout expired, clear the input buffers.
+                        for (i, input) in self.inputs.iter_mut().enumerate() {
+                            if !input.buffer.is_empty() {
+                                event!(::log::Level::Warn, "frame_dropped", {"input": i, "received": input.buffer.len(), "frame_size": input.frame_size},
+                                       "Discarded a partial frame of {} of {} bytes from input {}", input.buffer.len(), input.frame_size, i);
+                                if let Some(ref stats) = self.stats {
+                                    stats.add_short_frame();
+                                }
+                            }
+                            input.buffer.clear();
+                            if let Some(ref mut dec) = input.decoder {
+                                dec.clear_pending();
+                            }
                         }
-                    } else if rev.intersects(poll::EventFlags::POLLHUP|poll::EventFlags::POLLNVAL|poll::EventFlags::POLLERR) {
-                        num_open -= 1;
+                        self.last_data = now;
                     }
                 }
+            }
 
-                if num_open == 0 {
-                    if self.when_eof == WhenEOF::Close {
-                        return Ok(0);
-                    }
-                    // Prevent a busy wait for inputs that make poll return immediately.
-                    let wait = self.clear_timeout
-                        .unwrap_or_else(|| time::Duration::new(0, 10_000_000));
-                    thread::sleep(wait);
+            for i in ready {
+                match self.read_input(i, now)? {
+                    Progress::Eof => {
+                        if self.inputs[i].set_eof(&mut self.poller, now) {
+                            event!(::log::Level::Debug, "input_eof", {"input": i}, "Input {} reached EOF", i);
+                        }
+                        continue;
+                    },
+                    // A retried pipe that has a writer again but no data yet is alive as well.
+                    Progress::Idle => {
+                        if self.inputs[i].set_alive(&mut self.poller) {
+                            event!(::log::Level::Debug, "input_alive", {"input": i}, "Input {} is open again", i);
+                        }
+                        continue;
+                    },
+                    Progress::Partial | Progress::Frame => (),
+                }
+                self.last_data = now;
+                if self.inputs[i].set_alive(&mut self.poller) {
+                    event!(::log::Level::Debug, "input_alive", {"input": i}, "Input {} is open again", i);
                 }
+                if self.inputs[i].buffer.len() >= self.inputs[i].frame_size {
+                    return Ok(Some(i));
+                }
+            }
+        }
+    }
 
-                if let Some(i) = ready_index {
-                    let tail = self.buffers[i].split_off(self.switch_after);
-                    self.buffers.push(tail); // Later moved to index i by swap_remove.
-                    let buf = self.buffers.swap_remove(i);
-                    self.current = io::Cursor::new(buf);
-                    break;
+    /// Checks the size of the frames of the inputs that have paused or reached EOF since they last
+    /// sent data. The data of a frame that has the right size has been consumed by then, anything
+    /// that is left is the remainder of a short or long frame. Returns the index of an input of
+    /// which a short frame was padded to a full frame.
+    fn end_bursts(&mut self) -> io::Result<Option<usize>> {
+        let mode = match self.size_mismatch {
+            Some(mode) => mode,
+            None => return Ok(None),
+        };
+        for (i, input) in self.inputs.iter_mut().enumerate() {
+            let paused = input.idle.map(|(first, last)| first + FRAME_GAP <= last).unwrap_or(false);
+            if input.burst == 0 || (input.eof_at.is_none() && !paused) {
+                continue;
+            }
+            let received = mem::replace(&mut input.burst, 0);
+            input.idle = None;
+            if input.buffer.is_empty() {
+                continue;
+            }
+            if mode == SizeMismatch::Error {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("input {} sent a frame of {} bytes while the display takes {} bytes, check the geometry", i, received, input.frame_size)));
+            }
+            if !input.mismatch_reported {
+                event!(::log::Level::Warn, "frame_size_mismatch", {"input": i, "received": received, "frame_size": input.frame_size},
+                       "Input {} sent a frame of {} bytes while the display takes {} bytes", i, received, input.frame_size);
+                input.mismatch_reported = true;
+            }
+            if mode == SizeMismatch::Pad && received < input.frame_size {
+                let frame_size = input.frame_size;
+                input.buffer.resize(frame_size, 0);
+                return Ok(Some(i));
+            }
+            // The remainder of a long frame, or a short frame that is discarded.
+            input.buffer.clear();
+        }
+        Ok(None)
+    }
+
+    /// Takes the full frame of the input at the specified index and makes it the current output.
+    fn select_frame(&mut self, i: usize) {
+        let input = &mut self.inputs[i];
+        if let Some(ref stats) = self.stats {
+            stats.add_input_frame(i);
+        }
+        {
+            let frame = &input.buffer[..input.frame_size];
+            if let Some(ref mut mixer) = self.mixer {
+                // Placed inputs are composited onto a black layer before mixing.
+                match (input.placement, self.display) {
+                    (Some(placement), Some(display)) => {
+                        self.layer.clear();
+                        self.layer.resize(self.switch_after, 0);
+                        placement.composite(&mut self.layer, display, frame);
+                        mixer.mix(i, &self.layer, &mut self.canvas);
+                    },
+                    _ => mixer.mix(i, frame, &mut self.canvas),
+                }
+            } else {
+                if self.selected != Some(i) {
+                    event!(::log::Level::Debug, "input_switched", {"input": i}, "Switched to input {}", i);
+                    self.selected = Some(i);
+                }
+                match (input.placement, self.display) {
+                    (Some(placement), Some(display)) => {
+                        placement.composite(&mut self.canvas, display, frame);
+                    },
+                    _ => self.canvas.copy_from_slice(frame),
                 }
             }
         }
-        self.current.read(buf)
+        // Keep the start of the next frame that has already been received.
+        input.buffer.drain(..input.frame_size);
+        self.position = 0;
+    }
+}
+
+impl io::Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.canvas.len() {
+            // The end of the current buffer has been reached, fetch more data.
+            let ready_index = loop {
+                let i = match self.next_decoded() {
+                    Some(i) => i,
+                    None => match self.wait_frame()? {
+                        Some(i) => i,
+                        None => return Ok(0),
+                    },
+                };
+                match self.active {
+                    // Frames of inputs that are not shown are discarded so their writers do not
+                    // block.
+                    Some(active) if active != i => {
+                        let input = &mut self.inputs[i];
+                        input.buffer.drain(..input.frame_size);
+                    },
+                    _ => break i,
+                }
+            };
+            self.select_frame(ready_index);
+        }
+        let n = buf.len().min(self.canvas.len() - self.position);
+        buf[..n].copy_from_slice(&self.canvas[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
     }
 }
 
@@ -222,7 +818,7 @@ mod tests {
             len,
             WhenEOF::Close,
             None,
-        );
+        ).unwrap();
 
         for i in 0..num {
             let mut rd_buf = vec![0; len];
@@ -245,7 +841,7 @@ mod tests {
             len,
             WhenEOF::Close,
             None,
-        );
+        ).unwrap();
 
         for i in 1..num + 1 {
             let mut rd_buf = vec![0; len];
@@ -258,6 +854,143 @@ mod tests {
         });
     }
 
+    #[cfg(all(target_os = "linux", not(all(feature = "ci", target_arch = "arm"))))]
+    #[test]
+    fn read_placed_input() {
+        let display = Dimensions::Two(2, 2);
+        let mut reader = Reader::from(
+            vec![
+                new_iter_reader(vec![1; 12].into_iter()),
+                new_iter_reader(vec![2; 6].into_iter()),
+            ],
+            display.size() * 3,
+            WhenEOF::Close,
+            None,
+        ).unwrap();
+        reader.place(1, display, Placement { geometry: Dimensions::One(2), at: (0, 1) });
+
+        let mut rd_buf = vec![0; 12];
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![1; 12], rd_buf);
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2], rd_buf);
+    }
+
+    #[cfg(all(target_os = "linux", not(all(feature = "ci", target_arch = "arm"))))]
+    #[test]
+    fn read_delta_input() {
+        let data = vec![
+            0, 0, 0, 1, 0, 1, 1, 2, 3,
+            0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 1, 4, 5, 6,
+            0, 0, 0, 0, 0, 0,
+        ];
+        let mut reader = Reader::from(
+            vec![new_iter_reader(data.into_iter())],
+            2 * 3,
+            WhenEOF::Close,
+            None,
+        ).unwrap();
+        reader.delta(0);
+
+        let mut rd_buf = vec![0; 6];
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![0, 0, 0, 1, 2, 3], rd_buf);
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![4, 5, 6, 1, 2, 3], rd_buf);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", not(all(feature = "ci", target_arch = "arm"))))]
+    fn read_delta_input_invalid() {
+        // The run is past the end of the frame.
+        let delta = vec![
+            0, 0, 0, 2, 0, 1, 1, 2, 3,
+            0, 0, 0, 0, 0, 0,
+        ];
+        let mut reader = Reader::from(
+            vec![new_iter_reader(delta.into_iter()), new_iter_reader(vec![7, 8, 9, 7, 8, 9].into_iter())],
+            2 * 3,
+            WhenEOF::Close,
+            None,
+        ).unwrap();
+        reader.delta(0);
+
+        // The other input is still read from.
+        let mut rd_buf = vec![0; 6];
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![7, 8, 9, 7, 8, 9], rd_buf);
+    }
+
+    #[cfg(all(target_os = "linux", not(all(feature = "ci", target_arch = "arm"))))]
+    #[test]
+    fn read_added_input() {
+        let mut reader = Reader::from(
+            vec![new_iter_reader(vec![1; 3].into_iter())],
+            3,
+            WhenEOF::Close,
+            None,
+        ).unwrap();
+        let mut controller = reader.controller(Dimensions::One(1)).unwrap();
+
+        let mut rd_buf = vec![0; 3];
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![1; 3], rd_buf);
+
+        controller.send(Change::Remove(0)).unwrap();
+        controller.send(Change::Add {
+            input: *new_iter_reader(vec![2; 3].into_iter()),
+            placement: None,
+            format: Format::Raw,
+            weight: None,
+        }).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![2; 3], rd_buf);
+        timeout!(time::Duration::new(10, 0), {
+            assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
+        });
+    }
+
+    #[test]
+    fn read_cycled_inputs() {
+        let (rd_a, wr_a) = unistd::pipe().unwrap();
+        let (rd_b, wr_b) = unistd::pipe().unwrap();
+        let (mut wr_a, mut wr_b) = unsafe { (fs::File::from_raw_fd(wr_a), fs::File::from_raw_fd(wr_b)) };
+        let mut reader = Reader::from(
+            vec![
+                Box::new(unsafe { fs::File::from_raw_fd(rd_a) }),
+                Box::new(unsafe { fs::File::from_raw_fd(rd_b) }),
+            ],
+            3,
+            WhenEOF::Close,
+            None,
+        ).unwrap();
+        reader.cycle();
+        let mut controller = reader.controller(Dimensions::One(1)).unwrap();
+
+        let mut rd_buf = vec![0; 3];
+        wr_a.write_all(&[1; 3]).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![1; 3], rd_buf);
+
+        // The frames of the first input are discarded once the second one is shown.
+        controller.send(Change::Cycle).unwrap();
+        wr_a.write_all(&[2; 3]).unwrap();
+        wr_b.write_all(&[3; 3]).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![3; 3], rd_buf);
+
+        controller.send(Change::Select(0)).unwrap();
+        wr_b.write_all(&[4; 3]).unwrap();
+        wr_a.write_all(&[5; 3]).unwrap();
+        reader.read_exact(&mut rd_buf).unwrap();
+        assert_eq!(vec![5; 3], rd_buf);
+        drop((wr_a, wr_b));
+        timeout!(time::Duration::new(10, 0), {
+            assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
+        });
+    }
+
     #[cfg(all(target_os = "linux", not(all(feature = "ci", target_arch = "arm"))))]
     #[test]
     fn read_eof() {
@@ -266,7 +999,7 @@ mod tests {
             1,
             WhenEOF::Close,
             None,
-        );
+        ).unwrap();
         timeout!(time::Duration::new(10, 0), {
             assert_eq!(0, io::copy(&mut reader, &mut io::sink()).unwrap());
         });
@@ -281,7 +1014,7 @@ mod tests {
             1,
             WhenEOF::Retry,
             None,
-        );
+        ).unwrap();
         timeout!(time::Duration::new(0, 100_000_000), {
             io::copy(&mut reader, &mut io::sink()).unwrap();
         });
@@ -368,4 +1101,89 @@ mod tests {
 
         thread.join().unwrap();
     }
+
+    #[test]
+    fn size_mismatch() {
+        let len = 10;
+        let tmp = tempdir::TempDir::new("size_mismatch").unwrap();
+        let padded = vec![1, 1, 1, 1, 0, 0, 0, 0, 0, 0];
+        let cases = vec![
+            (SizeMismatch::Pad, vec![padded, vec![3; len], vec![2; len]]),
+            // The short frame is discarded.
+            (SizeMismatch::Truncate, vec![vec![3; len], vec![2; len]]),
+        ];
+        for (mode, expected) in cases {
+            let fifo_path = tmp.path().join(format!("{:?}", mode));
+            unistd::mkfifo(&fifo_path, Mode::from_bits(0o666).unwrap()).unwrap();
+            let mut reader = Reader::from_files(
+                vec![&fifo_path],
+                len,
+                WhenEOF::Close,
+                Some(time::Duration::from_secs(10)),
+            ).unwrap();
+            reader.size_mismatch(mode);
+            let mut fifo = fs::OpenOptions::new().write(true).open(&fifo_path).unwrap();
+
+            let thread = thread::spawn(move || {
+                // The remainder of the long frame is not taken to be part of the next frame.
+                for frame in expected {
+                    let mut rd_buf = vec![0; len];
+                    reader.read_exact(&mut rd_buf).unwrap();
+                    assert_eq!(frame, rd_buf);
+                }
+            });
+
+            // A short frame, a long frame and a frame of the right size, separated by pauses.
+            copy_iter(&mut fifo, iter::repeat_n(1, 4));
+            thread::sleep(FRAME_GAP * 10);
+            copy_iter(&mut fifo, iter::repeat_n(3, len + 4));
+            thread::sleep(FRAME_GAP * 10);
+            copy_iter(&mut fifo, iter::repeat_n(2, len));
+
+            timeout!(time::Duration::from_secs(5), { thread.join().unwrap() });
+        }
+    }
+
+    #[test]
+    fn size_mismatch_slow_reader() {
+        // The frame does not fit in the buffer of the pipe.
+        let len = 100 * 1024;
+        let tmp = tempdir::TempDir::new("size_mismatch_slow_reader").unwrap();
+        let fifo_path = tmp.path().join("fifo");
+        unistd::mkfifo(&fifo_path, Mode::from_bits(0o666).unwrap()).unwrap();
+        let mut reader = Reader::from_files(vec![&fifo_path], len, WhenEOF::Close, None).unwrap();
+        reader.size_mismatch(SizeMismatch::Error);
+        let mut fifo = fs::OpenOptions::new().write(true).open(&fifo_path).unwrap();
+        let writer = thread::spawn(move || copy_iter(&mut fifo, iter::repeat_n(1, len)));
+
+        // Read the first chunk once the pipe has filled up and take a while before reading the
+        // rest. The writer has not paused, so this is not the end of the frame.
+        thread::sleep(FRAME_GAP * 10);
+        let now = time::Instant::now();
+        while reader.inputs[0].buffer.is_empty() {
+            reader.read_input(0, now).unwrap();
+        }
+        assert!(reader.inputs[0].buffer.len() < len);
+        thread::sleep(FRAME_GAP * 4);
+
+        timeout!(time::Duration::from_secs(5), {
+            let mut rd_buf = vec![0; len];
+            reader.read_exact(&mut rd_buf).unwrap();
+            assert_eq!(vec![1; len], rd_buf);
+        });
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn size_mismatch_error() {
+        let tmp = tempdir::TempDir::new("size_mismatch_error").unwrap();
+        let fifo_path = tmp.path().join("fifo");
+        unistd::mkfifo(&fifo_path, Mode::from_bits(0o666).unwrap()).unwrap();
+        let mut reader = Reader::from_files(vec![&fifo_path], 10, WhenEOF::Close, None).unwrap();
+        reader.size_mismatch(SizeMismatch::Error);
+        let mut fifo = fs::OpenOptions::new().write(true).open(&fifo_path).unwrap();
+        copy_iter(&mut fifo, iter::repeat_n(1, 4));
+        let err = reader.read_exact(&mut [0; 10]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
 }
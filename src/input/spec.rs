@@ -0,0 +1,223 @@
+use std::cmp;
+use std::str;
+use regex::Regex;
+use input::geometry::*;
+
+
+/// Describes where the frames of an input that is smaller than the display should be placed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub geometry: Dimensions,
+    pub at: (usize, usize),
+}
+
+impl Placement {
+    /// The number of bytes in a single frame of the placed input.
+    pub fn frame_size(&self) -> usize {
+        self.geometry.size() * 3
+    }
+
+    /// Copies an RGB24 frame of the placed input into the frame of the full display. Pixels that
+    /// fall outside of the display are discarded.
+    pub fn composite(&self, canvas: &mut [u8], display: Dimensions, frame: &[u8]) {
+        assert_eq!(display.size() * 3, canvas.len());
+        assert_eq!(self.frame_size(), frame.len());
+        let (w, h) = extent(self.geometry);
+        let (dw, dh) = extent(display);
+        let (ax, ay) = self.at;
+        let row_len = cmp::min(w, dw.saturating_sub(ax));
+        if row_len == 0 {
+            return;
+        }
+        for y in 0..cmp::min(h, dh.saturating_sub(ay)) {
+            let src = y * w * 3;
+            let dst = ((ay + y) * dw + ax) * 3;
+            canvas[dst..dst + row_len * 3].copy_from_slice(&frame[src..src + row_len * 3]);
+        }
+    }
+}
+
+fn extent(dim: Dimensions) -> (usize, usize) {
+    match dim {
+        Dimensions::One(len) => (len, 1),
+        Dimensions::Two(w, h) => (w, h),
+    }
+}
+
+
+/// The encoding of the frames sent by an input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Full RGB24 frames.
+    Raw,
+    /// Runs of changed pixels, see `delta::Decoder`.
+    Delta,
+}
+
+
+/// The options that can be appended to the path of an input.
+const OPTIONS: &[&str] = &["geometry", "at", "weight", "format"];
+
+/// An input as specified on the command line.
+///
+/// The simplest form is just the path to read from. Options can be appended after a colon:
+/// `clock.fifo:geometry=32x8,at=0x0,weight=0.5`. A suffix with keys that are not options is
+/// part of the path, e.g. `/srv/run:x=1/clock.fifo`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spec {
+    pub path: String,
+    pub placement: Option<Placement>,
+    /// The weight of the input when inputs are mixed.
+    pub weight: Option<f32>,
+    pub format: Format,
+}
+
+impl str::FromStr for Spec {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(r"^(.+):((?:[a-z]+=[^,=]*,?)+)$").unwrap();
+        let is_option = |opt: &str| OPTIONS.contains(&opt.split('=').next().unwrap());
+        let captures = re.captures(s)
+            .filter(|cap| cap[2].split(',').filter(|o| !o.is_empty()).all(is_option));
+        let (path, opts) = match captures {
+            Some(cap) => (cap[1].to_string(), cap[2].to_string()),
+            None => {
+                return Ok(Spec {
+                    path: s.to_string(),
+                    placement: None,
+                    weight: None,
+                    format: Format::Raw,
+                });
+            },
+        };
+
+        let at_re = Regex::new(r"^(\d+)x(\d+)$").unwrap();
+        let mut geometry = None;
+        let mut at = None;
+        let mut weight = None;
+        let mut format = Format::Raw;
+        for opt in opts.split(',').filter(|o| !o.is_empty()) {
+            let mut kv = opt.splitn(2, '=');
+            let (key, val) = (kv.next().unwrap(), kv.next().unwrap());
+            match key {
+                "geometry" => geometry = Some(val.parse::<Dimensions>()?),
+                "at" => {
+                    let cap = at_re.captures(val)
+                        .ok_or_else(|| format!("can not parse \"{}\" into a position", val))?;
+                    let position_err = |_| format!("can not parse \"{}\" into a position", val);
+                    at = Some((cap[1].parse().map_err(position_err)?, cap[2].parse().map_err(position_err)?));
+                },
+                "weight" => {
+                    let w = val.parse::<f32>().map_err(|err| format!("{}", err))?;
+                    if w < 0.0 {
+                        return Err(format!("weight can not be negative: {}", w));
+                    }
+                    weight = Some(w);
+                },
+                "format" => {
+                    format = match val {
+                        "raw" => Format::Raw,
+                        "delta" => Format::Delta,
+                        _ => return Err(format!("unknown input format: {}", val)),
+                    };
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        let placement = match (geometry, at) {
+            (Some(geometry), at) => Some(Placement { geometry, at: at.unwrap_or((0, 0)) }),
+            (None, Some(_)) => return Err("the \"at\" input option requires a geometry".to_string()),
+            (None, None) => None,
+        };
+        Ok(Spec { path, placement, weight, format })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_parse_path_only() {
+        let spec = "/tmp/ledcat.fifo".parse::<Spec>().unwrap();
+        assert_eq!("/tmp/ledcat.fifo", spec.path);
+        assert_eq!(None, spec.placement);
+    }
+
+    #[test]
+    fn spec_parse_placement() {
+        let spec = "clock.fifo:geometry=32x8,at=4x2".parse::<Spec>().unwrap();
+        assert_eq!("clock.fifo", spec.path);
+        assert_eq!(Some(Placement { geometry: Dimensions::Two(32, 8), at: (4, 2) }),
+                   spec.placement);
+
+        let spec = "clock.fifo:geometry=10".parse::<Spec>().unwrap();
+        assert_eq!(Some(Placement { geometry: Dimensions::One(10), at: (0, 0) }),
+                   spec.placement);
+    }
+
+    #[test]
+    fn spec_parse_weight() {
+        let spec = "a.fifo:weight=0.25".parse::<Spec>().unwrap();
+        assert_eq!("a.fifo", spec.path);
+        assert_eq!(Some(0.25), spec.weight);
+        assert_eq!(None, spec.placement);
+    }
+
+    #[test]
+    fn spec_parse_format() {
+        assert_eq!(Format::Raw, "a.fifo".parse::<Spec>().unwrap().format);
+        assert_eq!(Format::Raw, "a.fifo:format=raw".parse::<Spec>().unwrap().format);
+        assert_eq!(Format::Delta, "a.fifo:format=delta".parse::<Spec>().unwrap().format);
+    }
+
+    #[test]
+    fn spec_parse_path_with_colon() {
+        let spec = "/srv/run:x=1/clock.fifo".parse::<Spec>().unwrap();
+        assert_eq!("/srv/run:x=1/clock.fifo", spec.path);
+        assert_eq!(None, spec.placement);
+
+        let spec = "clock.fifo:foo=bar".parse::<Spec>().unwrap();
+        assert_eq!("clock.fifo:foo=bar", spec.path);
+
+        let spec = "/srv/run:x=1/clock.fifo:geometry=8".parse::<Spec>().unwrap();
+        assert_eq!("/srv/run:x=1/clock.fifo", spec.path);
+        assert_eq!(Some(Placement { geometry: Dimensions::One(8), at: (0, 0) }),
+                   spec.placement);
+    }
+
+    #[test]
+    fn spec_parse_invalid() {
+        assert!("clock.fifo:at=1x1".parse::<Spec>().is_err());
+        assert!("clock.fifo:geometry=1x1,at=99999999999999999999x0".parse::<Spec>().is_err());
+        assert!("clock.fifo:geometry=0x0".parse::<Spec>().is_err());
+        assert!("clock.fifo:weight=-1".parse::<Spec>().is_err());
+        assert!("clock.fifo:format=png".parse::<Spec>().is_err());
+    }
+
+    #[test]
+    fn placement_composite() {
+        let display = Dimensions::Two(4, 3);
+        let mut canvas = vec![0; display.size() * 3];
+        let p = Placement { geometry: Dimensions::Two(2, 2), at: (1, 1) };
+        p.composite(&mut canvas, display, &[1; 2 * 2 * 3]);
+        let expected: Vec<u8> = [0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0].iter()
+            .flat_map(|&b| vec![b; 3])
+            .collect();
+        assert_eq!(expected, canvas);
+    }
+
+    #[test]
+    fn placement_composite_clipped() {
+        let display = Dimensions::Two(3, 2);
+        let mut canvas = vec![0; display.size() * 3];
+        let p = Placement { geometry: Dimensions::Two(2, 2), at: (2, 1) };
+        p.composite(&mut canvas, display, &[1; 2 * 2 * 3]);
+        let expected: Vec<u8> = [0, 0, 0, 0, 0, 1].iter()
+            .flat_map(|&b| vec![b; 3])
+            .collect();
+        assert_eq!(expected, canvas);
+    }
+}
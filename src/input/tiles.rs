@@ -0,0 +1,79 @@
+use input::geometry::Dimensions;
+
+
+/// Describes a display made of tiles that have some physical space between them.
+///
+/// Content is sampled as if the gaps contained invisible pixels, so images continue across the
+/// seams without being distorted. The gaps are measured in pixels, fractions are allowed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tiling {
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub gap: (f32, f32),
+}
+
+impl Tiling {
+    /// Checks whether a display with the specified dimensions can be divided into tiles.
+    pub fn check(&self, display: Dimensions) -> Result<(), String> {
+        match display {
+            Dimensions::Two(w, h) if w % self.tile_width == 0 && h % self.tile_height == 0 => Ok(()),
+            Dimensions::Two(..) => {
+                Err(format!("the display can not be divided into {}x{} tiles", self.tile_width, self.tile_height))
+            },
+            Dimensions::One(_) => Err("tiling requires 2D geometry to be specified".to_string()),
+        }
+    }
+
+    /// Returns for each pixel of the display the index of the pixel of a frame of the same size
+    /// that it should show.
+    pub fn project(&self, display: Dimensions) -> Vec<usize> {
+        let (w, h) = match display {
+            Dimensions::Two(w, h) => (w, h),
+            Dimensions::One(_) => unreachable!(),
+        };
+        let cols = w / self.tile_width;
+        let rows = h / self.tile_height;
+        // The size of the display including the gaps.
+        let virtual_w = w as f32 + (cols - 1) as f32 * self.gap.0;
+        let virtual_h = h as f32 + (rows - 1) as f32 * self.gap.1;
+        let mut pixels = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                // Sample at the center of the pixel.
+                let vx = x as f32 + (x / self.tile_width) as f32 * self.gap.0 + 0.5;
+                let vy = y as f32 + (y / self.tile_height) as f32 * self.gap.1 + 0.5;
+                let sx = ((vx / virtual_w * w as f32) as usize).min(w - 1);
+                let sy = ((vy / virtual_h * h as f32) as usize).min(h - 1);
+                pixels.push(sy * w + sx);
+            }
+        }
+        pixels
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiling_check() {
+        let t = Tiling { tile_width: 2, tile_height: 2, gap: (1.0, 1.0) };
+        assert!(t.check(Dimensions::Two(4, 2)).is_ok());
+        assert!(t.check(Dimensions::Two(3, 2)).is_err());
+        assert!(t.check(Dimensions::One(4)).is_err());
+    }
+
+    #[test]
+    fn tiling_project() {
+        let t = Tiling { tile_width: 2, tile_height: 1, gap: (0.0, 0.0) };
+        assert_eq!(vec![0, 1, 2, 3], t.project(Dimensions::Two(4, 1)));
+        // Two 2x1 tiles with a gap of 2 pixels, the virtual display is 6 pixels wide. The pixel
+        // of the frame that falls in the gap is not shown.
+        let t = Tiling { tile_width: 2, tile_height: 1, gap: (2.0, 0.0) };
+        assert_eq!(vec![0, 1, 3, 3], t.project(Dimensions::Two(4, 1)));
+        // The same applies vertically.
+        let t = Tiling { tile_width: 1, tile_height: 2, gap: (0.0, 2.0) };
+        assert_eq!(vec![0, 1, 3, 3], t.project(Dimensions::Two(1, 4)));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc;
+use std::thread;
+use nix::sys::termios;
+
+
+/// A key that is pressed on the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(u8),
+    Up,
+    Down,
+    Right,
+    Left,
+}
+
+/// Reads single key presses from the controlling terminal, which is kept in raw mode until the
+/// terminal is dropped.
+pub struct Terminal {
+    _raw: RawMode,
+    keys: mpsc::Receiver<Key>,
+}
+
+impl Terminal {
+    pub fn open() -> io::Result<Terminal> {
+        let tty = fs::File::open("/dev/tty")?;
+        let raw = RawMode::enable(&tty)?;
+        Ok(Terminal { _raw: raw, keys: read_keys(tty) })
+    }
+
+    /// The keys that are pressed, read in the background.
+    pub fn keys(&self) -> &mpsc::Receiver<Key> {
+        &self.keys
+    }
+}
+
+/// Puts a terminal in raw mode so single key presses can be read, until it is dropped.
+struct RawMode {
+    fd: RawFd,
+    original: termios::Termios,
+}
+
+impl RawMode {
+    fn enable(tty: &fs::File) -> io::Result<RawMode> {
+        let fd = tty.as_raw_fd();
+        let original = io_err!(termios::tcgetattr(fd))?;
+        let mut raw = original.clone();
+        raw.local_flags &= !(termios::LocalFlags::ICANON | termios::LocalFlags::ECHO | termios::LocalFlags::ISIG);
+        io_err!(termios::tcsetattr(fd, termios::SetArg::TCSANOW, &raw))?;
+        Ok(RawMode { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(self.fd, termios::SetArg::TCSANOW, &self.original);
+    }
+}
+
+fn read_keys(mut tty: fs::File) -> mpsc::Receiver<Key> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 8];
+        let mut parser = Parser::default();
+        while let Ok(n) = tty.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            for key in buf[..n].iter().filter_map(|&b| parser.feed(b)) {
+                if tx.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Turns the bytes read from a terminal into keys, including the escape sequences of the arrow
+/// keys.
+#[derive(Default)]
+struct Parser {
+    escape: Vec<u8>,
+}
+
+impl Parser {
+    fn feed(&mut self, b: u8) -> Option<Key> {
+        if self.escape.is_empty() && b != 0x1b {
+            return Some(Key::Char(b));
+        }
+        self.escape.push(b);
+        let key = match self.escape.as_slice() {
+            [0x1b] | [0x1b, b'['] => return None,
+            [0x1b, b'[', b'A'] => Some(Key::Up),
+            [0x1b, b'[', b'B'] => Some(Key::Down),
+            [0x1b, b'[', b'C'] => Some(Key::Right),
+            [0x1b, b'[', b'D'] => Some(Key::Left),
+            _ => None,
+        };
+        self.escape.clear();
+        key
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys() {
+        let mut parser = Parser::default();
+        let keys: Vec<Key> = b"n\x1b[A\x1b[B\x1b[C\x1b[D\x1b[Zq".iter().filter_map(|&b| parser.feed(b)).collect();
+        assert_eq!(vec![Key::Char(b'n'), Key::Up, Key::Down, Key::Right, Key::Left, Key::Char(b'q')], keys);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io;
+use std::time;
+use clap;
+use color::*;
+use device::*;
+use pace;
+
+
+/// How long to wait for a serial device to echo a frame with --echo.
+pub const ECHO_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name("latency")
+        .about("Send test frames and report the time from generating each frame until the \
+                output has taken it, to measure the latency of the output")
+        .arg(clap::Arg::with_name("frames")
+            .long("frames")
+            .takes_value(true)
+            .default_value("100")
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .help("The number of frames to send"))
+        .arg(clap::Arg::with_name("echo")
+            .long("echo")
+            .help("With the serial driver, wait until the frame has been read back from the \
+                   serial port, for devices that echo what they receive or a loopback cable"))
+}
+
+
+/// The latencies that were measured, one for each frame.
+pub struct Latencies(Vec<time::Duration>);
+
+impl Latencies {
+    /// The latency below which the specified percentage of the frames were sent.
+    fn percentile(&self, p: usize) -> time::Duration {
+        let mut sorted = self.0.clone();
+        sorted.sort();
+        let rank = (p * sorted.len()).div_ceil(100).max(1);
+        sorted[rank - 1]
+    }
+
+    fn mean(&self) -> time::Duration {
+        self.0.iter().sum::<time::Duration>() / self.0.len() as u32
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: time::Duration| format!("{:.3}ms", d.as_secs_f64() * 1000.0);
+        writeln!(f, "frames  {}", self.0.len())?;
+        writeln!(f, "min     {}", ms(self.percentile(0)))?;
+        writeln!(f, "mean    {}", ms(self.mean()))?;
+        writeln!(f, "p50     {}", ms(self.percentile(50)))?;
+        writeln!(f, "p95     {}", ms(self.percentile(95)))?;
+        writeln!(f, "p99     {}", ms(self.percentile(99)))?;
+        writeln!(f, "max     {}", ms(self.percentile(100)))
+    }
+}
+
+/// Sends the specified number of frames and measures for each of them the time from when it was
+/// generated until the output returned. The frames alternate between black and white, so
+/// outputs that skip unchanged frames still send each of them.
+pub fn measure(output: &mut dyn Output,
+               correction: &Correction,
+               num_outputs: usize,
+               frames: usize,
+               mut pacer: Option<pace::Pacer>)
+               -> io::Result<Latencies> {
+    let mut latencies = Vec::with_capacity(frames);
+    for i in 0..frames {
+        if let Some(ref mut pacer) = pacer {
+            pacer.wait();
+        }
+        let generated = time::Instant::now();
+        let color = [(i % 2) as f32; 3];
+        let frame: Vec<Pixel16> = (0..num_outputs)
+            .map(|index| correction.correct_at(color, index, i))
+            .collect();
+        output.output_frame_wide(&frame)?;
+        latencies.push(generated.elapsed());
+    }
+    output.output_frame_wide(&vec![Pixel16::default(); num_outputs])?;
+    Ok(Latencies(latencies))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_percentiles() {
+        let latencies = Latencies((1..=10).rev().map(time::Duration::from_millis).collect());
+        assert_eq!(time::Duration::from_millis(1), latencies.percentile(0));
+        assert_eq!(time::Duration::from_millis(5), latencies.percentile(50));
+        assert_eq!(time::Duration::from_millis(10), latencies.percentile(95));
+        assert_eq!(time::Duration::from_millis(10), latencies.percentile(100));
+        assert_eq!(time::Duration::from_micros(5500), latencies.mean());
+    }
+
+    #[test]
+    fn measure_frames() {
+        struct Frames(Vec<Vec<Pixel16>>);
+        impl Output for Frames {
+            fn color_correction(&self) -> Correction {
+                Correction::none()
+            }
+            fn output_frame(&mut self, _: &[Pixel]) -> io::Result<()> {
+                unreachable!()
+            }
+            fn output_frame_wide(&mut self, frame: &[Pixel16]) -> io::Result<()> {
+                self.0.push(frame.to_vec());
+                Ok(())
+            }
+        }
+        let mut output = Frames(Vec::new());
+        let latencies = measure(&mut output, &Correction::none(), 2, 3, None).unwrap();
+        assert_eq!(3, latencies.0.len());
+        let white = Pixel16 { r: 65535, g: 65535, b: 65535 };
+        // The frames alternate and the display is turned off afterwards.
+        assert_eq!(vec![
+            vec![Pixel16::default(); 2],
+            vec![white.clone(), white],
+            vec![Pixel16::default(); 2],
+            vec![Pixel16::default(); 2],
+        ], output.0);
+    }
+}
@@ -0,0 +1,56 @@
+//! The device encoders, drivers, color corrections and geometry of ledcat, for programs that
+//! drive LEDs themselves rather than piping frames to the `ledcat` binary.
+//!
+//! A device encodes frames in the wire format of some LED chip, a driver delivers the encoded
+//! frames to the hardware. For example, to send a frame to a strip of APA102 LEDs over spidev:
+//!
+//! ```no_run
+//! extern crate ledcat;
+//!
+//! use std::path::Path;
+//! use ledcat::color::Pixel;
+//! use ledcat::device::Device;
+//! use ledcat::device::apa102::Apa102;
+//! use ledcat::driver::spidev;
+//!
+//! let apa102 = Apa102 { grayscale: 31, spidev_clock: 500_000 };
+//! let mut spi = spidev::open(Path::new("/dev/spidev0.0"), &apa102).unwrap();
+//! let correction = apa102.color_correction();
+//! let frame: Vec<Pixel> = (0..30)
+//!     .map(|_| correction.correct(Pixel { r: 255, g: 0, b: 0 }))
+//!     .collect();
+//! apa102.write_frame(&mut spi, &frame).unwrap();
+//! ```
+
+extern crate byteorder;
+extern crate clap;
+#[macro_use]
+extern crate derive_error;
+extern crate gpio;
+extern crate net2;
+#[macro_use]
+extern crate nix;
+#[doc(hidden)]
+pub extern crate regex;
+// Re-exported for the exported macros, which can not rely on the crate that uses them to
+// depend on these.
+#[doc(hidden)]
+#[macro_use]
+pub extern crate serde_json;
+#[cfg(feature = "script")]
+extern crate rhai;
+
+#[macro_use]
+pub mod util;
+#[macro_use]
+pub mod log;
+pub mod color;
+pub mod control;
+pub mod device;
+pub mod driver;
+pub mod input;
+pub mod pace;
+pub mod pipeline;
+pub mod signal;
+pub mod stats;
+pub mod systemd;
@@ -0,0 +1,177 @@
+use std::io;
+use std::str;
+use std::sync::mpsc;
+use std::time;
+use clap;
+use color::*;
+use device::*;
+use input::geometry::Dimensions;
+use keys::{Key, Terminal};
+use signal;
+
+
+pub fn command<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name("locate")
+        .about("Blink the specified pixels on the display to find out where they are, e.g. when \
+                writing a map for a hand-wired layout")
+        .arg(clap::Arg::with_name("pixels")
+            .multiple(true)
+            .required_unless("walk")
+            .validator(|s| s.parse::<Target>().map(|_| ()))
+            .help("The index of a pixel in the output, or the X,Y position of a pixel in the \
+                   input frame, which is mapped through the transpositions and the map"))
+        .arg(clap::Arg::with_name("walk")
+            .long("walk")
+            .help("Step through the pixels one by one with the keyboard, starting at the first \
+                   pixel that is specified. Use n, space or the right arrow to go to the next \
+                   pixel, p or the left arrow to go back and q to quit"))
+        .arg(clap::Arg::with_name("interval")
+            .long("interval")
+            .takes_value(true)
+            .default_value("500")
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .help("The time in milliseconds that the pixels are on and off while blinking"))
+}
+
+
+/// A pixel to locate, either by its index in the output or by its position in the input frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Index(usize),
+    At(usize, usize),
+}
+
+impl Target {
+    /// Determines the index in the output of the pixel.
+    pub fn resolve(self, display: Dimensions, transposition: &[Option<usize>], num_outputs: usize) -> Result<usize, String> {
+        match self {
+            Target::Index(i) if i < num_outputs => Ok(i),
+            Target::Index(i) => Err(format!("pixel {} is out of range, the output has {} pixels", i, num_outputs)),
+            Target::At(x, y) => {
+                let (w, h) = match display {
+                    Dimensions::One(len) => (len, 1),
+                    Dimensions::Two(w, h) => (w, h),
+                };
+                if x >= w || y >= h {
+                    return Err(format!("{},{} is outside of the {} display", x, y, display));
+                }
+                transposition[y * w + x]
+                    .ok_or_else(|| format!("{},{} is masked and has no place in the output", x, y))
+            },
+        }
+    }
+}
+
+impl str::FromStr for Target {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected an index or X,Y: {}", s);
+        let mut split = s.splitn(2, ',');
+        let first = split.next().unwrap().parse().map_err(|_| invalid())?;
+        match split.next() {
+            Some(y) => Ok(Target::At(first, y.parse().map_err(|_| invalid())?)),
+            None => Ok(Target::Index(first)),
+        }
+    }
+}
+
+
+/// Blinks the pixels at the specified output indices until ledcat is interrupted.
+pub fn blink(output: &mut dyn Output, on: Pixel16, num_outputs: usize, indices: &[usize], interval: time::Duration) -> io::Result<()> {
+    let terminate = signal::on_terminate()?;
+    for lit in [true, false].iter().cycle() {
+        let mut frame = vec![Pixel16::default(); num_outputs];
+        if *lit {
+            for &i in indices {
+                frame[i] = on.clone();
+            }
+        }
+        output.output_frame_wide(&frame)?;
+        if terminate.recv_timeout(interval).is_ok() {
+            break;
+        }
+    }
+    output.output_frame_wide(&vec![Pixel16::default(); num_outputs])
+}
+
+/// The actions of the keys in walk mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Next,
+    Previous,
+    Quit,
+}
+
+impl Step {
+    fn from_key(key: Key) -> Option<Step> {
+        match key {
+            Key::Char(b'n') | Key::Char(b' ') | Key::Char(b'\r') | Key::Char(b'\n') | Key::Right => Some(Step::Next),
+            Key::Char(b'p') | Key::Char(0x7f) | Key::Left => Some(Step::Previous),
+            // Ctrl-C is read as a byte in raw mode.
+            Key::Char(b'q') | Key::Char(0x03) => Some(Step::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Blinks a single pixel, which is moved to the next or previous pixel with the keyboard.
+pub fn walk(output: &mut dyn Output, on: Pixel16, num_outputs: usize, start: usize, interval: time::Duration) -> io::Result<()> {
+    let terminal = Terminal::open()?;
+    let mut index = start;
+    eprintln!("pixel {}", index);
+    let mut lit = true;
+    loop {
+        let mut frame = vec![Pixel16::default(); num_outputs];
+        if lit {
+            frame[index] = on.clone();
+        }
+        output.output_frame_wide(&frame)?;
+        match terminal.keys().recv_timeout(interval).map(Step::from_key) {
+            Ok(Some(Step::Next)) => index = (index + 1) % num_outputs,
+            Ok(Some(Step::Previous)) => index = (index + num_outputs - 1) % num_outputs,
+            Ok(Some(Step::Quit)) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Ok(None) => continue,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                lit = !lit;
+                continue;
+            },
+        }
+        // Light up the new pixel right away.
+        lit = true;
+        eprintln!("pixel {}", index);
+    }
+    output.output_frame_wide(&vec![Pixel16::default(); num_outputs])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pixel() {
+        assert_eq!(Ok(Target::Index(12)), "12".parse());
+        assert_eq!(Ok(Target::At(3, 4)), "3,4".parse());
+        assert!("3,".parse::<Target>().is_err());
+        assert!("x".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn resolve_pixel() {
+        // A 2x2 display of which the first row is reversed and the last pixel is masked.
+        let transposition = [Some(1), Some(0), Some(2), None];
+        let display = Dimensions::Two(2, 2);
+        assert_eq!(Ok(2), Target::Index(2).resolve(display, &transposition, 3));
+        assert!(Target::Index(3).resolve(display, &transposition, 3).is_err());
+        assert_eq!(Ok(1), Target::At(0, 0).resolve(display, &transposition, 3));
+        assert_eq!(Ok(2), Target::At(0, 1).resolve(display, &transposition, 3));
+        assert!(Target::At(1, 1).resolve(display, &transposition, 3).is_err());
+        assert!(Target::At(2, 0).resolve(display, &transposition, 3).is_err());
+    }
+
+    #[test]
+    fn step_keys() {
+        let keys = [Key::Char(b'n'), Key::Char(b' '), Key::Char(b'p'), Key::Right, Key::Left, Key::Char(b'x'), Key::Char(b'q')];
+        let steps: Vec<Step> = keys.iter().filter_map(|&k| Step::from_key(k)).collect();
+        assert_eq!(vec![Step::Next, Step::Next, Step::Previous, Step::Next, Step::Previous, Step::Quit], steps);
+    }
+}
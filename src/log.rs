@@ -0,0 +1,181 @@
+use std::fmt;
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time;
+use serde_json;
+
+
+/// The level of detail of the messages that are written to stderr, from least to most verbose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Nothing is written at all.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    /// Also reports each frame and packet that is sent.
+    Trace,
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// Whether messages are written as JSON objects rather than as plain text.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+impl Level {
+    /// Determines the level from the number of times -v and -q have been set. A single -q only
+    /// leaves the errors, -qq silences ledcat entirely.
+    pub fn from_flags(verbose: u64, quiet: u64) -> Level {
+        match (verbose, quiet) {
+            (0, 0) => Level::Info,
+            (1, 0) => Level::Debug,
+            (_, 0) => Level::Trace,
+            (_, 1) => Level::Error,
+            (_, _) => Level::Off,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+
+/// The format of the messages that are written to stderr.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    /// A JSON object on each line, for installations that ship their logs to a collector.
+    Json,
+}
+
+impl str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a message to stderr. An event names what happened so it can be acted upon by
+/// programs, the fields of the event are only written in the JSON format.
+pub fn write(level: Level, event: Option<(&str, serde_json::Value)>, message: fmt::Arguments) {
+    if JSON.load(Ordering::Relaxed) {
+        eprintln!("{}", json_line(level, event, &message.to_string(), time::SystemTime::now()));
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+fn json_line(level: Level, event: Option<(&str, serde_json::Value)>, message: &str, now: time::SystemTime) -> serde_json::Value {
+    let time = now.duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let mut line = json!({
+        "time": time,
+        "level": level.name(),
+        "message": message,
+    });
+    if let Some((name, fields)) = event {
+        line["event"] = json!(name);
+        if let serde_json::Value::Object(fields) = fields {
+            for (key, value) in fields {
+                line[key] = value;
+            }
+        }
+    }
+    line
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, None, format_args!($($arg)*));
+        }
+    }
+}
+
+/// Logs a message along with a named event, e.g.
+/// `event!(Level::Debug, "input_switched", {"input": i}, "Switched to input {}", i)`.
+#[macro_export]
+macro_rules! event {
+    ($level:expr, $name:expr, $fields:tt, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, Some(($name, $crate::serde_json::json!($fields))), format_args!($($arg)*));
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Error, $($arg)*) }
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Warn, $($arg)*) }
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Info, $($arg)*) }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Debug, $($arg)*) }
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Trace, $($arg)*) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_from_flags() {
+        assert_eq!(Level::Info, Level::from_flags(0, 0));
+        assert_eq!(Level::Debug, Level::from_flags(1, 0));
+        assert_eq!(Level::Trace, Level::from_flags(5, 0));
+        assert_eq!(Level::Error, Level::from_flags(0, 1));
+        assert_eq!(Level::Off, Level::from_flags(0, 2));
+        assert_eq!(Level::Off, Level::from_flags(0, 9));
+    }
+
+    #[test]
+    fn json_format() {
+        let now = time::UNIX_EPOCH + time::Duration::from_millis(1500);
+        assert_eq!(json!({"time": 1.5, "level": "error", "message": "Could not open input"}),
+                   json_line(Level::Error, None, "Could not open input", now));
+        assert_eq!(json!({"time": 1.5, "level": "debug", "message": "Switched to input 2", "event": "input_switched", "input": 2}),
+                   json_line(Level::Debug, Some(("input_switched", json!({"input": 2}))), "Switched to input 2", now));
+    }
+}
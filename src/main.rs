@@ -1,38 +1,720 @@
-extern crate byteorder;
 extern crate clap;
 #[macro_use]
-extern crate derive_error;
-extern crate gpio;
-extern crate net2;
-#[macro_use]
+extern crate ledcat;
 extern crate nix;
 extern crate regex;
+extern crate toml;
 
 use std::borrow::Borrow;
-use std::collections;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path;
 use std::process;
-use std::sync::mpsc;
+use std::sync;
 use std::thread;
 use std::time;
+use nix::sys::signal::Signal;
 use ::color::*;
 use ::device::*;
 use ::driver::*;
 use ::input::*;
 use ::input::geometry::*;
+use ::pipeline::*;
 
-#[macro_use]
-mod util;
-mod color;
-mod device;
-mod driver;
-mod input;
+use ledcat::{color, control, device, driver, input, log, pace, pipeline, signal, stats, systemd};
+
+mod calibrate;
+mod config;
+mod doctor;
+mod keys;
+mod latency;
+mod locate;
+mod metrics;
+mod preset;
+mod privileges;
+mod profile;
+#[cfg(target_os = "linux")]
+mod realtime;
 
 
 fn main() {
+    let cli = cli();
+    let matches = parse_args(&cli);
+    let (sub_name, sub_matches) = matches.subcommand();
+    if sub_name == "" {
+        let mut out = io::stderr();
+        cli.write_help(&mut out).unwrap();
+        eprintln!();
+        process::exit(1);
+    }
+    if sub_name == "list-devices" {
+        list_devices();
+        return;
+    }
+    if sub_name == "list-presets" {
+        for (name, description) in preset::list() {
+            println!("{: <16} {}", name, description);
+        }
+        return;
+    }
+    if sub_name == "save-profile" {
+        let path = sub_matches.unwrap().value_of("path").unwrap();
+        let profile = profile::Profile::from_matches(&matches);
+        if let Err(err) = fs::write(path, profile.to_string()) {
+            error!("Could not write the profile: {}", err);
+        }
+        return;
+    }
+
+    let geometry = parse_geometry(&matches);
+    let repeat: usize = matches.value_of("repeat").unwrap().parse().unwrap();
+    let gargs = GlobalArgs {
+        // Don't require the display geomtry to be set just yet, a non-outputting subcommand may
+        // not need it anyway.
+        dimensions: geometry.segment.map(|d| match d {
+            Dimensions::One(len) => Dimensions::One(len * repeat),
+            Dimensions::Two(w, h) => Dimensions::Two(w, h * repeat),
+        }),
+        check: matches.is_present("check"),
+        byte_order: matches.value_of("byte-order").map(|v| v.parse().unwrap()),
+    };
+    if sub_name == "doctor" {
+        examine_output(&matches, sub_matches.unwrap(), &gargs);
+        return;
+    }
+    if sub_name == "map-preview" {
+        let result = gargs.dimensions()
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                let logical_dimensions = geometry.display.unwrap();
+                let transposition = output_transposition(logical_dimensions, transpose_operations(&matches), geometry.map.as_ref(), geometry.mask.as_ref(), geometry.mask_mode)?;
+                print_mapping(&mut io::stdout(), logical_dimensions, &transposition)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            error!("{}", err);
+        }
+        return;
+    }
+    let OpenedOutput { output, driver_output, device_constraints, tee } = match open_output(&matches, sub_name, sub_matches.unwrap(), &gargs, repeat) {
+        Some(opened) => opened,
+        None => return,
+    };
+    let dimensions = match gargs.dimensions() {
+        Ok(d) => d,
+        Err(err) => {
+            exit(StartError::Config(err.to_string()));
+        },
+    };
+
+    let logical_dimensions = geometry.display.unwrap();
+    let transposition = match output_transposition(logical_dimensions, transpose_operations(&matches), geometry.map.as_ref(), geometry.mask.as_ref(), geometry.mask_mode) {
+        Ok(t) => t,
+        Err(err) => {
+            exit(StartError::Config(err.to_string()));
+        }
+    };
+    assert_eq!(dimensions.size(), transposition.iter().filter(|t| t.is_some()).count() * repeat);
+    // From here on, the dimensions are those of the input frames which differ from those of the
+    // display if the frames are cropped or scaled.
+    let dimensions = geometry.input.unwrap();
+
+    let processing = parse_processing(&matches, &*output, transposition, geometry.segment);
+    let dim = matches.value_of("dim").unwrap().parse::<f32>().unwrap();
+    let linear_dim = matches.value_of("dim-mode").unwrap() == "linear";
+    let state = sync::Arc::new(control::State::new(dim, linear_dim));
+
+    let frame_interval = matches.value_of("framerate")
+        .map(|fps| time::Duration::new(1, 0) / fps.parse::<u32>().unwrap());
+    if let Some((constraints, num_pixels, frame_bytes)) = device_constraints {
+        // An adaptive frame rate does not go beyond what the device can handle.
+        let interval = frame_interval.filter(|_| !matches.is_present("adaptive-framerate"));
+        for violation in constraints.check(num_pixels, frame_bytes, interval) {
+            warn!("{}: {}", sub_name, violation);
+        }
+    }
+    let output = match run_output_tool(&matches, sub_matches.unwrap(), output, &processing, dim, logical_dimensions, frame_interval) {
+        Some(output) => output,
+        None => return,
+    };
+
+    let inputs: Vec<spec::Spec> = matches.values_of("input").unwrap()
+        .map(|s| s.parse().unwrap())
+        .collect();
+    // The user and group are looked up before anything is started, so typos are caught by --check.
+    let (user, group) = lookup_privileges(&matches);
+    if gargs.check {
+        check_inputs(&matches, &inputs, dimensions);
+        println!("device: {}", sub_name);
+        if let Some(driver_output) = driver_output {
+            println!("driver: {}", driver_output);
+        }
+        match matches.value_of("playlist") {
+            Some(path) => println!("playlist: {}", path),
+            None => println!("inputs: {}", matches.values_of("input").unwrap().collect::<Vec<_>>().join(" ")),
+        }
+        println!("input geometry: {}", dimensions);
+        println!("display geometry: {}", logical_dimensions);
+        println!("output pixels: {}", gargs.dimensions().unwrap().size());
+        println!("dim: {} ({})", dim, matches.value_of("dim-mode").unwrap());
+        match (frame_interval, matches.is_present("adaptive-framerate")) {
+            (Some(_), false) => println!("framerate: {}", matches.value_of("framerate").unwrap()),
+            (Some(_), true) => println!("framerate: adaptive, at most {}", matches.value_of("framerate").unwrap()),
+            (None, false) => println!("framerate: unlimited"),
+            (None, true) => println!("framerate: adaptive"),
+        }
+        return;
+    }
+    // The output is open, so the privileges needed for it can be dropped before the inputs,
+    // commands and sockets are set up.
+    drop_privileges(&matches, user, group);
+
+    // Take the socket passed by the service manager before any generators are started, so they
+    // do not inherit it.
+    let activated_listener = systemd::listener();
+    let watchdog = systemd::Watchdog::from_env();
+    let (input, stats) = open_inputs(&matches, &inputs, dimensions, frame_interval, activated_listener, &state);
+    let scale_filter = matches.value_of("scale-filter").unwrap().parse().unwrap();
+    let input = transform_input(input, geometry, scale_filter, matches.is_present("debug-overlay"));
+    run(&matches, input, output, processing, state, frame_interval, Observability { tee, stats, watchdog });
+}
+
+
+/// Determines the geometry from the options, exits if the options do not fit together.
+fn parse_geometry(matches: &clap::ArgMatches) -> Geometry {
+    let crop = matches.value_of("crop")
+        .map(|v| v.parse::<crop::Crop>().unwrap());
+    let points = match matches.value_of("points").map(points::Points::load) {
+        Some(Ok(points)) => Some(points),
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load points: {}", err)));
+        },
+        None => None,
+    };
+    let map = match matches.value_of("map").map(Map::load) {
+        Some(Ok(map)) => Some(map),
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load the pixel map: {}", err)));
+        },
+        None => None,
+    };
+    let input_dimensions: Option<Dimensions> = {
+        let env = env::var("LEDCAT_GEOMETRY");
+        match matches.value_of("geometry").unwrap() {
+            "env" => match env.as_ref().map(|e| e.as_str()) {
+                Err(_)|Ok("") => None,
+                Ok(e) => Some(e),
+            },
+            v => Some(v),
+        }.and_then(|v| v.parse().ok())
+    // Without a geometry, the size of the display follows from the points or the pixel map.
+    }.or_else(|| match (&points, &map) {
+        (Some(points), _) => points.grid_dimensions(),
+        (None, Some(map)) => Some(Dimensions::One(map.size())),
+        (None, None) => None,
+    });
+    if let (Some(crop), Some(dim)) = (crop, input_dimensions) {
+        if let Err(err) = crop.check(dim) {
+            exit(StartError::Config(err.to_string()));
+        }
+    }
+    let cropped_dimensions = match crop {
+        Some(crop) => input_dimensions.map(|_| crop.dimensions()),
+        None => input_dimensions,
+    };
+    let scale = matches.value_of("scale")
+        .map(|v| v.parse::<Dimensions>().unwrap());
+    let scaled_dimensions = input_dimensions.and(scale.or(cropped_dimensions));
+    let rings = matches.value_of("rings")
+        .map(|v| v.parse::<rings::Rings>().unwrap());
+    let tiling = matches.value_of("tiles").map(|tiles| {
+        let (tile_width, tile_height) = match tiles.parse::<Dimensions>().unwrap() {
+            Dimensions::Two(w, h) => (w, h),
+            Dimensions::One(_) => unreachable!(),
+        };
+        let gap: Vec<f32> = matches.value_of("tile-gap").unwrap_or("0")
+            .split(',')
+            .map(|v| v.parse().unwrap())
+            .collect();
+        tiles::Tiling { tile_width, tile_height, gap: (gap[0], *gap.get(1).unwrap_or(&gap[0])) }
+    });
+    if let (Some(tiling), Some(dim)) = (tiling, scaled_dimensions) {
+        if let Err(err) = tiling.check(dim) {
+            exit(StartError::Config(err.to_string()));
+        }
+    }
+    let samples = scaled_dimensions.and_then(|source| {
+        if let Some(ref points) = points {
+            let projection = matches.value_of("projection").unwrap().parse().unwrap();
+            Some(points.project(projection, source))
+        } else if let Some(ref rings) = rings {
+            Some(rings.project(source))
+        } else {
+            tiling.map(|tiling| tiling.project(source))
+        }
+    });
+    let display_dimensions = match samples {
+        Some(_) if tiling.is_some() => scaled_dimensions,
+        Some(ref samples) => Some(Dimensions::One(samples.len())),
+        None => scaled_dimensions,
+    };
+    if let (Some(ref map), Some(dim)) = (&map, display_dimensions) {
+        if map.size() != dim.size() {
+            exit(StartError::Config(format!("The pixel map has {} pixels while the display has {}", map.size(), dim.size())));
+        }
+    }
+    let mask = match matches.value_of("mask").map(mask::Mask::load) {
+        Some(Ok(mask)) => Some(mask),
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load the mask: {}", err)));
+        },
+        None => None,
+    };
+    if let (Some(ref mask), Some(dim)) = (&mask, display_dimensions) {
+        if let Err(err) = mask.check(dim) {
+            exit(StartError::Config(err.to_string()));
+        }
+    }
+    let mask_mode: mask::Mode = matches.value_of("mask-mode").unwrap().parse().unwrap();
+    let segment_dimensions = match (&mask, mask_mode) {
+        // Absent pixels are not part of the display.
+        (Some(mask), mask::Mode::Skip) => {
+            display_dimensions.map(|d| Dimensions::One(mask.num_present(d.size())))
+        },
+        _ => display_dimensions,
+    };
+    Geometry {
+        crop,
+        scale,
+        input: input_dimensions,
+        cropped: cropped_dimensions,
+        scaled: scaled_dimensions,
+        samples,
+        display: display_dimensions,
+        segment: segment_dimensions,
+        map,
+        mask,
+        mask_mode,
+    }
+}
+
+/// Determines the color correction and adjustments that are applied to each frame. The color
+/// correction of the output is used unless --color-correction is set.
+fn parse_processing(matches: &clap::ArgMatches,
+                    output: &dyn Output,
+                    transposition: Vec<Option<usize>>,
+                    segment_dimensions: Option<Dimensions>)
+                    -> Processing {
+    let color_correction = matches.values_of("color-correction")
+        .map(|values| values
+            .map(|v| v.parse::<Correction>().unwrap())
+            .fold(Correction::none(), Correction::then))
+        .unwrap_or_else(|| output.color_correction());
+    let color_correction = match matches.value_of("dim-schedule") {
+        Some(v) => color_correction.with_schedule(v.parse().unwrap()),
+        None => color_correction,
+    };
+    let color_correction = if matches.is_present("dither") {
+        color_correction.with_dither(matches.value_of("dither-bits").unwrap().parse().unwrap())
+    } else {
+        color_correction
+    };
+    let levels = match matches.value_of("levels").map(|v| {
+        let mut split = v.split(',').map(|n| n.parse::<u8>());
+        (split.next().unwrap(), split.next().unwrap())
+    }) {
+        None => (0, 255),
+        Some((Ok(black), Ok(white))) if black < white => (black, white),
+        Some(_) => {
+            exit(StartError::Config("The levels should be two values between 0 and 255 with BLACK below WHITE".to_string()));
+        },
+    };
+    let adjust = adjust::Adjust {
+        levels,
+        contrast: matches.value_of("contrast").unwrap().parse().unwrap(),
+        brightness_offset: matches.value_of("brightness-offset").unwrap().parse().unwrap(),
+        dim: 1.0,
+        saturation: matches.value_of("saturation").unwrap().parse().unwrap(),
+        hue_rotate: matches.value_of("hue-rotate").unwrap().parse().unwrap(),
+        white_balance: matches.value_of("white-balance")
+            .map(|v| adjust::parse_white_balance(v).unwrap())
+            .unwrap_or([1.0; 3]),
+        compensation: match matches.value_of("brightness-map").map(adjust::load_compensation) {
+            Some(Ok(compensation)) => Some(compensation),
+            Some(Err(err)) => {
+                exit(StartError::Config(format!("Could not load the brightness map: {}", err)));
+            },
+            None => None,
+        },
+    };
+    if let Some(ref compensation) = adjust.compensation {
+        let size = segment_dimensions.unwrap().size();
+        if compensation.len() != size {
+            exit(StartError::Config(format!("The brightness map has {} pixels while the display has {}", compensation.len(), size)));
+        }
+    }
+    Processing {
+        transposition,
+        correction: color_correction,
+        adjust,
+    }
+}
+
+/// Runs the locate, latency or calibrate subcommand of the device on the output. Returns the
+/// output if none of them was given.
+fn run_output_tool(matches: &clap::ArgMatches,
+                   sub_matches: &clap::ArgMatches,
+                   output: Box<dyn Output>,
+                   processing: &Processing,
+                   dim: f32,
+                   display: Dimensions,
+                   frame_interval: Option<time::Duration>)
+                   -> Option<Box<dyn Output>> {
+    let Processing { ref transposition, correction: ref color_correction, ref adjust } = *processing;
+    let num_outputs = transposition.iter().filter(|t| t.is_some()).count();
+    if let Some(locate_matches) = sub_matches.subcommand_matches("locate") {
+        if let Err(err) = locate_pixels(locate_matches, output, color_correction, dim, display, transposition) {
+            exit(err);
+        }
+        return None;
+    }
+    if let Some(latency_matches) = sub_matches.subcommand_matches("latency") {
+        let frames = latency_matches.value_of("frames").unwrap().parse().unwrap();
+        let pacer = frame_interval.map(|interval| pace::Pacer::new(interval, busy_wait(matches)));
+        let mut output = output;
+        match latency::measure(&mut output, color_correction, num_outputs, frames, pacer) {
+            Ok(latencies) => print!("{}", latencies),
+            Err(err) => exit(StartError::Output(format!("Could not measure the latency: {}", err))),
+        }
+        return None;
+    }
+    if let Some(calibrate_matches) = sub_matches.subcommand_matches("calibrate") {
+        // Start from the gamma of the color correction, if it is set to one.
+        let gamma = matches.values_of("color-correction")
+            .and_then(|values| values.rev().find_map(|v| v.strip_prefix("gamma:")))
+            .map(|v| v.parse().unwrap())
+            .unwrap_or(2.2);
+        let calibration = calibrate::Calibration::new(adjust.white_balance, gamma);
+        let path = path::Path::new(calibrate_matches.value_of("path").unwrap());
+        let mut output = output;
+        if let Err(err) = calibrate::run(&mut output, num_outputs, calibration, dim, profile::Profile::from_matches(matches), path) {
+            exit(StartError::Output(format!("Could not calibrate: {}", err)));
+        }
+        return None;
+    }
+    Some(output)
+}
+
+/// Looks up the user and group of --user and --group.
+fn lookup_privileges(matches: &clap::ArgMatches) -> (Option<(nix::libc::uid_t, nix::libc::gid_t)>, Option<nix::libc::gid_t>) {
+    let user = match matches.value_of("user").map(privileges::user) {
+        Some(Ok(user)) => Some(user),
+        Some(Err(err)) => exit(StartError::Config(format!("Invalid --user: {}", err))),
+        None => None,
+    };
+    let group = match matches.value_of("group").map(privileges::group) {
+        Some(Ok(gid)) => Some(gid),
+        Some(Err(err)) => exit(StartError::Config(format!("Invalid --group: {}", err))),
+        None => None,
+    };
+    (user, group)
+}
+
+/// Checks whether the inputs or the playlist can be opened for --check.
+fn check_inputs(matches: &clap::ArgMatches, inputs: &[spec::Spec], dimensions: Dimensions) {
+    let result = match matches.value_of("playlist") {
+        Some(path) => playlist::Playlist::load(path).map(|_| ()),
+        None => inputs.iter().try_for_each(|spec| {
+            input::check(spec, dimensions)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", spec.path, err)))
+        }),
+    };
+    if let Err(err) = result {
+        exit(StartError::Input(format!("Could not open input: {}", err)));
+    }
+}
+
+/// Switches to the user and group that were looked up and enables --realtime scheduling.
+fn drop_privileges(matches: &clap::ArgMatches, user: Option<(nix::libc::uid_t, nix::libc::gid_t)>, group: Option<nix::libc::gid_t>) {
+    #[cfg(target_os = "linux")]
+    let realtime = if matches.is_present("realtime") {
+        Some(matches.value_of("realtime").map(|p| p.parse().unwrap()).unwrap_or(realtime::DEFAULT_PRIORITY))
+    } else {
+        None
+    };
+    #[cfg(not(target_os = "linux"))]
+    {
+        if matches.is_present("realtime") {
+            warn!("Realtime scheduling is unsupported on this platform, ignoring --realtime");
+        }
+    }
+    if user.is_some() || group.is_some() {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(priority) = realtime {
+                if let Err(err) = realtime::raise_limits(priority) {
+                    debug!("Could not raise the limits for realtime scheduling: {}", err);
+                }
+            }
+        }
+        let gid = group.or_else(|| user.map(|(_, gid)| gid)).unwrap();
+        if let Err(err) = privileges::drop_to(user.map(|(uid, _)| uid), gid) {
+            exit(StartError::Setup(format!("Could not switch to {}: {}",
+                matches.value_of("user").or_else(|| matches.value_of("group")).unwrap(), err)));
+        }
+        debug!("Switched to uid {} and gid {}", nix::unistd::getuid(), nix::unistd::getgid());
+    }
+    // Realtime scheduling is applied before the inputs are opened, so their threads inherit it.
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(priority) = realtime {
+            realtime::enable_or_warn(priority);
+        }
+    }
+}
+
+/// Opens the inputs or the playlist and sets up what controls them: the statistics, the control
+/// socket and fifo, and the signals that cycle the inputs and step the brightness.
+fn open_inputs(matches: &clap::ArgMatches,
+               inputs: &[spec::Spec],
+               dimensions: Dimensions,
+               frame_interval: Option<time::Duration>,
+               activated_listener: Option<std::os::unix::net::UnixListener>,
+               state: &sync::Arc<control::State>)
+               -> (select::Reader, Option<sync::Arc<stats::Stats>>) {
+    let input_eof = if matches.is_present("linger") {
+        select::WhenEOF::Retry
+    } else {
+        select::WhenEOF::Close
+    };
+    let clear_timeout = frame_interval.map(|t| t * 2)
+        .unwrap_or_else(|| {
+            let ms = matches.value_of("clear-timeout")
+                .map(|v| v.parse::<u32>().unwrap())
+                .unwrap_or(100);
+            time::Duration::new(0, ms * 1_000_000)
+        });
+    // Generated inputs produce frames at the configured rate since they have no natural pace.
+    let generator_interval = frame_interval
+        .unwrap_or_else(|| time::Duration::new(1, 0) / input::DEFAULT_FRAMERATE);
+    let files: io::Result<Vec<fs::File>> = match matches.value_of("playlist") {
+        Some(path) => {
+            playlist::Playlist::load(path)
+                .and_then(|playlist| playlist.play(dimensions, generator_interval))
+                .map(|file| vec![file])
+        },
+        None => {
+            inputs.iter()
+                .map(|spec| {
+                    input::open(spec, dimensions, input_eof, generator_interval)
+                        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", spec.path, err)))
+                })
+                .collect()
+        },
+    };
+    let files = match files {
+        Ok(f) => f,
+        Err(err) => {
+            exit(StartError::Input(format!("Could not open input: {}", err)));
+        },
+    };
+    let mut input = match select::Reader::from(
+        files.into_iter().map(|f| Box::new(f) as Box<_>).collect(),
+        dimensions.size() * 3,
+        input_eof,
+        Some(clear_timeout),
+    ) {
+        Ok(input) => input,
+        Err(err) => exit(StartError::Input(format!("Could not set up reading the inputs: {}", err))),
+    };
+    let stats = if matches.is_present("stats") || matches.is_present("metrics-listen") {
+        let stats = sync::Arc::new(stats::Stats::default());
+        if matches.is_present("stats") {
+            let interval = matches.value_of("stats").map(|s| s.parse().unwrap()).unwrap_or(1);
+            stats::report_every(stats.clone(), time::Duration::from_secs(interval));
+        }
+        if let Some(addr) = matches.value_of("metrics-listen") {
+            if let Err(err) = metrics::Server::new(stats.clone(), state.clone()).listen(addr) {
+                exit(StartError::Setup(format!("Could not serve metrics on {}: {}", addr, err)));
+            }
+            debug!("Serving metrics at http://{}/metrics", addr);
+        }
+        input.stats(stats.clone());
+        Some(stats)
+    } else {
+        None
+    };
+    if let Some(mode) = matches.value_of("size-mismatch") {
+        input.size_mismatch(mode.parse().unwrap());
+    }
+    for (i, spec) in inputs.iter().enumerate() {
+        if let Some(placement) = spec.placement {
+            input.place(i, dimensions, placement);
+        }
+        if spec.format == spec::Format::Delta {
+            input.delta(i);
+        }
+    }
+    let control_socket = matches.value_of("control-socket");
+    let cycle = matches.value_of("input-mode").unwrap() == "cycle";
+    let controller = if activated_listener.is_some() || control_socket.is_some() || cycle {
+        match input.controller(dimensions) {
+            Ok(controller) => Some(controller),
+            Err(err) => exit(StartError::Setup(format!("Could not set up the input controller: {}", err))),
+        }
+    } else {
+        None
+    };
+    if cycle {
+        // SIGHUP shows the next input, so a button can flip between content sources through
+        // a small script.
+        input.cycle();
+        let mut controller = match controller.as_ref().unwrap().try_clone() {
+            Ok(controller) => controller,
+            Err(err) => exit(StartError::Setup(format!("Could not set up the input controller: {}", err))),
+        };
+        let cycle_signals = match signal::on(&[Signal::SIGHUP]) {
+            Ok(rx) => rx,
+            Err(err) => {
+                exit(StartError::Setup(format!("Could not install the signal handlers: {}", err)));
+            },
+        };
+        thread::spawn(move || {
+            for _ in cycle_signals {
+                if controller.send(select::Change::Cycle).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    if activated_listener.is_some() || control_socket.is_some() {
+        let controller = controller.unwrap();
+        let initial = match matches.value_of("playlist") {
+            Some(path) => vec![format!("--playlist {}", path)],
+            None => matches.values_of("input").unwrap().map(String::from).collect(),
+        };
+        let server = control::Server::new(initial, controller, state.clone(), move |spec| {
+            input::open(spec, dimensions, input_eof, generator_interval)
+        });
+        match (activated_listener, control_socket) {
+            (Some(listener), _) => {
+                debug!("Accepting control connections on the socket passed by the service manager");
+                server.serve_listener(listener);
+            },
+            (None, Some(socket_path)) => if let Err(err) = server.listen(socket_path) {
+                exit(StartError::Setup(format!("Could not listen on {}: {}", socket_path, err)));
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+    if let Some(fifo_path) = matches.value_of("control-fifo") {
+        match control::Fifo::open(fifo_path) {
+            Ok(fifo) => fifo.serve(state.clone()),
+            Err(err) => exit(StartError::Setup(format!("Could not open {}: {}", fifo_path, err))),
+        }
+    }
+    // SIGUSR1 and SIGUSR2 step the brightness so it can be controlled by simple scripts.
+    let dim_step = matches.value_of("dim-step").unwrap().parse::<f32>().unwrap();
+    let dim_signals = match signal::on(&[Signal::SIGUSR1, Signal::SIGUSR2]) {
+        Ok(rx) => rx,
+        Err(err) => {
+            exit(StartError::Setup(format!("Could not install the signal handlers: {}", err)));
+        },
+    };
+    let dim_state = state.clone();
+    thread::spawn(move || {
+        for sig in dim_signals {
+            let step = if sig == Signal::SIGUSR1 { dim_step } else { -dim_step };
+            dim_state.step_dim(step);
+        }
+    });
+    if matches.value_of("input-mode").unwrap() == "mix" {
+        let defaults = inputs.iter()
+            .map(|spec| spec.weight.unwrap_or(1.0))
+            .collect();
+        let weights = match matches.value_of("mix-weights") {
+            Some(path) => mix::Weights::watch(path, defaults),
+            None => mix::Weights::fixed(defaults),
+        };
+        input.mix(mix::Mixer::new(inputs.len(), weights));
+    }
+    (input, stats)
+}
+
+
+/// The time before the deadline of a frame that --busy-wait spins instead of sleeping.
+fn busy_wait(matches: &clap::ArgMatches) -> time::Duration {
+    if matches.is_present("busy-wait") {
+        let us = matches.value_of("busy-wait").map(|us| us.parse().unwrap()).unwrap_or(1000);
+        time::Duration::from_micros(us)
+    } else {
+        time::Duration::default()
+    }
+}
+
+/// Pipes the frames of the input to the output until the input ends or one of the limits of
+/// --num-frames and --duration is reached. Exits if writing to the output failed.
+fn run(matches: &clap::ArgMatches,
+       input: Box<dyn io::Read + Send>,
+       output: Box<dyn Output>,
+       processing: Processing,
+       state: sync::Arc<control::State>,
+       frame_interval: Option<time::Duration>,
+       observability: Observability) {
+    let num_frames = if matches.is_present("single-frame") {
+        Some(1)
+    } else {
+        matches.value_of("num-frames").map(|n| n.parse::<usize>().unwrap())
+    };
+    let duration = matches.value_of("duration")
+        .map(|d| playlist::parse_duration(d).unwrap());
+    let fade_in = matches.value_of("fade-in")
+        .map(|ms| time::Duration::from_millis(ms.parse().unwrap()));
+    let fade_out = matches.value_of("fade-out")
+        .map(|ms| time::Duration::from_millis(ms.parse().unwrap()));
+    let slew = matches.value_of("max-brightness-step")
+        .map(|step| pace::Slew::new(step.parse().unwrap()));
+    // The adaptive frame rate starts out at the limit and needs a pacer even without one.
+    let governor = if matches.is_present("adaptive-framerate") {
+        Some(pace::Governor::new(frame_interval.unwrap_or_default()))
+    } else {
+        None
+    };
+    let pacer = if frame_interval.is_some() || governor.is_some() {
+        Some(pace::Pacer::new(frame_interval.unwrap_or_default(), busy_wait(matches)))
+    } else {
+        None
+    };
+    let options = PipeOptions {
+        timing: Timing { num_frames, duration },
+        fading: Fading {
+            fade_in,
+            fade_out,
+            slew,
+            blank_on_exit: matches.is_present("blank-on-exit"),
+        },
+        pacing: Pacing {
+            skip_frames: matches.is_present("skip-frames"),
+            queue: matches.value_of("queue").unwrap().parse().unwrap(),
+            pacer,
+            governor,
+        },
+        observability,
+    };
+    let result = pipe_frames(input, output, processing, state, options);
+    // The end of the input is reported as an unexpected EOF.
+    match result {
+        Err(ref err) if err.kind() != io::ErrorKind::UnexpectedEof => {
+            error!("Stopped: {}", err);
+            process::exit(5);
+        },
+        _ => (),
+    }
+}
+
+fn cli() -> clap::App<'static, 'static> {
     let mut cli = clap::App::new("ledcat")
         .version("0.0.1")
         .author("polyfloyd <floyd@polyfloyd.net>")
@@ -42,7 +724,8 @@ fn main() {
             .long("output")
             .takes_value(true)
             .default_value("-")
-            .help("The output file to write to. Use - for stdout."))
+            .help("The output file to write to. Use - for stdout. A USB serial adapter can also be \
+                   specified by its vendor and product ID in hexadecimal, e.g. usb:0403:6001"))
         .arg(clap::Arg::with_name("input")
             .short("i")
             .long("input")
@@ -50,8 +733,60 @@ fn main() {
             .min_values(1)
             .multiple(true)
             .default_value("-")
+            .validator(|val| val.parse::<spec::Spec>().map(|_| ()))
             .help("The inputs to read from. Read the manual for how inputs are read and \
-                   prioritized."))
+                   prioritized. Inputs smaller than the display can be placed using \
+                   PATH:geometry=WxH,at=XxY. Delta frames are read using PATH:format=delta. \
+                   Use script:FILE to generate frames with a Rhai script"))
+        .arg(clap::Arg::with_name("playlist")
+            .long("playlist")
+            .takes_value(true)
+            .conflicts_with_all(&["input", "input-mode"])
+            .help("Cycle through the files, commands and colors listed in a playlist file instead \
+                   of reading from the inputs. Read the manual for the playlist format"))
+        .arg(clap::Arg::with_name("input-mode")
+            .long("input-mode")
+            .takes_value(true)
+            .possible_values(&["priority", "mix", "cycle"])
+            .default_value("priority")
+            .help("How frames from multiple inputs are combined. \"priority\" shows the frames \
+                   of the rightmost input that can be read from, \"mix\" blends all inputs \
+                   that are producing frames using their weights, \"cycle\" shows one input at \
+                   a time and moves on to the next one on SIGHUP"))
+        .arg(clap::Arg::with_name("mix-weights")
+            .long("mix-weights")
+            .takes_value(true)
+            .help("A file containing the weight of each input, one per line. Changes to the \
+                   file are read automatically. Only used if --input-mode is \"mix\""))
+        .arg(clap::Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .help("Load options, including those of the device, from a TOML configuration file. \
+                   Defaults to ~/.config/ledcat/ledcat.toml or /etc/ledcat/ledcat.toml if either \
+                   exists. Options on the command line take precedence"))
+        .arg(clap::Arg::with_name("preset")
+            .long("preset")
+            .takes_value(true)
+            .help("Set up the device, geometry and driver for a known piece of hardware, e.g. \
+                   p10-1/8-scan. Run the list-presets subcommand to see the available presets. \
+                   Options on the command line take precedence"))
+        .arg(clap::Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .help("Load calibration options such as the color correction, white balance and \
+                   brightness map from a TOML profile. Options on the command line take precedence"))
+        .arg(clap::Arg::with_name("control-socket")
+            .long("control-socket")
+            .takes_value(true)
+            .help("Listen on a unix socket at the specified path for commands to change the inputs \
+                   and brightness, pause the output and query the status while running. Read the \
+                   manual for the supported commands. A socket passed by systemd through socket \
+                   activation is used instead"))
+        .arg(clap::Arg::with_name("control-fifo")
+            .long("control-fifo")
+            .takes_value(true)
+            .help("Read commands to pause, resume or blank the output or to set the brightness \
+                   from a FIFO at the specified path, which is created if it does not exist"))
         .arg(clap::Arg::with_name("linger")
             .short("l")
             .long("linger")
@@ -63,6 +798,13 @@ fn main() {
             .conflicts_with("framerate")
             .help("Sets a timeout in milliseconds after which partially read frames are deleted.\
                    If a framerate is set, a timeout is calculated automatically."))
+        .arg(clap::Arg::with_name("size-mismatch")
+            .long("size-mismatch")
+            .takes_value(true)
+            .possible_values(&["pad", "truncate", "error"])
+            .help("Check that the frames of the inputs match the size of the display, taking a \
+                   pause of the input as the end of a frame. Short frames are padded with black \
+                   or discarded by truncate, long frames are cut off. With error, ledcat exits"))
         .arg(clap::Arg::with_name("geometry")
             .short("g")
             .long("geometry")
@@ -86,14 +828,123 @@ fn main() {
             .takes_value(true)
             .min_values(1)
             .multiple(true)
-            .possible_values(&["reverse", "zigzag_x", "zigzag_y", "mirror_x", "mirror_y"])
-            .help("Apply one or more transpositions to the output"))
+            .validator(|val| {
+                let mut split = val.splitn(2, ':');
+                let name = split.next().unwrap();
+                match TRANSPOSITIONS.iter().find(|&&(n, _)| n == name) {
+                    Some(&(_, takes_arg)) if takes_arg == split.next().is_some() => Ok(()),
+                    Some(&(_, true)) => Err(format!("{} requires an argument, e.g. {}:1", name, name)),
+                    Some(&(_, false)) => Err(format!("{} does not take an argument", name)),
+                    None => Err(format!("unknown transposition: {}", name)),
+                }
+            })
+            .help("Apply one or more transpositions to the output. Arguments follow the name \
+                   separated by colons. One of: reverse, zigzag_x, zigzag_y, mirror_x, \
+                   mirror_y, rotate:90|180|270, shift:N, shift_x:N, shift_y:N, \
+                   spiral:cw|ccw:tl|tr|bl|br|center, tile:WxH[:rot180], interleave:N, swap_xy, \
+                   expr:EXPRESSION"))
+        .arg(clap::Arg::with_name("crop")
+            .long("crop")
+            .takes_value(true)
+            .validator(|val| val.parse::<crop::Crop>().map(|_| ()))
+            .help("Only show the rectangle x,y,WxH of the input frames. The --geometry is then \
+                   the size of the input frames while the display has the size of the rectangle"))
+        .arg(clap::Arg::with_name("scale")
+            .long("scale")
+            .takes_value(true)
+            .validator(|val| val.parse::<Dimensions>().map(|_| ()))
+            .help("Scale the input frames to the specified display size. The --geometry is then \
+                   the size of the input frames. Applied after --crop"))
+        .arg(clap::Arg::with_name("points")
+            .long("points")
+            .takes_value(true)
+            .help("A file with the x,y,z position of each LED, one per line. The input frames \
+                   are projected onto the points, the display has as many pixels as there are \
+                   points. Applied after --crop and --scale"))
+        .arg(clap::Arg::with_name("rings")
+            .long("rings")
+            .takes_value(true)
+            .conflicts_with("points")
+            .validator(|val| val.parse::<rings::Rings>().map(|_| ()))
+            .help("The number of pixels in each ring of a display made of concentric rings, \
+                   from the inside out, e.g. 1,8,12,16. The input frames are mapped onto the \
+                   rings by angle and radius. Applied after --crop and --scale"))
+        .arg(clap::Arg::with_name("tiles")
+            .long("tiles")
+            .takes_value(true)
+            .conflicts_with_all(&["points", "rings"])
+            .validator(regex_validator!(r"^[1-9]\d*x[1-9]\d*$"))
+            .help("The size of the tiles the display is made of. Required for --tile-gap"))
+        .arg(clap::Arg::with_name("tile-gap")
+            .long("tile-gap")
+            .takes_value(true)
+            .requires("tiles")
+            .validator(regex_validator!(r"^\d+(\.\d+)?(,\d+(\.\d+)?)?$"))
+            .help("The physical space between tiles as X[,Y] in pixels. Content is sampled as if \
+                   the gaps contained pixels so images are not distorted across the seams"))
+        .arg(clap::Arg::with_name("projection")
+            .long("projection")
+            .takes_value(true)
+            .possible_values(&["planar", "cylindrical"])
+            .default_value("planar")
+            .help("How the input frames are projected onto the points set with --points"))
+        .arg(clap::Arg::with_name("mask")
+            .long("mask")
+            .takes_value(true)
+            .help("A file marking pixels that are absent or broken, either as a list of indices \
+                   or as a bitmap of '#' and '.' characters"))
+        .arg(clap::Arg::with_name("mask-mode")
+            .long("mask-mode")
+            .takes_value(true)
+            .possible_values(&["skip", "black"])
+            .default_value("skip")
+            .help("Whether masked pixels are left out of the output or turned off"))
+        .arg(clap::Arg::with_name("debug-overlay")
+            .long("debug-overlay")
+            .help("Show the number of each frame in binary on the first 8 pixels of the display, \
+                   to spot dropped or frozen frames while troubleshooting"))
+        .arg(clap::Arg::with_name("repeat")
+            .long("repeat")
+            .takes_value(true)
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .default_value("1")
+            .help("Show each frame on this number of identical segments that are chained \
+                   together. The --geometry is the size of a single segment"))
+        .arg(clap::Arg::with_name("color-order")
+            .long("color-order")
+            .takes_value(true)
+            .validator(|val| val.parse::<ColorOrder>().map(|_| ()))
+            .help("Send the channels of each pixel in another order, e.g. GRB, for devices that \
+                   are wired differently than the chip they are compatible with"))
+        .arg(clap::Arg::with_name("byte-order")
+            .long("byte-order")
+            .takes_value(true)
+            .possible_values(&["be", "le"])
+            .help("The byte order of the 16-bit values written by generic --depth 16 and \
+                   hexws2811, for boards that expect the other order than the default of the \
+                   device"))
+        .arg(clap::Arg::with_name("scale-filter")
+            .long("scale-filter")
+            .takes_value(true)
+            .possible_values(&["nearest", "box"])
+            .default_value("box")
+            .help("The filter used for scaling, \"box\" averages the covered pixels"))
+        .arg(clap::Arg::with_name("map")
+            .long("map")
+            .takes_value(true)
+            .help("A file listing for each output pixel the index of the input pixel to show, \
+                   either as CSV or as a JSON array. Applied after --transpose"))
         .arg(clap::Arg::with_name("color-correction")
             .short("c")
             .long("color-correction")
             .takes_value(true)
-            .possible_values(&["none", "srgb"])
-            .help("Override the default color correction. The default is determined per device."))
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|val| val.parse::<Correction>().map(|_| ()))
+            .help("Override the default color correction, one of none, srgb[:R,G,B], cie1931, \
+                   gamma:VALUE, balance:R,G,B, lut:FILE or cube:FILE. May be set more than once \
+                   to apply multiple corrections in order. The default is determined per \
+                   device."))
         .arg(clap::Arg::with_name("dim")
             .long("dim")
             .takes_value(true)
@@ -109,6 +960,100 @@ fn main() {
             })
             .help("Apply a global grayscale before the collor correction. The value should be \
                    between 0 and 1.0 inclusive"))
+        .arg(clap::Arg::with_name("dim-schedule")
+            .long("dim-schedule")
+            .takes_value(true)
+            .validator(|v| v.parse::<schedule::Schedule>().map(|_| ()))
+            .help("Change the brightness during the day, e.g. 22:00=0.2,07:00=1.0. Each level \
+                   is in effect from its time until the next one and is applied on top of --dim"))
+        .arg(clap::Arg::with_name("dim-mode")
+            .long("dim-mode")
+            .takes_value(true)
+            .possible_values(&["encoded", "linear"])
+            .default_value("encoded")
+            .help("Whether --dim scales the input values before the color correction or the \
+                   light output after it. Linear dimming keeps dark colors visible"))
+        .arg(clap::Arg::with_name("dim-step")
+            .long("dim-step")
+            .takes_value(true)
+            .default_value("0.1")
+            .validator(|v| {
+                let f = v.parse::<f32>()
+                    .map_err(|e| format!("{}", e))?;
+                if 0.0 < f && f <= 1.0 {
+                    Ok(())
+                } else {
+                    Err(format!("dim step out of range: {}", f))
+                }
+            })
+            .help("The amount by which the brightness is raised on SIGUSR1 and lowered on \
+                   SIGUSR2"))
+        .arg(clap::Arg::with_name("brightness-map")
+            .long("brightness-map")
+            .takes_value(true)
+            .help("A file with a brightness multiplier for each output pixel, applied after \
+                   --dim to compensate for differences between pixels"))
+        .arg(clap::Arg::with_name("white-balance")
+            .long("white-balance")
+            .takes_value(true)
+            .validator(|v| adjust::parse_white_balance(&v).map(|_| ()))
+            .help("Multipliers between 0 and 1.0 for the red, green and blue channels as R,G,B, \
+                   applied before the color correction to trim the white point"))
+        .arg(clap::Arg::with_name("levels")
+            .long("levels")
+            .takes_value(true)
+            .validator(regex_validator!(r"^\d+,\d+$"))
+            .help("The input values that are shown as black and full white as BLACK,WHITE, e.g. \
+                   16,235. Applied before the contrast and brightness"))
+        .arg(clap::Arg::with_name("contrast")
+            .long("contrast")
+            .takes_value(true)
+            .default_value("1.0")
+            .validator(|v| match v.parse::<f32>() {
+                Ok(f) if f >= 0.0 => Ok(()),
+                _ => Err(format!("invalid contrast: {}", v)),
+            })
+            .help("Scale the difference of each value with the middle gray, values above 1.0 \
+                   increase the contrast"))
+        .arg(clap::Arg::with_name("brightness-offset")
+            .long("brightness-offset")
+            .takes_value(true)
+            .default_value("0")
+            .allow_hyphen_values(true)
+            .validator(|v| match v.parse::<i16>() {
+                Ok(n) if (-255..=255).contains(&n) => Ok(()),
+                _ => Err(format!("invalid brightness offset: {}, expected -255 to 255", v)),
+            })
+            .help("Add this value to each channel, negative values make the image darker"))
+        .arg(clap::Arg::with_name("saturation")
+            .long("saturation")
+            .takes_value(true)
+            .default_value("1.0")
+            .validator(|v| match v.parse::<f32>() {
+                Ok(f) if f >= 0.0 => Ok(()),
+                _ => Err(format!("invalid saturation: {}", v)),
+            })
+            .help("Multiply the saturation of the colors before the color correction. Values \
+                   below 1.0 make the colors less vivid, 0 shows grayscale"))
+        .arg(clap::Arg::with_name("hue-rotate")
+            .long("hue-rotate")
+            .takes_value(true)
+            .default_value("0")
+            .allow_hyphen_values(true)
+            .validator(|v| v.parse::<f32>().map(|_| ()).map_err(|_| format!("invalid angle: {}", v)))
+            .help("Rotate the hue of the colors by this number of degrees before the color \
+                   correction"))
+        .arg(clap::Arg::with_name("dither")
+            .long("dither")
+            .help("Dither the corrected colors over consecutive frames to show smooth gradients \
+                   on devices with a low brightness resolution or when dimmed"))
+        .arg(clap::Arg::with_name("dither-bits")
+            .long("dither-bits")
+            .takes_value(true)
+            .validator(regex_validator!(r"^[1-8]$"))
+            .default_value("8")
+            .help("The number of bits per channel that the device shows when dithering, \
+                   e.g. 7 for the LPD8806"))
         .arg(clap::Arg::with_name("driver")
             .long("driver")
             .takes_value(true)
@@ -126,56 +1071,375 @@ fn main() {
             .takes_value(true)
             .validator(regex_validator!(r"^[1-9]\d*$"))
             .help("Limit the number of frames per second"))
+        .arg(clap::Arg::with_name("adaptive-framerate")
+            .long("adaptive-framerate")
+            .help("Measure how long the output takes to write a frame and lower or raise the \
+                   frame rate to the highest rate it can sustain, up to --framerate if it is set"))
+        .arg(clap::Arg::with_name("busy-wait")
+            .long("busy-wait")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .requires("framerate")
+            .validator(regex_validator!(r"^\d+$"))
+            .help("Spend the last number of microseconds before each frame in a busy loop instead \
+                   of sleeping, for more precise timing at the cost of CPU time. 1000 by default"))
+        .arg(clap::Arg::with_name("realtime")
+            .long("realtime")
+            .value_name("PRIORITY")
+            .min_values(0)
+            .require_equals(true)
+            .validator(|v| match v.parse::<i32>() {
+                Ok(p) if (1..=99).contains(&p) => Ok(()),
+                _ => Err(format!("the priority should be between 1 and 99, got {}", v)),
+            })
+            .help("Run the output with the SCHED_FIFO realtime policy at the given priority, 50 \
+                   by default, and lock the memory of ledcat to reduce the jitter of frames on \
+                   busy systems. Requires root or a raised rtprio and memlock limit. Only \
+                   supported on Linux"))
+        .arg(clap::Arg::with_name("user")
+            .long("user")
+            .takes_value(true)
+            .help("Switch to this user, by name or ID, after opening the output, so ledcat can \
+                   be started as root to open restricted devices without reading its inputs as \
+                   root. The group is the primary group of the user unless --group is set"))
+        .arg(clap::Arg::with_name("group")
+            .long("group")
+            .takes_value(true)
+            .help("Switch to this group, by name or ID, after opening the output"))
+        .arg(clap::Arg::with_name("skip-frames")
+            .long("skip-frames")
+            .help("Discard frames from the input while the output is still busy writing the \
+                   previous one, rather than falling behind a live source"))
+        .arg(clap::Arg::with_name("queue")
+            .long("queue")
+            .takes_value(true)
+            .value_name("FRAMES")
+            .default_value("1")
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .help("The number of processed frames that may wait for the output, so the input \
+                   keeps being read while a write briefly blocks"))
+        .arg(clap::Arg::with_name("tee")
+            .long("tee")
+            .help("Also write each frame to stdout as RGB after the transpositions and \
+                   adjustments, but before the color correction of the device, so it can be piped \
+                   into another ledcat or recorded"))
+        .arg(clap::Arg::with_name("capture")
+            .long("capture")
+            .takes_value(true)
+            .value_name("file")
+            .help("Decode each frame that the device writes back into pixels and write them to the \
+                   file as RGB, to verify what is sent to the hardware"))
+        .arg(clap::Arg::with_name("blank-on-exit")
+            .long("blank-on-exit")
+            .help("Turn all pixels off before exiting, when the input ends or when ledcat is \
+                   interrupted by SIGINT or SIGTERM"))
+        .arg(clap::Arg::with_name("fade-in")
+            .long("fade-in")
+            .takes_value(true)
+            .validator(validate_millis)
+            .help("Ramp the brightness up from black over the specified number of milliseconds \
+                   after the first frame"))
+        .arg(clap::Arg::with_name("fade-out")
+            .long("fade-out")
+            .takes_value(true)
+            .validator(validate_millis)
+            .help("Ramp the brightness of the last frame down to black over the specified number \
+                   of milliseconds before exiting, when the input ends or when ledcat is \
+                   interrupted by SIGINT or SIGTERM"))
+        .arg(clap::Arg::with_name("max-brightness-step")
+            .long("max-brightness-step")
+            .takes_value(true)
+            .validator(|v| {
+                let f = v.parse::<f32>()
+                    .map_err(|e| format!("{}", e))?;
+                if 0.0 < f && f <= 1.0 {
+                    Ok(())
+                } else {
+                    Err(format!("brightness step out of range: {}", f))
+                }
+            })
+            .help("Limit how much the total brightness of the display may rise from one frame to \
+                   the next, as a fraction of all pixels at full white. Frames that would exceed \
+                   it are dimmed, to protect power supplies from the inrush of sudden bright \
+                   frames"))
+        .arg(clap::Arg::with_name("stats")
+            .long("stats")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .help("Print the number of frames written, the frame rate, the number of discarded \
+                   partial frames and the time spent reading, transforming and writing each \
+                   frame to stderr every number of seconds, 1 by default"))
+        .arg(clap::Arg::with_name("metrics-listen")
+            .long("metrics-listen")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Serve metrics such as the number of frames written, the frame rate and the \
+                   write latency at /metrics on the specified address, e.g. 127.0.0.1:9100, \
+                   for Prometheus to scrape"))
+        .arg(clap::Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .help("Print details about driver detection, input switching and the like to \
+                   stderr. Set twice to also report each frame and packet that is sent"))
+        .arg(clap::Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .multiple(true)
+            .conflicts_with("verbose")
+            .help("Only print errors. Set twice to print nothing at all"))
+        .arg(clap::Arg::with_name("log-format")
+            .long("log-format")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .help("The format of the messages written to stderr. With json, each message is a \
+                   JSON object on a line of its own, events such as switching inputs and \
+                   dropping frames are named by the event field"))
+        .arg(clap::Arg::with_name("check")
+            .long("check")
+            .help("Validate the options, the geometry and the inputs and print the resulting \
+                   pipeline without opening the output or the inputs"))
         .arg(clap::Arg::with_name("single-frame")
             .short("1")
             .long("one")
             .conflicts_with("framerate")
-            .help("Send a single frame to the output and exit"));
+            .help("Send a single frame to the output and exit"))
+        .arg(clap::Arg::with_name("num-frames")
+            .long("num-frames")
+            .takes_value(true)
+            .value_name("N")
+            .validator(regex_validator!(r"^[1-9]\d*$"))
+            .conflicts_with("single-frame")
+            .help("Exit after the specified number of frames have been sent to the output"))
+        .arg(clap::Arg::with_name("duration")
+            .long("duration")
+            .takes_value(true)
+            .validator(|s| playlist::parse_duration(&s).map(|_| ()))
+            .help("Exit after running for the specified time, e.g. 500ms, 10s or 5m"));
 
-    let mut device_constructors = collections::HashMap::new();
     for device_init in device::devices() {
-        device_constructors.insert(device_init.0.get_name().to_string(), device_init.1);
-        cli = cli.subcommand(device_init.0);
+        cli = cli.subcommand(device_init.0
+            .subcommand(locate::command())
+            .subcommand(calibrate::command())
+            .subcommand(latency::command()));
     }
+    cli = cli.subcommand(clap::SubCommand::with_name("map-preview")
+        .about("Prints the output index of each pixel after the transpositions, map and mask \
+                have been applied, laid out like the input frame"));
+    cli = cli.subcommand(clap::SubCommand::with_name("list-devices")
+        .about("Lists the supported devices along with their protocol, drivers, default clock or \
+                baud rate and the number of bytes sent per pixel"));
+    cli = cli.subcommand(doctor::command());
+    cli = cli.subcommand(clap::SubCommand::with_name("list-presets")
+        .about("Lists the built-in and user-defined presets that can be set with --preset"));
+    cli = cli.subcommand(clap::SubCommand::with_name("save-profile")
+        .about("Writes the calibration options that are set, including those of --profile, to \
+                a profile")
+        .arg(clap::Arg::with_name("path")
+            .required(true)
+            .help("The file to write the profile to")));
+    cli
+}
 
-    let matches = cli.clone().get_matches();
-    let (sub_name, sub_matches) = matches.subcommand();
-    if sub_name == "" {
-        let mut out = io::stderr();
-        cli.write_help(&mut out).unwrap();
-        eprintln!();
-        process::exit(1);
+/// Checks that a value is a number of milliseconds that fits in a Duration.
+fn validate_millis(v: String) -> Result<(), String> {
+    match v.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not a number of milliseconds", v)),
     }
+}
 
-    let gargs = GlobalArgs {
-        // Don't require the display geomtry to be set just yet, a non-outputting subcommand may
-        // not need it anyway.
-        dimensions: {
-            let env = env::var("LEDCAT_GEOMETRY");
-            match matches.value_of("geometry").unwrap() {
-                "env" => match env.as_ref().map(|e| e.as_str()) {
-                    Err(_)|Ok("") => None,
-                    Ok(e) => Some(e),
+/// Parses the command line along with the options that are set elsewhere. Options on the command
+/// line take precedence over those of LEDCAT_* environment variables, then the configuration
+/// file, the preset and the profile.
+fn parse_args(cli: &clap::App<'static, 'static>) -> clap::ArgMatches<'static> {
+    let args: Vec<String> = env::args().collect();
+    let matches = cli.clone().get_matches_from(&args);
+    log::set_level(log::Level::from_flags(matches.occurrences_of("verbose"), matches.occurrences_of("quiet")));
+    log::set_format(matches.value_of("log-format").unwrap().parse().unwrap());
+    let args = with_env_options(cli, args, &matches);
+    let matches = cli.clone().get_matches_from(&args);
+    let args = with_config(args, &matches);
+    let matches = cli.clone().get_matches_from(&args);
+    let args = with_preset(args, &matches);
+    let matches = cli.clone().get_matches_from(&args);
+    let args = with_profile(args, &matches);
+    let matches = cli.clone().get_matches_from(&args);
+    // The configuration may set the verbosity as well.
+    log::set_level(log::Level::from_flags(matches.occurrences_of("verbose"), matches.occurrences_of("quiet")));
+    log::set_format(matches.value_of("log-format").unwrap().parse().unwrap());
+    matches
+}
+
+/// Inserts the options from LEDCAT_* environment variables before those on the command line.
+/// This happens before the configuration is loaded, so they take precedence over it.
+fn with_env_options(cli: &clap::App<'static, 'static>, args: Vec<String>, matches: &clap::ArgMatches) -> Vec<String> {
+    let env_options: Vec<(String, profile::Value)> = config::from_env().into_iter()
+        .filter(|(key, value)| {
+            let mut check_args: Vec<String> = args.iter().take(1).cloned().collect();
+            check_args.extend(profile::to_args(&[(key.clone(), value.clone())], |_| false));
+            match cli.clone().get_matches_from_safe(check_args) {
+                Err(ref err) if err.kind == clap::ErrorKind::UnknownArgument => {
+                    warn!("Ignoring {}, there is no --{} option", config::env_var(key), key);
+                    false
+                },
+                Ok(ref m) if matches!(*value, profile::Value::String(_)) && m.value_of(key).is_none() => {
+                    exit(StartError::Config(format!("{} should be true or false", config::env_var(key))));
                 },
-                v => Some(v),
-            }.and_then(|v| v.parse().ok())
+                // Invalid values are reported when the arguments are parsed below.
+                _ => true,
+            }
+        })
+        .collect();
+    if env_options.is_empty() {
+        args
+    } else {
+        let mut env_args: Vec<String> = args.iter().take(1).cloned().collect();
+        env_args.extend(profile::to_args(&env_options, |key| matches.occurrences_of(key) > 0));
+        env_args.extend(args.iter().skip(1).cloned());
+        env_args
+    }
+}
+
+/// Adds the options from the configuration to those on the command line.
+fn with_config(args: Vec<String>, matches: &clap::ArgMatches) -> Vec<String> {
+    let config_path = matches.value_of("config")
+        .map(path::PathBuf::from)
+        .or_else(config::default_path);
+    match config_path.as_ref().map(config::Config::load) {
+        Some(Ok(config)) => {
+            debug!("Loaded the configuration from {}", config_path.unwrap().display());
+            config.apply(&args, matches)
+        },
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load the configuration: {}", err)));
+        },
+        None => args,
+    }
+}
+
+/// Adds the options of a preset that are not set on the command line or in the configuration.
+fn with_preset(args: Vec<String>, matches: &clap::ArgMatches) -> Vec<String> {
+    match matches.value_of("preset").map(preset::load) {
+        Some(Ok(preset)) => {
+            debug!("Applying the preset {}", matches.value_of("preset").unwrap());
+            preset.apply(&args, matches)
         },
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load the preset: {}", err)));
+        },
+        None => args,
+    }
+}
+
+/// Inserts the options from a profile before those on the command line.
+fn with_profile(args: Vec<String>, matches: &clap::ArgMatches) -> Vec<String> {
+    match matches.value_of("profile").map(profile::Profile::load) {
+        Some(Ok(profile)) => {
+            let mut profile_args: Vec<String> = args.iter().take(1).cloned().collect();
+            profile_args.extend(profile.to_args(|key| matches.occurrences_of(key) > 0));
+            profile_args.extend(args.iter().skip(1).cloned());
+            profile_args
+        },
+        Some(Err(err)) => {
+            exit(StartError::Config(format!("Could not load the profile: {}", err)));
+        },
+        None => args,
+    }
+}
+
+/// Inspects the output for the doctor subcommand, exits with status 1 if a problem is found.
+fn examine_output(matches: &clap::ArgMatches, doctor_matches: &clap::ArgMatches, gargs: &GlobalArgs) {
+    // The name of the device may be followed by its options.
+    let device_args: Vec<&str> = doctor_matches.value_of("device")
+        .map(|d| d.split_whitespace().collect())
+        .unwrap_or_default();
+    let device = device::devices().into_iter()
+        .find(|d| device_args.first() == Some(&d.0.get_name()));
+    let (info, bytes_per_pixel) = match (device_args.first(), device) {
+        (Some(name), Some((command, from_command, info))) => {
+            let bytes_per_pixel = if info.drivers.is_empty() {
+                None
+            } else {
+                device::bytes_per_pixel(command, from_command, &device_args)
+                    .unwrap_or_else(|err| exit(StartError::Config(format!("Invalid options for {}: {}", name, err))))
+            };
+            (Some((*name, info)), bytes_per_pixel)
+        },
+        (Some(name), None) => exit(StartError::Config(format!("Unknown device: {}", name))),
+        (None, _) => (None, None),
+    };
+    let target = doctor::Target {
+        output: matches.value_of("output").unwrap(),
+        driver: matches.value_of("driver"),
+        baudrate: matches.value_of("serial-baudrate").unwrap().parse().unwrap(),
+        device: info,
+        bytes_per_pixel,
+        num_pixels: gargs.dimensions.map(|d| d.size()),
     };
-    let output: Box<Output> = {
-        let result = device_constructors[sub_name](sub_matches.unwrap(), &gargs);
+    let findings = doctor::examine(&target);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    if findings.iter().any(|f| f.severity == doctor::Severity::Problem) {
+        process::exit(1);
+    }
+}
+
+/// The output of the device along with what is known about it once it has been opened.
+struct OpenedOutput {
+    output: Box<dyn Output>,
+    /// The driver and file that a device writes to, reported by --check.
+    driver_output: Option<String>,
+    /// The limits of the device with the number of pixels and the size of a frame, checked once
+    /// the frame rate is known.
+    device_constraints: Option<(Constraints, usize, usize)>,
+    /// Set by --tee.
+    tee: Option<Box<dyn io::Write + Send>>,
+}
+
+/// Constructs the device of the subcommand and opens the output it writes to, including the
+/// --capture, --repeat and --color-order stages. Returns None if the device handled a subcommand
+/// of its own.
+fn open_output(matches: &clap::ArgMatches, sub_name: &str, sub_matches: &clap::ArgMatches, gargs: &GlobalArgs, repeat: usize) -> Option<OpenedOutput> {
+    let mut driver_output = None;
+    let mut device_constraints = None;
+    // The simulator draws on the terminal.
+    let mut output_to_stdout = sub_name == "show";
+    // The latency subcommand may wait for a serial device to echo each frame.
+    let echo = sub_matches.subcommand_matches("latency")
+        .map(|m| m.is_present("echo"))
+        .unwrap_or(false);
+    let output: Box<dyn Output> = {
+        let from_command = device::devices().into_iter()
+            .find(|d| d.0.get_name() == sub_name)
+            .unwrap().1;
+        let result = from_command(sub_matches, gargs);
         let from_command = match result {
             Ok(v) => v,
             Err(err) => {
-                println!("{}", err);
-                return;
+                exit(StartError::Output(err.to_string()));
             },
         };
         match from_command {
             FromCommand::Device(dev) => {
-                let output_file = path::PathBuf::from(match matches.value_of("output").unwrap() {
-                    "-" => "/dev/stdout",
-                    _ => matches.value_of("output").unwrap(),
-                });
+                let port = match matches.value_of("output").unwrap() {
+                    "-" => Ok(serial::Port::Path(path::PathBuf::from("/dev/stdout"))),
+                    output => output.parse::<serial::Port>(),
+                };
+                let port = match port {
+                    Ok(port) => port,
+                    Err(err) => exit(StartError::Config(err)),
+                };
+                // A USB serial adapter may be identified by its IDs, its device node is looked up.
+                let output_file = match port.resolve() {
+                    Ok(path) => path,
+                    Err(err) => exit(StartError::Output(format!("Could not find {}: {}", port, err))),
+                };
 
                 let driver_name = matches.value_of("driver")
                     .map(|s: &str| s.to_string())
@@ -183,226 +1447,215 @@ fn main() {
                 let driver_name = match driver_name {
                     Some(n) => n,
                     None => {
-                        eprintln!("Unable to determine the driver to use. Please set one using --driver.");
-                        return;
+                        exit(StartError::Output("Unable to determine the driver to use. Please set one using --driver.".to_string()));
                     }
                 };
-                let output: Box<io::Write + Send> = match driver_name.as_str() {
-                    "none" => Box::new(fs::OpenOptions::new().write(true).open(&output_file).unwrap()),
-                    "spidev" => {
-                        Box::new(spidev::open(&output_file, dev.borrow()).unwrap())
+                debug!("Writing to {} using the {} driver", output_file.display(), driver_name);
+                if echo && driver_name != "serial" {
+                    exit(StartError::Config("--echo requires the serial driver".to_string()));
+                }
+                if let Ok(num_pixels) = gargs.dimensions().map(|d| d.size()) {
+                    let mut constraints = dev.constraints();
+                    if driver_name == "serial" && constraints.bit_rate.is_none() {
+                        // Each byte is sent as 10 bits, including the start and stop bit.
+                        let baudrate = matches.value_of("serial-baudrate").unwrap().parse::<u32>().unwrap();
+                        constraints.bit_rate = Some(baudrate / 10 * 8);
+                    }
+                    device_constraints = Some((constraints, num_pixels, dev.written_frame_size(num_pixels)));
+                }
+                let dev: Box<dyn Device> = match matches.value_of("capture") {
+                    Some(capture) if path::Path::new(capture) == output_file => {
+                        exit(StartError::Config("--capture can not write to the output".to_string()));
+                    },
+                    Some(capture) => {
+                        let file: Box<dyn io::Write + Send> = if gargs.check {
+                            Box::new(io::sink())
+                        } else {
+                            match fs::File::create(capture) {
+                                Ok(file) => Box::new(file),
+                                Err(err) => exit(StartError::Setup(format!("Could not open {}: {}", capture, err))),
+                            }
+                        };
+                        match Capture::new(dev, file) {
+                            Ok(dev) => Box::new(dev),
+                            Err(err) => exit(StartError::Config(format!("Can not capture the output of {}: {}", sub_name, err))),
+                        }
+                    },
+                    None => dev,
+                };
+                let output: Box<dyn Output> = match driver_name.as_str() {
+                    "none" | "spidev" | "serial" if gargs.check => {
+                        if let Err(err) = fs::metadata(&output_file) {
+                            exit(StartError::Output(format!("Could not open {}: {}", output_file.display(), err)));
+                        }
+                        Box::new((dev, io::sink()))
+                    },
+                    "none" => match fs::OpenOptions::new().write(true).open(&output_file) {
+                        Ok(file) => Box::new((dev, file)),
+                        Err(err) => exit(StartError::open_output(&output_file, &driver_name, err)),
+                    },
+                    "spidev" => match spidev::open(&output_file, dev.borrow()) {
+                        Ok(file) => Box::new((dev, file)),
+                        Err(err) => exit(StartError::open_output(&output_file, &driver_name, err.into())),
                     },
                     "serial" => {
                         let baudrate = matches.value_of("serial-baudrate").unwrap().parse::<u32>().unwrap();
-                        Box::new(serial::open(&output_file, baudrate).unwrap())
+                        match serial::open(&output_file, baudrate) {
+                            Ok(file) if echo => Box::new(serial::Echo::new(dev, file, latency::ECHO_TIMEOUT)),
+                            Ok(file) => Box::new(serial::Reconnect::new(dev, port, baudrate, file)),
+                            Err(err) => exit(StartError::open_output(&output_file, &driver_name, err.into())),
+                        }
                     },
                     _ => {
-                        eprintln!("Unknown driver {}", driver_name);
-                        return;
+                        exit(StartError::Output(format!("Unknown driver {}", driver_name)));
                     }
                 };
-                Box::new((dev, output))
+                driver_output = Some(format!("{} {}", driver_name, output_file.display()));
+                output_to_stdout = output_file == path::Path::new("/dev/stdout");
+                output
+            },
+            FromCommand::Output(_) if echo => {
+                exit(StartError::Config("--echo requires the serial driver".to_string()));
+            },
+            FromCommand::Output(_) if matches.is_present("capture") => {
+                exit(StartError::Config(format!("Can not capture the output of {}, it does not write to a driver", sub_name)));
             },
             FromCommand::Output(output) => output,
-            FromCommand::SubcommandHandled => return,
+            FromCommand::SubcommandHandled => return None,
         }
     };
-    let dimensions = match gargs.dimensions() {
-        Ok(d) => d,
-        Err(err) => {
-            eprintln!("{}", err);
-            return;
-        },
+    let tee: Option<Box<dyn io::Write + Send>> = match (matches.is_present("tee"), output_to_stdout) {
+        (true, true) => exit(StartError::Config("--tee needs stdout, but the output is written there. Set --output".to_string())),
+        (true, false) => Some(Box::new(io::stdout())),
+        (false, _) => None,
     };
-
-    let transpose = matches.values_of("transpose")
-        .map(|v| v.collect())
-        .unwrap_or_else(Vec::new);
-    let transposition = match transposition_table(&dimensions, transpose) {
-        Ok(t) => t,
-        Err(err) => {
-            eprintln!("{}", err);
-            return;
-        }
+    let output: Box<dyn Output> = if repeat > 1 {
+        Box::new(Repeat::new(output, repeat))
+    } else {
+        output
     };
-    assert_eq!(dimensions.size(), transposition.len());
-
-    let color_correction = matches.value_of("color-correction")
-        .and_then(|name| match name {
-            "none" => Some(Correction::none()),
-            "srgb" => Some(Correction::srgb(255, 255, 255)),
-            _ => None,
-        })
-        .unwrap_or_else(|| output.color_correction());
-    let dim = (matches.value_of("dim")
-            .unwrap()
-            .parse::<f32>()
-            .unwrap() * 255.0)
-        .round() as u8;
+    let output: Box<dyn Output> = match matches.value_of("color-order") {
+        Some(order) => Box::new(Reorder::new(output, order.parse().unwrap())),
+        None => output,
+    };
+    Some(OpenedOutput { output, driver_output, device_constraints, tee })
+}
 
-    let frame_interval = matches.value_of("framerate")
-        .map(|fps| time::Duration::new(1, 0) / fps.parse::<u32>().unwrap());
-    let single_frame = matches.is_present("single-frame");
+/// Prints the supported devices for the list-devices subcommand.
+fn list_devices() {
+    println!("{: <16} {: <26} {: <14} {: <14} BYTES/PIXEL", "DEVICE", "PROTOCOL", "DRIVERS", "DEFAULT RATE");
+    for (command, from_command, info) in device::devices() {
+        let name = command.get_name().to_string();
+        let drivers = if info.drivers.is_empty() { "-".to_string() } else { info.drivers.join(",") };
+        let rate = info.rate.map(|r| r.to_string()).unwrap_or_else(|| "-".to_string());
+        // Devices that do their own IO require options that have no default, like a target.
+        let bpp = Some(&info).filter(|info| !info.drivers.is_empty())
+            .and_then(|_| device::bytes_per_pixel(command, from_command, &[&name]).ok()?)
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!("{: <16} {: <26} {: <14} {: <14} {}", name, info.protocol, drivers, rate, bpp);
+    }
+}
 
-    let inputs = matches.values_of("input").unwrap();
-    let input_eof = if matches.is_present("linger") {
-        select::WhenEOF::Retry
-    } else {
-        select::WhenEOF::Close
-    };
-    let files = inputs.map(|f| match f {
-            "-" => "/dev/stdin",
-            f => f,
-        })
-        .collect();
-    let clear_timeout = frame_interval.map(|t| t * 2)
-        .unwrap_or_else(|| {
-            let ms = matches.value_of("clear-timeout")
-                .map(|v| v.parse::<u32>().unwrap())
-                .unwrap_or(100);
-            time::Duration::new(0, ms * 1_000_000)
-        });
-    let input = select::Reader::from_files(files, dimensions.size() * 3, input_eof, Some(clear_timeout)).unwrap();
 
-    let _ = pipe_frames(
-        input,
-        output,
-        transposition,
-        color_correction,
-        dim,
-        single_frame,
-        frame_interval
-    );
+/// The errors that keep ledcat from starting. Each kind exits with its own status, so scripts and
+/// service managers can tell them apart.
+#[derive(Debug)]
+enum StartError {
+    /// The options, the configuration or the geometry are invalid.
+    Config(String),
+    /// The output could not be set up or opened.
+    Output(String),
+    /// An input could not be opened.
+    Input(String),
+    /// Anything else ledcat needs to run, such as the control socket.
+    Setup(String),
 }
 
-fn pipe_frames(mut input: impl io::Read + Send + 'static,
-               mut dev: impl Output + 'static,
-               transposition: Vec<usize>,
-               correction: Correction,
-               dim: u8,
-               single_frame: bool,
-               frame_interval: Option<time::Duration>)
-               -> io::Result<()> {
-    let (err_tx, err_rx) = mpsc::channel();
-    macro_rules! try_or_send {
-        ($tx:expr, $expression:expr) => (
-            match $expression {
-                Ok(val)  => val,
-                Err(err) => {
-                    $tx.send(Err(err)).unwrap();
-                    return;
-                }
-            }
-        )
+impl StartError {
+    fn open_output(path: &path::Path, driver: &str, err: io::Error) -> StartError {
+        StartError::Output(format!("Could not open {} with the {} driver: {}. The output is set with --output and the driver with --driver",
+                                   path.display(), driver, err))
     }
 
-    let local_err_tx = err_tx.clone();
-    let num_pixels = transposition.len();
-    let (input_tx, input_rx) = mpsc::sync_channel(1);
-    thread::spawn(move || {
-        loop {
-            // Read a full frame into a buffer. This prevents half frames being written to a
-            // potentially timing sensitive output if the input blocks and lets us apply the
-            // transpositions.
-            let mut bin_buffer = vec![0; num_pixels * 3];
-            try_or_send!(local_err_tx, input.read_exact(&mut bin_buffer));
-            input_tx.send(bin_buffer).unwrap();
-            if single_frame {
-                break;
-            }
+    fn exit_code(&self) -> i32 {
+        match *self {
+            StartError::Config(_) => 1,
+            StartError::Output(_) => 2,
+            StartError::Input(_) => 3,
+            StartError::Setup(_) => 4,
         }
+    }
+}
 
-    });
-
-    let (map_tx, map_rx) = mpsc::sync_channel(1);
-    thread::spawn(move || {
-        for bin_buffer in input_rx.into_iter() {
-            let mut buffer = vec![Pixel { r: 0, g: 0, b: 0 }; transposition.len()];
-            for (transpose_mapped, bin) in transposition.iter().zip(bin_buffer.chunks(3)) {
-                // Load the pixel.
-                let pix = Pixel {
-                    r: bin[0],
-                    g: bin[1],
-                    b: bin[2],
-                };
-                // Apply dimming.
-                let pix = {
-                    let dim16 = u16::from(dim);
-                    Pixel {
-                        r: ((u16::from(pix.r) * dim16) / 0xff) as u8,
-                        g: ((u16::from(pix.g) * dim16) / 0xff) as u8,
-                        b: ((u16::from(pix.b) * dim16) / 0xff) as u8,
-                    }
-                };
-                // Apply color correction.
-                let pix = correction.correct(pix);
-                // Apply transposition and store the pixel in the output buffer.
-                buffer[*transpose_mapped] = pix;
-            }
-            map_tx.send(buffer).unwrap();
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StartError::Config(ref msg) |
+            StartError::Output(ref msg) |
+            StartError::Input(ref msg) |
+            StartError::Setup(ref msg) => write!(f, "{}", msg),
         }
-    });
+    }
+}
 
-    thread::spawn(move || {
-        loop {
-            let start = time::Instant::now();
+/// Reports an error that keeps ledcat from starting and exits with its status.
+fn exit(err: StartError) -> ! {
+    error!("{}", err);
+    process::exit(err.exit_code());
+}
 
-            let buffer = match map_rx.recv() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            try_or_send!(err_tx, dev.output_frame(&buffer));
 
-            if let Some(interval) = frame_interval {
-                let el = start.elapsed();
-                if interval >= el {
-                    thread::sleep(interval - el);
-                }
-            }
-        }
-    });
+/// Blinks the pixels that are passed to the locate subcommand on the display.
+fn locate_pixels(matches: &clap::ArgMatches,
+                 mut output: Box<dyn Output>,
+                 correction: &Correction,
+                 dim: f32,
+                 display: Dimensions,
+                 transposition: &[Option<usize>])
+                 -> Result<(), StartError> {
+    let num_outputs = transposition.iter().filter(|t| t.is_some()).count();
+    let indices = matches.values_of("pixels")
+        .map(|values| values
+            .map(|v| v.parse::<locate::Target>().unwrap().resolve(display, transposition, num_outputs))
+            .collect::<Result<Vec<usize>, String>>())
+        .unwrap_or_else(|| Ok(vec![0]))
+        .map_err(StartError::Config)?;
+    let interval = time::Duration::from_millis(matches.value_of("interval").unwrap().parse().unwrap());
+    // The pixels are lit in white at the configured brightness, corrected for the device.
+    let on = correction.correct_at([dim; 3], 0, 0);
+    let result = if matches.is_present("walk") {
+        locate::walk(&mut output, on, num_outputs, indices[0], interval)
+    } else {
+        locate::blink(&mut output, on, num_outputs, &indices, interval)
+    };
+    result.map_err(|err| StartError::Output(format!("Could not locate the pixels: {}", err)))
+}
 
-    match err_rx.recv() {
-        Ok(err) => err,
-        Err(_) => Ok(()),
-    }
+
+/// The operations of --transpose, in the order in which they are applied.
+fn transpose_operations<'a>(matches: &'a clap::ArgMatches) -> Vec<&'a str> {
+    matches.values_of("transpose")
+        .map(|v| v.collect())
+        .unwrap_or_default()
 }
 
-fn transposition_table(dimensions: &Dimensions,
-                       operations: Vec<&str>)
-                       -> Result<Vec<usize>, String> {
-    let transpositions: Vec<Box<Transposition>> = try!(operations.into_iter()
-        .map(|name| -> Result<Box<Transposition>, String> {
-            match (name, *dimensions) {
-                ("reverse", dim) => Ok(Box::from(Reverse { length: dim.size() })),
-                ("zigzag_x", Dimensions::Two(w, h)) | ("zigzag_y", Dimensions::Two(w, h)) => {
-                    Ok(Box::from(Zigzag {
-                        width: w,
-                        height: h,
-                        major_axis: match name.chars().last().unwrap() {
-                            'x' => Axis::X,
-                            'y' => Axis::Y,
-                            _ => unreachable!(),
-                        },
-                    }))
-                },
-                ("mirror_x", Dimensions::Two(w, h)) | ("mirror_y", Dimensions::Two(w, h)) => {
-                    Ok(Box::from(Mirror {
-                        width: w,
-                        height: h,
-                        axis: match name.chars().last().unwrap() {
-                            'x' => Axis::X,
-                            'y' => Axis::Y,
-                            _ => unreachable!(),
-                        },
-                    }))
-                },
-                (name, Dimensions::One(_)) => Err(format!("{} requires 2D geometry to be specified", name)),
-                (name, _) => Err(format!("Unknown transposition: {}", name)),
-            }
-        })
-        .collect());
-    Ok((0..dimensions.size())
-        .map(|index| transpositions.transpose(index))
-        .collect())
+/// Writes the output index of each pixel in a grid with the dimensions of the display. Masked
+/// pixels that are left out are shown as a dash.
+fn print_mapping(out: &mut impl io::Write, dimensions: Dimensions, transposition: &[Option<usize>]) -> io::Result<()> {
+    let width = match dimensions {
+        Dimensions::One(len) => len,
+        Dimensions::Two(w, _) => w,
+    };
+    let cell = transposition.len().saturating_sub(1).to_string().len();
+    for row in transposition.chunks(width) {
+        let cells: Vec<String> = row.iter()
+            .map(|t| match *t {
+                Some(i) => format!("{:>1$}", i, cell),
+                None => format!("{:>1$}", "-", cell),
+            })
+            .collect();
+        writeln!(out, "{}", cells.join(" "))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,151 @@
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, Write};
+use std::net;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time;
+use control::State;
+use stats::{Stats, Totals};
+
+
+/// The interval at which the frame rate is measured.
+const FPS_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// Exports the statistics over HTTP in the text format of Prometheus.
+pub struct Server {
+    stats: Arc<Stats>,
+    state: Arc<State>,
+    /// The frame rate over the last FPS_INTERVAL, stored as the bits of an f64.
+    fps: Arc<AtomicU64>,
+}
+
+impl Server {
+    pub fn new(stats: Arc<Stats>, state: Arc<State>) -> Server {
+        Server { stats, state, fps: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Starts serving the metrics at /metrics on the specified address in the background.
+    pub fn listen<A: net::ToSocketAddrs>(self, addr: A) -> io::Result<()> {
+        let listener = net::TcpListener::bind(addr)?;
+
+        let (stats, fps) = (self.stats.clone(), self.fps.clone());
+        thread::spawn(move || {
+            let mut last = stats.totals().frames;
+            loop {
+                thread::sleep(FPS_INTERVAL);
+                let frames = stats.totals().frames;
+                let rate = (frames - last) as f64 / FPS_INTERVAL.as_secs_f64();
+                fps.store(rate.to_bits(), Ordering::Relaxed);
+                last = frames;
+            }
+        });
+
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let conn = match conn {
+                    Ok(c) => c,
+                    Err(_) => continue,
+                };
+                if let Err(err) = self.serve(conn) {
+                    debug!("metrics: {}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn serve(&self, conn: net::TcpStream) -> io::Result<()> {
+        conn.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+        let mut out = conn.try_clone()?;
+        let mut request = io::BufReader::new(conn);
+        let mut request_line = String::new();
+        request.read_line(&mut request_line)?;
+        // Skip the headers, the request has no body.
+        let mut header = String::new();
+        while request.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                let fps = f64::from_bits(self.fps.load(Ordering::Relaxed));
+                ("200 OK", render(&self.stats.totals(), fps, &self.state))
+            },
+            (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+        };
+        write!(out, "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               status, body.len(), body)?;
+        out.flush()
+    }
+}
+
+/// Formats the metrics in the text exposition format of Prometheus.
+fn render(totals: &Totals, fps: f64, state: &State) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (suffix, value) in samples {
+            let _ = writeln!(out, "{}{} {}", name, suffix, value);
+        }
+    };
+
+    metric("ledcat_frames_total", "counter", "Frames written to the output.",
+           &[(String::new(), totals.frames.to_string())]);
+    metric("ledcat_fps", "gauge", "Frames written to the output per second.",
+           &[(String::new(), format!("{:.2}", fps))]);
+    metric("ledcat_dropped_frames_total", "counter", "Partially received frames that were discarded after the clear timeout.",
+           &[(String::new(), totals.short_frames.to_string())]);
+    metric("ledcat_skipped_frames_total", "counter", "Frames that were discarded because the output could not keep up.",
+           &[(String::new(), totals.skipped_frames.to_string())]);
+
+    let mut write = Vec::new();
+    for &(le, count) in &totals.write_buckets {
+        write.push((format!("_bucket{{le=\"{}\"}}", le), count.to_string()));
+    }
+    write.push(("_bucket{le=\"+Inf\"}".to_string(), totals.frames.to_string()));
+    write.push(("_sum".to_string(), totals.write.as_secs_f64().to_string()));
+    write.push(("_count".to_string(), totals.frames.to_string()));
+    metric("ledcat_write_duration_seconds", "histogram", "The time it took to write a frame to the output.", &write);
+
+    let inputs: Vec<_> = totals.input_frames.iter().enumerate()
+        .map(|(i, n)| (format!("{{input=\"{}\"}}", i), n.to_string()))
+        .collect();
+    metric("ledcat_input_frames_total", "counter", "Frames selected from each input.", &inputs);
+
+    metric("ledcat_brightness", "gauge", "The global brightness between 0 and 1.",
+           &[(String::new(), state.dim().to_string())]);
+    metric("ledcat_paused", "gauge", "Whether writing to the output is paused.",
+           &[(String::new(), (state.paused() as u8).to_string())]);
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let stats = Stats::default();
+        stats.add_write(time::Duration::from_millis(2));
+        stats.add_short_frame();
+        stats.add_input_frame(1);
+        let state = State::new(0.5, false);
+        let text = render(&stats.totals(), 29.5, &state);
+        assert!(text.contains("# TYPE ledcat_frames_total counter\nledcat_frames_total 1\n"));
+        assert!(text.contains("ledcat_fps 29.50\n"));
+        assert!(text.contains("ledcat_dropped_frames_total 1\n"));
+        assert!(text.contains("ledcat_write_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("ledcat_write_duration_seconds_bucket{le=\"0.0025\"} 1\n"));
+        assert!(text.contains("ledcat_write_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("ledcat_write_duration_seconds_sum 0.002\n"));
+        assert!(text.contains("ledcat_write_duration_seconds_count 1\n"));
+        assert!(text.contains("ledcat_input_frames_total{input=\"0\"} 0\nledcat_input_frames_total{input=\"1\"} 1\n"));
+        assert!(text.contains("ledcat_brightness 0.5\n"));
+        assert!(text.contains("ledcat_paused 0\n"));
+    }
+}
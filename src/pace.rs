@@ -0,0 +1,219 @@
+use std::hint;
+use std::thread;
+use std::time;
+use color::Pixel16;
+
+
+/// Paces the output at a fixed frame rate.
+///
+/// Each frame is scheduled at an absolute deadline rather than by sleeping for the remainder of
+/// the interval after the previous frame, so the time spent writing and the inaccuracy of sleeping
+/// do not add up and the frame rate does not drift.
+pub struct Pacer {
+    interval: time::Duration,
+    /// The time before each deadline that is spent in a busy loop instead of sleeping, which
+    /// wakes up more precisely at the cost of CPU time.
+    spin: time::Duration,
+    next: Option<time::Instant>,
+}
+
+impl Pacer {
+    pub fn new(interval: time::Duration, spin: time::Duration) -> Pacer {
+        Pacer { interval, spin, next: None }
+    }
+
+    /// Blocks until the deadline of the next frame.
+    pub fn wait(&mut self) {
+        let deadline = self.advance(time::Instant::now());
+        let now = time::Instant::now();
+        if deadline > now + self.spin {
+            thread::sleep(deadline - now - self.spin);
+        }
+        while time::Instant::now() < deadline {
+            hint::spin_loop();
+        }
+    }
+
+    /// Determines the deadline of the next frame. A frame that is late is sent right away and the
+    /// schedule is kept so the next frames catch up. If the output has fallen behind by more than
+    /// a frame, e.g. because the input stalled, the schedule is restarted from now instead of
+    /// sending the missed frames in a burst.
+    fn advance(&mut self, now: time::Instant) -> time::Instant {
+        let next = match self.next {
+            Some(prev) if prev + self.interval * 2 >= now => prev + self.interval,
+            _ => now,
+        };
+        self.next = Some(next);
+        next
+    }
+
+    /// Changes the frame rate, starting with the frame after the next.
+    pub fn set_interval(&mut self, interval: time::Duration) {
+        self.interval = interval;
+    }
+}
+
+
+/// The share of the interval that writing a frame may take when the frame rate is adapted to the
+/// output. The rest is headroom for frames that take longer than usual.
+const GOVERNOR_LOAD: f32 = 0.8;
+
+/// The number of frames the frame rate has to be stable for before it is raised again.
+const GOVERNOR_SETTLE: usize = 30;
+
+/// Adapts the frame rate to the time the output takes to write a frame, settling at the highest
+/// rate the output can sustain. The rate is lowered as soon as the output falls behind and raised
+/// again slowly, up to a limit.
+pub struct Governor {
+    /// The interval of the highest frame rate that may be used.
+    fastest: time::Duration,
+    interval: time::Duration,
+    /// The moving average of the time it takes to write a frame, in seconds.
+    write: Option<f32>,
+    /// The number of frames since the frame rate was last changed.
+    settled: usize,
+}
+
+impl Governor {
+    pub fn new(fastest: time::Duration) -> Governor {
+        Governor { fastest, interval: fastest, write: None, settled: 0 }
+    }
+
+    /// Accounts for a frame that took `write` to write. Returns the new interval if the frame
+    /// rate should change.
+    pub fn update(&mut self, write: time::Duration) -> Option<time::Duration> {
+        let average = match self.write {
+            Some(average) => average * 0.9 + write.as_secs_f32() * 0.1,
+            None => write.as_secs_f32(),
+        };
+        self.write = Some(average);
+        self.settled += 1;
+        let target = time::Duration::from_secs_f32(average / GOVERNOR_LOAD).max(self.fastest);
+        let slower = target > self.interval.mul_f32(1.05);
+        let faster = self.settled >= GOVERNOR_SETTLE && target < self.interval.mul_f32(0.9);
+        if !slower && !faster {
+            return None;
+        }
+        self.interval = target;
+        self.settled = 0;
+        Some(target)
+    }
+}
+
+
+/// The interval at which the last frame is written again while it fades out.
+pub const FADE_INTERVAL: time::Duration = time::Duration::from_millis(20);
+
+/// The level of a fade that lasts `duration`, from 0 when it starts to 1.0 when it is done.
+pub fn ramp(elapsed: time::Duration, duration: time::Duration) -> f32 {
+    if elapsed >= duration {
+        return 1.0;
+    }
+    elapsed.as_secs_f32() / duration.as_secs_f32()
+}
+
+
+/// Limits how fast the total brightness of the frames that are written may rise, so a sudden full
+/// white frame does not draw more current than a marginal power supply can deliver at once.
+/// Lowering the brightness is not limited.
+pub struct Slew {
+    max_step: f32,
+    /// The total brightness of the last frame that was written. The display is assumed to be
+    /// black before the first frame.
+    last: f32,
+}
+
+impl Slew {
+    /// Creates a limiter that lets the total brightness rise by at most `max_step` of full white
+    /// per frame.
+    pub fn new(max_step: f32) -> Slew {
+        Slew { max_step, last: 0.0 }
+    }
+
+    /// Determines the level by which a frame with the specified total brightness should be
+    /// scaled to stay within the step of the previous frame.
+    pub fn limit(&mut self, brightness: f32) -> f32 {
+        let max = self.last + self.max_step;
+        let level = if brightness > max { max / brightness } else { 1.0 };
+        self.last = brightness * level;
+        level
+    }
+}
+
+/// The total brightness of a frame, from 0 when all pixels are off to 1.0 when they are all full
+/// white.
+pub fn brightness(frame: &[Pixel16]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+    let sum: u64 = frame.iter().map(|pix| u64::from(pix.r) + u64::from(pix.g) + u64::from(pix.b)).sum();
+    (sum as f64 / (frame.len() as f64 * 3.0 * 65535.0)) as f32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_deadlines() {
+        let ms = time::Duration::from_millis;
+        let start = time::Instant::now();
+        let mut pacer = Pacer::new(ms(10), ms(0));
+        assert_eq!(start, pacer.advance(start));
+        // A frame that took 3ms does not delay the next deadline.
+        assert_eq!(start + ms(10), pacer.advance(start + ms(3)));
+        assert_eq!(start + ms(20), pacer.advance(start + ms(19)));
+        // A late frame keeps the schedule.
+        assert_eq!(start + ms(30), pacer.advance(start + ms(35)));
+        assert_eq!(start + ms(40), pacer.advance(start + ms(36)));
+        // After a stall, the schedule starts over.
+        assert_eq!(start + ms(65), pacer.advance(start + ms(65)));
+        assert_eq!(start + ms(75), pacer.advance(start + ms(66)));
+    }
+
+    #[test]
+    fn governor_adapts() {
+        let ms = time::Duration::from_millis;
+        let rounded = |d: Option<time::Duration>| d.map(|d| (d.as_secs_f32() * 1000.0).round() as u64);
+        let mut governor = Governor::new(ms(10));
+        // An output that is fast enough keeps the highest frame rate.
+        for _ in 0..100 {
+            assert_eq!(None, governor.update(ms(5)));
+        }
+        // A slow output lowers the frame rate right away.
+        assert_eq!(Some(25), rounded(governor.update(ms(155))));
+        assert_eq!(None, governor.update(ms(20)));
+        // Once the output is faster again, the rate is raised after it has settled, up to the
+        // limit.
+        for _ in 2..GOVERNOR_SETTLE {
+            assert_eq!(None, governor.update(ms(5)));
+        }
+        assert_eq!(Some(10), rounded(governor.update(ms(5))));
+    }
+
+    #[test]
+    fn ramp_level() {
+        let ms = time::Duration::from_millis;
+        assert_eq!(0.0, ramp(ms(0), ms(200)));
+        assert_eq!(0.25, ramp(ms(50), ms(200)));
+        assert_eq!(1.0, ramp(ms(200), ms(200)));
+        assert_eq!(1.0, ramp(ms(300), ms(200)));
+        assert_eq!(1.0, ramp(ms(0), ms(0)));
+    }
+
+    #[test]
+    fn slew_limit() {
+        let white = vec![Pixel16 { r: 65535, g: 65535, b: 65535 }; 4];
+        assert_eq!(1.0, brightness(&white));
+        assert_eq!(0.5, brightness(&[Pixel16 { r: 65535, g: 65535, b: 65535 }, Pixel16::default()]));
+        let mut slew = Slew::new(0.25);
+        assert_eq!(0.25, slew.limit(1.0));
+        assert_eq!(0.5, slew.limit(1.0));
+        // Frames that are dim enough are not scaled.
+        assert_eq!(1.0, slew.limit(0.6));
+        // Going dark is not limited, but the brightness rises slowly again afterwards.
+        assert_eq!(1.0, slew.limit(0.0));
+        assert_eq!(0.5, slew.limit(0.5));
+    }
+}
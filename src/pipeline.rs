@@ -0,0 +1,592 @@
+use std::io;
+use std::process;
+use std::sync::{self, mpsc};
+use std::thread;
+use std::time;
+use color::*;
+use color::lut::Lut;
+use color::planar::Planes;
+use control;
+use device::Output;
+use input::{mask, overlay, scale, select, MapFrames};
+use input::crop::Crop;
+use input::geometry::{self, Dimensions, Map};
+use pace;
+use signal;
+use stats;
+use systemd;
+
+
+/// The geometry of the frames on their way from the inputs to the display, which follows from
+/// --geometry, --crop, --scale, --points, --rings, --tiles, --map and --mask.
+pub struct Geometry {
+    pub crop: Option<Crop>,
+    pub scale: Option<Dimensions>,
+    /// The geometry of the frames that are read from the inputs.
+    pub input: Option<Dimensions>,
+    pub cropped: Option<Dimensions>,
+    pub scaled: Option<Dimensions>,
+    /// The pixel of the scaled frame shown by each pixel of a display that is not a plain
+    /// rectangle.
+    pub samples: Option<Vec<usize>>,
+    /// The rectangular geometry of the frames after they have been cropped, scaled and projected.
+    pub display: Option<Dimensions>,
+    /// The geometry of a single segment of the display.
+    pub segment: Option<Dimensions>,
+    pub map: Option<Map>,
+    pub mask: Option<mask::Mask>,
+    pub mask_mode: mask::Mode,
+}
+
+/// Crops, scales, projects and masks the frames of the input to the geometry of the display and
+/// adds the --debug-overlay.
+pub fn transform_input(input: select::Reader,
+                       geometry: Geometry,
+                       scale_filter: scale::Filter,
+                       debug_overlay: bool)
+                       -> Box<dyn io::Read + Send> {
+    let dimensions = geometry.input.unwrap();
+    let logical_dimensions = geometry.display.unwrap();
+    let input: Box<dyn io::Read + Send> = match geometry.crop {
+        Some(crop) => {
+            let source_width = match dimensions {
+                Dimensions::Two(w, _) => w,
+                Dimensions::One(_) => unreachable!(),
+            };
+            Box::new(MapFrames::new(input, dimensions.size() * 3, move |frame| crop.apply(source_width, frame)))
+        },
+        None => Box::new(input),
+    };
+    let input: Box<dyn io::Read + Send> = match (geometry.scale, geometry.cropped) {
+        (Some(target), Some(source)) => {
+            let scale = scale::Scale {
+                source,
+                target,
+                filter: scale_filter,
+            };
+            Box::new(MapFrames::new(input, source.size() * 3, move |frame| scale.apply(frame)))
+        },
+        _ => input,
+    };
+    let input: Box<dyn io::Read + Send> = match (geometry.samples, geometry.scaled) {
+        (Some(samples), Some(source)) => {
+            Box::new(MapFrames::new(input, source.size() * 3, move |frame| {
+                samples.iter()
+                    .flat_map(|&i| frame[i * 3..i * 3 + 3].iter().cloned())
+                    .collect()
+            }))
+        },
+        _ => input,
+    };
+    let input: Box<dyn io::Read + Send> = match (geometry.mask, geometry.mask_mode) {
+        (Some(mask), mask::Mode::Black) => {
+            Box::new(MapFrames::new(input, logical_dimensions.size() * 3, move |frame| mask.apply_black(frame)))
+        },
+        _ => input,
+    };
+    if debug_overlay {
+        let mut counter = overlay::Counter::default();
+        Box::new(MapFrames::new(input, logical_dimensions.size() * 3, move |frame| counter.apply(frame)))
+    } else {
+        input
+    }
+}
+
+/// Builds the table that maps each pixel of the rectangular display to its index in the output,
+/// or None if the pixel is left out. The operations are those of --transpose.
+pub fn output_transposition(dimensions: Dimensions,
+                            transpose: Vec<&str>,
+                            map: Option<&Map>,
+                            mask: Option<&mask::Mask>,
+                            mask_mode: mask::Mode)
+                            -> Result<Vec<Option<usize>>, String> {
+    let transposition = geometry::transposition_table(&dimensions, transpose, map.cloned())?;
+    assert_eq!(dimensions.size(), transposition.len());
+    Ok(match (mask, mask_mode) {
+        (Some(mask), mask::Mode::Skip) => mask.skip(&transposition),
+        _ => transposition.into_iter().map(Some).collect(),
+    })
+}
+
+
+/// What is done to each frame between the input and the output.
+pub struct Processing {
+    /// The output index of each pixel of the input, or None if it is left out.
+    pub transposition: Vec<Option<usize>>,
+    pub correction: Correction,
+    pub adjust: adjust::Adjust,
+}
+
+/// When to stop piping frames, set by --num-frames and --duration.
+pub struct Timing {
+    pub num_frames: Option<usize>,
+    pub duration: Option<time::Duration>,
+}
+
+/// How the output is faded in and out and how fast it may change.
+pub struct Fading {
+    pub fade_in: Option<time::Duration>,
+    pub fade_out: Option<time::Duration>,
+    pub slew: Option<pace::Slew>,
+    pub blank_on_exit: bool,
+}
+
+/// How frames are buffered and at which rate they are written.
+pub struct Pacing {
+    pub skip_frames: bool,
+    /// The number of processed frames that may wait for the output.
+    pub queue: usize,
+    pub pacer: Option<pace::Pacer>,
+    pub governor: Option<pace::Governor>,
+}
+
+/// What reports on the frames that are written.
+pub struct Observability {
+    pub tee: Option<Box<dyn io::Write + Send>>,
+    pub stats: Option<sync::Arc<stats::Stats>>,
+    pub watchdog: Option<systemd::Watchdog>,
+}
+
+/// Everything that controls how pipe_frames moves the frames, besides what is done to them.
+pub struct PipeOptions {
+    pub timing: Timing,
+    pub fading: Fading,
+    pub pacing: Pacing,
+    pub observability: Observability,
+}
+
+/// Pipes the frames of the input to the output, processing each on the way, until the input ends,
+/// a limit of the timing is reached or the process is asked to terminate.
+pub fn pipe_frames(input: impl io::Read + Send + 'static,
+                   mut dev: impl Output + 'static,
+                   processing: Processing,
+                   state: sync::Arc<control::State>,
+                   options: PipeOptions)
+                   -> io::Result<()> {
+    let Processing { transposition, correction, adjust } = processing;
+    let PipeOptions {
+        timing: Timing { num_frames, duration },
+        fading: Fading { fade_in, fade_out, mut slew, blank_on_exit },
+        pacing: Pacing { skip_frames, queue, mut pacer, mut governor },
+        observability: Observability { mut tee, stats, mut watchdog },
+    } = options;
+    let (err_tx, err_rx) = mpsc::channel();
+    macro_rules! try_or_send {
+        ($tx:expr, $expression:expr) => (
+            match $expression {
+                Ok(val)  => val,
+                Err(err) => {
+                    $tx.send(Err(err)).unwrap();
+                    return;
+                }
+            }
+        )
+    }
+
+    let local_err_tx = err_tx.clone();
+    let num_pixels = transposition.len();
+    let num_outputs = transposition.iter().filter(|t| t.is_some()).count();
+    let (input_tx, input_rx) = mpsc::sync_channel(1);
+    // The buffers are passed back once a frame is done with them, so no memory is allocated for
+    // each frame.
+    let (bin_recycle_tx, bin_recycle_rx) = mpsc::channel::<Vec<u8>>();
+    let (recycle_tx, recycle_rx) = mpsc::channel::<(Vec<Pixel16>, Vec<u8>)>();
+    // Allocate the buffers of the frames in flight up front. With --realtime, the memory is
+    // locked by now, so the buffers are faulted in before the first frame instead of while it is
+    // being written. The input only reads into these buffers, of which at most three are in
+    // flight: being read, waiting in the channel and being mapped.
+    let tee_size = if tee.is_some() { num_outputs * 3 } else { 0 };
+    for _ in 0..queue + 3 {
+        bin_recycle_tx.send(vec![0; num_pixels * 3]).unwrap();
+        recycle_tx.send((vec![Pixel16::default(); num_outputs], vec![0; tee_size])).unwrap();
+    }
+    let input_stats = stats.clone();
+    thread::spawn(move || {
+        try_or_send!(local_err_tx, read_frames(input, input_tx, &bin_recycle_rx, skip_frames, num_frames, input_stats));
+    });
+
+    // Processed frames queue up for the output, so a write that blocks for a moment does not hold
+    // up the input.
+    let (map_tx, map_rx) = mpsc::sync_channel(queue);
+    let stop_tx = map_tx.clone();
+    let teeing = tee.is_some();
+    let map_stats = stats.clone();
+    let map_state = state.clone();
+    thread::spawn(move || {
+        let mut correction = correction;
+        let mut adjust = adjust;
+        // The lookup table for the current brightness, which is computed again when it changes.
+        let mut lut: Option<(Lut, (f32, f32))> = None;
+        // The channels of the frame when there is no lookup table, reused between frames.
+        let mut planes = Planes::default();
+        for (frame, bin_buffer) in input_rx.into_iter().enumerate() {
+            let start = time::Instant::now();
+            correction.update(time::SystemTime::now());
+            // The brightness may be changed at runtime through the control socket.
+            if map_state.linear_dim {
+                correction.set_brightness(map_state.dim());
+            } else {
+                adjust.dim = map_state.dim();
+            }
+            let brightness = (adjust.dim, correction.brightness());
+            if lut.as_ref().map(|&(_, b)| b) != Some(brightness) {
+                lut = Lut::new(&adjust, &correction).map(|l| (l, brightness));
+            }
+            let (mut buffer, mut tee_buffer) = recycle_rx.try_recv().unwrap_or_default();
+            buffer.clear();
+            buffer.resize(num_outputs, Pixel16::default());
+            if teeing {
+                tee_buffer.clear();
+                tee_buffer.resize(num_outputs * 3, 0);
+            }
+            match lut {
+                // Look up the adjusted and corrected value of each channel.
+                Some((ref lut, _)) => {
+                    for (transpose_mapped, bin) in transposition.iter().zip(bin_buffer.chunks(3)) {
+                        // Pixels that are masked have no place in the output.
+                        let transpose_mapped = match *transpose_mapped {
+                            Some(i) => i,
+                            None => continue,
+                        };
+                        let pix = Pixel {
+                            r: bin[0],
+                            g: bin[1],
+                            b: bin[2],
+                        };
+                        if !tee_buffer.is_empty() {
+                            let adjusted = lut.adjust(&pix);
+                            tee_buffer[transpose_mapped * 3..transpose_mapped * 3 + 3]
+                                .copy_from_slice(&[adjusted.r, adjusted.g, adjusted.b]);
+                        }
+                        // Apply transposition and store the pixel in the output buffer.
+                        buffer[transpose_mapped] = correction.dither_at(lut.correct(&pix), transpose_mapped, frame);
+                    }
+                },
+                None => {
+                    // Apply transposition, then dimming and other adjustments and the color
+                    // correction to a plane of each channel at a time. The color is kept at full
+                    // precision until it is quantized by the device.
+                    planes.reset(num_outputs);
+                    for (transpose_mapped, bin) in transposition.iter().zip(bin_buffer.chunks(3)) {
+                        if let Some(i) = *transpose_mapped {
+                            planes.set_pixel(i, &Pixel { r: bin[0], g: bin[1], b: bin[2] });
+                        }
+                    }
+                    adjust.apply_planar(&mut planes);
+                    if !tee_buffer.is_empty() {
+                        for (c, plane) in planes.channels.iter().enumerate() {
+                            for (i, v) in plane.iter().enumerate() {
+                                tee_buffer[i * 3 + c] = (v * 255.0).round() as u8;
+                            }
+                        }
+                    }
+                    correction.correct_planar(&mut planes, &mut buffer, frame);
+                },
+            }
+            let _ = bin_recycle_tx.send(bin_buffer);
+            if let Some(ref stats) = map_stats {
+                stats.add_transform(start.elapsed());
+            }
+            if map_tx.send(Some((buffer, tee_buffer))).is_err() {
+                break;
+            }
+        }
+        // The end of the input is signalled explicitly since the signal handling thread may hold
+        // on to a sender as well.
+        let _ = map_tx.send(None);
+    });
+
+    if blank_on_exit || fade_out.is_some() {
+        // On SIGINT or SIGTERM, the output is stopped after the frame it is writing so it can be
+        // blanked or faded out. The reading and mapping threads are left behind since the input may block
+        // indefinitely.
+        let terminate = signal::on_terminate()?;
+        let stop_err_tx = err_tx.clone();
+        thread::spawn(move || {
+            if terminate.recv().is_ok() {
+                let _ = stop_tx.send(None);
+                let _ = stop_err_tx.send(Ok(()));
+            }
+            // Exit right away if the output does not stop in time for the next signal.
+            if terminate.recv().is_ok() {
+                process::exit(1);
+            }
+        });
+    }
+
+    // A duration that ends beyond what the clock can represent never ends.
+    let end = duration.and_then(|d| time::Instant::now().checked_add(d));
+    let output_thread = thread::spawn(move || {
+        let mut fade_start = None;
+        // The last frame that was written and the level it was faded in to, which is faded out
+        // before exiting.
+        let mut last = None;
+        let mut faded = Vec::new();
+        let black = vec![Pixel16::default(); num_outputs];
+        loop {
+            let start = time::Instant::now();
+
+            // With a duration, the output is stopped at the end of it even if the input blocks.
+            let received = match end {
+                Some(end) if start >= end => None,
+                Some(end) => map_rx.recv_timeout(end - start).ok(),
+                None => map_rx.recv().ok(),
+            };
+            let (buffer, tee_buffer) = match received {
+                Some(Some(v)) => v,
+                Some(None) | None => break,
+            };
+            if let Some(ref mut pacer) = pacer {
+                pacer.wait();
+            }
+            // Frames are flowing, also while the output is paused.
+            if let Some(ref mut watchdog) = watchdog {
+                watchdog.frame();
+            }
+            // While paused, the frames are still consumed so the inputs do not block.
+            if !state.paused() {
+                let write_start = time::Instant::now();
+                let mut level = fade_in
+                    .map(|d| pace::ramp(fade_start.get_or_insert(write_start).elapsed(), d))
+                    .unwrap_or(1.0);
+                if let Some(ref mut slew) = slew {
+                    level *= slew.limit(pace::brightness(&buffer) * level);
+                }
+                if level < 1.0 {
+                    faded.clear();
+                    faded.extend(buffer.iter().map(|pix| pix.scale(level)));
+                    try_or_send!(err_tx, dev.output_frame_wide(&faded));
+                } else {
+                    try_or_send!(err_tx, dev.output_frame_wide(&buffer));
+                }
+                trace!("Wrote frame {} in {:?}", state.frames(), write_start.elapsed());
+                let adapted = governor.as_mut().and_then(|g| g.update(write_start.elapsed()));
+                if let (Some(interval), Some(pacer)) = (adapted, pacer.as_mut()) {
+                    debug!("Adapted the frame rate to {:.1} frames per second", 1.0 / interval.as_secs_f32());
+                    pacer.set_interval(interval);
+                }
+                if let Some(ref stats) = stats {
+                    stats.add_write(write_start.elapsed());
+                }
+                state.add_frame();
+                if let Some(ref mut tee) = tee {
+                    try_or_send!(err_tx, tee.write_all(&tee_buffer).and_then(|_| tee.flush()));
+                }
+                // The output is known to work once the first frame has been written.
+                if state.frames() == 1 {
+                    systemd::notify_or_warn("READY=1");
+                }
+                if fade_out.is_some() {
+                    // The frame is kept to be faded out, the one before it can be reused.
+                    if let Some((buffer, _)) = last.replace((buffer, level)) {
+                        let _ = recycle_tx.send((buffer, tee_buffer));
+                    }
+                    continue;
+                }
+            } else if state.take_blank() {
+                try_or_send!(err_tx, dev.output_frame_wide(&black));
+            }
+            let _ = recycle_tx.send((buffer, tee_buffer));
+        }
+        systemd::notify_or_warn("STOPPING=1");
+        let faded_out = match (fade_out, last) {
+            (Some(fade_out), Some((buffer, level))) if !state.paused() => {
+                let start = time::Instant::now();
+                loop {
+                    let out = level * (1.0 - pace::ramp(start.elapsed(), fade_out));
+                    faded.clear();
+                    faded.extend(buffer.iter().map(|pix| pix.scale(out)));
+                    try_or_send!(err_tx, dev.output_frame_wide(&faded));
+                    if out == 0.0 {
+                        break;
+                    }
+                    thread::sleep(pace::FADE_INTERVAL);
+                }
+                true
+            },
+            _ => false,
+        };
+        if blank_on_exit && !faded_out {
+            try_or_send!(err_tx, dev.output_frame_wide(&black));
+        }
+        // The error channel is not closed while the signal handling thread holds on to it.
+        let _ = err_tx.send(Ok(()));
+    });
+
+    let result = match err_rx.recv() {
+        Ok(err) => err,
+        Err(_) => Ok(()),
+    };
+    // Wait for the frames that were already read to be written and for the output to be dropped,
+    // so devices can clean up before the process exits.
+    let _ = output_thread.join();
+    result
+}
+
+/// Reads whole frames from the input into the buffers that come back through `recycle` and sends
+/// them on, until the input ends, `num_frames` have been sent or the receiving side hangs up.
+///
+/// With `skip_frames`, a frame that is read while the receiving side is still busy is discarded
+/// and the next frame is read into its buffer.
+fn read_frames(mut input: impl io::Read,
+               frames_tx: mpsc::SyncSender<Vec<u8>>,
+               recycle: &mpsc::Receiver<Vec<u8>>,
+               skip_frames: bool,
+               num_frames: Option<usize>,
+               stats: Option<sync::Arc<stats::Stats>>)
+               -> io::Result<()> {
+    let mut frames = 0;
+    // The buffer of a skipped frame, which the next frame is read into.
+    let mut spare = None;
+    loop {
+        let start = time::Instant::now();
+        // Read a full frame into a buffer. This prevents half frames being written to a
+        // potentially timing sensitive output if the input blocks and lets us apply the
+        // transpositions.
+        let mut bin_buffer = match spare.take() {
+            Some(buffer) => buffer,
+            None => match recycle.recv() {
+                Ok(buffer) => buffer,
+                Err(_) => return Ok(()),
+            },
+        };
+        input.read_exact(&mut bin_buffer)?;
+        if let Some(ref stats) = stats {
+            stats.add_read(start.elapsed());
+        }
+        // The output may have stopped, e.g. at the end of --duration.
+        if skip_frames {
+            // Keep reading while the output is busy, so frames do not pile up in the input.
+            match frames_tx.try_send(bin_buffer) {
+                Ok(()) => (),
+                Err(mpsc::TrySendError::Full(buffer)) => {
+                    trace!("Skipped a frame, the output can not keep up");
+                    if let Some(ref stats) = stats {
+                        stats.add_skipped_frame();
+                    }
+                    spare = Some(buffer);
+                    continue;
+                },
+                Err(mpsc::TrySendError::Disconnected(_)) => return Ok(()),
+            }
+        } else if frames_tx.send(bin_buffer).is_err() {
+            return Ok(());
+        }
+        frames += 1;
+        if num_frames == Some(frames) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct SlowOutput;
+
+    impl Output for SlowOutput {
+        fn color_correction(&self) -> Correction {
+            Correction::none()
+        }
+
+        fn output_frame(&mut self, _: &[Pixel]) -> io::Result<()> {
+            thread::sleep(time::Duration::from_millis(2));
+            Ok(())
+        }
+
+        fn output_frame_wide(&mut self, _: &[Pixel16]) -> io::Result<()> {
+            thread::sleep(time::Duration::from_millis(2));
+            Ok(())
+        }
+    }
+
+    /// Sends every frame that is written to it to a channel.
+    struct RecordingOutput(mpsc::Sender<Vec<Pixel16>>);
+
+    impl Output for RecordingOutput {
+        fn color_correction(&self) -> Correction {
+            Correction::none()
+        }
+
+        fn output_frame(&mut self, frame: &[Pixel]) -> io::Result<()> {
+            let wide = frame.iter().map(|pix| Pixel16 { r: pix.r.into(), g: pix.g.into(), b: pix.b.into() });
+            self.0.send(wide.collect()).unwrap();
+            Ok(())
+        }
+
+        fn output_frame_wide(&mut self, frame: &[Pixel16]) -> io::Result<()> {
+            self.0.send(frame.to_vec()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn skip_frames_slow_output() {
+        let processing = Processing {
+            transposition: (0..16).map(Some).collect(),
+            correction: Correction::none(),
+            adjust: adjust::Adjust::default(),
+        };
+        let stats = sync::Arc::new(stats::Stats::default());
+        let options = PipeOptions {
+            timing: Timing { num_frames: Some(50), duration: None },
+            fading: Fading { fade_in: None, fade_out: None, slew: None, blank_on_exit: false },
+            pacing: Pacing { skip_frames: true, queue: 1, pacer: None, governor: None },
+            observability: Observability { tee: None, stats: Some(stats.clone()), watchdog: None },
+        };
+        let state = sync::Arc::new(control::State::new(1.0, false));
+        pipe_frames(io::repeat(0), SlowOutput, processing, state, options).unwrap();
+        // The input is read much faster than the output writes it.
+        assert!(stats.totals().skipped_frames > 50);
+    }
+
+    #[test]
+    fn skip_frames_reuses_buffers() {
+        let (frames_tx, frames_rx) = mpsc::sync_channel(1);
+        let (recycle_tx, recycle_rx) = mpsc::channel();
+        for _ in 0..3 {
+            recycle_tx.send(vec![0; 3]).unwrap();
+        }
+        let stats = sync::Arc::new(stats::Stats::default());
+        let reader_stats = stats.clone();
+        let reader = thread::spawn(move || {
+            read_frames(io::repeat(0), frames_tx, &recycle_rx, true, Some(50), Some(reader_stats)).unwrap();
+            recycle_rx
+        });
+        let mut frames = 0;
+        while let Ok(frame) = frames_rx.recv_timeout(time::Duration::from_secs(5)) {
+            thread::sleep(time::Duration::from_millis(1));
+            frames += 1;
+            recycle_tx.send(frame).unwrap();
+        }
+        assert_eq!(frames, 50);
+        assert!(stats.totals().skipped_frames > 0);
+        // Every buffer came back, none of the skipped frames took its buffer with it.
+        assert_eq!(reader.join().unwrap().try_iter().count(), 3);
+    }
+
+    #[test]
+    fn blank_on_exit_at_end_of_input() {
+        let processing = Processing {
+            transposition: (0..2).map(Some).collect(),
+            correction: Correction::none(),
+            adjust: adjust::Adjust::default(),
+        };
+        let options = PipeOptions {
+            timing: Timing { num_frames: None, duration: None },
+            fading: Fading { fade_in: None, fade_out: None, slew: None, blank_on_exit: true },
+            pacing: Pacing { skip_frames: false, queue: 1, pacer: None, governor: None },
+            observability: Observability { tee: None, stats: None, watchdog: None },
+        };
+        let state = sync::Arc::new(control::State::new(1.0, false));
+        let (tx, rx) = mpsc::channel();
+        // The end of the input is reported as an unexpected EOF, which main treats as a clean exit.
+        let result = pipe_frames(io::Cursor::new(vec![0xff; 6]), RecordingOutput(tx), processing, state, options);
+        assert_eq!(io::ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        let frames: Vec<_> = rx.try_iter().collect();
+        assert_eq!(2, frames.len());
+        assert!(frames[0].iter().all(|pix| *pix != Pixel16::default()));
+        assert_eq!(vec![Pixel16::default(); 2], frames[1]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use std::path;
+use ::config::{self, Config};
+
+
+/// The presets that ship with ledcat. The first line of each preset describes the hardware.
+///
+/// The HUB75 panels are wired to a Raspberry Pi like the "regular" mapping of the rpi-rgb-led-matrix
+/// library.
+const BUILTIN: &[(&str, &str)] = &[
+    ("p10-1/8-scan", r#"# A 32x16 P10 HUB75 panel with 1/8 scan on a Raspberry Pi
+geometry = "32x16"
+device = "hub75"
+[hub75]
+level-select = "22,23,24"
+red = "11,8"
+green = "27,9"
+blue = "7,10"
+clock = 17
+latch = 4
+output-enable = 18
+"#),
+    ("hub75-32x32", r#"# A 32x32 HUB75 panel with 1/16 scan on a Raspberry Pi
+geometry = "32x32"
+device = "hub75"
+[hub75]
+level-select = "22,23,24,25"
+red = "11,8"
+green = "27,9"
+blue = "7,10"
+clock = 17
+latch = 4
+output-enable = 18
+"#),
+    ("hub75-64x32", r#"# A 64x32 HUB75 panel with 1/16 scan on a Raspberry Pi
+geometry = "64x32"
+device = "hub75"
+[hub75]
+level-select = "22,23,24,25"
+red = "11,8"
+green = "27,9"
+blue = "7,10"
+clock = 17
+latch = 4
+output-enable = 18
+"#),
+    ("hub75-64x64", r#"# A 64x64 HUB75 panel with 1/32 scan on a Raspberry Pi, the E line is wired to GPIO 15
+geometry = "64x64"
+device = "hub75"
+[hub75]
+level-select = "22,23,24,25,15"
+red = "11,8"
+green = "27,9"
+blue = "7,10"
+clock = 17
+latch = 4
+output-enable = 18
+"#),
+];
+
+/// The directory in each configuration directory that holds the user-defined presets.
+const DIR_NAME: &str = "presets";
+
+/// The extension of the files of user-defined presets.
+const EXTENSION: &str = "toml";
+
+
+/// Loads a preset by name. User-defined presets, stored as `presets/NAME.toml` in the
+/// configuration directories, take precedence over the built-in presets.
+pub fn load(name: &str) -> io::Result<Config> {
+    if let Some(path) = user_path(&config::dirs(), name) {
+        return Config::load(path);
+    }
+    match BUILTIN.iter().find(|&&(n, _)| n == name) {
+        Some(&(_, source)) => source.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown preset: {}", name))),
+    }
+}
+
+/// Returns the name and description of all presets, the user-defined presets first.
+pub fn list() -> Vec<(String, String)> {
+    list_in(&config::dirs())
+}
+
+fn list_in(dirs: &[path::PathBuf]) -> Vec<(String, String)> {
+    let mut presets: Vec<(String, String)> = Vec::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(dir.join(DIR_NAME)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut user_presets: Vec<(String, String)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|ext| ext == EXTENSION).unwrap_or(false))
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_string_lossy().into_owned();
+                let source = fs::read_to_string(&path).ok()?;
+                Some((name, description(&source)))
+            })
+            .filter(|(name, _)| !presets.iter().any(|(n, _)| n == name))
+            .collect();
+        user_presets.sort();
+        presets.extend(user_presets);
+    }
+    for &(name, source) in BUILTIN {
+        if !presets.iter().any(|(n, _)| n == name) {
+            presets.push((name.to_string(), description(source)));
+        }
+    }
+    presets
+}
+
+fn user_path(dirs: &[path::PathBuf], name: &str) -> Option<path::PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(DIR_NAME).join(format!("{}.{}", name, EXTENSION)))
+        .find(|path| path.is_file())
+}
+
+/// The description of a preset is the comment on its first line.
+fn description(source: &str) -> String {
+    source.lines().next()
+        .and_then(|line| line.trim().strip_prefix('#'))
+        .map(|d| d.trim().to_string())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use super::*;
+
+    #[test]
+    fn builtin_presets() {
+        for &(name, source) in BUILTIN {
+            let config: Config = source.parse().unwrap();
+            assert!(config.device.is_some(), "{} has no device", name);
+            assert!(config.options.iter().any(|(key, _)| key == "geometry"), "{} has no geometry", name);
+            assert!(!description(source).is_empty(), "{} has no description", name);
+        }
+    }
+
+    #[test]
+    fn load_unknown() {
+        assert_eq!(io::ErrorKind::NotFound, load("does-not-exist").unwrap_err().kind());
+    }
+
+    #[test]
+    fn list_user_presets() {
+        let tmp = tempdir::TempDir::new("ledcat-presets").unwrap();
+        let dirs = vec![tmp.path().to_path_buf()];
+        fs::create_dir(tmp.path().join(DIR_NAME)).unwrap();
+        fs::write(tmp.path().join(DIR_NAME).join("wall.toml"), "# The wall\ngeometry = \"8x8\"\n").unwrap();
+        fs::write(tmp.path().join(DIR_NAME).join("old.conf"), "# Not a preset\n").unwrap();
+        let presets = list_in(&dirs);
+        assert_eq!(("wall".to_string(), "The wall".to_string()), presets[0]);
+        assert!(!presets.iter().any(|(name, _)| name == "old"));
+        assert_eq!(Some(tmp.path().join(DIR_NAME).join("wall.toml")), user_path(&dirs, "wall"));
+    }
+}
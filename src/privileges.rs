@@ -0,0 +1,71 @@
+use std::ffi::CString;
+use std::io;
+use nix::libc;
+
+
+/// Looks up the ID and primary group of a user, given by name or number.
+pub fn user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let cname = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid user name"))?;
+    let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+    if !pw.is_null() {
+        return Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) });
+    }
+    let uid = name.parse::<libc::uid_t>()
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("no such user: {}", name)))?;
+    let pw = unsafe { libc::getpwuid(uid) };
+    if pw.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no such user: {}", name)));
+    }
+    Ok(unsafe { (uid, (*pw).pw_gid) })
+}
+
+/// Looks up the ID of a group, given by name or number.
+pub fn group(name: &str) -> io::Result<libc::gid_t> {
+    let cname = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid group name"))?;
+    let gr = unsafe { libc::getgrnam(cname.as_ptr()) };
+    if !gr.is_null() {
+        return Ok(unsafe { (*gr).gr_gid });
+    }
+    name.parse::<libc::gid_t>()
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("no such group: {}", name)))
+}
+
+/// Switches the process to the user and group for good. The supplementary groups are replaced by
+/// the group, so access granted through e.g. the spi or dialout group is lost as well.
+pub fn drop_to(uid: Option<libc::uid_t>, gid: libc::gid_t) -> io::Result<()> {
+    // The group can only be changed while the process still is root.
+    if unsafe { libc::setgroups(1, &gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Make sure root can not be regained.
+        if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "root privileges could be regained"));
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!((0, 0), user("root").unwrap());
+        assert_eq!((0, 0), user("0").unwrap());
+        assert_eq!(0, group("root").unwrap());
+        assert_eq!(12345, group("12345").unwrap());
+        assert_eq!(io::ErrorKind::NotFound, user("no-such-user").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::NotFound, group("no-such-group").unwrap_err().kind());
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use clap;
+use toml;
+
+
+/// The options that make up the calibration of a display and can be stored in a profile.
+pub const OPTIONS: &[&str] = &[
+    "color-correction",
+    "white-balance",
+    "brightness-map",
+    "color-order",
+    "byte-order",
+    "dim",
+    "dim-mode",
+    "dither",
+    "dither-bits",
+    "levels",
+    "contrast",
+    "brightness-offset",
+    "saturation",
+    "hue-rotate",
+];
+
+
+/// The value of an option in a profile or configuration file: a string, a number, `true` or
+/// `false`, or an array of values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    /// Numbers are kept as text, they are passed on as arguments anyway.
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::String(ref s) => write!(f, "{}", toml::Value::String(s.clone())),
+            Value::Number(ref n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Array(ref values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            },
+        }
+    }
+}
+
+
+/// A named set of calibration options for a physical display, such as its white balance, color
+/// correction and brightness map.
+///
+/// Profiles are stored as TOML, where the keys are the names of the command line options without
+/// the leading dashes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub entries: Vec<(String, Value)>,
+}
+
+impl Profile {
+    pub fn load<P: AsRef<path::Path>>(path: P) -> io::Result<Profile> {
+        parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Collects the calibration options that have been set on the command line.
+    pub fn from_matches(matches: &clap::ArgMatches) -> Profile {
+        let entries = OPTIONS.iter()
+            .filter(|&&name| matches.occurrences_of(name) > 0)
+            .map(|&name| {
+                let mut values: Vec<Value> = matches.values_of(name)
+                    .map(|values| values.map(to_value).collect())
+                    .unwrap_or_default();
+                let value = if values.is_empty() {
+                    // Flags have no value.
+                    Value::Bool(true)
+                } else if name == "color-correction" {
+                    Value::Array(values)
+                } else {
+                    values.remove(0)
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        Profile { entries }
+    }
+
+    /// Replaces the value of an option, or adds it if the profile does not set it yet.
+    pub fn set(&mut self, key: &str, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    /// Converts the profile to command line arguments, leaving out the options for which
+    /// `skip` returns true.
+    pub fn to_args<F>(&self, skip: F) -> Vec<String>
+        where F: Fn(&str) -> bool {
+        to_args(&self.entries, skip)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in &self.entries {
+            writeln!(f, "{} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts `key = value` entries to `--key=value` arguments. Arrays are passed as an argument
+/// for each value, flags are passed if they are true.
+pub fn to_args<F>(entries: &[(String, Value)], skip: F) -> Vec<String>
+    where F: Fn(&str) -> bool {
+    let mut args = Vec::new();
+    for (key, value) in entries.iter().filter(|(k, _)| !skip(k)) {
+        let values = match *value {
+            Value::Array(ref values) => values.clone(),
+            ref v => vec![v.clone()],
+        };
+        for value in values {
+            match value {
+                Value::Bool(false) => (),
+                Value::Bool(true) => args.push(format!("--{}", key)),
+                Value::String(s) | Value::Number(s) => args.push(format!("--{}={}", key, s)),
+                Value::Array(_) => (),
+            }
+        }
+    }
+    args
+}
+
+fn to_value(s: &str) -> Value {
+    // Numbers are written without quotes, so only those that are numbers in TOML as well.
+    let number = s.parse::<f64>().is_ok()
+        && format!("n = {}", s).parse::<toml::Table>()
+            .map(|table| table["n"].is_integer() || table["n"].is_float())
+            .unwrap_or(false);
+    if number {
+        Value::Number(s.to_string())
+    } else {
+        Value::String(s.to_string())
+    }
+}
+
+fn parse(s: &str) -> Result<Profile, String> {
+    let table: toml::Table = s.parse().map_err(|err: toml::de::Error| err.to_string())?;
+    let entries = table.into_iter()
+        .map(|(key, value)| {
+            if !OPTIONS.contains(&key.as_str()) {
+                return Err(format!("unknown key: {}", key));
+            }
+            let value = from_toml(value).map_err(|err| format!("{}: {}", key, err))?;
+            Ok((key, value))
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(Profile { entries })
+}
+
+/// Converts a TOML value to the value of an option. Tables and arrays of arrays can not be passed
+/// as an option and datetimes are passed as they are written.
+pub fn from_toml(value: toml::Value) -> Result<Value, String> {
+    match value {
+        toml::Value::String(s) => Ok(Value::String(s)),
+        toml::Value::Integer(i) => Ok(Value::Number(i.to_string())),
+        toml::Value::Float(f) => Ok(Value::Number(f.to_string())),
+        toml::Value::Boolean(b) => Ok(Value::Bool(b)),
+        toml::Value::Datetime(d) => Ok(Value::String(d.to_string())),
+        toml::Value::Array(values) => values.into_iter()
+            .map(|value| match value {
+                toml::Value::Array(_) => Err("arrays can not be nested".to_string()),
+                value => from_toml(value),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        toml::Value::Table(_) => Err("expected a value, not a table".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_parse() {
+        let profile = parse("# Living room\n\
+                             color-correction = [\"balance:1,0.9,0.95\", 'gamma:2.2'] # comment\n\
+                             \n\
+                             color-order = \"GRB\"\n\
+                             dim = 0.5\n\
+                             dither = true\n").unwrap();
+        assert_eq!(vec![
+            ("color-correction".to_string(), Value::Array(vec![
+                Value::String("balance:1,0.9,0.95".to_string()),
+                Value::String("gamma:2.2".to_string()),
+            ])),
+            ("color-order".to_string(), Value::String("GRB".to_string())),
+            ("dim".to_string(), Value::Number("0.5".to_string())),
+            ("dither".to_string(), Value::Bool(true)),
+        ], profile.entries);
+        assert_eq!(profile, parse(&profile.to_string()).unwrap());
+    }
+
+    #[test]
+    fn profile_parse_invalid() {
+        assert!(parse("geometry = 10").is_err());
+        assert!(parse("dim 0.5").is_err());
+        assert!(parse("dim = foo").is_err());
+        assert!(parse("color-order = \"GRB").is_err());
+        assert!(parse("color-correction = [\"srgb\"").is_err());
+        assert!(parse("dim = 0.5 0.6").is_err());
+        assert!(parse("color-correction = [\"srgb\",\ndim = 0.5").is_err());
+    }
+
+    #[test]
+    fn profile_parse_multiline_array() {
+        let profile = parse("color-correction = [\n\
+                             \x20   \"srgb\", # [the base\n\
+                             \x20   # comment\n\
+                             \x20   \"gamma:2.2\",\n\
+                             ]\n\
+                             dim = 0.5\n").unwrap();
+        assert_eq!(vec![
+            ("color-correction".to_string(), Value::Array(vec![
+                Value::String("srgb".to_string()),
+                Value::String("gamma:2.2".to_string()),
+            ])),
+            ("dim".to_string(), Value::Number("0.5".to_string())),
+        ], profile.entries);
+    }
+
+    #[test]
+    fn profile_to_args() {
+        let profile = parse("color-correction = [\"srgb\", \"gamma:2\"]\n\
+                             dim = 0.5\n\
+                             dither = true\n\
+                             color-order = \"GRB\"\n").unwrap();
+        assert_eq!(vec!["--color-correction=srgb", "--color-correction=gamma:2", "--dim=0.5", "--dither", "--color-order=GRB"],
+                   profile.to_args(|_| false));
+        assert_eq!(vec!["--dither", "--color-order=GRB"], profile.to_args(|k| k == "dim" || k == "color-correction"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::io;
+use std::ptr;
+use nix::libc;
+
+
+/// The SCHED_FIFO priority that is used when --realtime is set without a value. This is above
+/// the threads of most services and equal to the interrupt threads of PREEMPT_RT kernels.
+pub const DEFAULT_PRIORITY: i32 = 50;
+
+/// The amount of stack that is touched in advance, so growing the stack does not fault.
+const PREFAULT_STACK: usize = 256 * 1024;
+
+/// Runs the calling thread with the SCHED_FIFO policy at the specified priority. Threads that are
+/// spawned afterwards inherit the policy, but processes such as the commands of inputs do not.
+pub fn set_fifo(priority: i32) -> io::Result<()> {
+    let param = libc::sched_param { sched_priority: priority };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO | libc::SCHED_RESET_ON_FORK, &param) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Locks all current and future pages of the process in memory and keeps the allocator from
+/// returning memory to the system, so reused frame buffers never page fault.
+pub fn lock_memory() -> io::Result<()> {
+    // Once locked, all memory that is mapped later counts towards the limit as well. Failing to
+    // allocate would crash ledcat, so a limit is only acceptable for root, which ignores it.
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if limit.rlim_cur != libc::RLIM_INFINITY && unsafe { libc::geteuid() } != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+            format!("the memlock limit of {}KiB is too low to lock memory allocated later", limit.rlim_cur / 1024)));
+    }
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        libc::mallopt(libc::M_TRIM_THRESHOLD, -1);
+        libc::mallopt(libc::M_MMAP_MAX, 0);
+    }
+    prefault_stack();
+    Ok(())
+}
+
+/// Raises the limits that allow realtime scheduling and locking memory, while ledcat is still
+/// root, so --realtime keeps working after the privileges are dropped.
+pub fn raise_limits(priority: i32) -> io::Result<()> {
+    let rtprio = libc::rlimit { rlim_cur: priority as libc::rlim_t, rlim_max: priority as libc::rlim_t };
+    let memlock = libc::rlimit { rlim_cur: libc::RLIM_INFINITY, rlim_max: libc::RLIM_INFINITY };
+    let mut result = Ok(());
+    for &(resource, ref limit) in &[(libc::RLIMIT_RTPRIO, rtprio), (libc::RLIMIT_MEMLOCK, memlock)] {
+        if unsafe { libc::setrlimit(resource, limit) } != 0 {
+            result = Err(io::Error::last_os_error());
+        }
+    }
+    result
+}
+
+#[inline(never)]
+fn prefault_stack() {
+    let mut stack = [0u8; PREFAULT_STACK];
+    for page in stack.chunks_mut(4096) {
+        unsafe { ptr::write_volatile(&mut page[0], 0) };
+    }
+}
+
+/// Applies --realtime, only warning about what is not permitted so ledcat still runs.
+pub fn enable_or_warn(priority: i32) {
+    match lock_memory() {
+        Ok(()) => debug!("Locked the memory of the process"),
+        Err(err) => warn!("Could not lock the memory of the process: {}. Raise the memlock limit, \
+                           e.g. with LimitMEMLOCK=infinity in a systemd unit", err),
+    }
+    match set_fifo(priority) {
+        Ok(()) => debug!("Running with SCHED_FIFO at priority {}", priority),
+        Err(err) => warn!("Could not switch to realtime scheduling: {}. Run ledcat as root or raise \
+                           the rtprio limit, e.g. with LimitRTPRIO={} in a systemd unit", err, priority),
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd;
+
+
+/// The writing end of the pipe that the signal handler writes the number of each signal to.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// The channels that the signals read from the pipe are delivered to.
+static SUBSCRIBERS: Mutex<Vec<(Signal, mpsc::Sender<Signal>)>> = Mutex::new(Vec::new());
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+extern "C" fn handle_signal(signum: libc::c_int) {
+    let fd = SIGNAL_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // Only async-signal-safe functions may be called from a signal handler. The write may
+        // change errno, which the interrupted code could be about to inspect. The pipe is non
+        // blocking, so a full pipe drops the signal rather than hanging the handler.
+        let b = signum as u8;
+        unsafe {
+            let errno = *errno_location();
+            libc::write(fd, &b as *const u8 as *const libc::c_void, 1);
+            *errno_location() = errno;
+        }
+    }
+}
+
+/// Installs handlers for the specified signals and returns a channel that receives each of them.
+///
+/// The handlers are process wide and replace the default action of the signals.
+pub fn on(signals: &[Signal]) -> io::Result<mpsc::Receiver<Signal>> {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if SIGNAL_FD.load(Ordering::Relaxed) < 0 {
+        let (rd, wr) = io_err!(unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK))?;
+        // Only the signal handler should not block, the thread below waits for signals.
+        io_err!(fcntl::fcntl(rd, FcntlArg::F_SETFL(OFlag::empty())))?;
+        SIGNAL_FD.store(wr, Ordering::Relaxed);
+        let mut pipe = unsafe { fs::File::from_raw_fd(rd) };
+        thread::spawn(move || {
+            let mut buf = [0; 1];
+            while let Ok(1) = pipe.read(&mut buf) {
+                let sig = match Signal::from_c_int(libc::c_int::from(buf[0])) {
+                    Ok(sig) => sig,
+                    Err(_) => continue,
+                };
+                SUBSCRIBERS.lock().unwrap()
+                    .retain(|(s, tx)| *s != sig || tx.send(sig).is_ok());
+            }
+        });
+    }
+
+    let action = SigAction::new(SigHandler::Handler(handle_signal), SaFlags::SA_RESTART, SigSet::empty());
+    let (tx, rx) = mpsc::channel();
+    for &sig in signals {
+        io_err!(unsafe { signal::sigaction(sig, &action) })?;
+        subscribers.push((sig, tx.clone()));
+    }
+    Ok(rx)
+}
+
+/// Installs handlers for SIGINT and SIGTERM and returns a channel that receives each of these
+/// signals, so the process can shut down cleanly instead of being killed.
+pub fn on_terminate() -> io::Result<mpsc::Receiver<Signal>> {
+    on(&[Signal::SIGINT, Signal::SIGTERM])
+}
@@ -0,0 +1,266 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time;
+
+
+/// The upper bounds in seconds of the buckets that the write latencies are counted in.
+pub const WRITE_BUCKETS: [f64; 10] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+/// Counters that are updated by the stages that frames pass through, from reading the input to
+/// writing to the output.
+#[derive(Debug, Default)]
+pub struct Stats {
+    frames: AtomicUsize,
+    short_frames: AtomicUsize,
+    skipped_frames: AtomicUsize,
+    reads: AtomicUsize,
+    transforms: AtomicUsize,
+    /// The time spent in each stage in nanoseconds.
+    read_ns: AtomicU64,
+    transform_ns: AtomicU64,
+    write_ns: AtomicU64,
+    /// The counters below are never reset, they are exported as metrics.
+    total_frames: AtomicU64,
+    total_short_frames: AtomicU64,
+    total_skipped_frames: AtomicU64,
+    total_write_ns: AtomicU64,
+    /// The number of writes that took at most the time of the corresponding WRITE_BUCKETS.
+    write_buckets: [AtomicU64; 10],
+    /// The number of frames that have been selected from each input.
+    input_frames: Mutex<Vec<u64>>,
+}
+
+impl Stats {
+    /// Records the time it took to read a frame, including the time spent waiting for the input.
+    pub fn add_read(&self, d: time::Duration) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.read_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the time it took to adjust, color correct and transpose a frame.
+    pub fn add_transform(&self, d: time::Duration) {
+        self.transforms.fetch_add(1, Ordering::Relaxed);
+        self.transform_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records a frame that has been written to the output and the time it took.
+    pub fn add_write(&self, d: time::Duration) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.write_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+        self.total_frames.fetch_add(1, Ordering::Relaxed);
+        self.total_write_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+        if let Some(i) = WRITE_BUCKETS.iter().position(|&le| d.as_secs_f64() <= le) {
+            self.write_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a partially received frame that was discarded after the clear timeout.
+    pub fn add_short_frame(&self) {
+        self.short_frames.fetch_add(1, Ordering::Relaxed);
+        self.total_short_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a frame that was read but discarded because the output was still busy.
+    pub fn add_skipped_frame(&self) {
+        self.skipped_frames.fetch_add(1, Ordering::Relaxed);
+        self.total_skipped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a frame that has been selected from the input at the specified index.
+    pub fn add_input_frame(&self, input: usize) {
+        let mut inputs = self.input_frames.lock().unwrap();
+        if inputs.len() <= input {
+            inputs.resize(input + 1, 0);
+        }
+        inputs[input] += 1;
+    }
+
+    /// Forgets the input at the specified index, the inputs after it shift down by one.
+    pub fn remove_input(&self, input: usize) {
+        let mut inputs = self.input_frames.lock().unwrap();
+        if input < inputs.len() {
+            inputs.remove(input);
+        }
+    }
+
+    /// Returns the counters that have accumulated since ledcat was started.
+    pub fn totals(&self) -> Totals {
+        let mut count = 0;
+        let write_buckets = WRITE_BUCKETS.iter()
+            .zip(self.write_buckets.iter())
+            .map(|(&le, n)| {
+                count += n.load(Ordering::Relaxed);
+                (le, count)
+            })
+            .collect();
+        Totals {
+            frames: self.total_frames.load(Ordering::Relaxed),
+            short_frames: self.total_short_frames.load(Ordering::Relaxed),
+            skipped_frames: self.total_skipped_frames.load(Ordering::Relaxed),
+            write: time::Duration::from_nanos(self.total_write_ns.load(Ordering::Relaxed)),
+            write_buckets,
+            input_frames: self.input_frames.lock().unwrap().clone(),
+        }
+    }
+
+    /// Returns the counters since the last call and resets them.
+    pub fn take(&self) -> Report {
+        let average = |total: &AtomicU64, count: usize| {
+            time::Duration::from_nanos(total.swap(0, Ordering::Relaxed) / (count.max(1) as u64))
+        };
+        let frames = self.frames.swap(0, Ordering::Relaxed);
+        let reads = self.reads.swap(0, Ordering::Relaxed);
+        let transforms = self.transforms.swap(0, Ordering::Relaxed);
+        Report {
+            frames,
+            short_frames: self.short_frames.swap(0, Ordering::Relaxed),
+            skipped_frames: self.skipped_frames.swap(0, Ordering::Relaxed),
+            read: average(&self.read_ns, reads),
+            transform: average(&self.transform_ns, transforms),
+            write: average(&self.write_ns, frames),
+        }
+    }
+}
+
+
+/// The statistics of a period of time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// The number of frames written to the output.
+    pub frames: usize,
+    /// The number of partially received frames that were discarded.
+    pub short_frames: usize,
+    /// The number of frames that were discarded because the output could not keep up.
+    pub skipped_frames: usize,
+    /// The average time spent in each stage per frame.
+    pub read: time::Duration,
+    pub transform: time::Duration,
+    pub write: time::Duration,
+}
+
+impl Report {
+    /// Formats the report of a period of the specified length, of which the frames add up to
+    /// `total_frames`.
+    pub fn display(&self, period: time::Duration, total_frames: usize) -> String {
+        let ms = |d: time::Duration| d.as_secs_f64() * 1000.0;
+        format!("frames: {}, fps: {:.1}, short frames: {}, skipped frames: {}, read: {:.2}ms, transform: {:.2}ms, write: {:.2}ms",
+                total_frames, self.frames as f64 / period.as_secs_f64(), self.short_frames, self.skipped_frames,
+                ms(self.read), ms(self.transform), ms(self.write))
+    }
+}
+
+/// The counters since ledcat was started.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Totals {
+    /// The number of frames written to the output.
+    pub frames: u64,
+    /// The number of partially received frames that were discarded.
+    pub short_frames: u64,
+    /// The number of frames that were discarded because the output could not keep up.
+    pub skipped_frames: u64,
+    /// The total time spent writing frames.
+    pub write: time::Duration,
+    /// The cumulative number of writes that took at most the number of seconds of each bucket.
+    pub write_buckets: Vec<(f64, u64)>,
+    /// The number of frames that have been selected from each input.
+    pub input_frames: Vec<u64>,
+}
+
+
+/// Prints a report to stderr at the specified interval.
+pub fn report_every(stats: Arc<Stats>, interval: time::Duration) {
+    thread::spawn(move || {
+        let mut total_frames = 0;
+        let mut last = time::Instant::now();
+        loop {
+            thread::sleep(interval);
+            let report = stats.take();
+            total_frames += report.frames;
+            let period = last.elapsed();
+            let ms = |d: time::Duration| d.as_secs_f64() * 1000.0;
+            event!(::log::Level::Info, "stats", {
+                "frames": total_frames,
+                "fps": report.frames as f64 / period.as_secs_f64(),
+                "short_frames": report.short_frames,
+                "skipped_frames": report.skipped_frames,
+                "read_ms": ms(report.read),
+                "transform_ms": ms(report.transform),
+                "write_ms": ms(report.write),
+            }, "{}", report.display(period, total_frames));
+            last = time::Instant::now();
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_take() {
+        let stats = Stats::default();
+        stats.add_read(time::Duration::from_millis(10));
+        stats.add_read(time::Duration::from_millis(20));
+        stats.add_transform(time::Duration::from_millis(1));
+        stats.add_write(time::Duration::from_millis(4));
+        stats.add_write(time::Duration::from_millis(2));
+        stats.add_short_frame();
+        stats.add_skipped_frame();
+        assert_eq!(Report {
+            frames: 2,
+            short_frames: 1,
+            skipped_frames: 1,
+            read: time::Duration::from_millis(15),
+            transform: time::Duration::from_millis(1),
+            write: time::Duration::from_millis(3),
+        }, stats.take());
+        assert_eq!(Report {
+            frames: 0,
+            short_frames: 0,
+            skipped_frames: 0,
+            read: time::Duration::new(0, 0),
+            transform: time::Duration::new(0, 0),
+            write: time::Duration::new(0, 0),
+        }, stats.take());
+    }
+
+    #[test]
+    fn stats_totals() {
+        let stats = Stats::default();
+        stats.add_write(time::Duration::from_micros(300));
+        stats.add_write(time::Duration::from_millis(2));
+        stats.add_write(time::Duration::from_secs(1));
+        stats.add_short_frame();
+        stats.add_skipped_frame();
+        stats.add_input_frame(0);
+        stats.add_input_frame(2);
+        stats.add_input_frame(2);
+        stats.take();
+        let totals = stats.totals();
+        assert_eq!(3, totals.frames);
+        assert_eq!(1, totals.short_frames);
+        assert_eq!(1, totals.skipped_frames);
+        assert_eq!(time::Duration::from_micros(1_002_300), totals.write);
+        let counts: Vec<u64> = totals.write_buckets.iter().map(|b| b.1).collect();
+        assert_eq!(vec![0, 0, 1, 1, 2, 2, 2, 2, 2, 2], counts);
+        assert_eq!(vec![1, 0, 2], totals.input_frames);
+        stats.remove_input(1);
+        assert_eq!(vec![1, 2], stats.totals().input_frames);
+    }
+
+    #[test]
+    fn report_display() {
+        let report = Report {
+            frames: 60,
+            short_frames: 1,
+            skipped_frames: 3,
+            read: time::Duration::from_micros(33_250),
+            transform: time::Duration::from_micros(120),
+            write: time::Duration::from_millis(1),
+        };
+        assert_eq!("frames: 600, fps: 30.0, short frames: 1, skipped frames: 3, read: 33.25ms, transform: 0.12ms, write: 1.00ms",
+                   report.display(time::Duration::new(2, 0), 600));
+    }
+}
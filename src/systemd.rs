@@ -0,0 +1,213 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::ops::Range;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net;
+use std::time;
+use nix::fcntl::{self, FcntlArg, FdFlag};
+use nix::libc;
+use nix::unistd;
+
+
+/// The first file descriptor that is passed by the service manager, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends a state change like "READY=1" to the service manager, see sd_notify(3).
+///
+/// Returns false if ledcat was not started by a service manager that expects notifications.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let addr = notify_addr(&path)?;
+    net::UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// Like `notify`, but only logs the errors since the service manager will report a service that
+/// failed to notify it.
+pub fn notify_or_warn(state: &str) {
+    match notify(state) {
+        Ok(true) => debug!("Notified the service manager: {}", state),
+        Ok(false) => (),
+        Err(err) => warn!("Could not notify the service manager: {}", err),
+    }
+}
+
+/// Keeps the watchdog of the service manager from restarting ledcat, see sd_watchdog_enabled(3).
+pub struct Watchdog {
+    interval: time::Duration,
+    last_ping: Option<time::Instant>,
+}
+
+impl Watchdog {
+    /// Returns the watchdog if the service manager has enabled it for ledcat. The variables in the
+    /// environment are cleared, so child processes do not mistake it for their own.
+    pub fn from_env() -> Option<Watchdog> {
+        let interval = watchdog_interval(env::var("WATCHDOG_USEC").ok().as_deref(),
+                                         env::var("WATCHDOG_PID").ok().as_deref(),
+                                         unistd::getpid().into());
+        env::remove_var("WATCHDOG_USEC");
+        env::remove_var("WATCHDOG_PID");
+        interval.map(|interval| Watchdog { interval, last_ping: None })
+    }
+
+    /// Pings the watchdog if half of its interval has passed since the last ping. This should be
+    /// called for each frame, so the service is restarted when frames stop flowing.
+    pub fn frame(&mut self) {
+        let now = time::Instant::now();
+        if self.last_ping.map(|t| now - t < self.interval / 2).unwrap_or(false) {
+            return;
+        }
+        self.last_ping = Some(now);
+        match notify("WATCHDOG=1") {
+            Ok(_) => trace!("Pinged the watchdog of the service manager"),
+            Err(err) => warn!("Could not ping the watchdog of the service manager: {}", err),
+        }
+    }
+}
+
+/// Determines the interval of the watchdog from the values of $WATCHDOG_USEC and $WATCHDOG_PID.
+/// The watchdog is meant for us if the PID is not set or matches our own.
+fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: i32) -> Option<time::Duration> {
+    let for_us = watchdog_pid
+        .map(|s| s.parse::<i32>().ok() == Some(pid))
+        .unwrap_or(true);
+    usec.and_then(|s| s.parse::<u64>().ok())
+        .filter(|&usec| usec > 0 && for_us)
+        .map(time::Duration::from_micros)
+}
+
+/// The path of the notification socket starts with '@' if it lives in the abstract namespace.
+#[cfg(target_os = "linux")]
+fn notify_addr(path: &OsStr) -> io::Result<net::SocketAddr> {
+    match path.as_bytes() {
+        [b'@', name @ ..] => net::SocketAddr::from_abstract_name(name),
+        _ => net::SocketAddr::from_pathname(path),
+    }
+}
+
+/// The abstract namespace only exists on Linux, elsewhere the path is a file.
+#[cfg(not(target_os = "linux"))]
+fn notify_addr(path: &OsStr) -> io::Result<net::SocketAddr> {
+    net::SocketAddr::from_pathname(path)
+}
+
+/// Takes the unix stream socket that has been passed by the service manager through socket
+/// activation, see sd_listen_fds(3). The variables in the environment are cleared, so the socket
+/// is not passed on to child processes such as generators.
+pub fn listener() -> Option<net::UnixListener> {
+    let fds = listen_fds(env::var("LISTEN_PID").ok().as_deref(),
+                         env::var("LISTEN_FDS").ok().as_deref(),
+                         unistd::getpid().into());
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let mut listener = None;
+    for fd in fds {
+        let _ = fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC));
+        let is_stream_listener = socket_option(fd, libc::SO_TYPE).ok() == Some(libc::SOCK_STREAM)
+            && socket_option(fd, libc::SO_ACCEPTCONN).ok() == Some(1);
+        if !is_stream_listener {
+            warn!("Ignoring fd {} passed by the service manager, expected a single unix stream socket", fd);
+            continue;
+        }
+        // The address can only be read if the socket is a unix socket.
+        let sock = unsafe { net::UnixListener::from_raw_fd(fd) };
+        if listener.is_none() && sock.local_addr().is_ok() {
+            listener = Some(sock);
+        } else {
+            warn!("Ignoring fd {} passed by the service manager, expected a single unix stream socket", fd);
+        }
+    }
+    listener
+}
+
+/// Reads an integer socket option. The SO_TYPE wrapper of nix can not be used here, it
+/// zero-initializes an enum.
+fn socket_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, option, &mut value as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Determines the range of file descriptors that have been passed from the values of $LISTEN_PID
+/// and $LISTEN_FDS. The descriptors are only meant for us if the PID matches our own.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: i32) -> Range<RawFd> {
+    let for_us = listen_pid
+        .and_then(|s| s.parse::<i32>().ok())
+        .map(|p| p == pid)
+        .unwrap_or(false);
+    let num = listen_fds
+        .and_then(|s| s.parse::<RawFd>().ok())
+        .filter(|_| for_us)
+        .unwrap_or(0);
+    LISTEN_FDS_START..LISTEN_FDS_START + num
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use super::*;
+
+    #[test]
+    fn parse_listen_fds() {
+        assert_eq!(3..5, listen_fds(Some("42"), Some("2"), 42));
+        assert_eq!(3..3, listen_fds(Some("43"), Some("2"), 42));
+        assert_eq!(3..3, listen_fds(None, Some("2"), 42));
+        assert_eq!(3..3, listen_fds(Some("42"), None, 42));
+        assert_eq!(3..3, listen_fds(Some("42"), Some("two"), 42));
+    }
+
+    #[test]
+    fn parse_watchdog_interval() {
+        let interval = Some(time::Duration::from_secs(10));
+        assert_eq!(interval, watchdog_interval(Some("10000000"), Some("42"), 42));
+        assert_eq!(interval, watchdog_interval(Some("10000000"), None, 42));
+        assert_eq!(None, watchdog_interval(Some("10000000"), Some("43"), 42));
+        assert_eq!(None, watchdog_interval(Some("0"), None, 42));
+        assert_eq!(None, watchdog_interval(Some("ten"), None, 42));
+        assert_eq!(None, watchdog_interval(None, Some("42"), 42));
+    }
+
+    #[test]
+    fn parse_notify_addr() {
+        let addr = notify_addr(OsStr::new("/run/systemd/notify")).unwrap();
+        assert_eq!(Some(::std::path::Path::new("/run/systemd/notify")), addr.as_pathname());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn parse_notify_addr_abstract() {
+        let addr = notify_addr(OsStr::new("@/org/freedesktop/systemd1/notify")).unwrap();
+        assert_eq!(Some(&b"/org/freedesktop/systemd1/notify"[..]), addr.as_abstract_name());
+    }
+
+    #[test]
+    fn notify_datagram() {
+        let dir = tempdir::TempDir::new("ledcat-notify").unwrap();
+        let path = dir.path().join("notify");
+        let sock = net::UnixDatagram::bind(&path).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+        assert!(notify("READY=1").unwrap());
+        env::remove_var("NOTIFY_SOCKET");
+        let mut buf = [0; 16];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"READY=1", &buf[..n]);
+    }
+}
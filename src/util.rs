@@ -1,7 +1,7 @@
+#[macro_export]
 macro_rules! regex_validator {
     ($expression:expr) => ({
-        use regex::Regex;
-        let ex = Regex::new($expression).unwrap();
+        let ex = $crate::regex::Regex::new($expression).unwrap();
         move |val: String| {
             if ex.is_match(val.as_str()) {
                 Ok(())
@@ -12,8 +12,9 @@ macro_rules! regex_validator {
     })
 }
 
+#[macro_export]
 macro_rules! io_err {
     ($expr:expr) => {
-        $expr.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        $expr.map_err(|err| ::std::io::Error::new(::std::io::ErrorKind::Other, err))
     }
 }
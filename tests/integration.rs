@@ -1,5 +1,9 @@
 #![cfg(test)]
 extern crate assert_cli;
+extern crate ledcat;
+
+use ledcat::log::Level;
+use ledcat::{event, warn};
 
 #[test]
 #[cfg(not(feature = "ci"))]
@@ -22,3 +26,70 @@ fn calling_generic_pipe() {
         .stdout().is(data)
         .unwrap();
 }
+
+#[test]
+#[cfg(not(feature = "ci"))]
+fn calling_check() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--geometry", "3", "--driver", "none", "--check", "generic"])
+        .stdout().contains("output pixels: 3")
+        .unwrap();
+    assert_cli::Assert::main_binary()
+        .with_args(&["--geometry", "3", "--input", "/nonexistent", "--driver", "none", "--check", "generic"])
+        .fails()
+        .stderr().contains("/nonexistent")
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "ci"))]
+fn calling_num_frames() {
+    let data = "\x00\x01\x02\x03\x04\x05\x06\x07\x08";
+    assert_cli::Assert::main_binary()
+        .with_args(&["--geometry", "1", "--driver", "none", "--num-frames", "2", "generic"])
+        .stdin(data)
+        .stdout().is("\x00\x01\x02\x03\x04\x05")
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "ci"))]
+fn calling_tee() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--geometry", "2", "--transpose", "reverse", "--driver", "none", "--output", "/dev/null", "--tee", "generic"])
+        .stdin("\x01\x02\x03\x04\x05\x06")
+        .stdout().is("\x04\x05\x06\x01\x02\x03")
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "ci"))]
+fn calling_fade_out() {
+    // The last frame is written again at a decreasing brightness, ending in black.
+    assert_cli::Assert::main_binary()
+        .with_args(&["--geometry", "1", "-c", "none", "--driver", "none", "--output", "/dev/stdout", "--fade-out", "100", "generic"])
+        .stdin("\x7f\x7f\x7f")
+        .stdout().contains("\x7f\x7f\x7f\x7f\x7f\x7f")
+        .stdout().contains("\x00\x00\x00")
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "ci"))]
+fn calling_fade_overflow() {
+    for option in &["--fade-in", "--fade-out"] {
+        assert_cli::Assert::main_binary()
+            .with_args(&["--geometry", "1", "--driver", "none", "--output", "/dev/null", option, "99999999999999999999", "generic"])
+            .fails_with(1)
+            .stderr().contains("is not a number of milliseconds")
+            .unwrap();
+    }
+}
+
+#[test]
+fn logging_macros() {
+    // The macros are usable on their own, without the crates they call into.
+    ledcat::log::set_level(Level::Off);
+    warn!("Not {}", "written");
+    event!(Level::Error, "not_written", {"frame": 1}, "Not written");
+}